@@ -0,0 +1,339 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use super::{
+    diff::{diff_checkpoints, DEFAULT_CONTEXT_LINES},
+    restore::write_entry,
+    storage::CheckpointStorage,
+    Checkpoint, CheckpointDiff, CheckpointPaths,
+};
+
+/// How much of a command's output is kept for each step, from the end
+const MAX_OUTPUT_BYTES: usize = 16 * 1024;
+
+/// How often a running command is checked for completion
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The outcome of running the test command on one checkpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BisectStep {
+    pub checkpoint_id: String,
+    pub passed: bool,
+    /// Exit code of the command; `None` if it was killed or timed out
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    /// The end of the command's combined stdout and stderr
+    pub output: String,
+}
+
+/// Result of bisecting the path from the root to the current checkpoint
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BisectResult {
+    /// The first checkpoint on the path where the command fails; `None` if
+    /// it passes on the current checkpoint
+    pub first_bad: Option<Checkpoint>,
+    /// The checkpoint just before the first bad one, where the command passes
+    pub last_good: Option<Checkpoint>,
+    /// What changed between the last good and the first bad checkpoint
+    pub diff: Option<CheckpointDiff>,
+    /// Every run of the command, in the order they happened
+    pub steps: Vec<BisectStep>,
+    /// Number of checkpoints on the path that was searched
+    pub path_length: usize,
+}
+
+impl CheckpointStorage {
+    /// Find the first checkpoint between the root and the current checkpoint
+    /// at which `command` fails.
+    ///
+    /// Each checkpoint tested is written to a scratch directory and the
+    /// command is run there through the shell; a zero exit code counts as a
+    /// pass. Only files captured by the checkpoint are written, so ignored
+    /// files such as build output or dependencies are not available. The
+    /// current checkpoint is tested first, then the path is binary searched,
+    /// assuming that once the command fails it keeps failing.
+    pub fn bisect_checkpoints(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        command: &str,
+        timeout: Option<Duration>,
+    ) -> Result<BisectResult> {
+        if command.trim().is_empty() {
+            anyhow::bail!("No test command given");
+        }
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        let timeline = self.load_timeline(&paths)?;
+        let current_id = match &timeline.current_checkpoint_id {
+            Some(current_id) => current_id.clone(),
+            None => anyhow::bail!("The session has no current checkpoint to bisect to"),
+        };
+
+        let path = timeline
+            .path_to(&current_id)
+            .context("The current checkpoint is not in the timeline")?;
+
+        let mut steps = Vec::new();
+        let mut test = |checkpoint: &Checkpoint| -> Result<bool> {
+            let step =
+                self.test_checkpoint(project_id, session_id, checkpoint, command, timeout)?;
+            log::info!(
+                "Bisect: checkpoint {} {}",
+                checkpoint.id,
+                if step.passed { "passed" } else { "failed" }
+            );
+            let passed = step.passed;
+            steps.push(step);
+            Ok(passed)
+        };
+
+        // Nothing to look for unless the command fails on the current checkpoint
+        let mut bad = path.len() - 1;
+        if test(&path[bad])? {
+            return Ok(BisectResult {
+                first_bad: None,
+                last_good: path.last().cloned(),
+                diff: None,
+                steps,
+                path_length: path.len(),
+            });
+        }
+
+        let mut good: Option<usize> = None;
+        loop {
+            let low = good.map_or(0, |good| good + 1);
+            if low == bad {
+                break;
+            }
+            let middle = low + (bad - low) / 2;
+            if test(&path[middle])? {
+                good = Some(middle);
+            } else {
+                bad = middle;
+            }
+        }
+
+        let first_bad = path[bad].clone();
+        let last_good = bad.checked_sub(1).map(|index| path[index].clone());
+        let diff = match &last_good {
+            Some(last_good) => {
+                let (_, good_files, _) =
+                    self.load_checkpoint(project_id, session_id, &last_good.id)?;
+                let (_, bad_files, _) =
+                    self.load_checkpoint(project_id, session_id, &first_bad.id)?;
+                Some(diff_checkpoints(
+                    last_good,
+                    &good_files,
+                    &first_bad,
+                    &bad_files,
+                    DEFAULT_CONTEXT_LINES,
+                ))
+            }
+            None => None,
+        };
+
+        Ok(BisectResult {
+            first_bad: Some(first_bad),
+            last_good,
+            diff,
+            steps,
+            path_length: path.len(),
+        })
+    }
+
+    /// Write the files of a checkpoint into `dir`, which should be empty
+    pub fn materialize_checkpoint(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        checkpoint_id: &str,
+        dir: &Path,
+    ) -> Result<usize> {
+        let (_, snapshots, _) = self.load_checkpoint(project_id, session_id, checkpoint_id)?;
+        let mut written = 0;
+        for snapshot in snapshots.iter().filter(|snapshot| !snapshot.is_deleted) {
+            write_entry(&dir.join(&snapshot.file_path), snapshot)
+                .with_context(|| format!("Failed to write {:?}", snapshot.file_path))?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Run the test command on a fresh copy of a checkpoint
+    fn test_checkpoint(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        checkpoint: &Checkpoint,
+        command: &str,
+        timeout: Option<Duration>,
+    ) -> Result<BisectStep> {
+        let scratch = tempfile::tempdir().context("Failed to create scratch directory")?;
+        self.materialize_checkpoint(project_id, session_id, &checkpoint.id, scratch.path())?;
+
+        let (exit_code, timed_out, output) = run_command(command, scratch.path(), timeout)?;
+        Ok(BisectStep {
+            checkpoint_id: checkpoint.id.clone(),
+            passed: exit_code == Some(0),
+            exit_code,
+            timed_out,
+            output,
+        })
+    }
+}
+
+/// Run a shell command in `dir`, returning its exit code, whether it timed
+/// out and the end of its output
+fn run_command(
+    command: &str,
+    dir: &Path,
+    timeout: Option<Duration>,
+) -> Result<(Option<i32>, bool, String)> {
+    // Output goes to a file rather than a pipe, so a chatty command cannot
+    // block while it is being waited on
+    let mut log = tempfile::tempfile().context("Failed to create output file")?;
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    };
+    // Its own process group, so whatever the command starts can be killed with it
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+    let mut child = shell
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone().context("Failed to create output file")?)
+        .stderr(log.try_clone().context("Failed to create output file")?)
+        .spawn()
+        .with_context(|| format!("Failed to run {}", command))?;
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().context("Failed to wait for command")? {
+            // Nothing it left running in the background may keep writing to the log
+            #[cfg(unix)]
+            kill_process_tree(&mut child);
+            break Some(status);
+        }
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            kill_process_tree(&mut child);
+            let _ = child.wait();
+            break None;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    let mut output = Vec::new();
+    log.seek(SeekFrom::Start(0))
+        .and_then(|_| log.read_to_end(&mut output))
+        .context("Failed to read command output")?;
+    let tail = output.len().saturating_sub(MAX_OUTPUT_BYTES);
+    let output = String::from_utf8_lossy(&output[tail..]).into_owned();
+
+    Ok((
+        status.and_then(|status| status.code()),
+        status.is_none(),
+        output,
+    ))
+}
+
+/// Kill a command's shell along with everything it started, such as the
+/// test runner of a `cargo test`
+fn kill_process_tree(child: &mut Child) {
+    #[cfg(unix)]
+    // SAFETY: kill() has no memory-safety requirements; a negative pid
+    // addresses the process group the shell was made the leader of
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(windows)]
+    let _ = Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &child.id().to_string()])
+        .output();
+    let _ = child.kill();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::{checkpoint_with_prompt, snapshot};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_bisect_finds_first_failing_checkpoint() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+
+        // The build breaks at the sixth of eight checkpoints
+        let mut parent: Option<String> = None;
+        for step in 0..8 {
+            let id = format!("checkpoint-{}", step);
+            let status = if step < 5 { "pass" } else { "fail" };
+            let checkpoint = Checkpoint {
+                message_index: step,
+                ..checkpoint_with_prompt(&id, parent.as_deref(), &format!("Step {}", step))
+            };
+            let snapshot = snapshot(&id, "status.txt", status);
+            storage
+                .save_checkpoint("project", "session", &checkpoint, vec![snapshot], "")
+                .unwrap();
+            parent = Some(id);
+        }
+
+        let result = storage
+            .bisect_checkpoints("project", "session", "grep -q pass status.txt", None)
+            .unwrap();
+        let first_bad = result.first_bad.unwrap();
+        assert_eq!(first_bad.id, "checkpoint-5");
+        assert_eq!(first_bad.metadata.user_prompt, "Step 5");
+        assert_eq!(result.last_good.unwrap().id, "checkpoint-4");
+        assert_eq!(result.diff.unwrap().modified_files.len(), 1);
+        assert_eq!(result.path_length, 8);
+        // The current checkpoint, then a binary search over the other seven
+        assert_eq!(result.steps.len(), 4);
+
+        let result = storage
+            .bisect_checkpoints("project", "session", "true", None)
+            .unwrap();
+        assert!(result.first_bad.is_none());
+    }
+
+    #[test]
+    fn test_timeout_kills_the_whole_command() {
+        let dir = TempDir::new().unwrap();
+        let (exit_code, timed_out, _) = run_command(
+            "sleep 30 & echo $! > child.pid; wait",
+            dir.path(),
+            Some(Duration::from_millis(300)),
+        )
+        .unwrap();
+        assert!(timed_out);
+        assert_eq!(exit_code, None);
+
+        let pid = std::fs::read_to_string(dir.path().join("child.pid")).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        // Gone, or a zombie waiting for init to reap it
+        let state = Command::new("ps")
+            .args(["-o", "stat=", "-p", pid.trim()])
+            .output()
+            .unwrap();
+        let state = String::from_utf8_lossy(&state.stdout);
+        assert!(
+            state.trim().is_empty() || state.starts_with('Z'),
+            "{}",
+            state
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use similar::{capture_diff_slices, Algorithm, DiffTag};
+use std::path::{Path, PathBuf};
+
+use super::{is_binary_content, storage::CheckpointStorage, Checkpoint, CheckpointPaths, FileKind};
+
+/// A run of consecutive lines introduced by the same checkpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlameSpan {
+    /// First line of the span, counting from 1
+    pub start_line: usize,
+    /// Last line of the span, inclusive
+    pub end_line: usize,
+    pub checkpoint_id: String,
+    pub timestamp: DateTime<Utc>,
+    /// The prompt that led to the checkpoint that wrote these lines
+    pub user_prompt: String,
+    pub description: Option<String>,
+}
+
+/// Which checkpoint introduced each line of a file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileBlame {
+    pub file_path: PathBuf,
+    /// Checkpoint whose version of the file was blamed
+    pub checkpoint_id: String,
+    pub total_lines: usize,
+    pub spans: Vec<BlameSpan>,
+}
+
+impl CheckpointStorage {
+    /// Attribute every line of a file, as it is at `checkpoint_id` (the
+    /// current checkpoint by default), to the checkpoint that introduced it.
+    ///
+    /// The file's versions are compared along the path from the root to the
+    /// checkpoint. Lines kept from one version to the next keep their origin,
+    /// and added or changed lines are attributed to the checkpoint where they
+    /// first appear. Lines brought in by a merge are attributed to the merge
+    /// checkpoint, and a file that is deleted and recreated, or replaced by a
+    /// symlink for a while, starts over.
+    pub fn blame_file(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        file_path: &Path,
+        checkpoint_id: Option<&str>,
+    ) -> Result<FileBlame> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        let timeline = self.load_timeline(&paths)?;
+        let checkpoint_id = match checkpoint_id.or(timeline.current_checkpoint_id.as_deref()) {
+            Some(checkpoint_id) => checkpoint_id.to_string(),
+            None => anyhow::bail!("The session has no current checkpoint"),
+        };
+        let path = timeline
+            .path_to(&checkpoint_id)
+            .with_context(|| format!("Checkpoint {} is not in the timeline", checkpoint_id))?;
+
+        // The current version's lines and, for each, the index into `path`
+        // of the checkpoint that introduced it
+        let mut lines: Vec<String> = Vec::new();
+        let mut origins: Vec<usize> = Vec::new();
+        let mut previous_hash: Option<String> = None;
+        for (index, checkpoint) in path.iter().enumerate() {
+            let entry = match self.read_manifest(&paths, &checkpoint.id)? {
+                Some(manifest) => manifest
+                    .files
+                    .get(file_path)
+                    .filter(|entry| !entry.deleted && entry.kind == FileKind::File)
+                    .cloned(),
+                None => None,
+            };
+            let entry = match entry {
+                Some(entry) => entry,
+                None => {
+                    lines.clear();
+                    origins.clear();
+                    previous_hash = None;
+                    continue;
+                }
+            };
+            if previous_hash.as_deref() == Some(entry.hash.as_str()) {
+                continue;
+            }
+
+            let content = self
+                .read_content(&paths, &entry.hash)
+                .with_context(|| format!("Failed to load content of {}", file_path.display()))?;
+            if is_binary_content(&content) {
+                anyhow::bail!("{} is a binary file", file_path.display());
+            }
+            let text = String::from_utf8_lossy(&content);
+            let new_lines: Vec<String> = text.split_inclusive('\n').map(str::to_string).collect();
+
+            let mut new_origins = vec![index; new_lines.len()];
+            for op in capture_diff_slices(Algorithm::Myers, &lines, &new_lines) {
+                let (tag, old_range, new_range) = op.as_tag_tuple();
+                if tag == DiffTag::Equal {
+                    new_origins[new_range].copy_from_slice(&origins[old_range]);
+                }
+            }
+            lines = new_lines;
+            origins = new_origins;
+            previous_hash = Some(entry.hash);
+        }
+
+        if previous_hash.is_none() {
+            anyhow::bail!(
+                "{} is not a file at checkpoint {}",
+                file_path.display(),
+                checkpoint_id
+            );
+        }
+
+        Ok(FileBlame {
+            file_path: file_path.to_path_buf(),
+            checkpoint_id,
+            total_lines: lines.len(),
+            spans: spans(&origins, &path),
+        })
+    }
+}
+
+/// Group consecutive lines with the same origin into spans
+fn spans(origins: &[usize], path: &[Checkpoint]) -> Vec<BlameSpan> {
+    let mut spans: Vec<BlameSpan> = Vec::new();
+    let mut start = 0;
+    for line in 1..=origins.len() {
+        if line < origins.len() && origins[line] == origins[start] {
+            continue;
+        }
+        let checkpoint = &path[origins[start]];
+        spans.push(BlameSpan {
+            start_line: start + 1,
+            end_line: line,
+            checkpoint_id: checkpoint.id.clone(),
+            timestamp: checkpoint.timestamp,
+            user_prompt: checkpoint.metadata.user_prompt.clone(),
+            description: checkpoint.description.clone(),
+        });
+        start = line;
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::{checkpoint_with_prompt, snapshot};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_blame_attributes_lines_to_checkpoints() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+
+        let versions = [
+            ("a", "Write the function", "fn main() {\n    run();\n}\n"),
+            ("b", "Rename the readme", "fn main() {\n    run();\n}\n"),
+            (
+                "c",
+                "Add logging",
+                "fn main() {\n    log();\n    run();\n}\n",
+            ),
+            (
+                "d",
+                "Handle errors",
+                "fn main() {\n    log();\n    run().unwrap();\n}\n",
+            ),
+        ];
+        let mut parent: Option<&str> = None;
+        for (id, prompt, content) in versions {
+            let checkpoint = checkpoint_with_prompt(id, parent, prompt);
+            let snapshot = snapshot(id, "src/main.rs", content);
+            storage
+                .save_checkpoint("project", "session", &checkpoint, vec![snapshot], "")
+                .unwrap();
+            parent = Some(id);
+        }
+
+        let blame = storage
+            .blame_file("project", "session", Path::new("src/main.rs"), None)
+            .unwrap();
+        assert_eq!(blame.checkpoint_id, "d");
+        assert_eq!(blame.total_lines, 4);
+        let spans: Vec<(usize, usize, &str)> = blame
+            .spans
+            .iter()
+            .map(|span| (span.start_line, span.end_line, span.checkpoint_id.as_str()))
+            .collect();
+        assert_eq!(spans, [(1, 1, "a"), (2, 2, "c"), (3, 3, "d"), (4, 4, "a")]);
+        assert_eq!(blame.spans[1].user_prompt, "Add logging");
+
+        // An earlier version can be blamed too
+        let blame = storage
+            .blame_file("project", "session", Path::new("src/main.rs"), Some("b"))
+            .unwrap();
+        assert_eq!(blame.spans.len(), 1);
+        assert_eq!(blame.spans[0].end_line, 3);
+
+        assert!(storage
+            .blame_file("project", "session", Path::new("missing.rs"), None)
+            .is_err());
+    }
+}
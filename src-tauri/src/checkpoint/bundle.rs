@@ -0,0 +1,784 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path};
+
+use super::{
+    manifest::CheckpointManifest, messages::split_messages, storage::CheckpointStorage, Checkpoint,
+    CheckpointPaths, SessionTimeline, TimelineNode,
+};
+
+/// Current bundle format version
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// zstd level for bundles; they are written once and moved around, so favor size
+const BUNDLE_COMPRESSION_LEVEL: i32 = 10;
+
+/// Archive entry holding the [`BundleHeader`], always the first one
+const HEADER_ENTRY: &str = "bundle.json";
+
+/// Archive directory of raw file contents, each named by its hash
+const OBJECTS_DIR: &str = "objects/";
+
+/// Archive directory of [`BundledCheckpoint`]s, each named by its ID
+const CHECKPOINTS_DIR: &str = "checkpoints/";
+
+/// What a bundle holds, written before its checkpoints.
+///
+/// A bundle is a zstd-compressed tar archive, written and read as a stream:
+/// this header, then for each checkpoint, parents first, the file contents it
+/// adds as raw `objects/<hash>` entries followed by the checkpoint itself as
+/// `checkpoints/<id>.json`. Contents are stored once per hash, so the bundle
+/// is self-contained and independent of the local layout of the content
+/// pool.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleHeader {
+    /// Format version, bumped on incompatible changes
+    pub version: u32,
+    /// When the bundle was written
+    pub exported_at: DateTime<Utc>,
+    /// Session the checkpoints were exported from
+    pub session_id: String,
+    /// Project path on the exporting machine
+    pub project_path: String,
+    /// Settings and current checkpoint of the exported timeline; the tree is
+    /// rebuilt from the checkpoints
+    pub timeline: SessionTimeline,
+}
+
+/// A single checkpoint inside a bundle
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundledCheckpoint {
+    pub checkpoint: Checkpoint,
+    pub manifest: CheckpointManifest,
+    pub file_snapshot_ids: Vec<String>,
+    /// Number of leading messages taken from the parent's transcript
+    pub inherited_messages: usize,
+    /// JSONL messages appended since the parent
+    pub messages: String,
+}
+
+/// Outcome of exporting or importing a bundle
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleSummary {
+    /// Session the checkpoints were exported from or imported into
+    pub session_id: String,
+    /// Number of checkpoints in the bundle
+    pub checkpoints: usize,
+    /// Number of distinct file contents in the bundle
+    pub objects: usize,
+    /// Size of the bundle file in bytes
+    pub bundle_size: u64,
+}
+
+impl CheckpointStorage {
+    /// Export a session's timeline, or the subset given by `checkpoint_ids`,
+    /// into a single bundle file.
+    ///
+    /// Checkpoints left out of a subset are skipped over: their selected
+    /// descendants are attached to the nearest selected ancestor, and merges
+    /// of them now merge their nearest selected ancestor.
+    pub fn export_bundle(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        project_path: &str,
+        checkpoint_ids: Option<&[String]>,
+        output_path: &Path,
+    ) -> Result<BundleSummary> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        let timeline = self.load_timeline(&paths)?;
+        if timeline.root_node.is_none() {
+            anyhow::bail!("Session {} has no checkpoints to export", session_id);
+        }
+
+        let selected: HashSet<String> = match checkpoint_ids {
+            Some(ids) => {
+                for id in ids {
+                    if timeline.find_checkpoint(id).is_none() {
+                        anyhow::bail!("Checkpoint not found: {}", id);
+                    }
+                }
+                ids.iter().cloned().collect()
+            }
+            None => timeline
+                .nodes()
+                .into_iter()
+                .map(|node| node.checkpoint.id.clone())
+                .collect(),
+        };
+        if selected.is_empty() {
+            anyhow::bail!("No checkpoints selected for export");
+        }
+
+        let nodes = prune_timeline(&timeline, &selected);
+        let roots = nodes
+            .iter()
+            .filter(|node| node.checkpoint.parent_checkpoint_id.is_none())
+            .count();
+        if roots != 1 {
+            anyhow::bail!(
+                "The selected checkpoints do not form a single tree; include a checkpoint they all descend from"
+            );
+        }
+
+        // Keep the current checkpoint if it was exported, otherwise the latest one
+        let current_checkpoint_id = timeline
+            .current_checkpoint_id
+            .clone()
+            .filter(|id| selected.contains(id))
+            .or_else(|| {
+                nodes
+                    .iter()
+                    .max_by_key(|node| node.checkpoint.timestamp)
+                    .map(|node| node.checkpoint.id.clone())
+            });
+        let header = BundleHeader {
+            version: BUNDLE_VERSION,
+            exported_at: Utc::now(),
+            session_id: session_id.to_string(),
+            project_path: project_path.to_string(),
+            timeline: SessionTimeline {
+                root_node: None,
+                current_checkpoint_id,
+                total_checkpoints: nodes.len(),
+                last_restore: None,
+                ..timeline
+            },
+        };
+
+        // Write next to the destination and move into place once complete
+        let file_name = output_path
+            .file_name()
+            .context("Bundle path has no file name")?
+            .to_string_lossy();
+        let partial_path = output_path.with_file_name(format!("{}.partial", file_name));
+        let file = File::create(&partial_path).context("Failed to create checkpoint bundle")?;
+        let written = self
+            .write_bundle(&paths, &header, &nodes, file)
+            .and_then(|objects| {
+                fs::rename(&partial_path, output_path)
+                    .context("Failed to move checkpoint bundle into place")?;
+                Ok(objects)
+            });
+        let objects = match written {
+            Ok(objects) => objects,
+            Err(e) => {
+                let _ = fs::remove_file(&partial_path);
+                return Err(e);
+            }
+        };
+
+        Ok(BundleSummary {
+            session_id: session_id.to_string(),
+            checkpoints: nodes.len(),
+            objects,
+            bundle_size: fs::metadata(output_path)
+                .context("Failed to read checkpoint bundle size")?
+                .len(),
+        })
+    }
+
+    /// Stream a bundle of `nodes`, listed parents first, into `file`,
+    /// returning the number of distinct file contents written
+    fn write_bundle(
+        &self,
+        paths: &CheckpointPaths,
+        header: &BundleHeader,
+        nodes: &[TimelineNode],
+        file: File,
+    ) -> Result<usize> {
+        let encoder = zstd::stream::Encoder::new(file, BUNDLE_COMPRESSION_LEVEL)
+            .context("Failed to compress checkpoint bundle")?;
+        let mut archive = tar::Builder::new(encoder);
+        append_json(&mut archive, HEADER_ENTRY, header)?;
+
+        let mut objects = HashSet::new();
+        for node in nodes {
+            let checkpoint = &node.checkpoint;
+            let manifest = self
+                .read_manifest(paths, &checkpoint.id)?
+                .with_context(|| format!("Checkpoint {} has no manifest", checkpoint.id))?;
+            for entry in manifest.files.values().filter(|entry| !entry.deleted) {
+                if objects.insert(entry.hash.clone()) {
+                    let content = self.read_content(paths, &entry.hash)?;
+                    append_entry(
+                        &mut archive,
+                        &format!("{}{}", OBJECTS_DIR, entry.hash),
+                        &content,
+                    )?;
+                }
+            }
+
+            // Only what was appended since the parent, which comes earlier
+            let messages = self.read_messages(paths, &checkpoint.id)?;
+            let lines = split_messages(&messages);
+            let inherited_messages = match &checkpoint.parent_checkpoint_id {
+                Some(parent_id) => {
+                    let parent_messages = self.read_messages(paths, parent_id)?;
+                    split_messages(&parent_messages)
+                        .iter()
+                        .zip(&lines)
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                }
+                None => 0,
+            };
+            let bundled = BundledCheckpoint {
+                checkpoint: checkpoint.clone(),
+                manifest,
+                file_snapshot_ids: node.file_snapshot_ids.clone(),
+                inherited_messages,
+                messages: lines[inherited_messages..].join("\n"),
+            };
+            append_json(
+                &mut archive,
+                &format!("{}{}.json", CHECKPOINTS_DIR, checkpoint.id),
+                &bundled,
+            )?;
+        }
+
+        let encoder = archive
+            .into_inner()
+            .context("Failed to write checkpoint bundle")?;
+        encoder
+            .finish()
+            .and_then(|file| file.sync_all())
+            .context("Failed to write checkpoint bundle")?;
+        Ok(objects.len())
+    }
+
+    /// Import a bundle as a new session of `project_id`.
+    ///
+    /// Session IDs and the project path recorded in messages are rewritten for
+    /// this machine, and the session JSONL is recreated from the current
+    /// checkpoint so the conversation can be resumed. A fresh session ID is
+    /// generated unless one is given, and checkpoints always get fresh IDs so
+    /// a bundle can be imported more than once.
+    pub fn import_bundle(
+        &self,
+        bundle_path: &Path,
+        project_id: &str,
+        project_path: &str,
+        session_id: Option<String>,
+    ) -> Result<BundleSummary> {
+        let session_id = session_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        if !is_single_component(&session_id) {
+            anyhow::bail!("Invalid session ID {}", session_id);
+        }
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, &session_id);
+        let mut index = self.open_index(&paths)?;
+        self.ensure_indexed(&mut index, &paths)?;
+        if index.has_session(&session_id)? {
+            anyhow::bail!("Session {} already has a checkpoint timeline", session_id);
+        }
+        drop(index);
+
+        let file = File::open(bundle_path).context("Failed to read checkpoint bundle")?;
+        let bundle_size = file
+            .metadata()
+            .context("Failed to read checkpoint bundle")?
+            .len();
+        let decoder =
+            zstd::stream::Decoder::new(file).context("Failed to decompress checkpoint bundle")?;
+        let mut archive = tar::Archive::new(decoder);
+
+        // File contents go to the content pool as they arrive, where they are
+        // harmless until referenced. Checkpoints are only written once the
+        // whole bundle has been checked.
+        let mut header: Option<BundleHeader> = None;
+        let mut objects = HashSet::new();
+        let mut checkpoints: Vec<BundledCheckpoint> = Vec::new();
+        let mut checkpoint_ids = HashSet::new();
+        for entry in archive
+            .entries()
+            .context("Failed to read checkpoint bundle")?
+        {
+            let mut entry = entry.context("Failed to read checkpoint bundle")?;
+            let name = entry
+                .path()
+                .context("Invalid entry in checkpoint bundle")?
+                .to_string_lossy()
+                .into_owned();
+            let mut content = Vec::new();
+            entry
+                .read_to_end(&mut content)
+                .with_context(|| format!("Failed to read {} from checkpoint bundle", name))?;
+
+            if name == HEADER_ENTRY {
+                let parsed: BundleHeader = serde_json::from_slice(&content)
+                    .context("Failed to parse checkpoint bundle")?;
+                if parsed.version > BUNDLE_VERSION {
+                    anyhow::bail!(
+                        "Unsupported checkpoint bundle version {} (expected <= {})",
+                        parsed.version,
+                        BUNDLE_VERSION
+                    );
+                }
+                header = Some(parsed);
+                continue;
+            }
+            if header.is_none() {
+                anyhow::bail!("Checkpoint bundle does not start with its header");
+            }
+
+            if let Some(hash) = name.strip_prefix(OBJECTS_DIR) {
+                if Self::calculate_file_hash(&content) != hash {
+                    anyhow::bail!("Object {} in the bundle is corrupt", hash);
+                }
+                self.write_content(&paths, hash, &content, None)?;
+                objects.insert(hash.to_string());
+            } else if name.starts_with(CHECKPOINTS_DIR) {
+                let bundled: BundledCheckpoint = serde_json::from_slice(&content)
+                    .with_context(|| format!("Failed to parse {} in checkpoint bundle", name))?;
+                let id = &bundled.checkpoint.id;
+                if !is_single_component(id) || checkpoint_ids.contains(id) {
+                    anyhow::bail!("Invalid checkpoint ID {} in checkpoint bundle", id);
+                }
+                for (path, entry) in &bundled.manifest.files {
+                    if !is_relative_path(path) {
+                        anyhow::bail!(
+                            "Checkpoint {} in the bundle has an invalid path {}",
+                            id,
+                            path.display()
+                        );
+                    }
+                    if !entry.deleted && !objects.contains(&entry.hash) {
+                        anyhow::bail!(
+                            "Bundle is missing the content of {} in checkpoint {}",
+                            path.display(),
+                            bundled.checkpoint.id
+                        );
+                    }
+                }
+                if let Some(parent_id) = &bundled.checkpoint.parent_checkpoint_id {
+                    if !checkpoint_ids.contains(parent_id) {
+                        anyhow::bail!(
+                            "Checkpoint {} comes before its parent {} in the bundle",
+                            bundled.checkpoint.id,
+                            parent_id
+                        );
+                    }
+                }
+                for merge_parent_id in &bundled.checkpoint.merge_parent_ids {
+                    if !checkpoint_ids.contains(merge_parent_id) {
+                        anyhow::bail!(
+                            "Checkpoint {} comes before its merge parent {} in the bundle",
+                            bundled.checkpoint.id,
+                            merge_parent_id
+                        );
+                    }
+                }
+                checkpoint_ids.insert(bundled.checkpoint.id.clone());
+                checkpoints.push(bundled);
+            } else {
+                anyhow::bail!("Unexpected entry {} in checkpoint bundle", name);
+            }
+        }
+        let header = header.context("Checkpoint bundle has no header")?;
+        let timeline = &header.timeline;
+        let referenced = timeline.current_checkpoint_id.iter().chain(
+            timeline
+                .last_restore
+                .iter()
+                .flat_map(|r| [&r.restored_checkpoint_id, &r.pre_restore_checkpoint_id]),
+        );
+        for id in referenced {
+            if !checkpoint_ids.contains(id) {
+                anyhow::bail!(
+                    "Checkpoint bundle refers to checkpoint {} it does not hold",
+                    id
+                );
+            }
+        }
+
+        // Importing the same bundle twice must not produce the same IDs
+        let new_ids: HashMap<String, String> = checkpoint_ids
+            .into_iter()
+            .map(|id| (id, Self::generate_checkpoint_id()))
+            .collect();
+        let new_id = |id: &String| new_ids[id].clone();
+
+        self.init_storage(project_id, &session_id)?;
+        let mut nodes = Vec::with_capacity(checkpoints.len());
+        let mut session_messages = None;
+        for mut bundled in checkpoints {
+            let mut checkpoint = bundled.checkpoint;
+            let bundled_id = checkpoint.id.clone();
+            checkpoint.id = new_id(&bundled_id);
+            checkpoint.parent_checkpoint_id = checkpoint.parent_checkpoint_id.as_ref().map(new_id);
+            checkpoint.merge_parent_ids = checkpoint.merge_parent_ids.iter().map(new_id).collect();
+            bundled.manifest.checkpoint_id = checkpoint.id.clone();
+            checkpoint.session_id = session_id.clone();
+            checkpoint.project_id = project_id.to_string();
+            let appended = remap_messages(
+                &bundled.messages,
+                &session_id,
+                &header.project_path,
+                project_path,
+            );
+            let messages = match &checkpoint.parent_checkpoint_id {
+                Some(parent_id) if bundled.inherited_messages > 0 => {
+                    let parent_messages = self.read_messages(&paths, parent_id)?;
+                    let mut lines = split_messages(&parent_messages);
+                    if bundled.inherited_messages > lines.len() {
+                        anyhow::bail!(
+                            "Checkpoint {} inherits {} messages but its parent has {}",
+                            checkpoint.id,
+                            bundled.inherited_messages,
+                            lines.len()
+                        );
+                    }
+                    lines.truncate(bundled.inherited_messages);
+                    lines.extend(split_messages(&appended));
+                    lines.join("\n")
+                }
+                _ => appended,
+            };
+
+            self.write_checkpoint_record(&paths, &checkpoint, &messages)?;
+            bundled
+                .manifest
+                .save(&paths.manifest_file(&checkpoint.id))?;
+
+            if header.timeline.current_checkpoint_id.as_ref() == Some(&bundled_id) {
+                session_messages = Some(messages);
+            }
+            nodes.push(TimelineNode {
+                checkpoint,
+                children: Vec::new(),
+                file_snapshot_ids: bundled.file_snapshot_ids,
+            });
+        }
+
+        let checkpoint_count = nodes.len();
+        let mut timeline = header.timeline;
+        timeline.session_id = session_id.clone();
+        timeline.current_checkpoint_id = timeline.current_checkpoint_id.as_ref().map(new_id);
+        if let Some(record) = &mut timeline.last_restore {
+            record.restored_checkpoint_id = new_id(&record.restored_checkpoint_id);
+            record.pre_restore_checkpoint_id = new_id(&record.pre_restore_checkpoint_id);
+        }
+        timeline.root_node = SessionTimeline::assemble_tree(nodes)?;
+        timeline.total_checkpoints = checkpoint_count;
+        self.save_timeline(&paths, &timeline)?;
+
+        // Recreate the conversation so the imported session can be resumed
+        if let Some(messages) = session_messages {
+            let session_file = self
+                .claude_dir
+                .join("projects")
+                .join(project_id)
+                .join(format!("{}.jsonl", session_id));
+            if !session_file.exists() {
+                if let Some(dir) = session_file.parent() {
+                    fs::create_dir_all(dir).context("Failed to create project directory")?;
+                }
+                fs::write(&session_file, format!("{}\n", messages))
+                    .context("Failed to write imported session")?;
+            }
+        }
+
+        Ok(BundleSummary {
+            session_id,
+            checkpoints: checkpoint_count,
+            objects: objects.len(),
+            bundle_size,
+        })
+    }
+}
+
+/// Keep only the selected checkpoints of a timeline, listed parents first.
+///
+/// A selected checkpoint's parent becomes its nearest selected ancestor, and
+/// each of its merge parents the nearest selected ancestor of that merge
+/// parent; merge parents with none left, or that now equal the parent, are
+/// dropped.
+fn prune_timeline(timeline: &SessionTimeline, selected: &HashSet<String>) -> Vec<TimelineNode> {
+    let nodes = timeline.nodes();
+    let parents: HashMap<&str, Option<&str>> = nodes
+        .iter()
+        .map(|node| {
+            (
+                node.checkpoint.id.as_str(),
+                node.checkpoint.parent_checkpoint_id.as_deref(),
+            )
+        })
+        .collect();
+    let selected_ancestor = |id: &str| -> Option<String> {
+        let mut next = Some(id);
+        while let Some(id) = next {
+            if selected.contains(id) {
+                return Some(id.to_string());
+            }
+            next = parents.get(id).copied().flatten();
+        }
+        None
+    };
+
+    nodes
+        .into_iter()
+        .filter(|node| selected.contains(&node.checkpoint.id))
+        .map(|node| {
+            let mut checkpoint = node.checkpoint.clone();
+            checkpoint.parent_checkpoint_id = checkpoint
+                .parent_checkpoint_id
+                .as_deref()
+                .and_then(selected_ancestor);
+            let mut merge_parent_ids: Vec<String> = Vec::new();
+            for id in node
+                .checkpoint
+                .merge_parent_ids
+                .iter()
+                .filter_map(|id| selected_ancestor(id))
+            {
+                if Some(&id) != checkpoint.parent_checkpoint_id.as_ref()
+                    && !merge_parent_ids.contains(&id)
+                {
+                    merge_parent_ids.push(id);
+                }
+            }
+            checkpoint.merge_parent_ids = merge_parent_ids;
+            TimelineNode {
+                checkpoint,
+                children: Vec::new(),
+                file_snapshot_ids: node.file_snapshot_ids.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Whether `name` is a single plain path component, safe to join onto a
+/// directory
+fn is_single_component(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(components.next(), Some(Component::Normal(c)) if c == name)
+        && components.next().is_none()
+}
+
+/// Whether `path` stays inside the directory it is joined onto
+fn is_relative_path(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Add a file to a bundle archive
+fn append_entry<W: Write>(archive: &mut tar::Builder<W>, name: &str, content: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    archive
+        .append_data(&mut header, name, content)
+        .with_context(|| format!("Failed to write {} to checkpoint bundle", name))
+}
+
+/// Add a value to a bundle archive as a JSON file
+fn append_json<W: Write>(
+    archive: &mut tar::Builder<W>,
+    name: &str,
+    value: &impl Serialize,
+) -> Result<()> {
+    let json = serde_json::to_vec(value)
+        .with_context(|| format!("Failed to serialize {} for checkpoint bundle", name))?;
+    append_entry(archive, name, &json)
+}
+
+/// Rewrite session messages for another session and project location.
+///
+/// Every `sessionId` is replaced, and `cwd` values inside the old project
+/// path are moved under the new one. Lines that are not JSON objects are kept
+/// as they are.
+fn remap_messages(messages: &str, session_id: &str, old_path: &str, new_path: &str) -> String {
+    messages
+        .lines()
+        .map(
+            |line| match serde_json::from_str::<serde_json::Value>(line) {
+                Ok(serde_json::Value::Object(mut message)) => {
+                    if message.contains_key("sessionId") {
+                        message.insert(
+                            "sessionId".to_string(),
+                            serde_json::Value::String(session_id.to_string()),
+                        );
+                    }
+                    if let Some(serde_json::Value::String(cwd)) = message.get_mut("cwd") {
+                        if let Some(rest) = cwd.strip_prefix(old_path) {
+                            if rest.is_empty() || rest.starts_with('/') || rest.starts_with('\\') {
+                                *cwd = format!("{}{}", new_path, rest);
+                            }
+                        }
+                    }
+                    serde_json::Value::Object(message).to_string()
+                }
+                _ => line.to_string(),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::{checkpoint_with_prompt, snapshot};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_export_subset_and_import_into_new_session() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().join("claude"));
+        storage.init_storage("project", "session").unwrap();
+
+        // a -> b -> c and a -> d merging b and c, exporting all but b
+        let message = r#"{"type":"user","sessionId":"session","cwd":"/home/alice/app/src"}"#;
+        let mut messages = Vec::new();
+        for (id, parent, content) in [
+            ("a", None, "one"),
+            ("b", Some("a"), "two"),
+            ("c", Some("b"), "three"),
+            ("d", Some("a"), "four"),
+        ] {
+            messages.push(message);
+            let mut checkpoint = checkpoint_with_prompt(id, parent, id);
+            if id == "d" {
+                checkpoint.merge_parent_ids = vec!["b".to_string(), "c".to_string()];
+            }
+            storage
+                .save_checkpoint(
+                    "project",
+                    "session",
+                    &checkpoint,
+                    vec![snapshot(id, "main.rs", content)],
+                    &messages.join("\n"),
+                )
+                .unwrap();
+        }
+
+        let bundle_path = temp_dir.path().join("handoff.bundle");
+        let exported = storage
+            .export_bundle(
+                "project",
+                "session",
+                "/home/alice/app",
+                Some(&["a".to_string(), "c".to_string(), "d".to_string()]),
+                &bundle_path,
+            )
+            .unwrap();
+        assert_eq!((exported.checkpoints, exported.objects), (3, 3));
+
+        let other = CheckpointStorage::new(temp_dir.path().join("other"));
+        let imported = other
+            .import_bundle(&bundle_path, "bob-project", "/Users/bob/app", None)
+            .unwrap();
+        assert_ne!(imported.session_id, "session");
+
+        let paths = CheckpointPaths::new(
+            &temp_dir.path().join("other"),
+            "bob-project",
+            &imported.session_id,
+        );
+        let timeline = other.load_timeline(&paths).unwrap();
+        // Imported checkpoints get fresh IDs; the prompt tells which is which
+        let ids: HashMap<String, String> = timeline
+            .nodes()
+            .into_iter()
+            .map(|node| {
+                (
+                    node.checkpoint.metadata.user_prompt.clone(),
+                    node.checkpoint.id.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(ids.len(), 3);
+        assert!(!ids.contains_key("b") && ids.iter().all(|(old, new)| old != new));
+        let c = timeline.find_checkpoint(&ids["c"]).unwrap();
+        assert_eq!(c.checkpoint.parent_checkpoint_id.as_ref(), Some(&ids["a"]));
+        assert_eq!(c.checkpoint.session_id, imported.session_id);
+        // The merge of b is now the merge of a, which is already d's parent
+        let d = timeline.find_checkpoint(&ids["d"]).unwrap();
+        assert_eq!(d.checkpoint.merge_parent_ids, [ids["c"].clone()]);
+        assert_eq!(timeline.current_checkpoint_id.as_ref(), Some(&ids["d"]));
+
+        let (_, files, messages) = other
+            .load_checkpoint("bob-project", &imported.session_id, &ids["c"])
+            .unwrap();
+        assert_eq!(files[0].content, b"three");
+        // c's messages are rebuilt from a's and the ones it added
+        assert_eq!(messages.lines().count(), 3);
+        for line in messages.lines() {
+            let message: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(message["sessionId"], imported.session_id.as_str());
+            assert_eq!(message["cwd"], "/Users/bob/app/src");
+        }
+
+        // The conversation can be resumed under the new session
+        assert!(temp_dir
+            .path()
+            .join("other/projects/bob-project")
+            .join(format!("{}.jsonl", imported.session_id))
+            .exists());
+    }
+
+    #[test]
+    fn test_import_rejects_paths_outside_the_session() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().join("claude"));
+        let header = BundleHeader {
+            version: BUNDLE_VERSION,
+            exported_at: Utc::now(),
+            session_id: "session".to_string(),
+            project_path: "/app".to_string(),
+            timeline: SessionTimeline::new("session".to_string()),
+        };
+        let write = |name: &str, id: &str, path: &str| {
+            let mut manifest = CheckpointManifest::new(id);
+            manifest.apply_snapshot(&snapshot(id, path, "one"));
+            let bundled = BundledCheckpoint {
+                checkpoint: checkpoint_with_prompt(id, None, "a"),
+                manifest,
+                file_snapshot_ids: Vec::new(),
+                inherited_messages: 0,
+                messages: String::new(),
+            };
+            let bundle_path = temp_dir.path().join(name);
+            let file = File::create(&bundle_path).unwrap();
+            let mut archive = tar::Builder::new(zstd::stream::Encoder::new(file, 1).unwrap());
+            append_json(&mut archive, HEADER_ENTRY, &header).unwrap();
+            let hash = CheckpointStorage::calculate_file_hash(b"one");
+            append_entry(&mut archive, &format!("{}{}", OBJECTS_DIR, hash), b"one").unwrap();
+            // The ID inside is what counts, not the entry name
+            append_json(
+                &mut archive,
+                &format!("{}a.json", CHECKPOINTS_DIR),
+                &bundled,
+            )
+            .unwrap();
+            archive.into_inner().unwrap().finish().unwrap();
+            bundle_path
+        };
+
+        for bundle_path in [
+            write("id.bundle", "../escape", "main.rs"),
+            write("path.bundle", "a", "../../escape.rs"),
+            write("absolute.bundle", "a", "/etc/escape"),
+        ] {
+            assert!(storage
+                .import_bundle(&bundle_path, "project", "/app", None)
+                .is_err());
+        }
+        assert!(!temp_dir.path().join("escape.rs").exists());
+        assert!(!temp_dir
+            .path()
+            .join("claude/projects/project")
+            .join("escape")
+            .exists());
+    }
+}
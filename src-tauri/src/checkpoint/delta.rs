@@ -0,0 +1,353 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use zstd::stream::{read::Decoder, write::Encoder};
+
+/// Maximum number of deltas between a stored object and a full blob.
+///
+/// Reading an object decompresses every link of its chain, so this bounds the
+/// cost of loading a file that changed in every checkpoint.
+pub const MAX_DELTA_CHAIN: u32 = 8;
+
+/// Marks a pool object stored as a delta (zstd frames start with `28 b5 2f fd`)
+const DELTA_MAGIC: &[u8; 4] = b"CKD1";
+
+/// Largest zstd window a decoder accepts without extra configuration
+const MAX_WINDOW_LOG: u32 = 27;
+
+/// Header of a pool object stored as a delta against another object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaHeader {
+    /// Hash of the object the delta was computed against
+    pub base_hash: String,
+    /// Number of deltas from this object down to a full blob, including this one
+    pub depth: u32,
+}
+
+impl DeltaHeader {
+    /// Parse the header of a delta object, returning it with the compressed
+    /// payload that follows, or `None` for a plain zstd object
+    pub fn parse(object: &[u8]) -> Option<(Self, &[u8])> {
+        let rest = object.strip_prefix(DELTA_MAGIC)?;
+        let depth = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?);
+        let hash_len = *rest.get(4)? as usize;
+        let base_hash = std::str::from_utf8(rest.get(5..5 + hash_len)?).ok()?;
+        Some((
+            Self {
+                base_hash: base_hash.to_string(),
+                depth,
+            },
+            &rest[5 + hash_len..],
+        ))
+    }
+
+    /// Read just the header of a pool object on disk
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        let mut prefix = Vec::new();
+        File::open(path)
+            .context("Failed to open pool object")?
+            .take((DELTA_MAGIC.len() + 5 + u8::MAX as usize) as u64)
+            .read_to_end(&mut prefix)
+            .context("Failed to read pool object")?;
+        Ok(Self::parse(&prefix).map(|(header, _)| header))
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(DELTA_MAGIC);
+        out.extend_from_slice(&self.depth.to_le_bytes());
+        out.push(self.base_hash.len() as u8);
+        out.extend_from_slice(self.base_hash.as_bytes());
+    }
+}
+
+/// Compress `content` using `base` as a zstd reference prefix, producing a
+/// complete delta object
+pub fn encode_delta(
+    content: &[u8],
+    base: &[u8],
+    header: &DeltaHeader,
+    level: i32,
+) -> Result<Vec<u8>> {
+    let mut object = Vec::new();
+    header.write_to(&mut object);
+
+    // The window has to reach back over the whole base for matches to be found
+    let needed = (base.len() + content.len())
+        .max(1)
+        .next_power_of_two()
+        .trailing_zeros();
+    let mut encoder =
+        Encoder::with_ref_prefix(object, level, base).context("Failed to start delta")?;
+    encoder
+        .window_log(needed.clamp(10, MAX_WINDOW_LOG))
+        .context("Failed to size delta window")?;
+    encoder
+        .long_distance_matching(true)
+        .context("Failed to enable long distance matching")?;
+    encoder
+        .write_all(content)
+        .context("Failed to compress delta")?;
+    encoder.finish().context("Failed to finish delta")
+}
+
+/// Rebuild content from a delta payload and the content of its base
+pub fn decode_delta(payload: &[u8], base: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = Decoder::with_ref_prefix(payload, base).context("Failed to start delta")?;
+    decoder
+        .window_log_max(MAX_WINDOW_LOG)
+        .context("Failed to size delta window")?;
+    let mut content = Vec::new();
+    decoder
+        .read_to_end(&mut content)
+        .context("Failed to decompress delta")?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::{checkpoint, snapshot};
+    use crate::checkpoint::{
+        storage::CheckpointStorage, Checkpoint, CheckpointPaths, FileSnapshot,
+    };
+    use std::collections::HashSet;
+    use std::fs;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    /// Save one checkpoint per version of `src/lib.rs`, each the child of the last
+    fn save_versions(storage: &CheckpointStorage, versions: &[Vec<u8>]) {
+        storage.init_storage("project", "session").unwrap();
+        for (i, content) in versions.iter().enumerate() {
+            let id = format!("cp{}", i);
+            let parent = (i > 0).then(|| format!("cp{}", i - 1));
+            storage
+                .save_checkpoint(
+                    "project",
+                    "session",
+                    &checkpoint(&id, parent.as_deref()),
+                    vec![snapshot(&id, "src/lib.rs", content)],
+                    "",
+                )
+                .unwrap();
+        }
+    }
+
+    /// A large source file edited one line at a time
+    fn one_line_edits(source: &str, versions: usize) -> Vec<Vec<u8>> {
+        let mut lines: Vec<String> = source.lines().map(String::from).collect();
+        (0..versions)
+            .map(|i| {
+                let line = (i * 37) % lines.len();
+                lines[line] = format!("// edit {}", i);
+                (lines.join("\n") + "\n").into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_successive_versions_are_stored_as_bounded_deltas() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        let source: String = (0..5000)
+            .map(|i| format!("fn function_{}(value: u64) -> u64 {{ value * {} }}\n", i, i))
+            .collect();
+        let versions = one_line_edits(&source, MAX_DELTA_CHAIN as usize + 3);
+        save_versions(&storage, &versions);
+
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "session");
+        let mut depths = Vec::new();
+        for content in &versions {
+            let hash = CheckpointStorage::calculate_file_hash(content);
+            let object = paths.content_object_file(&hash);
+            let header = DeltaHeader::read(&object).unwrap();
+            depths.push(header.map_or(0, |header| header.depth));
+            if depths.len() > 1 && *depths.last().unwrap() > 0 {
+                assert!(fs::metadata(&object).unwrap().len() < 1024);
+            }
+            assert_eq!(&storage.read_content(&paths, &hash).unwrap(), content);
+        }
+
+        // The chain restarts with a full blob once it reaches the limit
+        assert_eq!(depths[..3], [0, 1, 2]);
+        assert_eq!(depths[MAX_DELTA_CHAIN as usize + 1], 0);
+        assert!(depths.iter().all(|depth| *depth <= MAX_DELTA_CHAIN));
+
+        let (_, files, _) = storage
+            .load_checkpoint("project", "session", &format!("cp{}", versions.len() - 1))
+            .unwrap();
+        assert_eq!(&files[0].content, versions.last().unwrap());
+    }
+
+    /// A checkpoint with every file it holds, as saved in a recorded session
+    type Recorded = (Checkpoint, Vec<FileSnapshot>);
+
+    /// Copy a directory tree
+    fn copy_dir(from: &Path, to: &Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            let target = to.join(entry.file_name());
+            if entry.file_type().unwrap().is_dir() {
+                copy_dir(&entry.path(), &target);
+            } else {
+                fs::copy(entry.path(), target).unwrap();
+            }
+        }
+    }
+
+    /// Every checkpoint of a session recorded under `~/.claude`, given as
+    /// `<project id>/<session id>`, parents first.
+    ///
+    /// The session and the project's pool are read from a copy, since
+    /// loading a session can migrate or index it.
+    fn recorded_session(session: &str) -> Box<dyn Iterator<Item = Recorded>> {
+        let (project_id, session_id) = session
+            .split_once('/')
+            .expect("CHECKPOINT_BENCH_SESSION is <project id>/<session id>");
+        let (project_id, session_id) = (project_id.to_string(), session_id.to_string());
+        let live = CheckpointPaths::new(
+            &dirs::home_dir().unwrap().join(".claude"),
+            &project_id,
+            &session_id,
+        );
+        let copy = TempDir::new().unwrap();
+        let source = CheckpointStorage::new(copy.path().to_path_buf());
+        let paths = CheckpointPaths::new(&source.claude_dir, &project_id, &session_id);
+        copy_dir(
+            &live.timelines_dir.join(&session_id),
+            &paths.timelines_dir.join(&session_id),
+        );
+        if live.pool_dir().exists() {
+            copy_dir(&live.pool_dir(), &paths.pool_dir());
+        }
+        let ids: Vec<String> = source
+            .load_timeline(&paths)
+            .unwrap()
+            .nodes()
+            .into_iter()
+            .map(|node| node.checkpoint.id.clone())
+            .collect();
+        Box::new(ids.into_iter().map(move |id| {
+            let _copy = &copy;
+            let (checkpoint, files, _) = source
+                .load_checkpoint(&project_id, &session_id, &id)
+                .unwrap();
+            (checkpoint, files)
+        }))
+    }
+
+    /// Every committed version of a file of this repository, oldest first,
+    /// each as a checkpoint continuing the previous one
+    fn committed_versions(path: &Path) -> Box<dyn Iterator<Item = Recorded>> {
+        fn git(dir: &Path, args: &[&str]) -> Vec<u8> {
+            let output = std::process::Command::new("git")
+                .args(args)
+                .current_dir(dir)
+                .output()
+                .expect("git is needed to replay the file's history");
+            assert!(output.status.success(), "git {:?} failed", args);
+            output.stdout
+        }
+
+        let dir = path.parent().unwrap().to_path_buf();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let log = git(&dir, &["log", "--reverse", "--format=%H", "--", &name]);
+        let revisions: Vec<String> = String::from_utf8(log)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        Box::new(revisions.into_iter().enumerate().map(move |(i, revision)| {
+            let content = git(&dir, &["show", &format!("{}:./{}", revision, name)]);
+            let id = format!("cp{}", i);
+            let parent = (i > 0).then(|| format!("cp{}", i - 1));
+            (
+                checkpoint(&id, parent.as_deref()),
+                vec![snapshot(&id, "src/lib.rs", content)],
+            )
+        }))
+    }
+
+    /// Replays recorded checkpoints into an empty store and reports the space
+    /// their file contents take with deltas against full blobs, and the time
+    /// to save and load them.
+    ///
+    /// With `CHECKPOINT_BENCH_SESSION=<project id>/<session id>` it replays a
+    /// copy of a session recorded under `~/.claude`; otherwise every committed version
+    /// of `commands/claude.rs` in this repository's history. Run with
+    /// `cargo test bench_delta -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_delta_storage_on_session_timeline() {
+        let (source, recorded) = match std::env::var("CHECKPOINT_BENCH_SESSION") {
+            Ok(session) => (format!("session {}", session), recorded_session(&session)),
+            Err(_) => {
+                let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join(file!())
+                    .parent()
+                    .unwrap()
+                    .join("../commands/claude.rs");
+                (
+                    "the history of commands/claude.rs".to_string(),
+                    committed_versions(&path),
+                )
+            }
+        };
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+
+        let mut ids = Vec::new();
+        let mut hashes = HashSet::new();
+        let (mut full, mut save_time) = (0, Duration::ZERO);
+        for (checkpoint, files) in recorded {
+            // What the pool would hold without deltas
+            for file in files.iter().filter(|file| !file.is_deleted) {
+                if hashes.insert(file.hash.clone()) {
+                    full += zstd::stream::encode_all(&file.content[..], storage.compression_level())
+                        .unwrap()
+                        .len() as u64;
+                }
+            }
+            let checkpoint = Checkpoint {
+                project_id: "project".to_string(),
+                session_id: "session".to_string(),
+                ..checkpoint
+            };
+            let started = Instant::now();
+            storage
+                .save_checkpoint("project", "session", &checkpoint, files, "")
+                .unwrap();
+            save_time += started.elapsed();
+            ids.push(checkpoint.id);
+        }
+
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "session");
+        let stored: u64 = fs::read_dir(paths.content_pool_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum();
+
+        let started = Instant::now();
+        for id in &ids {
+            storage.load_checkpoint("project", "session", id).unwrap();
+        }
+        let load_time = started.elapsed();
+
+        println!(
+            "{} checkpoints with {} distinct file contents from {}: {} bytes as full blobs, \
+             {} bytes with deltas ({:.2}x); saved in {:?}, loaded in {:?}",
+            ids.len(),
+            hashes.len(),
+            source,
+            full,
+            stored,
+            full as f64 / stored.max(1) as f64,
+            save_time,
+            load_time
+        );
+        assert!(stored <= full);
+    }
+}
@@ -0,0 +1,330 @@
+use similar::{ChangeTag, TextDiff};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use super::{
+    is_binary_content, Checkpoint, CheckpointDiff, FileDiff, FileKind, FileSnapshot, RenamedFile,
+};
+
+/// Default number of context lines around each hunk
+pub const DEFAULT_CONTEXT_LINES: usize = 3;
+
+/// Minimum line similarity for an added/deleted pair to be reported as a rename
+const RENAME_SIMILARITY_THRESHOLD: f32 = 0.5;
+
+/// Files larger than this are only matched as renames when their hashes are identical
+const MAX_RENAME_CANDIDATE_SIZE: usize = 1024 * 1024;
+
+/// Compute a line-level diff for a single file
+pub fn diff_file(path: &Path, old: &[u8], new: &[u8], context_lines: usize) -> FileDiff {
+    diff_file_with_paths(path, path, old, new, context_lines)
+}
+
+/// Compute a line-level diff where the old and new sides live at different paths
+pub fn diff_file_with_paths(
+    old_path: &Path,
+    new_path: &Path,
+    old: &[u8],
+    new: &[u8],
+    context_lines: usize,
+) -> FileDiff {
+    if is_binary_content(old) || is_binary_content(new) {
+        return FileDiff {
+            path: new_path.to_path_buf(),
+            is_binary: true,
+            additions: 0,
+            deletions: 0,
+            diff_content: Some(format!(
+                "Binary files a/{} and b/{} differ\n",
+                old_path.display(),
+                new_path.display()
+            )),
+        };
+    }
+
+    // Both sides are valid UTF-8 at this point
+    let old_text = String::from_utf8_lossy(old);
+    let new_text = String::from_utf8_lossy(new);
+    let text_diff = TextDiff::from_lines(old_text.as_ref(), new_text.as_ref());
+
+    let mut additions = 0;
+    let mut deletions = 0;
+    for change in text_diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => additions += 1,
+            ChangeTag::Delete => deletions += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+
+    let diff_content = if additions == 0 && deletions == 0 {
+        None
+    } else {
+        Some(
+            text_diff
+                .unified_diff()
+                .context_radius(context_lines)
+                .header(
+                    &format!("a/{}", old_path.display()),
+                    &format!("b/{}", new_path.display()),
+                )
+                .to_string(),
+        )
+    };
+
+    FileDiff {
+        path: new_path.to_path_buf(),
+        is_binary: false,
+        additions,
+        deletions,
+        diff_content,
+    }
+}
+
+/// Line similarity between two file versions in the range 0.0..=1.0
+pub fn similarity(old: &[u8], new: &[u8]) -> f32 {
+    if old == new {
+        return 1.0;
+    }
+    if is_binary_content(old) || is_binary_content(new) {
+        return 0.0;
+    }
+    let old_text = String::from_utf8_lossy(old);
+    let new_text = String::from_utf8_lossy(new);
+    TextDiff::from_lines(old_text.as_ref(), new_text.as_ref()).ratio()
+}
+
+/// Compute the full diff between two checkpoints' file snapshots
+pub fn diff_checkpoints(
+    from_checkpoint: &Checkpoint,
+    from_files: &[FileSnapshot],
+    to_checkpoint: &Checkpoint,
+    to_files: &[FileSnapshot],
+    context_lines: usize,
+) -> CheckpointDiff {
+    // Only files that exist at each checkpoint take part in the diff; empty
+    // directories have no content to compare
+    let from_map: HashMap<&PathBuf, &FileSnapshot> = from_files
+        .iter()
+        .filter(|s| !s.is_deleted && s.kind != FileKind::Directory)
+        .map(|s| (&s.file_path, s))
+        .collect();
+    let to_map: HashMap<&PathBuf, &FileSnapshot> = to_files
+        .iter()
+        .filter(|s| !s.is_deleted && s.kind != FileKind::Directory)
+        .map(|s| (&s.file_path, s))
+        .collect();
+
+    let mut modified_files = Vec::new();
+    let mut deleted: Vec<&FileSnapshot> = Vec::new();
+    let mut added: Vec<&FileSnapshot> = Vec::new();
+
+    for (path, from_file) in &from_map {
+        match to_map.get(path) {
+            Some(to_file) if from_file.hash != to_file.hash => {
+                modified_files.push(diff_file(
+                    path,
+                    &from_file.content,
+                    &to_file.content,
+                    context_lines,
+                ));
+            }
+            Some(_) => {}
+            None => deleted.push(from_file),
+        }
+    }
+
+    for (path, to_file) in &to_map {
+        if !from_map.contains_key(path) {
+            added.push(to_file);
+        }
+    }
+
+    let renamed_files = detect_renames(&mut added, &mut deleted, context_lines);
+
+    modified_files.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut added_files: Vec<PathBuf> = added.iter().map(|s| s.file_path.clone()).collect();
+    added_files.sort();
+    let mut deleted_files: Vec<PathBuf> = deleted.iter().map(|s| s.file_path.clone()).collect();
+    deleted_files.sort();
+
+    let token_delta = (to_checkpoint.metadata.total_tokens as i64)
+        - (from_checkpoint.metadata.total_tokens as i64);
+
+    CheckpointDiff {
+        from_checkpoint_id: from_checkpoint.id.clone(),
+        to_checkpoint_id: to_checkpoint.id.clone(),
+        modified_files,
+        added_files,
+        deleted_files,
+        renamed_files,
+        token_delta,
+    }
+}
+
+/// Pair up deleted and added files that are the same file under a new path.
+///
+/// Exact content matches are paired first, then the remaining text files are
+/// matched greedily by line similarity. Paired files are removed from `added`
+/// and `deleted`.
+fn detect_renames(
+    added: &mut Vec<&FileSnapshot>,
+    deleted: &mut Vec<&FileSnapshot>,
+    context_lines: usize,
+) -> Vec<RenamedFile> {
+    let mut renames = Vec::new();
+    if added.is_empty() || deleted.is_empty() {
+        return renames;
+    }
+
+    // Keep pairing deterministic regardless of HashMap iteration order
+    added.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    deleted.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+    let mut used_added = HashSet::new();
+    let mut used_deleted = HashSet::new();
+
+    // Exact renames: identical content hash
+    for (di, old) in deleted.iter().enumerate() {
+        if let Some((ai, new)) = added
+            .iter()
+            .enumerate()
+            .find(|(ai, new)| !used_added.contains(ai) && new.hash == old.hash)
+        {
+            used_added.insert(ai);
+            used_deleted.insert(di);
+            renames.push(RenamedFile {
+                from: old.file_path.clone(),
+                to: new.file_path.clone(),
+                similarity: 1.0,
+                diff: diff_file_with_paths(
+                    &old.file_path,
+                    &new.file_path,
+                    &old.content,
+                    &new.content,
+                    context_lines,
+                ),
+            });
+        }
+    }
+
+    // Similar renames: score every remaining text pair and take the best matches first
+    let is_candidate = |s: &FileSnapshot| {
+        s.content.len() <= MAX_RENAME_CANDIDATE_SIZE && !is_binary_content(&s.content)
+    };
+    let mut scored = Vec::new();
+    for (di, old) in deleted.iter().enumerate() {
+        if used_deleted.contains(&di) || !is_candidate(old) {
+            continue;
+        }
+        for (ai, new) in added.iter().enumerate() {
+            if used_added.contains(&ai) || !is_candidate(new) {
+                continue;
+            }
+            let score = similarity(&old.content, &new.content);
+            if score >= RENAME_SIMILARITY_THRESHOLD {
+                scored.push((score, di, ai));
+            }
+        }
+    }
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    for (score, di, ai) in scored {
+        if used_deleted.contains(&di) || used_added.contains(&ai) {
+            continue;
+        }
+        used_deleted.insert(di);
+        used_added.insert(ai);
+        let (old, new) = (deleted[di], added[ai]);
+        renames.push(RenamedFile {
+            from: old.file_path.clone(),
+            to: new.file_path.clone(),
+            similarity: score,
+            diff: diff_file_with_paths(
+                &old.file_path,
+                &new.file_path,
+                &old.content,
+                &new.content,
+                context_lines,
+            ),
+        });
+    }
+
+    let mut index = 0;
+    added.retain(|_| {
+        let keep = !used_added.contains(&index);
+        index += 1;
+        keep
+    });
+    let mut index = 0;
+    deleted.retain(|_| {
+        let keep = !used_deleted.contains(&index);
+        index += 1;
+        keep
+    });
+
+    renames.sort_by(|a, b| a.to.cmp(&b.to));
+    renames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::snapshot;
+
+    #[test]
+    fn test_diff_file_counts_changed_lines_only() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nb\nc\nD\ne\nf\ng\nh\ni\n";
+        let diff = diff_file(Path::new("src/lib.rs"), old.as_bytes(), new.as_bytes(), 1);
+
+        assert_eq!(diff.additions, 2);
+        assert_eq!(diff.deletions, 1);
+        let content = diff.diff_content.unwrap();
+        assert!(content.starts_with("--- a/src/lib.rs\n+++ b/src/lib.rs\n"));
+        assert!(content.contains("-d\n+D\n"));
+        // With one line of context the two changes form separate hunks
+        assert_eq!(content.matches("@@ ").count(), 2);
+    }
+
+    #[test]
+    fn test_diff_file_marks_binary() {
+        let diff = diff_file(Path::new("logo.png"), b"\x89PNG\0\x01", b"\x89PNG\0\x02", 3);
+        assert!(diff.is_binary);
+        assert_eq!((diff.additions, diff.deletions), (0, 0));
+    }
+
+    #[test]
+    fn test_detect_renames() {
+        let body: String = (0..20).map(|i| format!("line {}\n", i)).collect();
+        let edited = body.replace("line 7\n", "line seven\n");
+
+        let mut added = vec![];
+        let mut deleted = vec![];
+        let (old_exact, new_exact) = (
+            snapshot("test", "a/old.rs", &body),
+            snapshot("test", "b/new.rs", &body),
+        );
+        let (old_edit, new_edit) = (
+            snapshot("test", "x.rs", &body),
+            snapshot("test", "y.rs", &edited),
+        );
+        let unrelated = snapshot("test", "z.rs", "something else entirely\n");
+        added.extend([&new_exact, &new_edit, &unrelated]);
+        deleted.extend([&old_exact, &old_edit]);
+
+        let renames = detect_renames(&mut added, &mut deleted, 3);
+
+        assert_eq!(renames.len(), 2);
+        assert_eq!(renames[0].from, PathBuf::from("a/old.rs"));
+        assert_eq!(renames[0].similarity, 1.0);
+        assert_eq!(renames[1].from, PathBuf::from("x.rs"));
+        assert_eq!(
+            (renames[1].diff.additions, renames[1].diff.deletions),
+            (1, 1)
+        );
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].file_path, PathBuf::from("z.rs"));
+        assert!(deleted.is_empty());
+    }
+}
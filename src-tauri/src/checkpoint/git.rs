@@ -0,0 +1,177 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Command;
+
+/// State of the project's git repository when a checkpoint was taken
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GitState {
+    /// Commit checked out at HEAD; `None` in a repository without commits
+    pub head: Option<String>,
+    /// Branch checked out; `None` when HEAD is detached
+    pub branch: Option<String>,
+    /// Whether the working tree or index had uncommitted changes
+    pub dirty: bool,
+    /// Stash entries, newest first, as listed by `git stash list`
+    #[serde(default)]
+    pub stashes: Vec<String>,
+}
+
+/// What to do when restoring a checkpoint taken on a different branch or commit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GitMismatchPolicy {
+    /// Restore without checking
+    Ignore,
+    /// Restore and report the mismatch as a warning
+    #[default]
+    Warn,
+    /// Refuse to restore unless forced
+    Refuse,
+}
+
+impl GitState {
+    /// Capture the repository state of `project_path`, or `None` when it is
+    /// not inside a git repository or git is not available
+    pub fn capture(project_path: &Path) -> Option<Self> {
+        git(project_path, &["rev-parse", "--git-dir"]).ok()?;
+
+        let head = git(project_path, &["rev-parse", "--verify", "-q", "HEAD"]).ok();
+        let branch = git(project_path, &["symbolic-ref", "--short", "-q", "HEAD"]).ok();
+        let dirty = git(project_path, &["status", "--porcelain"])
+            .map(|status| !status.is_empty())
+            .unwrap_or(false);
+        let stashes = git(project_path, &["stash", "list"])
+            .map(|list| list.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        Some(Self {
+            head,
+            branch,
+            dirty,
+            stashes,
+        })
+    }
+
+    /// Ways in which `current` differs from this recorded state that make a
+    /// restore unsafe: another branch or another HEAD commit. Empty when
+    /// they match.
+    pub fn mismatches(&self, current: Option<&GitState>) -> Vec<String> {
+        let current = match current {
+            Some(current) => current,
+            None => {
+                return vec![
+                    "The project is no longer a git repository, but the checkpoint was taken in one"
+                        .to_string(),
+                ]
+            }
+        };
+
+        let mut mismatches = Vec::new();
+        if self.branch != current.branch {
+            mismatches.push(format!(
+                "The checkpoint was taken on {} but {} is checked out",
+                describe_branch(self.branch.as_deref()),
+                describe_branch(current.branch.as_deref())
+            ));
+        }
+        if self.head != current.head {
+            mismatches.push(format!(
+                "The checkpoint was taken at commit {} but HEAD is {}",
+                describe_commit(self.head.as_deref()),
+                describe_commit(current.head.as_deref())
+            ));
+        }
+        mismatches
+    }
+}
+
+/// Run a git command in `dir` and return its trimmed output, failing when git
+/// exits unsuccessfully
+pub fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn describe_branch(branch: Option<&str>) -> String {
+    match branch {
+        Some(branch) => format!("branch '{}'", branch),
+        None => "a detached HEAD".to_string(),
+    }
+}
+
+fn describe_commit(commit: Option<&str>) -> &str {
+    match commit {
+        Some(commit) => commit.get(..12).unwrap_or(commit),
+        None => "(none)",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn commit(dir: &Path, message: &str) {
+        git(dir, &["add", "-A"]).unwrap();
+        git(
+            dir,
+            &[
+                "-c",
+                "user.name=Test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "-q",
+                "-m",
+                message,
+            ],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_capture_and_compare_git_state() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        assert!(GitState::capture(dir).is_none());
+
+        git(dir, &["init", "-q", "-b", "main"]).unwrap();
+        fs::write(dir.join("a.txt"), "one").unwrap();
+        commit(dir, "first");
+
+        let recorded = GitState::capture(dir).unwrap();
+        assert_eq!(recorded.branch.as_deref(), Some("main"));
+        assert!(recorded.head.is_some());
+        assert!(!recorded.dirty);
+        assert!(recorded.mismatches(Some(&recorded)).is_empty());
+
+        // Uncommitted changes alone are not a mismatch
+        fs::write(dir.join("a.txt"), "two").unwrap();
+        let dirty = GitState::capture(dir).unwrap();
+        assert!(dirty.dirty);
+        assert!(recorded.mismatches(Some(&dirty)).is_empty());
+
+        git(dir, &["checkout", "-q", "-b", "feature"]).unwrap();
+        commit(dir, "second");
+        let moved = GitState::capture(dir).unwrap();
+        let mismatches = recorded.mismatches(Some(&moved));
+        assert_eq!(mismatches.len(), 2);
+        assert!(mismatches[0].contains("branch 'main'"));
+
+        assert_eq!(recorded.mismatches(None).len(), 1);
+    }
+}
@@ -0,0 +1,816 @@
+use anyhow::{Context, Result};
+use rusqlite::{
+    params, params_from_iter, types::Value, Connection, OptionalExtension, Transaction,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::{
+    search::CheckpointQuery, storage::CheckpointStorage, Checkpoint, CheckpointPaths,
+    SessionTimeline, TimelineNode,
+};
+
+/// How long a connection waits for another session writing to the index
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        session_id TEXT PRIMARY KEY,
+        settings TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS checkpoints (
+        session_id TEXT NOT NULL,
+        id TEXT NOT NULL,
+        parent_id TEXT,
+        position INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        pinned BOOLEAN NOT NULL,
+        snapshot_size INTEGER NOT NULL,
+        file_snapshot_ids TEXT NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (session_id, id)
+    );
+    CREATE INDEX IF NOT EXISTS checkpoints_by_time ON checkpoints (timestamp);
+    CREATE TABLE IF NOT EXISTS checkpoint_tags (
+        session_id TEXT NOT NULL,
+        checkpoint_id TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (session_id, checkpoint_id, tag)
+    );
+    CREATE INDEX IF NOT EXISTS checkpoint_tags_by_tag ON checkpoint_tags (tag);
+    CREATE TABLE IF NOT EXISTS checkpoint_paths (
+        session_id TEXT NOT NULL,
+        checkpoint_id TEXT NOT NULL,
+        path TEXT NOT NULL,
+        PRIMARY KEY (session_id, checkpoint_id, path)
+    );
+";
+
+/// SQLite database of the checkpoint timelines of every session of a project.
+///
+/// The database is the record of each session's timeline: every checkpoint
+/// with its parent, its position in the tree, its size, its tags and the paths
+/// it touched, and a row of session settings. Saving a checkpoint adds its
+/// rows in one transaction, and saving a whole timeline only writes the rows
+/// that changed. `timeline.json` is an export of the timeline, written after
+/// every change; a session that is not in the database yet is imported from
+/// it.
+pub struct CheckpointIndex {
+    conn: Connection,
+}
+
+/// A checkpoint row as stored, to tell which rows a saved timeline changes
+struct StoredRow {
+    parent_id: Option<String>,
+    position: i64,
+    file_snapshot_ids: String,
+    data: String,
+}
+
+impl CheckpointIndex {
+    /// Open an index, creating it if it does not exist
+    pub fn open(index_file: &Path) -> Result<Self> {
+        if let Some(dir) = index_file.parent() {
+            fs::create_dir_all(dir).context("Failed to create checkpoint index directory")?;
+        }
+        let conn = Connection::open(index_file).context("Failed to open checkpoint index")?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)
+            .context("Failed to create checkpoint index tables")?;
+        Ok(Self { conn })
+    }
+
+    /// Whether a session's timeline is in the index
+    pub fn has_session(&self, session_id: &str) -> Result<bool> {
+        Ok(read_settings(&self.conn, session_id)?.is_some())
+    }
+
+    /// Add a checkpoint without children to a session, as a child of its
+    /// parent or as the root of an empty timeline, and make it current.
+    ///
+    /// The checkpoint's row, tags, touched paths and the session settings are
+    /// written in one transaction.
+    pub fn add_checkpoint(
+        &mut self,
+        session_id: &str,
+        node: &TimelineNode,
+        touched_paths: &[PathBuf],
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        let checkpoint = &node.checkpoint;
+        let mut settings = read_settings(&tx, session_id)?
+            .with_context(|| format!("Session {} has no timeline", session_id))?;
+
+        let has_checkpoint = |id: Option<&str>| -> Result<bool> {
+            let found = tx
+                .query_row(
+                    "SELECT 1 FROM checkpoints WHERE session_id = ?1 AND (?2 IS NULL OR id = ?2)
+                     LIMIT 1",
+                    params![session_id, id],
+                    |_| Ok(()),
+                )
+                .optional()?;
+            Ok(found.is_some())
+        };
+        match &checkpoint.parent_checkpoint_id {
+            Some(parent_id) if !has_checkpoint(Some(parent_id))? => {
+                anyhow::bail!("Parent checkpoint not found: {}", parent_id)
+            }
+            None if has_checkpoint(None)? => {
+                anyhow::bail!("Session {} already has a root checkpoint", session_id)
+            }
+            _ => {}
+        }
+        if has_checkpoint(Some(&checkpoint.id))? {
+            anyhow::bail!("Checkpoint {} already exists", checkpoint.id);
+        }
+
+        let position: i64 = tx.query_row(
+            "SELECT COALESCE(MAX(position) + 1, 0) FROM checkpoints WHERE session_id = ?1",
+            params![session_id],
+            |row| row.get(0),
+        )?;
+        write_row(
+            &tx,
+            session_id,
+            node,
+            checkpoint.parent_checkpoint_id.as_deref(),
+            position,
+        )?;
+        write_tags(&tx, session_id, checkpoint)?;
+        write_paths(&tx, session_id, &checkpoint.id, touched_paths)?;
+
+        settings.current_checkpoint_id = Some(checkpoint.id.clone());
+        settings.total_checkpoints += 1;
+        write_settings(&tx, session_id, &settings)?;
+
+        tx.commit().context("Failed to commit checkpoint index")?;
+        Ok(())
+    }
+
+    /// Store `timeline` as a session's timeline, writing only the rows that
+    /// differ from what is stored.
+    ///
+    /// `touched_files` is asked for the paths of checkpoints that are new or
+    /// have a new parent.
+    pub fn save_session(
+        &mut self,
+        session_id: &str,
+        timeline: &SessionTimeline,
+        touched_files: impl Fn(&Checkpoint) -> Result<Vec<PathBuf>>,
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+
+        let mut stored: HashMap<String, StoredRow> = {
+            let mut stmt = tx.prepare(
+                "SELECT id, parent_id, position, file_snapshot_ids, data FROM checkpoints
+                 WHERE session_id = ?1",
+            )?;
+            let rows = stmt.query_map(params![session_id], |row| {
+                Ok((
+                    row.get(0)?,
+                    StoredRow {
+                        parent_id: row.get(1)?,
+                        position: row.get(2)?,
+                        file_snapshot_ids: row.get(3)?,
+                        data: row.get(4)?,
+                    },
+                ))
+            })?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        // Each checkpoint with its parent, each before its children
+        let mut order: Vec<(&TimelineNode, Option<&str>)> = Vec::new();
+        let mut stack: Vec<(&TimelineNode, Option<&str>)> =
+            timeline.root_node.iter().map(|root| (root, None)).collect();
+        while let Some((node, parent_id)) = stack.pop() {
+            order.push((node, parent_id));
+            stack.extend(
+                node.children
+                    .iter()
+                    .rev()
+                    .map(|child| (child, Some(node.checkpoint.id.as_str()))),
+            );
+        }
+        let positions = positions(&order, &stored);
+
+        for ((node, parent_id), position) in order.iter().zip(positions) {
+            let checkpoint = &node.checkpoint;
+            let file_snapshot_ids = serde_json::to_string(&node.file_snapshot_ids)?;
+            let data = serde_json::to_string(checkpoint)?;
+            let previous = stored.remove(&checkpoint.id);
+            let unchanged = previous.as_ref().is_some_and(|row| {
+                row.parent_id.as_deref() == *parent_id
+                    && row.position == position
+                    && row.file_snapshot_ids == file_snapshot_ids
+                    && row.data == data
+            });
+            if unchanged {
+                continue;
+            }
+
+            write_row(&tx, session_id, node, *parent_id, position)?;
+            if previous.as_ref().is_none_or(|row| row.data != data) {
+                write_tags(&tx, session_id, checkpoint)?;
+            }
+            if previous
+                .as_ref()
+                .is_none_or(|row| row.parent_id.as_deref() != *parent_id)
+            {
+                let paths = touched_files(checkpoint).with_context(|| {
+                    format!(
+                        "Failed to index files touched by checkpoint {}",
+                        checkpoint.id
+                    )
+                })?;
+                write_paths(&tx, session_id, &checkpoint.id, &paths)?;
+            }
+        }
+
+        for checkpoint_id in stored.keys() {
+            for table in ["checkpoint_tags", "checkpoint_paths"] {
+                tx.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE session_id = ?1 AND checkpoint_id = ?2",
+                        table
+                    ),
+                    params![session_id, checkpoint_id],
+                )?;
+            }
+            tx.execute(
+                "DELETE FROM checkpoints WHERE session_id = ?1 AND id = ?2",
+                params![session_id, checkpoint_id],
+            )?;
+        }
+
+        write_settings(&tx, session_id, timeline)?;
+        tx.commit().context("Failed to commit checkpoint index")?;
+        Ok(())
+    }
+
+    /// Remove checkpoints from a session, handing each one's children to its
+    /// parent, without rewriting the session's other rows.
+    ///
+    /// The root and checkpoints the session does not have are skipped.
+    /// `touched_files` is asked for the paths of the children, which now
+    /// differ from a new parent. Returns the IDs removed and the IDs of the
+    /// children handed on.
+    pub fn remove_checkpoints(
+        &mut self,
+        session_id: &str,
+        checkpoint_ids: &[String],
+        touched_files: impl Fn(&Checkpoint) -> Result<Vec<PathBuf>>,
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        let tx = self.conn.transaction()?;
+        let mut removed = Vec::new();
+        let mut adopted = Vec::new();
+        for checkpoint_id in checkpoint_ids {
+            let parent_id: Option<Option<String>> = tx
+                .query_row(
+                    "SELECT parent_id FROM checkpoints WHERE session_id = ?1 AND id = ?2",
+                    params![session_id, checkpoint_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let Some(Some(parent_id)) = parent_id else {
+                continue;
+            };
+
+            let children: Vec<String> = {
+                let mut stmt = tx.prepare(
+                    "SELECT data FROM checkpoints WHERE session_id = ?1 AND parent_id = ?2",
+                )?;
+                let rows = stmt.query_map(params![session_id, checkpoint_id], |row| row.get(0))?;
+                rows.collect::<rusqlite::Result<_>>()?
+            };
+            for data in children {
+                let mut child: Checkpoint =
+                    serde_json::from_str(&data).context("Failed to parse indexed checkpoint")?;
+                child.parent_checkpoint_id = Some(parent_id.clone());
+                tx.execute(
+                    "UPDATE checkpoints SET parent_id = ?3, data = ?4
+                     WHERE session_id = ?1 AND id = ?2",
+                    params![
+                        session_id,
+                        child.id,
+                        parent_id,
+                        serde_json::to_string(&child)?
+                    ],
+                )?;
+                let paths = touched_files(&child).with_context(|| {
+                    format!("Failed to index files touched by checkpoint {}", child.id)
+                })?;
+                write_paths(&tx, session_id, &child.id, &paths)?;
+                adopted.push(child.id);
+            }
+
+            for table in ["checkpoint_tags", "checkpoint_paths"] {
+                tx.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE session_id = ?1 AND checkpoint_id = ?2",
+                        table
+                    ),
+                    params![session_id, checkpoint_id],
+                )?;
+            }
+            tx.execute(
+                "DELETE FROM checkpoints WHERE session_id = ?1 AND id = ?2",
+                params![session_id, checkpoint_id],
+            )?;
+            removed.push(checkpoint_id.clone());
+        }
+
+        if !removed.is_empty() {
+            let mut settings = read_settings(&tx, session_id)?
+                .with_context(|| format!("Session {} has no timeline", session_id))?;
+            settings.total_checkpoints = settings.total_checkpoints.saturating_sub(removed.len());
+            write_settings(&tx, session_id, &settings)?;
+        }
+        tx.commit().context("Failed to commit checkpoint index")?;
+        Ok((removed, adopted))
+    }
+
+    /// Rebuild a session's timeline from its rows; `None` if it is not indexed
+    pub fn timeline(&self, session_id: &str) -> Result<Option<SessionTimeline>> {
+        let Some(mut timeline) = read_settings(&self.conn, session_id)? else {
+            return Ok(None);
+        };
+
+        let mut stmt = self.conn.prepare(
+            "SELECT parent_id, file_snapshot_ids, data FROM checkpoints
+             WHERE session_id = ?1 ORDER BY position",
+        )?;
+        let rows = stmt.query_map(params![session_id], |row| {
+            Ok((
+                row.get::<_, Option<String>>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        let mut nodes = Vec::new();
+        for row in rows {
+            let (parent_id, file_snapshot_ids, data) = row?;
+            let node = TimelineNode {
+                checkpoint: serde_json::from_str(&data)
+                    .context("Failed to parse indexed checkpoint")?,
+                children: Vec::new(),
+                file_snapshot_ids: serde_json::from_str(&file_snapshot_ids)
+                    .context("Failed to parse indexed checkpoint")?,
+            };
+            nodes.push((parent_id, node));
+        }
+
+        timeline.root_node = build_tree(nodes);
+        Ok(Some(timeline))
+    }
+
+    /// A session's checkpoints in timeline order, each before its children
+    pub fn checkpoints(&self, session_id: &str) -> Result<Vec<Checkpoint>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT data FROM checkpoints WHERE session_id = ?1 ORDER BY position")?;
+        let rows = stmt.query_map(params![session_id], |row| row.get::<_, String>(0))?;
+        let mut checkpoints = Vec::new();
+        for data in rows {
+            checkpoints
+                .push(serde_json::from_str(&data?).context("Failed to parse indexed checkpoint")?);
+        }
+        Ok(checkpoints)
+    }
+
+    /// Checkpoints of the given sessions that match the query's tags, dates
+    /// and pinned state, newest first, with the session each belongs to.
+    ///
+    /// Dates are compared to the microsecond, so callers needing exact
+    /// bounds check the returned checkpoints again.
+    pub fn find_checkpoints(
+        &self,
+        session_ids: &[String],
+        query: &CheckpointQuery,
+    ) -> Result<Vec<(String, Checkpoint)>> {
+        if session_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut sql = format!(
+            "SELECT session_id, data FROM checkpoints c WHERE session_id IN ({})",
+            vec!["?"; session_ids.len()].join(", ")
+        );
+        let mut values: Vec<Value> = session_ids
+            .iter()
+            .map(|session_id| Value::Text(session_id.clone()))
+            .collect();
+        if let Some(since) = query.since {
+            sql.push_str(" AND timestamp >= ?");
+            values.push(Value::Integer(since.timestamp_micros()));
+        }
+        if let Some(until) = query.until {
+            sql.push_str(" AND timestamp <= ?");
+            values.push(Value::Integer(until.timestamp_micros()));
+        }
+        if let Some(pinned) = query.pinned {
+            sql.push_str(" AND pinned = ?");
+            values.push(Value::Integer(pinned as i64));
+        }
+        for tag in &query.tags {
+            sql.push_str(
+                " AND EXISTS (SELECT 1 FROM checkpoint_tags t WHERE t.session_id = c.session_id
+                     AND t.checkpoint_id = c.id AND t.tag = ?)",
+            );
+            values.push(Value::Text(tag.trim().to_string()));
+        }
+        sql.push_str(" ORDER BY timestamp DESC, session_id, position");
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut found = Vec::new();
+        for row in rows {
+            let (session_id, data) = row?;
+            let checkpoint =
+                serde_json::from_str(&data).context("Failed to parse indexed checkpoint")?;
+            found.push((session_id, checkpoint));
+        }
+        Ok(found)
+    }
+
+    /// Files a checkpoint added, changed or deleted relative to its parent
+    pub fn touched_paths(&self, session_id: &str, checkpoint_id: &str) -> Result<Vec<PathBuf>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path FROM checkpoint_paths WHERE session_id = ?1 AND checkpoint_id = ?2",
+        )?;
+        let rows = stmt.query_map(params![session_id, checkpoint_id], |row| {
+            row.get::<_, String>(0)
+        })?;
+        let mut paths = rows
+            .map(|path| path.map(PathBuf::from))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+/// Positions for checkpoints listed each before its children.
+///
+/// Stored checkpoints keep their positions and new ones are numbered after
+/// them. Positions order children after their parent and siblings as listed;
+/// if the stored ones no longer do, such as after a checkpoint moved to
+/// another branch, every checkpoint is numbered again.
+fn positions(
+    order: &[(&TimelineNode, Option<&str>)],
+    stored: &HashMap<String, StoredRow>,
+) -> Vec<i64> {
+    let mut next = stored
+        .values()
+        .map(|row| row.position + 1)
+        .max()
+        .unwrap_or(0);
+    let positions: Vec<i64> = order
+        .iter()
+        .map(|(node, _)| match stored.get(&node.checkpoint.id) {
+            Some(row) => row.position,
+            None => {
+                next += 1;
+                next - 1
+            }
+        })
+        .collect();
+
+    let by_id: HashMap<&str, i64> = order
+        .iter()
+        .zip(&positions)
+        .map(|((node, _), position)| (node.checkpoint.id.as_str(), *position))
+        .collect();
+    let consistent = order.iter().all(|(node, _)| {
+        let position = by_id[node.checkpoint.id.as_str()];
+        let mut previous = position;
+        node.children.iter().all(|child| {
+            let child_position = by_id[child.checkpoint.id.as_str()];
+            let in_order = child_position > previous;
+            previous = child_position;
+            in_order
+        })
+    });
+    if consistent {
+        positions
+    } else {
+        (0..order.len() as i64).collect()
+    }
+}
+
+fn read_settings(conn: &Connection, session_id: &str) -> Result<Option<SessionTimeline>> {
+    let settings: Option<String> = conn
+        .query_row(
+            "SELECT settings FROM sessions WHERE session_id = ?1",
+            params![session_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    settings
+        .map(|settings| {
+            serde_json::from_str(&settings).context("Failed to parse indexed timeline settings")
+        })
+        .transpose()
+}
+
+/// Store everything of a timeline but its tree, which is kept in the
+/// checkpoint rows
+fn write_settings(tx: &Transaction, session_id: &str, timeline: &SessionTimeline) -> Result<()> {
+    let settings = SessionTimeline {
+        session_id: timeline.session_id.clone(),
+        root_node: None,
+        current_checkpoint_id: timeline.current_checkpoint_id.clone(),
+        auto_checkpoint_enabled: timeline.auto_checkpoint_enabled,
+        checkpoint_strategy: timeline.checkpoint_strategy.clone(),
+        total_checkpoints: timeline.total_checkpoints,
+        max_file_size: timeline.max_file_size,
+        last_restore: timeline.last_restore.clone(),
+        auto_checkpoint_rules: timeline.auto_checkpoint_rules.clone(),
+        git_mismatch_policy: timeline.git_mismatch_policy,
+    };
+    tx.execute(
+        "INSERT INTO sessions (session_id, settings) VALUES (?1, ?2)
+         ON CONFLICT (session_id) DO UPDATE SET settings = excluded.settings",
+        params![session_id, serde_json::to_string(&settings)?],
+    )?;
+    Ok(())
+}
+
+fn write_row(
+    tx: &Transaction,
+    session_id: &str,
+    node: &TimelineNode,
+    parent_id: Option<&str>,
+    position: i64,
+) -> Result<()> {
+    let checkpoint = &node.checkpoint;
+    tx.execute(
+        "INSERT INTO checkpoints (session_id, id, parent_id, position, timestamp, pinned,
+             snapshot_size, file_snapshot_ids, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+         ON CONFLICT (session_id, id) DO UPDATE SET
+             parent_id = excluded.parent_id,
+             position = excluded.position,
+             timestamp = excluded.timestamp,
+             pinned = excluded.pinned,
+             snapshot_size = excluded.snapshot_size,
+             file_snapshot_ids = excluded.file_snapshot_ids,
+             data = excluded.data",
+        params![
+            session_id,
+            checkpoint.id,
+            parent_id,
+            position,
+            checkpoint.timestamp.timestamp_micros(),
+            checkpoint.pinned,
+            checkpoint.metadata.snapshot_size as i64,
+            serde_json::to_string(&node.file_snapshot_ids)?,
+            serde_json::to_string(checkpoint)?,
+        ],
+    )?;
+    Ok(())
+}
+
+fn write_tags(tx: &Transaction, session_id: &str, checkpoint: &Checkpoint) -> Result<()> {
+    tx.execute(
+        "DELETE FROM checkpoint_tags WHERE session_id = ?1 AND checkpoint_id = ?2",
+        params![session_id, checkpoint.id],
+    )?;
+    for tag in &checkpoint.tags {
+        tx.execute(
+            "INSERT OR IGNORE INTO checkpoint_tags (session_id, checkpoint_id, tag)
+             VALUES (?1, ?2, ?3)",
+            params![session_id, checkpoint.id, tag],
+        )?;
+    }
+    Ok(())
+}
+
+fn write_paths(
+    tx: &Transaction,
+    session_id: &str,
+    checkpoint_id: &str,
+    paths: &[PathBuf],
+) -> Result<()> {
+    tx.execute(
+        "DELETE FROM checkpoint_paths WHERE session_id = ?1 AND checkpoint_id = ?2",
+        params![session_id, checkpoint_id],
+    )?;
+    for path in paths {
+        tx.execute(
+            "INSERT OR IGNORE INTO checkpoint_paths (session_id, checkpoint_id, path)
+             VALUES (?1, ?2, ?3)",
+            params![session_id, checkpoint_id, path.to_string_lossy()],
+        )?;
+    }
+    Ok(())
+}
+
+/// Assemble a tree from nodes listed each before its children.
+///
+/// Going backwards, every node is complete by the time it is reached, so it
+/// can be moved into its parent without recursion.
+fn build_tree(nodes: Vec<(Option<String>, TimelineNode)>) -> Option<TimelineNode> {
+    let positions: HashMap<String, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(position, (_, node))| (node.checkpoint.id.clone(), position))
+        .collect();
+    let parents: Vec<Option<usize>> = nodes
+        .iter()
+        .map(|(parent_id, _)| {
+            parent_id
+                .as_ref()
+                .and_then(|parent_id| positions.get(parent_id).copied())
+        })
+        .collect();
+    let mut slots: Vec<Option<TimelineNode>> =
+        nodes.into_iter().map(|(_, node)| Some(node)).collect();
+
+    for position in (1..slots.len()).rev() {
+        let Some(mut node) = slots[position].take() else {
+            continue;
+        };
+        // Children were added last to first
+        node.children.reverse();
+        match parents[position].and_then(|parent| slots[parent].as_mut()) {
+            Some(parent) => parent.children.push(node),
+            None => log::warn!(
+                "Dropping indexed checkpoint {} without a parent in the tree",
+                node.checkpoint.id
+            ),
+        }
+    }
+
+    let mut root = slots.into_iter().next().flatten()?;
+    root.children.reverse();
+    Some(root)
+}
+
+impl CheckpointStorage {
+    /// Open the checkpoint index of the project `paths` belong to
+    pub fn open_index(&self, paths: &CheckpointPaths) -> Result<CheckpointIndex> {
+        CheckpointIndex::open(&paths.index_file())
+    }
+
+    /// Import a session from its `timeline.json` if it is not in the index
+    /// yet, such as when it was written by an older version
+    pub fn ensure_indexed(
+        &self,
+        index: &mut CheckpointIndex,
+        paths: &CheckpointPaths,
+    ) -> Result<()> {
+        if index.has_session(&paths.session_id)? || !paths.timeline_file.exists() {
+            return Ok(());
+        }
+
+        log::info!("Indexing checkpoints of session {}", paths.session_id);
+        let timeline = Self::read_timeline_file(&paths.timeline_file)?;
+        index.save_session(&paths.session_id, &timeline, |checkpoint| {
+            self.touched_files(paths, checkpoint)
+        })
+    }
+
+    /// A session's timeline as stored in the index
+    pub fn indexed_timeline(&self, paths: &CheckpointPaths) -> Result<Option<SessionTimeline>> {
+        let mut index = self.open_index(paths)?;
+        self.ensure_indexed(&mut index, paths)?;
+        index.timeline(&paths.session_id)
+    }
+
+    /// List the checkpoints of a session from the index, each before its
+    /// children
+    pub fn list_checkpoints(&self, project_id: &str, session_id: &str) -> Result<Vec<Checkpoint>> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        let mut index = self.open_index(&paths)?;
+        self.ensure_indexed(&mut index, &paths)?;
+        index.checkpoints(session_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::checkpoint;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_index_serves_long_timelines() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+
+        // A chain deeper than serde_json would parse by default, with a branch
+        let mut parent: Option<String> = None;
+        for step in 0..100 {
+            let id = format!("checkpoint-{:03}", step);
+            storage
+                .save_checkpoint(
+                    "project",
+                    "session",
+                    &checkpoint(&id, parent.as_deref()),
+                    Vec::new(),
+                    "",
+                )
+                .unwrap();
+            parent = Some(id);
+        }
+        storage
+            .save_checkpoint(
+                "project",
+                "session",
+                &checkpoint("branch", Some("checkpoint-050")),
+                Vec::new(),
+                "",
+            )
+            .unwrap();
+
+        let listed = storage.list_checkpoints("project", "session").unwrap();
+        assert_eq!(listed.len(), 101);
+        assert_eq!(listed[0].id, "checkpoint-000");
+        // Each checkpoint comes before its children, branches in order
+        assert_eq!(listed[51].id, "checkpoint-051");
+        assert_eq!(listed[100].id, "branch");
+
+        let paths = CheckpointPaths::new(&temp_dir.path().to_path_buf(), "project", "session");
+        let timeline = storage.load_timeline(&paths).unwrap();
+        assert_eq!(timeline.total_checkpoints, 101);
+        assert_eq!(timeline.current_checkpoint_id.as_deref(), Some("branch"));
+        let fork = timeline.find_checkpoint("checkpoint-050").unwrap();
+        assert_eq!(fork.children.len(), 2);
+        assert_eq!(fork.children[1].checkpoint.id, "branch");
+
+        // Every new checkpoint is exported too
+        let exported = CheckpointStorage::read_timeline_file(&paths.timeline_file).unwrap();
+        assert_eq!(
+            serde_json::to_value(&exported).unwrap(),
+            serde_json::to_value(&timeline).unwrap()
+        );
+
+        // A session missing from the index is imported from its export
+        fs::remove_file(paths.index_file()).unwrap();
+        let reindexed = storage.load_timeline(&paths).unwrap();
+        assert_eq!(
+            serde_json::to_value(&reindexed).unwrap(),
+            serde_json::to_value(&timeline).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_saved_timeline_keeps_tree_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+        for (id, parent) in [
+            ("a", None),
+            ("b", Some("a")),
+            ("c", Some("b")),
+            ("d", Some("a")),
+        ] {
+            storage
+                .save_checkpoint(
+                    "project",
+                    "session",
+                    &checkpoint(id, parent),
+                    Vec::new(),
+                    "",
+                )
+                .unwrap();
+        }
+        let paths = CheckpointPaths::new(&temp_dir.path().to_path_buf(), "project", "session");
+
+        // Move c ahead of b under a, which no stored position allows
+        let mut timeline = storage.load_timeline(&paths).unwrap();
+        let root = timeline.root_node.as_mut().unwrap();
+        let mut c = root.children[0].children.remove(0);
+        c.checkpoint.parent_checkpoint_id = Some("a".to_string());
+        c.checkpoint.pinned = true;
+        root.children.insert(0, c);
+        storage.save_timeline(&paths, &timeline).unwrap();
+
+        let saved = storage.load_timeline(&paths).unwrap();
+        let order: Vec<&str> = saved
+            .nodes()
+            .iter()
+            .map(|node| node.checkpoint.id.as_str())
+            .collect();
+        assert_eq!(order, ["a", "c", "b", "d"]);
+        assert!(saved.find_checkpoint("c").unwrap().checkpoint.pinned);
+
+        let index = storage.open_index(&paths).unwrap();
+        let pinned = CheckpointQuery {
+            pinned: Some(true),
+            ..Default::default()
+        };
+        let found = index
+            .find_checkpoints(&["session".to_string()], &pinned)
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1.id, "c");
+    }
+}
@@ -0,0 +1,555 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+
+use super::{
+    delta::{DeltaHeader, MAX_DELTA_CHAIN},
+    manifest::CheckpointManifest,
+    storage::CheckpointStorage,
+    Checkpoint, CheckpointPaths, FileSnapshot,
+};
+
+/// A single problem found while verifying a checkpoint store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum IntegrityIssue {
+    /// The timeline could not be read from the index or imported
+    UnreadableTimeline { error: String },
+    /// A checkpoint listed in the timeline has no metadata on disk
+    MissingMetadata { checkpoint_id: String },
+    /// A checkpoint's metadata exists but cannot be parsed
+    UnparsableMetadata {
+        checkpoint_id: String,
+        error: String,
+    },
+    /// A checkpoint's compressed messages cannot be read
+    UnreadableMessages {
+        checkpoint_id: String,
+        error: String,
+    },
+    /// A checkpoint has no file manifest
+    MissingManifest { checkpoint_id: String },
+    /// A checkpoint's manifest cannot be read or parsed
+    UnreadableManifest {
+        checkpoint_id: String,
+        error: String,
+    },
+    /// Content referenced by a manifest is not in the pool
+    MissingObject {
+        hash: String,
+        referenced_by: Vec<String>,
+    },
+    /// A delta in the pool whose base, or an object further down its chain,
+    /// is not in the pool; the delta itself may be intact
+    MissingDeltaBase {
+        hash: String,
+        base_hash: String,
+        referenced_by: Vec<String>,
+    },
+    /// Content in the pool no longer matches its hash
+    CorruptObject {
+        hash: String,
+        error: String,
+        referenced_by: Vec<String>,
+    },
+    /// Content in the pool that no checkpoint references
+    OrphanObject { hash: String },
+    /// A checkpoint directory that the timeline does not know about
+    OrphanCheckpoint { checkpoint_id: String },
+}
+
+/// Result of verifying a session's checkpoint store
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityReport {
+    /// Number of checkpoints examined
+    pub checkpoints_checked: usize,
+    /// Number of content objects re-hashed
+    pub objects_checked: usize,
+    /// Problems found
+    pub issues: Vec<IntegrityIssue>,
+    /// Checkpoints that cannot be restored safely
+    pub damaged_checkpoints: Vec<String>,
+    /// Repair actions that were taken
+    pub repairs: Vec<String>,
+}
+
+impl IntegrityReport {
+    /// Whether the store is free of problems
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl CheckpointStorage {
+    /// Verify the whole checkpoint store of a session.
+    ///
+    /// Walks the timeline, every checkpoint's metadata, messages and
+    /// manifest, and re-hashes every referenced content object. With `repair`
+    /// set, objects no session references are deleted and corrupt objects are
+    /// moved to the pool's `quarantine` directory so they can no longer be
+    /// restored from.
+    pub fn verify_checkpoints(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        repair: bool,
+    ) -> Result<IntegrityReport> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        let mut report = IntegrityReport::default();
+
+        // Checkpoints known to the timeline
+        let mut timeline_ids = Vec::new();
+        match self.load_timeline(&paths) {
+            Ok(timeline) => {
+                timeline_ids.extend(
+                    timeline
+                        .nodes()
+                        .into_iter()
+                        .map(|node| node.checkpoint.id.clone()),
+                );
+            }
+            Err(e) => report.issues.push(IntegrityIssue::UnreadableTimeline {
+                error: format!("{:#}", e),
+            }),
+        }
+
+        // Checkpoints present on disk
+        let mut disk_ids = BTreeSet::new();
+        if paths.checkpoints_dir.exists() {
+            for entry in fs::read_dir(&paths.checkpoints_dir)? {
+                let entry = entry?;
+                if entry.path().is_dir() {
+                    if let Some(id) = entry.file_name().to_str() {
+                        disk_ids.insert(id.to_string());
+                    }
+                }
+            }
+        }
+
+        let known: HashSet<&String> = timeline_ids.iter().collect();
+        for id in &disk_ids {
+            if !known.contains(id) {
+                report.issues.push(IntegrityIssue::OrphanCheckpoint {
+                    checkpoint_id: id.clone(),
+                });
+            }
+        }
+
+        // Verify each checkpoint and gather the objects it references.
+        // Orphaned checkpoints still hold references, so they are walked too.
+        let mut all_ids: BTreeSet<String> = timeline_ids.into_iter().collect();
+        all_ids.extend(disk_ids);
+
+        let mut damaged = BTreeSet::new();
+        let mut references: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for checkpoint_id in &all_ids {
+            report.checkpoints_checked += 1;
+            let issues = self.verify_checkpoint_files(&paths, checkpoint_id, &mut references);
+            if !issues.is_empty() {
+                damaged.insert(checkpoint_id.clone());
+                report.issues.extend(issues);
+            }
+        }
+
+        // Re-hash every referenced object
+        for (hash, referenced_by) in &references {
+            report.objects_checked += 1;
+            if let Some(issue) = self.verify_object(&paths, hash, referenced_by) {
+                damaged.extend(referenced_by.iter().cloned());
+                if repair {
+                    if let IntegrityIssue::CorruptObject { .. } = issue {
+                        report.repairs.push(self.quarantine_object(&paths, hash)?);
+                    }
+                }
+                report.issues.push(issue);
+            }
+        }
+
+        // Objects no checkpoint of any session references. The pool is shared,
+        // so the whole project is consulted, and objects young enough to belong
+        // to a checkpoint still being written are left alone.
+        let _lock = self.lock_pool(&paths)?;
+        let refcounts = self.content_refcounts(project_id)?;
+        for path in self.collectable_objects(&paths, &refcounts)? {
+            let hash = match path.file_name().and_then(|n| n.to_str()) {
+                Some(hash) => hash.to_string(),
+                None => continue,
+            };
+            if repair {
+                fs::remove_file(&path).context("Failed to remove orphaned object")?;
+                report
+                    .repairs
+                    .push(format!("Removed orphaned object {}", hash));
+            }
+            report.issues.push(IntegrityIssue::OrphanObject { hash });
+        }
+
+        report.damaged_checkpoints = damaged.into_iter().collect();
+        Ok(report)
+    }
+
+    /// Verify everything a single checkpoint needs to be restored.
+    ///
+    /// Returns the problems found; an empty list means the checkpoint is intact.
+    pub fn verify_checkpoint(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        checkpoint_id: &str,
+    ) -> Vec<IntegrityIssue> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        let mut references = BTreeMap::new();
+        let mut issues = self.verify_checkpoint_files(&paths, checkpoint_id, &mut references);
+        for (hash, referenced_by) in &references {
+            if let Some(issue) = self.verify_object(&paths, hash, referenced_by) {
+                issues.push(issue);
+            }
+        }
+        issues
+    }
+
+    /// Fail if anything a checkpoint needs to be restored completely is
+    /// missing or damaged, see [`verify_checkpoint`](Self::verify_checkpoint)
+    pub fn ensure_restorable(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        checkpoint_id: &str,
+    ) -> Result<()> {
+        let issues = self.verify_checkpoint(project_id, session_id, checkpoint_id);
+        refuse_damaged(checkpoint_id, &issues)
+    }
+
+    /// Load a checkpoint to restore it, refusing if anything it needs is
+    /// missing or damaged.
+    ///
+    /// The checks are those of [`verify_checkpoint`](Self::verify_checkpoint),
+    /// and the content read to re-hash each object is the content returned,
+    /// so nothing is read and decompressed twice.
+    pub fn load_restorable_checkpoint(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        checkpoint_id: &str,
+    ) -> Result<(Checkpoint, Vec<FileSnapshot>, String)> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        let checked = self.check_checkpoint_files(&paths, checkpoint_id);
+        let mut issues = checked.issues;
+
+        let mut references = BTreeMap::new();
+        if let Some(manifest) = &checked.manifest {
+            record_references(manifest, &mut references);
+        }
+        let mut contents = HashMap::new();
+        for (hash, referenced_by) in &references {
+            match self.check_object(&paths, hash, referenced_by) {
+                Ok(content) => {
+                    contents.insert(hash.as_str(), content);
+                }
+                Err(issue) => issues.push(issue),
+            }
+        }
+
+        refuse_damaged(checkpoint_id, &issues)?;
+        let (Some(checkpoint), Some(messages), Some(manifest)) =
+            (checked.checkpoint, checked.messages, checked.manifest)
+        else {
+            anyhow::bail!("Checkpoint {} could not be read", checkpoint_id);
+        };
+
+        let snapshots = manifest
+            .files
+            .iter()
+            .map(|(path, entry)| {
+                let content = if entry.deleted {
+                    Vec::new()
+                } else {
+                    contents
+                        .get(entry.hash.as_str())
+                        .cloned()
+                        .unwrap_or_default()
+                };
+                entry.to_snapshot(checkpoint_id, path, content)
+            })
+            .collect();
+        Ok((checkpoint, snapshots, messages))
+    }
+
+    /// Check a checkpoint's metadata, messages and manifest, recording the
+    /// content hashes its manifest references
+    fn verify_checkpoint_files(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint_id: &str,
+        references: &mut BTreeMap<String, Vec<String>>,
+    ) -> Vec<IntegrityIssue> {
+        let checked = self.check_checkpoint_files(paths, checkpoint_id);
+        if let Some(manifest) = &checked.manifest {
+            record_references(manifest, references);
+        }
+        checked.issues
+    }
+
+    /// Read a checkpoint's metadata, messages and manifest, noting what
+    /// could not be read
+    fn check_checkpoint_files(&self, paths: &CheckpointPaths, checkpoint_id: &str) -> CheckedFiles {
+        let mut checked = CheckedFiles::default();
+
+        let metadata_path = paths.checkpoint_metadata_file(checkpoint_id);
+        if !metadata_path.exists() {
+            checked.issues.push(IntegrityIssue::MissingMetadata {
+                checkpoint_id: checkpoint_id.to_string(),
+            });
+        } else {
+            match fs::read_to_string(&metadata_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| serde_json::from_str::<Checkpoint>(&json).map_err(Into::into))
+            {
+                Ok(checkpoint) => checked.checkpoint = Some(checkpoint),
+                Err(e) => checked.issues.push(IntegrityIssue::UnparsableMetadata {
+                    checkpoint_id: checkpoint_id.to_string(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        // The transcript must be rebuildable through every checkpoint it inherits from
+        match self.read_messages(paths, checkpoint_id) {
+            Ok(messages) => checked.messages = Some(messages),
+            Err(e) => checked.issues.push(IntegrityIssue::UnreadableMessages {
+                checkpoint_id: checkpoint_id.to_string(),
+                error: format!("{:#}", e),
+            }),
+        }
+
+        match self.read_manifest(paths, checkpoint_id) {
+            Ok(Some(manifest)) => checked.manifest = Some(manifest),
+            Ok(None) => checked.issues.push(IntegrityIssue::MissingManifest {
+                checkpoint_id: checkpoint_id.to_string(),
+            }),
+            Err(e) => checked.issues.push(IntegrityIssue::UnreadableManifest {
+                checkpoint_id: checkpoint_id.to_string(),
+                error: format!("{:#}", e),
+            }),
+        }
+
+        checked
+    }
+
+    /// Re-hash a single pool object
+    fn verify_object(
+        &self,
+        paths: &CheckpointPaths,
+        hash: &str,
+        referenced_by: &[String],
+    ) -> Option<IntegrityIssue> {
+        self.check_object(paths, hash, referenced_by).err()
+    }
+
+    /// Read a pool object, wherever it is kept, and re-hash it
+    fn check_object(
+        &self,
+        paths: &CheckpointPaths,
+        hash: &str,
+        referenced_by: &[String],
+    ) -> Result<Vec<u8>, IntegrityIssue> {
+        // Sessions not yet migrated to the shared pool still keep their objects
+        if !self.object_file(paths, hash).exists() {
+            return Err(IntegrityIssue::MissingObject {
+                hash: hash.to_string(),
+                referenced_by: referenced_by.to_vec(),
+            });
+        }
+
+        // A lost base makes a delta unreadable without making it corrupt
+        let mut object = hash.to_string();
+        for _ in 0..=MAX_DELTA_CHAIN {
+            let Ok(Some(header)) = DeltaHeader::read(&self.object_file(paths, &object)) else {
+                break;
+            };
+            if !self.object_file(paths, &header.base_hash).exists() {
+                return Err(IntegrityIssue::MissingDeltaBase {
+                    hash: hash.to_string(),
+                    base_hash: header.base_hash,
+                    referenced_by: referenced_by.to_vec(),
+                });
+            }
+            object = header.base_hash;
+        }
+
+        self.read_content(paths, hash)
+            .map_err(|e| IntegrityIssue::CorruptObject {
+                hash: hash.to_string(),
+                error: format!("{:#}", e),
+                referenced_by: referenced_by.to_vec(),
+            })
+    }
+
+    /// Move a corrupt object out of the pool so it is reported as missing from now on
+    fn quarantine_object(&self, paths: &CheckpointPaths, hash: &str) -> Result<String> {
+        let quarantine_dir = paths.pool_dir().join("quarantine");
+        fs::create_dir_all(&quarantine_dir).context("Failed to create quarantine directory")?;
+        fs::rename(self.object_file(paths, hash), quarantine_dir.join(hash))
+            .context("Failed to quarantine corrupt object")?;
+        Ok(format!("Quarantined corrupt object {}", hash))
+    }
+}
+
+/// Fail with a summary of `issues`, if there are any
+fn refuse_damaged(checkpoint_id: &str, issues: &[IntegrityIssue]) -> Result<()> {
+    if let Some(issue) = issues.first() {
+        anyhow::bail!(
+            "Checkpoint {} is damaged and cannot be restored ({} problems found, first: {:?}); run verify_checkpoints for details",
+            checkpoint_id,
+            issues.len(),
+            issue
+        );
+    }
+    Ok(())
+}
+
+/// What [`CheckpointStorage::check_checkpoint_files`] could read
+#[derive(Default)]
+struct CheckedFiles {
+    issues: Vec<IntegrityIssue>,
+    checkpoint: Option<Checkpoint>,
+    messages: Option<String>,
+    manifest: Option<CheckpointManifest>,
+}
+
+fn record_references(
+    manifest: &CheckpointManifest,
+    references: &mut BTreeMap<String, Vec<String>>,
+) {
+    for entry in manifest.files.values().filter(|entry| !entry.deleted) {
+        let referenced_by = references.entry(entry.hash.clone()).or_default();
+        if !referenced_by.contains(&manifest.checkpoint_id) {
+            referenced_by.push(manifest.checkpoint_id.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::{checkpoint, snapshot};
+    use crate::checkpoint::FileSnapshot;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_verify_detects_missing_and_corrupt_objects() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+
+        let snapshots: Vec<FileSnapshot> = [("a.txt", "alpha"), ("b.txt", "beta")]
+            .iter()
+            .map(|(path, content)| snapshot("cp1", path, content))
+            .collect();
+        let (alpha, beta) = (snapshots[0].hash.clone(), snapshots[1].hash.clone());
+        let checkpoint = checkpoint("cp1", None);
+        storage
+            .save_checkpoint("project", "session", &checkpoint, snapshots, "")
+            .unwrap();
+
+        let report = storage
+            .verify_checkpoints("project", "session", false)
+            .unwrap();
+        assert!(report.is_healthy(), "{:?}", report.issues);
+
+        // An object still in the session's own pool, from before the pool
+        // was shared, is found there
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "session");
+        fs::create_dir_all(paths.legacy_content_pool_dir()).unwrap();
+        fs::rename(
+            paths.content_object_file(&alpha),
+            paths.legacy_content_pool_dir().join(&alpha),
+        )
+        .unwrap();
+        assert!(storage
+            .verify_checkpoint("project", "session", "cp1")
+            .is_empty());
+        let (_, files, _) = storage
+            .load_restorable_checkpoint("project", "session", "cp1")
+            .unwrap();
+        assert_eq!(files[0].content, b"alpha");
+
+        // Lose one object and corrupt the other
+        fs::remove_file(paths.legacy_content_pool_dir().join(&alpha)).unwrap();
+        fs::write(
+            paths.content_object_file(&beta),
+            zstd::stream::encode_all(&b"tampered"[..], 3).unwrap(),
+        )
+        .unwrap();
+
+        let report = storage
+            .verify_checkpoints("project", "session", true)
+            .unwrap();
+        assert_eq!(report.damaged_checkpoints, vec!["cp1".to_string()]);
+        assert!(report
+            .issues
+            .iter()
+            .any(|i| matches!(i, IntegrityIssue::MissingObject { hash, .. } if *hash == alpha)));
+        assert!(report
+            .issues
+            .iter()
+            .any(|i| matches!(i, IntegrityIssue::CorruptObject { hash, .. } if *hash == beta)));
+        assert!(!paths.content_object_file(&beta).exists());
+
+        // Loading must fail instead of handing back empty content
+        assert!(storage
+            .load_checkpoint("project", "session", "cp1")
+            .is_err());
+        assert!(storage
+            .load_restorable_checkpoint("project", "session", "cp1")
+            .is_err());
+    }
+
+    #[test]
+    fn test_verify_reports_missing_delta_bases_without_quarantine() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+
+        // Content that compresses poorly, so the second version is a delta
+        let base: String = (0..2000u64)
+            .map(|i| format!("{:x}\n", i.wrapping_mul(0x9e37_79b9_7f4a_7c15)))
+            .collect();
+        let versions = [base.clone(), format!("{}changed\n", base)];
+        for (i, content) in versions.iter().enumerate() {
+            let id = format!("cp{}", i);
+            let parent = (i > 0).then_some("cp0");
+            storage
+                .save_checkpoint(
+                    "project",
+                    "session",
+                    &checkpoint(&id, parent),
+                    vec![snapshot(&id, "lib.rs", content)],
+                    "",
+                )
+                .unwrap();
+        }
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "session");
+        let base_hash = CheckpointStorage::calculate_file_hash(versions[0].as_bytes());
+        let delta_hash = CheckpointStorage::calculate_file_hash(versions[1].as_bytes());
+        let delta = paths.content_object_file(&delta_hash);
+        assert!(DeltaHeader::read(&delta).unwrap().is_some());
+
+        fs::remove_file(paths.content_object_file(&base_hash)).unwrap();
+        let report = storage
+            .verify_checkpoints("project", "session", true)
+            .unwrap();
+        assert!(report.issues.iter().any(|i| matches!(
+            i,
+            IntegrityIssue::MissingDeltaBase { hash, base_hash: base, .. }
+                if *hash == delta_hash && *base == base_hash
+        )));
+        assert!(delta.exists());
+        assert!(report.repairs.is_empty());
+        assert!(storage
+            .ensure_restorable("project", "session", "cp1")
+            .is_err());
+    }
+}
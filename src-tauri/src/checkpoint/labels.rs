@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use std::fs;
+
+use super::{storage::CheckpointStorage, Checkpoint, CheckpointPaths};
+
+impl CheckpointStorage {
+    /// Pin or unpin a checkpoint; pinned checkpoints are never removed by
+    /// retention
+    pub fn set_checkpoint_pinned(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        checkpoint_id: &str,
+        pinned: bool,
+    ) -> Result<Checkpoint> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        self.update_checkpoint(&paths, checkpoint_id, |checkpoint| {
+            checkpoint.pinned = pinned;
+        })
+    }
+
+    /// Replace the tags of a checkpoint
+    pub fn set_checkpoint_tags(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        checkpoint_id: &str,
+        tags: &[String],
+    ) -> Result<Checkpoint> {
+        let tags = normalize_tags(tags)?;
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        self.update_checkpoint(&paths, checkpoint_id, |checkpoint| {
+            checkpoint.tags = tags.clone();
+        })
+    }
+
+    /// Apply the same change to a checkpoint's metadata and to its node in the
+    /// timeline, which both hold a copy of it
+    pub fn update_checkpoint(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint_id: &str,
+        update: impl Fn(&mut Checkpoint),
+    ) -> Result<Checkpoint> {
+        let _lock = self.lock_timeline(paths)?;
+        let mut timeline = self.load_timeline(paths)?;
+        let node = match timeline.find_checkpoint_mut(checkpoint_id) {
+            Some(node) => node,
+            None => anyhow::bail!("Checkpoint {} is not in the timeline", checkpoint_id),
+        };
+        update(&mut node.checkpoint);
+
+        let metadata_path = paths.checkpoint_metadata_file(checkpoint_id);
+        let metadata_json =
+            fs::read_to_string(&metadata_path).context("Failed to read checkpoint metadata")?;
+        let mut checkpoint: Checkpoint =
+            serde_json::from_str(&metadata_json).context("Failed to parse checkpoint metadata")?;
+        update(&mut checkpoint);
+        let metadata_json = serde_json::to_string_pretty(&checkpoint)
+            .context("Failed to serialize checkpoint metadata")?;
+        fs::write(&metadata_path, metadata_json).context("Failed to write checkpoint metadata")?;
+
+        self.save_timeline(paths, &timeline)?;
+        Ok(checkpoint)
+    }
+}
+
+/// Trim tags and drop duplicates, keeping their order
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            anyhow::bail!("Tags cannot be empty");
+        }
+        if !normalized.iter().any(|existing| existing == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+    Ok(normalized)
+}
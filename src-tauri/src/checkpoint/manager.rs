@@ -1,18 +1,30 @@
 use anyhow::{Context, Result};
 use chrono::{DateTime, TimeZone, Utc};
 use log;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 use super::{
+    diff,
+    git::{GitMismatchPolicy, GitState},
+    merge::MergeResult,
+    restore::{self, PathFilter, RestorePlan},
+    rewind,
+    rules::{AutoCheckpointRules, RuleProgress},
     storage::{self, CheckpointStorage},
-    Checkpoint, CheckpointMetadata, CheckpointPaths, CheckpointResult, CheckpointStrategy,
-    FileSnapshot, FileState, FileTracker, SessionTimeline,
+    walker,
+    watcher::FileWatcher,
+    Checkpoint, CheckpointKind, CheckpointMetadata, CheckpointPaths, CheckpointResult,
+    CheckpointStrategy, FileEvent, FileKind, FileSnapshot, FileState, FileTracker, RestoreRecord,
+    SessionTimeline,
 };
 
+/// A checkpoint's metadata, files and messages, as read to restore it
+type LoadedCheckpoint = (Checkpoint, Vec<FileSnapshot>, String);
+
 /// Manages checkpoint operations for a session
 pub struct CheckpointManager {
     project_id: String,
@@ -22,6 +34,11 @@ pub struct CheckpointManager {
     pub storage: Arc<CheckpointStorage>,
     timeline: Arc<RwLock<SessionTimeline>>,
     current_messages: Arc<RwLock<Vec<String>>>, // JSONL messages
+    /// Feeds real disk changes into the file tracker; `None` if watching failed
+    watcher: Option<FileWatcher>,
+    /// When the manager was created, the starting point for time-based rules
+    /// before the first checkpoint
+    started_at: DateTime<Utc>,
 }
 
 impl CheckpointManager {
@@ -39,24 +56,44 @@ impl CheckpointManager {
 
         // Load or create timeline
         let paths = CheckpointPaths::new(&claude_dir, &project_id, &session_id);
-        let timeline = if paths.timeline_file.exists() {
-            storage.load_timeline(&paths.timeline_file)?
-        } else {
-            SessionTimeline::new(session_id.clone())
-        };
+        let timeline = storage
+            .indexed_timeline(&paths)?
+            .unwrap_or_else(|| SessionTimeline::new(session_id.clone()));
 
-        let file_tracker = FileTracker {
+        let file_tracker = Arc::new(RwLock::new(FileTracker {
             tracked_files: HashMap::new(),
+            events: VecDeque::new(),
+        }));
+
+        let timeline = Arc::new(RwLock::new(timeline));
+
+        // Without a watcher, changes are inferred from tool usage instead
+        let watcher = match FileWatcher::start(
+            &project_path,
+            Arc::clone(&file_tracker),
+            Arc::clone(&timeline),
+        ) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                log::warn!(
+                    "File watcher unavailable for {}, falling back to tool tracking: {:#}",
+                    project_path.display(),
+                    e
+                );
+                None
+            }
         };
 
         Ok(Self {
             project_id,
             session_id,
             project_path,
-            file_tracker: Arc::new(RwLock::new(file_tracker)),
+            file_tracker,
             storage,
-            timeline: Arc::new(RwLock::new(timeline)),
+            timeline,
             current_messages: Arc::new(RwLock::new(Vec::new())),
+            watcher,
+            started_at: Utc::now(),
         })
     }
 
@@ -85,8 +122,18 @@ impl CheckpointManager {
         Ok(())
     }
 
+    /// Replace the tracked conversation with the session's current messages
+    pub async fn replace_messages(&self, messages: Vec<String>) {
+        *self.current_messages.write().await = messages;
+    }
+
     /// Track file operations from tool usage
     async fn track_tool_operation(&self, tool: &str, input: &serde_json::Value) -> Result<()> {
+        // The watcher records what tools actually change once they have run
+        if self.watcher.is_some() {
+            return Ok(());
+        }
+
         match tool.to_lowercase().as_str() {
             "edit" | "write" | "multiedit" => {
                 if let Some(file_path) = input.get("file_path").and_then(|p| p.as_str()) {
@@ -109,28 +156,28 @@ impl CheckpointManager {
         let mut tracker = self.file_tracker.write().await;
         let full_path = self.project_path.join(file_path);
 
-        // Read current file state
-        let (hash, exists, _size, modified) = if full_path.exists() {
-            let content = fs::read_to_string(&full_path).unwrap_or_default();
-            let metadata = fs::metadata(&full_path)?;
-            let modified = metadata
-                .modified()
-                .ok()
-                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                .map(|d| {
-                    Utc.timestamp_opt(d.as_secs() as i64, d.subsec_nanos())
-                        .unwrap()
-                })
-                .unwrap_or_else(Utc::now);
-
-            (
-                storage::CheckpointStorage::calculate_file_hash(&content),
-                true,
-                metadata.len(),
-                modified,
-            )
-        } else {
-            (String::new(), false, 0, Utc::now())
+        // Read current file state, without following symlinks
+        let (hash, exists, kind, mode, modified) = match walker::read_entry(&full_path)? {
+            Some(entry) => {
+                let modified = fs::symlink_metadata(&full_path)?
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|d| {
+                        Utc.timestamp_opt(d.as_secs() as i64, d.subsec_nanos())
+                            .unwrap()
+                    })
+                    .unwrap_or_else(Utc::now);
+
+                (
+                    storage::CheckpointStorage::calculate_file_hash(&entry.content),
+                    true,
+                    entry.kind,
+                    entry.mode,
+                    modified,
+                )
+            }
+            None => (String::new(), false, FileKind::File, None, Utc::now()),
         };
 
         // Check if file has actually changed
@@ -138,10 +185,12 @@ impl CheckpointManager {
             if let Some(existing_state) = tracker.tracked_files.get(&PathBuf::from(file_path)) {
                 // File is modified if:
                 // 1. Hash has changed
-                // 2. Existence state has changed
+                // 2. Existence state, type or permissions have changed
                 // 3. It was already marked as modified
                 existing_state.last_hash != hash
                     || existing_state.exists != exists
+                    || existing_state.kind != kind
+                    || existing_state.mode != mode
                     || existing_state.is_modified
             } else {
                 // New file is always considered modified
@@ -155,6 +204,8 @@ impl CheckpointManager {
                 is_modified,
                 last_modified: modified,
                 exists,
+                kind,
+                mode,
             },
         );
 
@@ -190,6 +241,100 @@ impl CheckpointManager {
         description: Option<String>,
         parent_checkpoint_id: Option<String>,
     ) -> Result<CheckpointResult> {
+        let mut result = self
+            .create_checkpoint_of_kind(description, parent_checkpoint_id, CheckpointKind::Regular)
+            .await?;
+        self.apply_retention(&mut result).await;
+        Ok(result)
+    }
+
+    /// Apply the project's retention policy, if it has one, after a new
+    /// checkpoint. Failures are reported as warnings rather than failing the
+    /// checkpoint.
+    ///
+    /// Other sessions of the project may lose checkpoints too; callers reload
+    /// the managers of the sessions in [`CheckpointResult::retention`].
+    async fn apply_retention(&self, result: &mut CheckpointResult) {
+        let storage = self.storage.clone();
+        let project_id = self.project_id.clone();
+        let report = tokio::task::spawn_blocking(move || {
+            match storage.load_retention_policy(&project_id)? {
+                Some(policy) => storage.apply_retention(&project_id, &policy).map(Some),
+                None => Ok(None),
+            }
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|report| report);
+        let report = match report {
+            Ok(Some(report)) => Ok(report),
+            Ok(None) => return,
+            Err(e) => Err(e),
+        };
+        match report {
+            Ok(report) => {
+                if !report.pruned.is_empty() {
+                    log::info!(
+                        "Retention pruned {} checkpoints and reclaimed {} bytes",
+                        report.pruned.len(),
+                        report.reclaimed_bytes
+                    );
+                    if let Err(e) = self.reload_timeline().await {
+                        result
+                            .warnings
+                            .push(format!("Failed to reload timeline: {}", e));
+                    }
+                }
+                result.retention = Some(report);
+            }
+            Err(e) => {
+                log::warn!("Failed to apply retention policy: {:#}", e);
+                result
+                    .warnings
+                    .push(format!("Failed to apply retention policy: {}", e));
+            }
+        }
+    }
+
+    /// Replace the in-memory timeline with the one on disk, after checkpoints
+    /// were pruned from it
+    pub async fn reload_timeline(&self) -> Result<()> {
+        let paths =
+            CheckpointPaths::new(&self.storage.claude_dir, &self.project_id, &self.session_id);
+        let timeline = self.storage.load_timeline(&paths)?;
+        *self.timeline.write().await = timeline;
+        Ok(())
+    }
+
+    /// Change the timeline and save it.
+    ///
+    /// The change is applied to the timeline on disk, under the session's
+    /// timeline lock, since retention in another session may have pruned
+    /// checkpoints this manager still has in memory.
+    async fn update_timeline(&self, update: impl FnOnce(&mut SessionTimeline)) -> Result<()> {
+        let paths =
+            CheckpointPaths::new(&self.storage.claude_dir, &self.project_id, &self.session_id);
+        let mut timeline = self.timeline.write().await;
+        let _lock = self.storage.lock_timeline(&paths)?;
+        let mut updated = match self.storage.indexed_timeline(&paths)? {
+            Some(stored) => stored,
+            None => timeline.clone(),
+        };
+        update(&mut updated);
+        self.storage.save_timeline(&paths, &updated)?;
+        *timeline = updated;
+        Ok(())
+    }
+
+    async fn create_checkpoint_of_kind(
+        &self,
+        description: Option<String>,
+        parent_checkpoint_id: Option<String>,
+        kind: CheckpointKind,
+    ) -> Result<CheckpointResult> {
+        // Capture the repository state while the project is walked and hashed
+        let git_state = self.capture_git_state();
+
         let messages = self.current_messages.read().await;
         let message_index = messages.len().saturating_sub(1);
 
@@ -197,37 +342,40 @@ impl CheckpointManager {
         let (user_prompt, model_used, total_tokens) =
             self.extract_checkpoint_metadata(&messages).await?;
 
-        // Ensure every file in the project is tracked so new checkpoints include all files
-        // Recursively walk the project directory and track each file
-        fn collect_files(
-            dir: &std::path::Path,
-            base: &std::path::Path,
-            files: &mut Vec<std::path::PathBuf>,
-        ) -> Result<(), std::io::Error> {
-            for entry in std::fs::read_dir(dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_dir() {
-                    // Skip hidden directories like .git
-                    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                        if name.starts_with('.') {
-                            continue;
-                        }
-                    }
-                    collect_files(&path, base, files)?;
-                } else if path.is_file() {
-                    // Compute relative path from project root
-                    if let Ok(rel) = path.strip_prefix(base) {
-                        files.push(rel.to_path_buf());
-                    }
-                }
+        // Ensure every file in the project is tracked so new checkpoints include all
+        // files, leaving out ignored and oversized ones
+        let max_file_size = self.timeline.read().await.max_file_size;
+        let project_files = walker::walk_project(&self.project_path, max_file_size)?;
+        let included: HashSet<PathBuf> = project_files
+            .files
+            .iter()
+            .chain(&project_files.empty_dirs)
+            .cloned()
+            .collect();
+
+        let parent_checkpoint_id = match parent_checkpoint_id {
+            Some(parent_id) => Some(parent_id),
+            // Perform an asynchronous read to avoid blocking within the runtime
+            None => self.timeline.read().await.current_checkpoint_id.clone(),
+        };
+
+        // Also re-check files known to the tracker or the parent checkpoint, so
+        // files deleted since then are recorded as deletions in the manifest
+        let mut known_files = included.clone();
+        known_files.extend(self.file_tracker.read().await.tracked_files.keys().cloned());
+        if let Some(parent_id) = &parent_checkpoint_id {
+            if let Some(manifest) =
+                self.storage
+                    .load_manifest(&self.project_id, &self.session_id, parent_id)?
+            {
+                known_files.extend(manifest.live_paths().cloned());
             }
-            Ok(())
         }
-        let mut all_files = Vec::new();
-        let project_dir = &self.project_path;
-        let _ = collect_files(project_dir.as_path(), project_dir.as_path(), &mut all_files);
-        for rel in all_files {
+        known_files.retain(|path| {
+            included.contains(path) || fs::symlink_metadata(self.project_path.join(path)).is_err()
+        });
+
+        for rel in known_files {
             if let Some(p) = rel.to_str() {
                 // Track each file for snapshot
                 let _ = self.track_file_modification(p).await;
@@ -238,7 +386,9 @@ impl CheckpointManager {
         let checkpoint_id = storage::CheckpointStorage::generate_checkpoint_id();
 
         // Create file snapshots
-        let file_snapshots = self.create_file_snapshots(&checkpoint_id).await?;
+        let file_snapshots = self
+            .create_file_snapshots(&checkpoint_id, &included)
+            .await?;
 
         // Generate checkpoint struct
         let checkpoint = Checkpoint {
@@ -248,15 +398,7 @@ impl CheckpointManager {
             message_index,
             timestamp: Utc::now(),
             description,
-            parent_checkpoint_id: {
-                if let Some(parent_id) = parent_checkpoint_id {
-                    Some(parent_id)
-                } else {
-                    // Perform an asynchronous read to avoid blocking within the runtime
-                    let timeline = self.timeline.read().await;
-                    timeline.current_checkpoint_id.clone()
-                }
-            },
+            parent_checkpoint_id,
             metadata: CheckpointMetadata {
                 total_tokens,
                 model_used,
@@ -266,23 +408,31 @@ impl CheckpointManager {
                     &messages.join("\n"),
                     &file_snapshots,
                 ),
+                git: git_state.await,
             },
+            kind,
+            pinned: false,
+            tags: Vec::new(),
+            merge_parent_ids: Vec::new(),
         };
 
         // Save checkpoint
         let messages_content = messages.join("\n");
-        let result = self.storage.save_checkpoint(
+        let mut result = self.storage.save_checkpoint(
             &self.project_id,
             &self.session_id,
             &checkpoint,
             file_snapshots,
             &messages_content,
         )?;
+        result
+            .warnings
+            .extend(project_files.warnings(max_file_size));
 
         // Reload timeline from disk so in-memory timeline has updated nodes and total_checkpoints
         let claude_dir = self.storage.claude_dir.clone();
         let paths = CheckpointPaths::new(&claude_dir, &self.project_id, &self.session_id);
-        let updated_timeline = self.storage.load_timeline(&paths.timeline_file)?;
+        let updated_timeline = self.storage.load_timeline(&paths)?;
         {
             let mut timeline_lock = self.timeline.write().await;
             *timeline_lock = updated_timeline;
@@ -343,62 +493,22 @@ impl CheckpointManager {
                     }
                 }
 
-                // Count tokens - check both top-level and nested usage
-                // First check for usage in message.usage (assistant messages)
-                if let Some(message) = msg.get("message") {
-                    if let Some(usage) = message.get("usage") {
-                        if let Some(input) = usage.get("input_tokens").and_then(|t| t.as_u64()) {
-                            total_tokens += input;
-                        }
-                        if let Some(output) = usage.get("output_tokens").and_then(|t| t.as_u64()) {
-                            total_tokens += output;
-                        }
-                        // Also count cache tokens
-                        if let Some(cache_creation) = usage
-                            .get("cache_creation_input_tokens")
-                            .and_then(|t| t.as_u64())
-                        {
-                            total_tokens += cache_creation;
-                        }
-                        if let Some(cache_read) = usage
-                            .get("cache_read_input_tokens")
-                            .and_then(|t| t.as_u64())
-                        {
-                            total_tokens += cache_read;
-                        }
-                    }
-                }
-
-                // Then check for top-level usage (result messages)
-                if let Some(usage) = msg.get("usage") {
-                    if let Some(input) = usage.get("input_tokens").and_then(|t| t.as_u64()) {
-                        total_tokens += input;
-                    }
-                    if let Some(output) = usage.get("output_tokens").and_then(|t| t.as_u64()) {
-                        total_tokens += output;
-                    }
-                    // Also count cache tokens
-                    if let Some(cache_creation) = usage
-                        .get("cache_creation_input_tokens")
-                        .and_then(|t| t.as_u64())
-                    {
-                        total_tokens += cache_creation;
-                    }
-                    if let Some(cache_read) = usage
-                        .get("cache_read_input_tokens")
-                        .and_then(|t| t.as_u64())
-                    {
-                        total_tokens += cache_read;
-                    }
-                }
+                total_tokens += message_tokens(&msg);
             }
         }
 
         Ok((user_prompt, model_used, total_tokens))
     }
 
-    /// Create file snapshots for all tracked modified files
-    async fn create_file_snapshots(&self, checkpoint_id: &str) -> Result<Vec<FileSnapshot>> {
+    /// Create file snapshots for all tracked modified files.
+    ///
+    /// Files that exist but are not in `included` (ignored or oversized) are
+    /// left out even if a tool touched them.
+    async fn create_file_snapshots(
+        &self,
+        checkpoint_id: &str,
+        included: &HashSet<PathBuf>,
+    ) -> Result<Vec<FileSnapshot>> {
         let tracker = self.file_tracker.read().await;
         let mut snapshots = Vec::new();
 
@@ -408,161 +518,317 @@ impl CheckpointManager {
                 continue;
             }
 
-            let full_path = self.project_path.join(rel_path);
-
-            let (content, exists, permissions, size, current_hash) = if full_path.exists() {
-                let content = fs::read_to_string(&full_path).unwrap_or_default();
-                let current_hash = storage::CheckpointStorage::calculate_file_hash(&content);
-
-                // Don't skip based on hash - if is_modified is true, we should snapshot it
-                // The hash check in track_file_modification already determined if it changed
+            // Symlinks are read as links, so a dangling one still exists
+            let entry = walker::read_entry(&self.project_path.join(rel_path))?;
+            if entry.is_some() && !included.contains(rel_path) {
+                continue;
+            }
 
-                let metadata = fs::metadata(&full_path)?;
-                let permissions = {
-                    #[cfg(unix)]
-                    {
-                        use std::os::unix::fs::PermissionsExt;
-                        Some(metadata.permissions().mode())
-                    }
-                    #[cfg(not(unix))]
-                    {
-                        None
-                    }
-                };
-                (content, true, permissions, metadata.len(), current_hash)
-            } else {
-                (String::new(), false, None, 0, String::new())
+            // Don't skip based on hash - if is_modified is true, we should snapshot it
+            // The hash check in track_file_modification already determined if it changed
+            let snapshot = match entry {
+                Some(entry) => FileSnapshot {
+                    checkpoint_id: checkpoint_id.to_string(),
+                    file_path: rel_path.clone(),
+                    hash: storage::CheckpointStorage::calculate_file_hash(&entry.content),
+                    size: entry.content.len() as u64,
+                    content: entry.content,
+                    is_deleted: false,
+                    permissions: entry.mode,
+                    kind: entry.kind,
+                },
+                None => FileSnapshot {
+                    checkpoint_id: checkpoint_id.to_string(),
+                    file_path: rel_path.clone(),
+                    content: Vec::new(),
+                    hash: String::new(),
+                    is_deleted: true,
+                    permissions: None,
+                    size: 0,
+                    kind: FileKind::File,
+                },
             };
-
-            snapshots.push(FileSnapshot {
-                checkpoint_id: checkpoint_id.to_string(),
-                file_path: rel_path.clone(),
-                content,
-                hash: current_hash,
-                is_deleted: !exists,
-                permissions,
-                size,
-            });
+            snapshots.push(snapshot);
         }
 
         Ok(snapshots)
     }
 
-    /// Restore a checkpoint
-    pub async fn restore_checkpoint(&self, checkpoint_id: &str) -> Result<CheckpointResult> {
-        // Load checkpoint data
-        let (checkpoint, file_snapshots, messages) =
-            self.storage
-                .load_checkpoint(&self.project_id, &self.session_id, checkpoint_id)?;
+    /// Restore a checkpoint.
+    ///
+    /// The working tree and conversation are first saved as a pre-restore
+    /// checkpoint (unless they still match the current checkpoint), so the
+    /// restore can be reverted with [`undo_last_restore`](Self::undo_last_restore).
+    ///
+    /// If the checkpoint was taken on another branch or commit than the one
+    /// checked out now, the session's [`GitMismatchPolicy`] decides whether
+    /// to warn or refuse; `force` restores anyway.
+    pub async fn restore_checkpoint(
+        &self,
+        checkpoint_id: &str,
+        force: bool,
+    ) -> Result<CheckpointResult> {
+        let loaded = self.load_restorable(checkpoint_id)?;
+        let git_warnings = self.check_git_state(checkpoint_id, force).await?;
 
-        // First, collect all files currently in the project to handle deletions
-        fn collect_all_project_files(
-            dir: &std::path::Path,
-            base: &std::path::Path,
-            files: &mut Vec<std::path::PathBuf>,
-        ) -> Result<(), std::io::Error> {
-            for entry in std::fs::read_dir(dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_dir() {
-                    // Skip hidden directories like .git
-                    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                        if name.starts_with('.') {
-                            continue;
-                        }
-                    }
-                    collect_all_project_files(&path, base, files)?;
-                } else if path.is_file() {
-                    // Compute relative path from project root
-                    if let Ok(rel) = path.strip_prefix(base) {
-                        files.push(rel.to_path_buf());
-                    }
-                }
+        let description = format!(
+            "Before restoring checkpoint {}",
+            checkpoint_id.get(..8).unwrap_or(checkpoint_id)
+        );
+        let pre_restore_checkpoint_id = self.save_pre_restore_state(description).await?;
+        let mut result = self
+            .switch_to_checkpoint(loaded, pre_restore_checkpoint_id)
+            .await?;
+        result.warnings.splice(0..0, git_warnings);
+        Ok(result)
+    }
+
+    /// Continue from a checkpoint in a new Claude session, leaving this
+    /// session's timeline, current checkpoint and conversation untouched.
+    ///
+    /// The checkpoint's files are written to the working tree and its
+    /// conversation to a new session, returned in the result's `session_id`,
+    /// whose timeline starts from a copy of the checkpoint. As nothing saves
+    /// the working state first, changes since this session's current
+    /// checkpoint are refused unless `force` is set.
+    pub async fn restore_in_new_session(
+        &self,
+        checkpoint_id: &str,
+        force: bool,
+    ) -> Result<CheckpointResult> {
+        let (checkpoint, file_snapshots, messages) = self.load_restorable(checkpoint_id)?;
+        let git_warnings = self.check_git_state(checkpoint_id, force).await?;
+
+        let current_checkpoint_id = self.timeline.read().await.current_checkpoint_id.clone();
+        if let Some(current_id) = current_checkpoint_id.filter(|_| !force) {
+            let (_, current_files, _) =
+                self.storage
+                    .load_checkpoint(&self.project_id, &self.session_id, &current_id)?;
+            if !self
+                .plan_restore(&current_id, &current_files, None)
+                .await?
+                .is_empty()
+            {
+                anyhow::bail!(
+                    "The project has changes since checkpoint {}; create a checkpoint first or force the restore",
+                    current_id.get(..8).unwrap_or(&current_id)
+                );
             }
-            Ok(())
         }
 
-        let mut current_files = Vec::new();
-        let _ =
-            collect_all_project_files(&self.project_path, &self.project_path, &mut current_files);
+        let plan = self
+            .plan_restore(checkpoint_id, &file_snapshots, None)
+            .await?;
 
-        // Create a set of files that should exist after restore
-        let mut checkpoint_files = std::collections::HashSet::new();
-        for snapshot in &file_snapshots {
-            if !snapshot.is_deleted {
-                checkpoint_files.insert(snapshot.file_path.clone());
-            }
+        // Seed the new session before touching the working tree, so a failure
+        // leaves the project as it was
+        let session_id = uuid::Uuid::new_v4().to_string();
+        rewind::seed_rewound_timeline(
+            &self.storage,
+            &checkpoint,
+            file_snapshots.clone(),
+            &messages,
+            &session_id,
+        )
+        .context("Failed to seed the new session's timeline")?;
+
+        let (files_processed, mut warnings) = self.apply_restore_plan(&plan, &file_snapshots).await;
+        rewind::write_rewound_session(
+            &self.storage.claude_dir,
+            &checkpoint,
+            &messages,
+            &session_id,
+        )?;
+
+        warnings.splice(0..0, git_warnings);
+        Ok(CheckpointResult {
+            checkpoint,
+            files_processed,
+            warnings,
+            session_id: Some(session_id),
+            retention: None,
+        })
+    }
+
+    /// Capture the repository state of the project on the blocking pool, as it
+    /// runs several git subprocesses
+    fn capture_git_state(&self) -> impl std::future::Future<Output = Option<GitState>> {
+        let project_path = self.project_path.clone();
+        let task = tokio::task::spawn_blocking(move || GitState::capture(&project_path));
+        async move {
+            task.await.unwrap_or_else(|e| {
+                log::warn!("Failed to capture git state: {}", e);
+                None
+            })
         }
+    }
 
-        // Delete files that exist now but shouldn't exist in the checkpoint
-        let mut warnings = Vec::new();
-        let mut files_processed = 0;
+    /// Compare the repository state recorded in a checkpoint with the current
+    /// one, returning the differences as warnings or failing when the policy
+    /// refuses them
+    async fn check_git_state(&self, checkpoint_id: &str, force: bool) -> Result<Vec<String>> {
+        let (policy, recorded) = {
+            let timeline = self.timeline.read().await;
+            let recorded = timeline
+                .find_checkpoint(checkpoint_id)
+                .and_then(|node| node.checkpoint.metadata.git.clone());
+            (timeline.git_mismatch_policy, recorded)
+        };
+        // Checkpoints taken outside a repository, or before git state was
+        // recorded, have nothing to compare against
+        let recorded = match recorded {
+            Some(recorded) if policy != GitMismatchPolicy::Ignore => recorded,
+            _ => return Ok(Vec::new()),
+        };
 
-        for current_file in current_files {
-            if !checkpoint_files.contains(&current_file) {
-                // This file exists now but not in the checkpoint, so delete it
-                let full_path = self.project_path.join(&current_file);
-                match fs::remove_file(&full_path) {
-                    Ok(_) => {
-                        files_processed += 1;
-                        log::info!("Deleted file not in checkpoint: {:?}", current_file);
-                    }
-                    Err(e) => {
-                        warnings.push(format!(
-                            "Failed to delete {}: {}",
-                            current_file.display(),
-                            e
-                        ));
-                    }
-                }
-            }
+        let mismatches = recorded.mismatches(self.capture_git_state().await.as_ref());
+        if !mismatches.is_empty() && policy == GitMismatchPolicy::Refuse && !force {
+            anyhow::bail!(
+                "{}; check out the checkpoint's commit first or force the restore",
+                mismatches.join("; ")
+            );
         }
+        Ok(mismatches)
+    }
 
-        // Clean up empty directories
-        fn remove_empty_dirs(
-            dir: &std::path::Path,
-            base: &std::path::Path,
-        ) -> Result<bool, std::io::Error> {
-            if dir == base {
-                return Ok(false); // Don't remove the base directory
+    /// Three-way merge `theirs_id` into `ours_id` and continue from the merge
+    /// checkpoint.
+    ///
+    /// As with a restore, the working state is saved first, so the merge can
+    /// be reverted with [`undo_last_restore`](Self::undo_last_restore), and
+    /// the session's [`GitMismatchPolicy`] applies to both checkpoints.
+    /// Conflicting files are written with conflict markers.
+    pub async fn merge_checkpoints(
+        &self,
+        ours_id: &str,
+        theirs_id: &str,
+        base_id: Option<&str>,
+        force: bool,
+    ) -> Result<MergeResult> {
+        self.ensure_restorable(ours_id)?;
+        self.ensure_restorable(theirs_id)?;
+        let mut git_warnings = self.check_git_state(ours_id, force).await?;
+        for warning in self.check_git_state(theirs_id, force).await? {
+            if !git_warnings.contains(&warning) {
+                git_warnings.push(warning);
             }
+        }
 
-            let mut is_empty = true;
-            for entry in fs::read_dir(dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_dir() {
-                    if !remove_empty_dirs(&path, base)? {
-                        is_empty = false;
-                    }
-                } else {
-                    is_empty = false;
-                }
-            }
+        let description = format!(
+            "Before merging {} into {}",
+            theirs_id.get(..8).unwrap_or(theirs_id),
+            ours_id.get(..8).unwrap_or(ours_id)
+        );
+        let pre_restore_checkpoint_id = self.save_pre_restore_state(description).await?;
+        let mut merge = self.storage.merge_checkpoints(
+            &self.project_id,
+            &self.session_id,
+            ours_id,
+            theirs_id,
+            base_id,
+        )?;
+        let loaded = self.load_restorable(&merge.checkpoint.id)?;
+        let result = self
+            .switch_to_checkpoint(loaded, pre_restore_checkpoint_id)
+            .await?;
+        merge.warnings = git_warnings;
+        merge.warnings.extend(result.warnings);
+        Ok(merge)
+    }
 
-            if is_empty {
-                fs::remove_dir(dir)?;
-                Ok(true)
-            } else {
-                Ok(false)
+    /// Apply a checkpoint to the working tree and conversation and make it
+    /// the current one, recording how to undo the switch
+    async fn switch_to_checkpoint(
+        &self,
+        loaded: LoadedCheckpoint,
+        pre_restore_checkpoint_id: String,
+    ) -> Result<CheckpointResult> {
+        let checkpoint_id = loaded.0.id.clone();
+        let result = self.apply_checkpoint(loaded).await?;
+
+        // Persist the new position so the restore survives a restart and can be undone
+        self.update_timeline(|timeline| {
+            timeline.current_checkpoint_id = Some(checkpoint_id.to_string());
+            timeline.last_restore = Some(RestoreRecord {
+                restored_checkpoint_id: checkpoint_id.to_string(),
+                pre_restore_checkpoint_id,
+                timestamp: Utc::now(),
+            });
+        })
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Return to the working state saved before the last restore.
+    ///
+    /// The undo is itself a restore, so undoing twice returns to the
+    /// checkpoint that was originally restored.
+    pub async fn undo_last_restore(&self, force: bool) -> Result<CheckpointResult> {
+        let last_restore = self.timeline.read().await.last_restore.clone();
+        match last_restore {
+            Some(record) => {
+                self.restore_checkpoint(&record.pre_restore_checkpoint_id, force)
+                    .await
             }
+            None => anyhow::bail!("There is no restore to undo"),
         }
+    }
 
-        // Clean up any empty directories left after file deletion
-        let _ = remove_empty_dirs(&self.project_path, &self.project_path);
-
-        // Restore files from checkpoint
-        for snapshot in &file_snapshots {
-            match self.restore_file_snapshot(snapshot).await {
-                Ok(_) => files_processed += 1,
-                Err(e) => warnings.push(format!(
-                    "Failed to restore {}: {}",
-                    snapshot.file_path.display(),
-                    e
-                )),
+    /// Make sure the current working state can be returned to after a
+    /// restore, returning the checkpoint that holds it
+    async fn save_pre_restore_state(&self, description: String) -> Result<String> {
+        let current_checkpoint_id = self.timeline.read().await.current_checkpoint_id.clone();
+        if let Some(current_id) = current_checkpoint_id {
+            if self.matches_checkpoint(&current_id).await {
+                return Ok(current_id);
             }
         }
 
+        let result = self
+            .create_checkpoint_of_kind(Some(description), None, CheckpointKind::PreRestore)
+            .await
+            .context("Failed to save the working state before restoring")?;
+        Ok(result.checkpoint.id)
+    }
+
+    /// Whether the working tree and conversation are exactly those of a checkpoint
+    async fn matches_checkpoint(&self, checkpoint_id: &str) -> bool {
+        let (_, file_snapshots, messages) =
+            match self
+                .storage
+                .load_checkpoint(&self.project_id, &self.session_id, checkpoint_id)
+            {
+                Ok(data) => data,
+                Err(_) => return false,
+            };
+
+        let same_files = match self
+            .plan_restore(checkpoint_id, &file_snapshots, None)
+            .await
+        {
+            Ok(plan) => plan.is_empty(),
+            Err(_) => false,
+        };
+        same_files
+            && self
+                .current_messages
+                .read()
+                .await
+                .iter()
+                .eq(messages.lines())
+    }
+
+    /// Replace the working tree and conversation with a checkpoint's
+    async fn apply_checkpoint(&self, loaded: LoadedCheckpoint) -> Result<CheckpointResult> {
+        let (checkpoint, file_snapshots, messages) = loaded;
+
+        // Work out which files to create, overwrite and delete, then apply it
+        let plan = self
+            .plan_restore(&checkpoint.id, &file_snapshots, None)
+            .await?;
+        let (files_processed, warnings) = self.apply_restore_plan(&plan, &file_snapshots).await;
+
         // Update current messages
         let mut current_messages = self.current_messages.write().await;
         current_messages.clear();
@@ -570,10 +836,6 @@ impl CheckpointManager {
             current_messages.push(line.to_string());
         }
 
-        // Update timeline
-        let mut timeline = self.timeline.write().await;
-        timeline.current_checkpoint_id = Some(checkpoint_id.to_string());
-
         // Update file tracker
         let mut tracker = self.file_tracker.write().await;
         tracker.tracked_files.clear();
@@ -586,6 +848,8 @@ impl CheckpointManager {
                         is_modified: false,
                         last_modified: Utc::now(),
                         exists: true,
+                        kind: snapshot.kind,
+                        mode: snapshot.permissions.map(|mode| mode & 0o7777),
                     },
                 );
             }
@@ -595,35 +859,201 @@ impl CheckpointManager {
             checkpoint: checkpoint.clone(),
             files_processed,
             warnings,
+            session_id: None,
+            retention: None,
+        })
+    }
+
+    /// Restore only the files selected by `patterns` from a checkpoint.
+    ///
+    /// Matching files are rewritten from the snapshot, and matching files that
+    /// did not exist at the checkpoint are deleted. Everything else in the
+    /// working tree and the conversation is left untouched. As with a full
+    /// restore, the working state is saved first, so the restore can be
+    /// reverted with [`undo_last_restore`](Self::undo_last_restore), and the
+    /// session's [`GitMismatchPolicy`] applies, unless `force` is set.
+    pub async fn restore_paths(
+        &self,
+        checkpoint_id: &str,
+        patterns: &[String],
+        force: bool,
+    ) -> Result<CheckpointResult> {
+        let filter = PathFilter::new(patterns)?;
+        let (checkpoint, file_snapshots, _) = self.load_restorable(checkpoint_id)?;
+        let git_warnings = self.check_git_state(checkpoint_id, force).await?;
+
+        let plan = self
+            .plan_restore(checkpoint_id, &file_snapshots, Some(&filter))
+            .await?;
+        let (files_processed, mut warnings) = if plan.is_empty() {
+            (0, Vec::new())
+        } else {
+            let description = format!(
+                "Before restoring files from checkpoint {}",
+                checkpoint_id.get(..8).unwrap_or(checkpoint_id)
+            );
+            let pre_restore_checkpoint_id = self.save_pre_restore_state(description).await?;
+            let applied = self.apply_restore_plan(&plan, &file_snapshots).await;
+            self.update_timeline(|timeline| {
+                timeline.last_restore = Some(RestoreRecord {
+                    restored_checkpoint_id: checkpoint_id.to_string(),
+                    pre_restore_checkpoint_id,
+                    timestamp: Utc::now(),
+                });
+            })
+            .await?;
+            applied
+        };
+
+        if files_processed == 0 && warnings.is_empty() && plan.unchanged == 0 {
+            warnings.push("No files matched the given paths".to_string());
+        }
+        warnings.splice(0..0, git_warnings);
+
+        // The working tree now differs from the current checkpoint for these files
+        for path in plan.changed_paths() {
+            if let Some(p) = path.to_str() {
+                let _ = self.track_file_modification(p).await;
+            }
+        }
+
+        Ok(CheckpointResult {
+            checkpoint,
+            files_processed,
+            warnings,
+            session_id: None,
+            retention: None,
         })
     }
 
+    /// Describe what restoring a checkpoint would change without touching anything.
+    ///
+    /// With `patterns`, the preview covers the same selective restore as
+    /// [`restore_paths`](Self::restore_paths).
+    pub async fn preview_restore(
+        &self,
+        checkpoint_id: &str,
+        patterns: Option<&[String]>,
+    ) -> Result<RestorePlan> {
+        let filter = patterns.map(PathFilter::new).transpose()?;
+        let (_, file_snapshots, _) =
+            self.storage
+                .load_checkpoint(&self.project_id, &self.session_id, checkpoint_id)?;
+
+        self.plan_restore(checkpoint_id, &file_snapshots, filter.as_ref())
+            .await
+    }
+
+    /// Build a restore plan against the current working tree
+    async fn plan_restore(
+        &self,
+        checkpoint_id: &str,
+        file_snapshots: &[FileSnapshot],
+        filter: Option<&PathFilter>,
+    ) -> Result<RestorePlan> {
+        // Files captured by the current checkpoint can be recovered after the
+        // restore; anything else on disk is untracked and would be lost
+        let (current_checkpoint_id, max_file_size) = {
+            let timeline = self.timeline.read().await;
+            (
+                timeline.current_checkpoint_id.clone(),
+                timeline.max_file_size,
+            )
+        };
+        let mut tracked = HashSet::new();
+        if let Some(current_id) = current_checkpoint_id {
+            if let Some(manifest) =
+                self.storage
+                    .load_manifest(&self.project_id, &self.session_id, &current_id)?
+            {
+                tracked.extend(manifest.live_paths().cloned());
+            }
+        }
+
+        restore::plan_restore(
+            &self.project_path,
+            checkpoint_id,
+            file_snapshots,
+            &tracked,
+            filter,
+            max_file_size,
+        )
+    }
+
+    /// Apply a restore plan to the working tree, returning the number of
+    /// files changed and any warnings
+    async fn apply_restore_plan(
+        &self,
+        plan: &RestorePlan,
+        file_snapshots: &[FileSnapshot],
+    ) -> (usize, Vec<String>) {
+        let mut warnings = Vec::new();
+        let mut files_processed = 0;
+
+        // Delete files that exist now but shouldn't exist in the checkpoint
+        for diff in plan.delete.iter().chain(&plan.delete_untracked) {
+            let full_path = self.project_path.join(&diff.path);
+            match fs::remove_file(&full_path) {
+                Ok(_) => {
+                    files_processed += 1;
+                    log::info!("Deleted file not in checkpoint: {:?}", diff.path);
+                }
+                Err(e) => warnings.push(format!("Failed to delete {}: {}", diff.path.display(), e)),
+            }
+        }
+
+        // Clean up directories left empty, deepest first
+        for dir in plan.prune_dirs.iter().rev() {
+            let _ = fs::remove_dir(self.project_path.join(dir));
+        }
+
+        // Restore files from checkpoint
+        let snapshots: HashMap<&PathBuf, &FileSnapshot> = file_snapshots
+            .iter()
+            .map(|snapshot| (&snapshot.file_path, snapshot))
+            .collect();
+        for diff in plan.create.iter().chain(&plan.overwrite) {
+            let snapshot = match snapshots.get(&diff.path) {
+                Some(snapshot) => snapshot,
+                None => continue,
+            };
+            match self.restore_file_snapshot(snapshot).await {
+                Ok(_) => files_processed += 1,
+                Err(e) => warnings.push(format!(
+                    "Failed to restore {}: {}",
+                    snapshot.file_path.display(),
+                    e
+                )),
+            }
+        }
+
+        (files_processed, warnings)
+    }
+
+    /// Load a checkpoint to restore, failing if it is missing data it needs
+    /// to be restored completely
+    fn load_restorable(&self, checkpoint_id: &str) -> Result<LoadedCheckpoint> {
+        self.storage
+            .load_restorable_checkpoint(&self.project_id, &self.session_id, checkpoint_id)
+    }
+
+    /// Fail if a checkpoint is missing data it needs to be restored completely
+    fn ensure_restorable(&self, checkpoint_id: &str) -> Result<()> {
+        self.storage
+            .ensure_restorable(&self.project_id, &self.session_id, checkpoint_id)
+    }
+
     /// Restore a single file from snapshot
     async fn restore_file_snapshot(&self, snapshot: &FileSnapshot) -> Result<()> {
         let full_path = self.project_path.join(&snapshot.file_path);
 
         if snapshot.is_deleted {
             // Delete the file if it exists
-            if full_path.exists() {
+            if fs::symlink_metadata(&full_path).is_ok() {
                 fs::remove_file(&full_path).context("Failed to delete file")?;
             }
         } else {
-            // Create parent directories if needed
-            if let Some(parent) = full_path.parent() {
-                fs::create_dir_all(parent).context("Failed to create parent directories")?;
-            }
-
-            // Write file content
-            fs::write(&full_path, &snapshot.content).context("Failed to write file")?;
-
-            // Restore permissions if available
-            #[cfg(unix)]
-            if let Some(mode) = snapshot.permissions {
-                use std::os::unix::fs::PermissionsExt;
-                let permissions = std::fs::Permissions::from_mode(mode);
-                fs::set_permissions(&full_path, permissions)
-                    .context("Failed to set file permissions")?;
-            }
+            restore::write_entry(&full_path, snapshot)?;
         }
 
         Ok(())
@@ -634,29 +1064,28 @@ impl CheckpointManager {
         self.timeline.read().await.clone()
     }
 
-    /// List all checkpoints
+    /// List all checkpoints, from the project's checkpoint index
     pub async fn list_checkpoints(&self) -> Vec<Checkpoint> {
+        match self
+            .storage
+            .list_checkpoints(&self.project_id, &self.session_id)
+        {
+            Ok(checkpoints) => return checkpoints,
+            Err(e) => log::warn!(
+                "Listing checkpoints of session {} without the index: {:#}",
+                self.session_id,
+                e
+            ),
+        }
+
         let timeline = self.timeline.read().await;
         let mut checkpoints = Vec::new();
-
         if let Some(root) = &timeline.root_node {
-            Self::collect_checkpoints_from_node(root, &mut checkpoints);
+            CheckpointStorage::collect_checkpoints(root, &mut checkpoints);
         }
-
         checkpoints
     }
 
-    /// Recursively collect checkpoints from timeline tree
-    fn collect_checkpoints_from_node(
-        node: &super::TimelineNode,
-        checkpoints: &mut Vec<Checkpoint>,
-    ) {
-        checkpoints.push(node.checkpoint.clone());
-        for child in &node.children {
-            Self::collect_checkpoints_from_node(child, checkpoints);
-        }
-    }
-
     /// Fork from a checkpoint
     pub async fn fork_from_checkpoint(
         &self,
@@ -669,7 +1098,7 @@ impl CheckpointManager {
                 .load_checkpoint(&self.project_id, &self.session_id, checkpoint_id)?;
 
         // Restore to that checkpoint first
-        self.restore_checkpoint(checkpoint_id).await?;
+        self.restore_checkpoint(checkpoint_id, false).await?;
 
         // Create a new checkpoint with the fork
         let fork_description =
@@ -681,14 +1110,23 @@ impl CheckpointManager {
 
     /// Check if auto-checkpoint should be triggered
     pub async fn should_auto_checkpoint(&self, message: &str) -> bool {
-        let timeline = self.timeline.read().await;
-
-        if !timeline.auto_checkpoint_enabled {
-            return false;
-        }
+        let (strategy, rules) = {
+            let timeline = self.timeline.read().await;
+            if !timeline.auto_checkpoint_enabled {
+                return false;
+            }
+            (
+                timeline.checkpoint_strategy.clone(),
+                timeline.auto_checkpoint_rules.clone(),
+            )
+        };
 
-        match timeline.checkpoint_strategy {
+        match strategy {
             CheckpointStrategy::Manual => false,
+            CheckpointStrategy::Rules => {
+                let progress = self.rule_progress(rules.needs_line_counts()).await;
+                rules.is_met(&progress)
+            }
             CheckpointStrategy::PerPrompt => {
                 // Check if message is a user prompt
                 if let Ok(msg) = serde_json::from_str::<serde_json::Value>(message) {
@@ -715,6 +1153,14 @@ impl CheckpointManager {
                     false
                 }
             }
+            CheckpointStrategy::Smart if self.watcher.is_some() => {
+                // Checkpoint once files have actually changed on disk
+                let tracker = self.file_tracker.read().await;
+                tracker
+                    .tracked_files
+                    .values()
+                    .any(|state| state.is_modified)
+            }
             CheckpointStrategy::Smart => {
                 // Smart strategy: checkpoint after destructive operations
                 if let Ok(msg) = serde_json::from_str::<serde_json::Value>(message) {
@@ -745,28 +1191,125 @@ impl CheckpointManager {
         }
     }
 
-    /// Update checkpoint settings
+    /// Measure what happened since the current checkpoint, for the `Rules`
+    /// strategy.
+    ///
+    /// A file counts as changed when its content, type or permissions differ
+    /// from the checkpoint, not merely because the tracker flagged it.
+    /// Changed lines are only counted with `count_lines`, as that diffs every
+    /// changed file against the checkpoint.
+    pub async fn rule_progress(&self, count_lines: bool) -> RuleProgress {
+        let (since, first_new_message, checkpoint_id) = {
+            let timeline = self.timeline.read().await;
+            match timeline
+                .current_checkpoint_id
+                .as_ref()
+                .and_then(|id| timeline.find_checkpoint(id))
+            {
+                Some(node) => (
+                    node.checkpoint.timestamp,
+                    node.checkpoint.message_index + 1,
+                    Some(node.checkpoint.id.clone()),
+                ),
+                None => (self.started_at, 0, None),
+            }
+        };
+
+        let tokens = {
+            let messages = self.current_messages.read().await;
+            messages[first_new_message.min(messages.len())..]
+                .iter()
+                .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+                .map(|message| message_tokens(&message))
+                .sum()
+        };
+
+        // The bash heuristic flags files it never looked at, so the flagged
+        // files are only candidates, compared with the checkpoint by hash
+        let candidates: Vec<PathBuf> = {
+            let tracker = self.file_tracker.read().await;
+            tracker
+                .tracked_files
+                .iter()
+                .filter(|(_, state)| state.is_modified)
+                .map(|(path, _)| path.clone())
+                .collect()
+        };
+
+        let storage = Arc::clone(&self.storage);
+        let (project_id, session_id) = (self.project_id.clone(), self.session_id.clone());
+        let project_path = self.project_path.clone();
+        let (changed_files, changed_lines) = tokio::task::spawn_blocking(move || {
+            measure_changes(
+                &storage,
+                &project_id,
+                &session_id,
+                checkpoint_id.as_deref(),
+                &project_path,
+                &candidates,
+                count_lines,
+            )
+        })
+        .await
+        .unwrap_or_else(|e| {
+            log::warn!("Failed to measure changes since the checkpoint: {}", e);
+            (0, 0)
+        });
+
+        RuleProgress {
+            elapsed_seconds: (Utc::now() - since).num_seconds().max(0) as u64,
+            tokens,
+            changed_files,
+            changed_lines,
+        }
+    }
+
+    /// Update checkpoint settings, keeping the current file size limit,
+    /// auto-checkpoint rules and git mismatch policy when they are `None`
     pub async fn update_settings(
         &self,
         auto_checkpoint_enabled: bool,
         checkpoint_strategy: CheckpointStrategy,
+        max_file_size: Option<u64>,
+        auto_checkpoint_rules: Option<AutoCheckpointRules>,
+        git_mismatch_policy: Option<GitMismatchPolicy>,
     ) -> Result<()> {
-        let mut timeline = self.timeline.write().await;
-        timeline.auto_checkpoint_enabled = auto_checkpoint_enabled;
-        timeline.checkpoint_strategy = checkpoint_strategy;
-
-        // Save updated timeline
-        let claude_dir = self.storage.claude_dir.clone();
-        let paths = CheckpointPaths::new(&claude_dir, &self.project_id, &self.session_id);
-        self.storage
-            .save_timeline(&paths.timeline_file, &timeline)?;
-
-        Ok(())
+        self.update_timeline(|timeline| {
+            timeline.auto_checkpoint_enabled = auto_checkpoint_enabled;
+            timeline.checkpoint_strategy = checkpoint_strategy;
+            if let Some(max_file_size) = max_file_size {
+                timeline.max_file_size = max_file_size;
+            }
+            if let Some(auto_checkpoint_rules) = auto_checkpoint_rules {
+                timeline.auto_checkpoint_rules = auto_checkpoint_rules;
+            }
+            if let Some(git_mismatch_policy) = git_mismatch_policy {
+                timeline.git_mismatch_policy = git_mismatch_policy;
+            }
+        })
+        .await
     }
 
     /// Get files modified since a given timestamp
     pub async fn get_files_modified_since(&self, since: DateTime<Utc>) -> Vec<PathBuf> {
         let tracker = self.file_tracker.read().await;
+        if self.watcher.is_some() {
+            // Every path touched by an observed change, including rename sources
+            let mut paths = Vec::new();
+            for event in tracker
+                .events
+                .iter()
+                .filter(|event| event.timestamp > since)
+            {
+                for path in std::iter::once(&event.path).chain(&event.renamed_from) {
+                    if !paths.contains(path) {
+                        paths.push(path.clone());
+                    }
+                }
+            }
+            return paths;
+        }
+
         tracker
             .tracked_files
             .iter()
@@ -775,6 +1318,17 @@ impl CheckpointManager {
             .collect()
     }
 
+    /// Changes observed on disk since a given timestamp, oldest first
+    pub async fn get_file_events_since(&self, since: DateTime<Utc>) -> Vec<FileEvent> {
+        let tracker = self.file_tracker.read().await;
+        tracker
+            .events
+            .iter()
+            .filter(|event| event.timestamp > since)
+            .cloned()
+            .collect()
+    }
+
     /// Get the last modification time of any tracked file
     pub async fn get_last_modification_time(&self) -> Option<DateTime<Utc>> {
         let tracker = self.file_tracker.read().await;
@@ -785,3 +1339,385 @@ impl CheckpointManager {
             .max()
     }
 }
+
+/// Count the files among `candidates` that differ from the checkpoint's
+/// manifest, and the lines added or removed in them when `count_lines` is
+/// set. Before the first checkpoint every candidate that exists is new.
+fn measure_changes(
+    storage: &CheckpointStorage,
+    project_id: &str,
+    session_id: &str,
+    checkpoint_id: Option<&str>,
+    project_path: &Path,
+    candidates: &[PathBuf],
+    count_lines: bool,
+) -> (usize, usize) {
+    let paths = CheckpointPaths::new(&storage.claude_dir, project_id, session_id);
+    let manifest = checkpoint_id.and_then(|id| {
+        storage
+            .load_manifest(project_id, session_id, id)
+            .ok()
+            .flatten()
+    });
+
+    let mut changed_files = 0;
+    let mut changed_lines = 0;
+    for path in candidates {
+        let recorded = manifest
+            .as_ref()
+            .and_then(|manifest| manifest.files.get(path))
+            .filter(|entry| !entry.deleted);
+        let current = walker::read_entry(&project_path.join(path)).ok().flatten();
+        let changed = match (recorded, &current) {
+            (Some(entry), Some(current)) => {
+                entry.kind != current.kind
+                    || entry.mode != current.mode
+                    || entry.hash != CheckpointStorage::calculate_file_hash(&current.content)
+            }
+            (None, None) => false,
+            _ => true,
+        };
+        if !changed {
+            continue;
+        }
+
+        changed_files += 1;
+        if count_lines {
+            let old = recorded
+                .and_then(|entry| storage.read_content(&paths, &entry.hash).ok())
+                .unwrap_or_default();
+            let new = current.map(|current| current.content).unwrap_or_default();
+            let diff = diff::diff_file(path, &old, &new, 0);
+            changed_lines += diff.additions + diff.deletions;
+        }
+    }
+    (changed_files, changed_lines)
+}
+
+/// Tokens a message reports as used, from both the nested `message.usage`
+/// of assistant messages and the top-level `usage` of result messages
+fn message_tokens(message: &serde_json::Value) -> u64 {
+    message
+        .get("message")
+        .and_then(|m| m.get("usage"))
+        .into_iter()
+        .chain(message.get("usage"))
+        .map(|usage| {
+            [
+                "input_tokens",
+                "output_tokens",
+                "cache_creation_input_tokens",
+                "cache_read_input_tokens",
+            ]
+            .iter()
+            .filter_map(|field| usage.get(field).and_then(|t| t.as_u64()))
+            .sum::<u64>()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    async fn test_manager(temp_dir: &TempDir) -> CheckpointManager {
+        let project_path = temp_dir.path().join("project");
+        fs::create_dir_all(&project_path).unwrap();
+        CheckpointManager::new(
+            "project".to_string(),
+            "session".to_string(),
+            project_path,
+            temp_dir.path().join("claude"),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn write(manager: &CheckpointManager, path: &str, content: &str) {
+        let full_path = manager.project_path.join(path);
+        fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+        fs::write(full_path, content).unwrap();
+    }
+
+    fn read(manager: &CheckpointManager, path: &str) -> Option<String> {
+        fs::read_to_string(manager.project_path.join(path)).ok()
+    }
+
+    #[tokio::test]
+    async fn test_restore_paths_only_touches_selected_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = test_manager(&temp_dir).await;
+
+        write(&manager, "src/lib.rs", "original lib");
+        write(&manager, "src/ui/view.rs", "original view");
+        write(&manager, "README.md", "original readme");
+        let first = manager.create_checkpoint(None, None).await.unwrap();
+
+        write(&manager, "src/lib.rs", "broken lib");
+        write(&manager, "src/ui/view.rs", "broken view");
+        write(&manager, "src/ui/extra.rs", "new file");
+        write(&manager, "README.md", "better readme");
+        let second = manager.create_checkpoint(None, None).await.unwrap();
+
+        let result = manager
+            .restore_paths(&first.checkpoint.id, &["src/ui".to_string()], false)
+            .await
+            .unwrap();
+
+        assert!(result.warnings.is_empty(), "{:?}", result.warnings);
+        assert_eq!(
+            read(&manager, "src/ui/view.rs").as_deref(),
+            Some("original view")
+        );
+        assert_eq!(read(&manager, "src/ui/extra.rs"), None);
+        assert_eq!(read(&manager, "src/lib.rs").as_deref(), Some("broken lib"));
+        assert_eq!(
+            read(&manager, "README.md").as_deref(),
+            Some("better readme")
+        );
+        let timeline = manager.get_timeline().await;
+        assert_eq!(
+            timeline.current_checkpoint_id,
+            Some(second.checkpoint.id.clone())
+        );
+
+        // The working state matched the second checkpoint, which undo returns to
+        let record = timeline.last_restore.unwrap();
+        assert_eq!(record.restored_checkpoint_id, first.checkpoint.id);
+        assert_eq!(record.pre_restore_checkpoint_id, second.checkpoint.id);
+        manager.undo_last_restore(false).await.unwrap();
+        assert_eq!(
+            read(&manager, "src/ui/view.rs").as_deref(),
+            Some("broken view")
+        );
+        assert_eq!(
+            read(&manager, "src/ui/extra.rs").as_deref(),
+            Some("new file")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_in_new_session_leaves_session_alone() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = test_manager(&temp_dir).await;
+        let message = |text: &str| {
+            serde_json::json!({ "type": "user", "sessionId": "session", "message": text })
+                .to_string()
+        };
+
+        write(&manager, "src/lib.rs", "first");
+        manager.track_message(message("one")).await.unwrap();
+        let first = manager.create_checkpoint(None, None).await.unwrap();
+        write(&manager, "src/lib.rs", "second");
+        manager.track_message(message("two")).await.unwrap();
+        let second = manager.create_checkpoint(None, None).await.unwrap();
+
+        // Unsaved work would be lost, as nothing saves it
+        write(&manager, "src/lib.rs", "unsaved");
+        assert!(manager
+            .restore_in_new_session(&first.checkpoint.id, false)
+            .await
+            .is_err());
+        write(&manager, "src/lib.rs", "second");
+
+        let result = manager
+            .restore_in_new_session(&first.checkpoint.id, false)
+            .await
+            .unwrap();
+        assert_eq!(read(&manager, "src/lib.rs").as_deref(), Some("first"));
+
+        let timeline = manager.get_timeline().await;
+        assert_eq!(timeline.current_checkpoint_id, Some(second.checkpoint.id));
+        assert_eq!(timeline.total_checkpoints, 2);
+        assert!(timeline.last_restore.is_none());
+        assert_eq!(manager.current_messages.read().await.len(), 2);
+
+        // The new session starts from a copy of the checkpoint
+        let new_session_id = result.session_id.unwrap();
+        let paths = CheckpointPaths::new(&manager.storage.claude_dir, "project", &new_session_id);
+        let seeded = manager.storage.load_timeline(&paths).unwrap();
+        assert_eq!(seeded.total_checkpoints, 1);
+        let seed_id = seeded.current_checkpoint_id.unwrap();
+        let (_, files, messages) = manager
+            .storage
+            .load_checkpoint("project", &new_session_id, &seed_id)
+            .unwrap();
+        assert_eq!(files[0].content, b"first");
+        assert!(messages.contains(&new_session_id));
+
+        let jsonl = manager
+            .storage
+            .claude_dir
+            .join("projects/project")
+            .join(format!("{}.jsonl", new_session_id));
+        assert_eq!(fs::read_to_string(jsonl).unwrap().lines().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_undo_last_restore_recovers_unsaved_work() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = test_manager(&temp_dir).await;
+
+        write(&manager, "src/lib.rs", "checkpointed");
+        let first = manager.create_checkpoint(None, None).await.unwrap();
+
+        // Edits that were never checkpointed
+        write(&manager, "src/lib.rs", "unsaved work");
+        write(&manager, "notes.txt", "scratch");
+
+        manager
+            .restore_checkpoint(&first.checkpoint.id, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            read(&manager, "src/lib.rs").as_deref(),
+            Some("checkpointed")
+        );
+        assert_eq!(read(&manager, "notes.txt"), None);
+
+        let timeline = manager.get_timeline().await;
+        let record = timeline.last_restore.clone().unwrap();
+        assert_eq!(record.restored_checkpoint_id, first.checkpoint.id);
+        let safety = timeline
+            .find_checkpoint(&record.pre_restore_checkpoint_id)
+            .unwrap();
+        assert_eq!(safety.checkpoint.kind, CheckpointKind::PreRestore);
+
+        manager.undo_last_restore(false).await.unwrap();
+        assert_eq!(
+            read(&manager, "src/lib.rs").as_deref(),
+            Some("unsaved work")
+        );
+        assert_eq!(read(&manager, "notes.txt").as_deref(), Some("scratch"));
+
+        // Undoing again goes back to the restored checkpoint without a new
+        // safety checkpoint, since nothing changed in between
+        let before = manager.list_checkpoints().await.len();
+        manager.undo_last_restore(false).await.unwrap();
+        assert_eq!(
+            read(&manager, "src/lib.rs").as_deref(),
+            Some("checkpointed")
+        );
+        assert_eq!(manager.list_checkpoints().await.len(), before);
+
+        // The restore position is persisted
+        let paths = CheckpointPaths::new(&temp_dir.path().join("claude"), "project", "session");
+        let saved = manager.storage.load_timeline(&paths).unwrap();
+        assert_eq!(
+            saved.current_checkpoint_id.as_deref(),
+            Some(first.checkpoint.id.as_str())
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_restore_preserves_modes_symlinks_and_empty_dirs() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let manager = test_manager(&temp_dir).await;
+        let project = manager.project_path.clone();
+
+        write(&manager, "build.sh", "#!/bin/sh\ncargo build\n");
+        fs::set_permissions(project.join("build.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        write(&manager, "config/default.toml", "debug = false");
+        std::os::unix::fs::symlink("config/default.toml", project.join("config.toml")).unwrap();
+        fs::create_dir_all(project.join("logs")).unwrap();
+        let first = manager.create_checkpoint(None, None).await.unwrap();
+
+        fs::set_permissions(project.join("build.sh"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::remove_file(project.join("config.toml")).unwrap();
+        write(&manager, "config.toml", "debug = true");
+        fs::remove_dir(project.join("logs")).unwrap();
+        manager.create_checkpoint(None, None).await.unwrap();
+
+        manager
+            .restore_checkpoint(&first.checkpoint.id, false)
+            .await
+            .unwrap();
+        let mode = fs::metadata(project.join("build.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(
+            fs::read_link(project.join("config.toml")).unwrap(),
+            PathBuf::from("config/default.toml")
+        );
+        assert!(project.join("logs").is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_rules_strategy_measures_progress_since_checkpoint() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = test_manager(&temp_dir).await;
+        let message = |tokens: u64| {
+            serde_json::json!({
+                "type": "assistant",
+                "message": { "usage": { "input_tokens": tokens, "output_tokens": 10 } }
+            })
+            .to_string()
+        };
+        write(&manager, "src/lib.rs", "a\nb\nc\n");
+        manager.track_file_modification("src/lib.rs").await.unwrap();
+        manager.track_message(message(1000)).await.unwrap();
+        manager.create_checkpoint(None, None).await.unwrap();
+
+        // Only what happens after the checkpoint counts
+        let rules = AutoCheckpointRules {
+            combinator: crate::checkpoint::rules::RuleCombinator::All,
+            tokens: Some(100),
+            changed_lines: Some(2),
+            ..Default::default()
+        };
+        manager
+            .update_settings(true, CheckpointStrategy::Rules, None, Some(rules), None)
+            .await
+            .unwrap();
+        manager.track_message(message(50)).await.unwrap();
+        assert!(!manager.should_auto_checkpoint(&message(50)).await);
+
+        // Commands that may write files flag them, but only content changes count
+        manager
+            .track_bash_side_effects("cat src/lib.rs")
+            .await
+            .unwrap();
+        assert_eq!(manager.rule_progress(true).await.changed_files, 0);
+
+        write(&manager, "src/lib.rs", "a\nB\nc\n");
+        manager.track_file_modification("src/lib.rs").await.unwrap();
+        manager.track_message(message(50)).await.unwrap();
+        let progress = manager.rule_progress(true).await;
+        assert_eq!(progress.tokens, 120);
+        assert_eq!(progress.changed_files, 1);
+        assert_eq!(progress.changed_lines, 2);
+        assert!(manager.should_auto_checkpoint(&message(50)).await);
+    }
+
+    #[tokio::test]
+    async fn test_watcher_reports_changes_made_outside_tools() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = test_manager(&temp_dir).await;
+        if manager.watcher.is_none() {
+            // Watching is unavailable in this environment
+            return;
+        }
+        let since = Utc::now();
+
+        // Written by "another process", not through a tool call
+        write(&manager, "src/generated.rs", "// generated");
+
+        let mut modified = Vec::new();
+        for _ in 0..50 {
+            modified = manager.get_files_modified_since(since).await;
+            if !modified.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(modified, vec![PathBuf::from("src/generated.rs")]);
+        let events = manager.get_file_events_since(since).await;
+        assert_eq!(events[0].kind, crate::checkpoint::FileEventKind::Created);
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{FileKind, FileSnapshot};
+
+/// Current on-disk manifest format version
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// The complete file state of a single checkpoint.
+///
+/// One manifest is written per checkpoint and lists every file that exists
+/// (or was deleted) at that point, keyed by its project-relative path. Content
+/// lives in the content pool and is referenced by hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckpointManifest {
+    /// Format version, bumped on incompatible changes
+    pub version: u32,
+    /// Checkpoint this manifest describes
+    pub checkpoint_id: String,
+    /// File entries keyed by relative path
+    pub files: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+/// A single file entry in a checkpoint manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    /// SHA-256 of the file content (empty for deleted files)
+    pub hash: String,
+    /// File permissions (Unix mode)
+    pub mode: Option<u32>,
+    /// File size in bytes
+    pub size: u64,
+    /// Whether the file was deleted at this checkpoint
+    pub deleted: bool,
+    /// Regular file, symlink or empty directory
+    #[serde(default)]
+    pub kind: FileKind,
+}
+
+impl ManifestEntry {
+    /// The snapshot of the file at `path` this entry describes
+    pub fn to_snapshot(&self, checkpoint_id: &str, path: &Path, content: Vec<u8>) -> FileSnapshot {
+        FileSnapshot {
+            checkpoint_id: checkpoint_id.to_string(),
+            file_path: path.to_path_buf(),
+            content,
+            hash: self.hash.clone(),
+            is_deleted: self.deleted,
+            permissions: self.mode,
+            size: self.size,
+            kind: self.kind,
+        }
+    }
+}
+
+impl CheckpointManifest {
+    /// Create an empty manifest for a checkpoint
+    pub fn new(checkpoint_id: &str) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            checkpoint_id: checkpoint_id.to_string(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Start a child manifest from this one.
+    ///
+    /// Live files carry over unchanged; deletion markers do not, since the file
+    /// was already gone at the parent.
+    pub fn inherit(&self, checkpoint_id: &str) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            checkpoint_id: checkpoint_id.to_string(),
+            files: self
+                .files
+                .iter()
+                .filter(|(_, entry)| !entry.deleted)
+                .map(|(path, entry)| (path.clone(), entry.clone()))
+                .collect(),
+        }
+    }
+
+    /// Record a file snapshot in the manifest
+    pub fn apply_snapshot(&mut self, snapshot: &FileSnapshot) {
+        self.files.insert(
+            snapshot.file_path.clone(),
+            ManifestEntry {
+                hash: snapshot.hash.clone(),
+                mode: snapshot.permissions,
+                size: snapshot.size,
+                deleted: snapshot.is_deleted,
+                kind: snapshot.kind,
+            },
+        );
+    }
+
+    /// Paths of files that exist at this checkpoint
+    pub fn live_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.files
+            .iter()
+            .filter(|(_, entry)| !entry.deleted)
+            .map(|(path, _)| path)
+    }
+
+    /// Load a manifest from disk
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path).context("Failed to read checkpoint manifest")?;
+        let manifest: Self =
+            serde_json::from_str(&json).context("Failed to parse checkpoint manifest")?;
+        if manifest.version > MANIFEST_VERSION {
+            anyhow::bail!(
+                "Unsupported checkpoint manifest version {} (expected <= {})",
+                manifest.version,
+                MANIFEST_VERSION
+            );
+        }
+        Ok(manifest)
+    }
+
+    /// Write the manifest to disk atomically
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create manifests directory")?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .context("Failed to serialize checkpoint manifest")?;
+
+        // Write to a temporary file first so a crash never leaves a truncated manifest
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, json).context("Failed to write checkpoint manifest")?;
+        fs::rename(&tmp_path, path).context("Failed to move checkpoint manifest into place")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,693 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use similar::{capture_diff_slices, Algorithm, DiffTag};
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::ops::Range;
+use std::path::PathBuf;
+
+use super::{
+    is_binary_content,
+    manifest::{CheckpointManifest, ManifestEntry},
+    storage::CheckpointStorage,
+    Checkpoint, CheckpointKind, CheckpointMetadata, CheckpointPaths, FileKind, FileSnapshot,
+    SessionTimeline, TimelineNode,
+};
+
+/// Why a file could not be merged cleanly
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    /// Both sides changed the same lines; the file holds conflict markers
+    Content,
+    /// One side changed the file and the other deleted it; the changed
+    /// version is kept
+    ModifyDelete,
+    /// Both sides changed a binary file, a symlink or a directory; our
+    /// version is kept
+    Binary,
+}
+
+/// A file that needs attention after a merge
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeConflict {
+    pub path: PathBuf,
+    pub kind: ConflictKind,
+    /// Number of conflicting regions marked in the file
+    pub regions: usize,
+}
+
+/// Outcome of merging two checkpoints
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeResult {
+    /// The merge checkpoint, a child of ours with theirs as a merge parent
+    pub checkpoint: Checkpoint,
+    /// Common ancestor the changes of both sides were taken from
+    pub base_checkpoint_id: String,
+    /// Files changed on both sides and combined without conflicts
+    pub merged_files: Vec<PathBuf>,
+    pub conflicts: Vec<MergeConflict>,
+    /// Problems applying the merge, and differences between the repository
+    /// state the merged checkpoints were taken in and the current one
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// How an edge of the timeline graph connects two checkpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeKind {
+    Parent,
+    Merge,
+}
+
+/// An edge from a parent to a child checkpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineEdge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+}
+
+/// The timeline as a DAG, with merges joining branches of the tree
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineGraph {
+    /// Checkpoints in tree order, parents before children
+    pub nodes: Vec<Checkpoint>,
+    pub edges: Vec<TimelineEdge>,
+}
+
+/// Text produced by a line-level three-way merge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedText {
+    pub text: String,
+    /// Regions marked with conflict markers
+    pub conflicts: usize,
+}
+
+impl SessionTimeline {
+    /// The timeline with merge parents as extra edges
+    pub fn graph(&self) -> TimelineGraph {
+        let mut graph = TimelineGraph::default();
+        // Depth-first with an explicit stack, as timelines can be deep
+        let mut stack: Vec<&TimelineNode> = self.root_node.iter().collect();
+        while let Some(node) = stack.pop() {
+            add_to_graph(&node.checkpoint, &mut graph);
+            stack.extend(node.children.iter().rev());
+        }
+        graph
+    }
+
+    /// Nearest checkpoint that both `a` and `b` descend from, following
+    /// merge parents as well as the tree
+    pub fn merge_base(&self, a: &str, b: &str) -> Option<String> {
+        let ancestors_of_a: HashSet<String> = self.ancestors(a).into_iter().collect();
+        self.ancestors(b)
+            .into_iter()
+            .find(|id| ancestors_of_a.contains(id))
+    }
+
+    /// A checkpoint and all its ancestors, nearest first
+    fn ancestors(&self, checkpoint_id: &str) -> Vec<String> {
+        let mut ancestors = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([checkpoint_id.to_string()]);
+        while let Some(id) = queue.pop_front() {
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            if let Some(node) = self.find_checkpoint(&id) {
+                queue.extend(node.checkpoint.parent_checkpoint_id.clone());
+                queue.extend(node.checkpoint.merge_parent_ids.iter().cloned());
+                ancestors.push(id);
+            }
+        }
+        ancestors
+    }
+}
+
+fn add_to_graph(checkpoint: &Checkpoint, graph: &mut TimelineGraph) {
+    if let Some(parent_id) = &checkpoint.parent_checkpoint_id {
+        graph.edges.push(TimelineEdge {
+            from: parent_id.clone(),
+            to: checkpoint.id.clone(),
+            kind: EdgeKind::Parent,
+        });
+    }
+    for parent_id in &checkpoint.merge_parent_ids {
+        graph.edges.push(TimelineEdge {
+            from: parent_id.clone(),
+            to: checkpoint.id.clone(),
+            kind: EdgeKind::Merge,
+        });
+    }
+    graph.nodes.push(checkpoint.clone());
+}
+
+impl CheckpointStorage {
+    /// Three-way merge the files of `theirs_id` into `ours_id` and record the
+    /// result as a merge checkpoint.
+    ///
+    /// Changes are taken relative to `base_id`, or to the nearest common
+    /// ancestor when none is given. Conflicting text regions are written with
+    /// conflict markers and reported. The merge checkpoint continues our
+    /// conversation.
+    pub fn merge_checkpoints(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        ours_id: &str,
+        theirs_id: &str,
+        base_id: Option<&str>,
+    ) -> Result<MergeResult> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        let timeline = self.load_timeline(&paths)?;
+        let ours = match timeline.find_checkpoint(ours_id) {
+            Some(node) => node.checkpoint.clone(),
+            None => anyhow::bail!("Checkpoint {} is not in the timeline", ours_id),
+        };
+        if timeline.find_checkpoint(theirs_id).is_none() {
+            anyhow::bail!("Checkpoint {} is not in the timeline", theirs_id);
+        }
+        let base_id = match base_id {
+            Some(base_id) => base_id.to_string(),
+            None => match timeline.merge_base(ours_id, theirs_id) {
+                Some(base_id) => base_id,
+                None => anyhow::bail!("Checkpoints have no common ancestor"),
+            },
+        };
+        if base_id == theirs_id {
+            anyhow::bail!("Checkpoint {} already contains {}", ours_id, theirs_id);
+        }
+
+        let manifest = |id: &str| -> Result<CheckpointManifest> {
+            self.read_manifest(&paths, id)?
+                .with_context(|| format!("Checkpoint {} has no file manifest", id))
+        };
+        let base = manifest(&base_id)?;
+        let ours_manifest = manifest(ours_id)?;
+        let theirs_manifest = manifest(theirs_id)?;
+
+        let checkpoint_id = Self::generate_checkpoint_id();
+        let labels = MarkerLabels {
+            ours: format!("ours ({})", short_id(ours_id)),
+            base: format!("base ({})", short_id(&base_id)),
+            theirs: format!("theirs ({})", short_id(theirs_id)),
+        };
+
+        let mut all_paths = BTreeSet::new();
+        for manifest in [&base, &ours_manifest, &theirs_manifest] {
+            all_paths.extend(manifest.live_paths().cloned());
+        }
+
+        let mut snapshots = Vec::new();
+        let mut merged_files = Vec::new();
+        let mut conflicts = Vec::new();
+        for path in all_paths {
+            let live = |manifest: &CheckpointManifest| -> Option<ManifestEntry> {
+                manifest.files.get(&path).filter(|e| !e.deleted).cloned()
+            };
+            let (b, o, t) = (live(&base), live(&ours_manifest), live(&theirs_manifest));
+            // Content, type and permissions all count as changes
+            let state = |entry: &Option<ManifestEntry>| {
+                entry
+                    .as_ref()
+                    .map(|e| (e.hash.clone(), e.kind, e.mode.map(|mode| mode & 0o7777)))
+            };
+
+            // Only one side changed the file, or both made the same change
+            if state(&o) == state(&t) || state(&t) == state(&b) {
+                continue;
+            }
+            if state(&o) == state(&b) {
+                snapshots.push(self.merged_snapshot(&paths, &checkpoint_id, &path, t.as_ref())?);
+                continue;
+            }
+
+            // Both sides changed the file differently
+            let (o, t) = match (o, t) {
+                (Some(o), Some(t)) => (o, t),
+                (o, t) => {
+                    // Keep whichever side still has the file
+                    if o.is_none() {
+                        snapshots.push(self.merged_snapshot(
+                            &paths,
+                            &checkpoint_id,
+                            &path,
+                            t.as_ref(),
+                        )?);
+                    }
+                    conflicts.push(MergeConflict {
+                        path,
+                        kind: ConflictKind::ModifyDelete,
+                        regions: 0,
+                    });
+                    continue;
+                }
+            };
+            // Symlinks and directories have nothing to merge line by line
+            if o.kind != FileKind::File || t.kind != FileKind::File {
+                conflicts.push(MergeConflict {
+                    path,
+                    kind: ConflictKind::Binary,
+                    regions: 0,
+                });
+                continue;
+            }
+            let base_content = match &b {
+                Some(b) => self.read_content(&paths, &b.hash)?,
+                None => Vec::new(),
+            };
+            let ours_content = self.read_content(&paths, &o.hash)?;
+            let theirs_content = self.read_content(&paths, &t.hash)?;
+            let texts = (
+                as_text(&base_content),
+                as_text(&ours_content),
+                as_text(&theirs_content),
+            );
+            let (base_text, ours_text, theirs_text) = match texts {
+                (Some(b), Some(o), Some(t)) => (b, o, t),
+                _ => {
+                    conflicts.push(MergeConflict {
+                        path,
+                        kind: ConflictKind::Binary,
+                        regions: 0,
+                    });
+                    continue;
+                }
+            };
+
+            let merged = merge_text(base_text, ours_text, theirs_text, &labels);
+            if merged.conflicts > 0 {
+                conflicts.push(MergeConflict {
+                    path: path.clone(),
+                    kind: ConflictKind::Content,
+                    regions: merged.conflicts,
+                });
+            } else {
+                merged_files.push(path.clone());
+            }
+            let content = merged.text.into_bytes();
+            snapshots.push(FileSnapshot {
+                checkpoint_id: checkpoint_id.clone(),
+                hash: Self::calculate_file_hash(&content),
+                is_deleted: false,
+                permissions: o.mode,
+                size: content.len() as u64,
+                file_path: path,
+                content,
+                kind: FileKind::File,
+            });
+        }
+
+        let messages = self.read_messages(&paths, ours_id)?;
+        let checkpoint = Checkpoint {
+            id: checkpoint_id,
+            session_id: session_id.to_string(),
+            project_id: project_id.to_string(),
+            message_index: ours.message_index,
+            timestamp: Utc::now(),
+            description: Some(format!(
+                "Merge {} into {}",
+                short_id(theirs_id),
+                short_id(ours_id)
+            )),
+            parent_checkpoint_id: Some(ours_id.to_string()),
+            metadata: CheckpointMetadata {
+                total_tokens: ours.metadata.total_tokens,
+                model_used: ours.metadata.model_used.clone(),
+                user_prompt: ours.metadata.user_prompt.clone(),
+                file_changes: snapshots.len(),
+                snapshot_size: Self::estimate_checkpoint_size(&messages, &snapshots),
+                git: ours.metadata.git.clone(),
+            },
+            kind: CheckpointKind::Merge,
+            pinned: false,
+            tags: Vec::new(),
+            merge_parent_ids: vec![theirs_id.to_string()],
+        };
+        // Store the merged files before the checkpoint is recorded, so a
+        // failure leaves no incomplete merge in the timeline
+        for snapshot in snapshots.iter().filter(|snapshot| !snapshot.is_deleted) {
+            let base_hash = ours_manifest
+                .files
+                .get(&snapshot.file_path)
+                .filter(|entry| !entry.deleted)
+                .map(|entry| entry.hash.as_str());
+            self.write_content(&paths, &snapshot.hash, &snapshot.content, base_hash)
+                .with_context(|| {
+                    format!("Failed to save merge of {}", snapshot.file_path.display())
+                })?;
+        }
+        let result =
+            self.save_checkpoint(project_id, session_id, &checkpoint, snapshots, &messages)?;
+        for warning in &result.warnings {
+            log::warn!("Merge {}: {}", checkpoint.id, warning);
+        }
+
+        Ok(MergeResult {
+            checkpoint,
+            base_checkpoint_id: base_id,
+            merged_files,
+            conflicts,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// Snapshot of a file taken as-is from one side of a merge, or its
+    /// deletion when that side does not have it
+    fn merged_snapshot(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint_id: &str,
+        path: &std::path::Path,
+        entry: Option<&ManifestEntry>,
+    ) -> Result<FileSnapshot> {
+        let (content, hash, permissions) = match entry {
+            Some(entry) => (
+                self.read_content(paths, &entry.hash)?,
+                entry.hash.clone(),
+                entry.mode,
+            ),
+            None => (Vec::new(), String::new(), None),
+        };
+        Ok(FileSnapshot {
+            checkpoint_id: checkpoint_id.to_string(),
+            file_path: path.to_path_buf(),
+            size: content.len() as u64,
+            content,
+            hash,
+            is_deleted: entry.is_none(),
+            permissions,
+            kind: entry.map_or(FileKind::File, |entry| entry.kind),
+        })
+    }
+}
+
+/// Names shown after the conflict markers
+pub struct MarkerLabels {
+    pub ours: String,
+    pub base: String,
+    pub theirs: String,
+}
+
+/// A change one side made: base lines `base` replaced by side lines `side`
+struct Hunk {
+    base: Range<usize>,
+    side: Range<usize>,
+}
+
+/// Line-level three-way merge in diff3 style.
+///
+/// Changes that overlap or touch are a conflict unless both sides made the
+/// same change; conflicts are written with `<<<<<<<`, `|||||||`, `=======` and
+/// `>>>>>>>` markers around our, the base and their lines.
+pub fn merge_text(base: &str, ours: &str, theirs: &str, labels: &MarkerLabels) -> MergedText {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours_lines: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs_lines: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let ours_hunks = hunks(&base_lines, &ours_lines);
+    let theirs_hunks = hunks(&base_lines, &theirs_lines);
+
+    let mut merged = MergedText {
+        text: String::new(),
+        conflicts: 0,
+    };
+    let (mut i, mut j, mut position) = (0, 0, 0);
+    while i < ours_hunks.len() || j < theirs_hunks.len() {
+        // Start a region at the earliest change, then pull in every change
+        // of either side that overlaps or touches it
+        let start = match (ours_hunks.get(i), theirs_hunks.get(j)) {
+            (Some(o), Some(t)) => o.base.start.min(t.base.start),
+            (Some(o), None) => o.base.start,
+            (None, Some(t)) => t.base.start,
+            (None, None) => break,
+        };
+        let (ours_from, theirs_from) = (i, j);
+        let mut end = start;
+        loop {
+            if let Some(hunk) = ours_hunks.get(i).filter(|h| h.base.start <= end) {
+                end = end.max(hunk.base.end);
+                i += 1;
+            } else if let Some(hunk) = theirs_hunks.get(j).filter(|h| h.base.start <= end) {
+                end = end.max(hunk.base.end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        merged
+            .text
+            .extend(base_lines[position..start].iter().copied());
+        position = end;
+
+        let region = start..end;
+        let ours_version = apply(&base_lines, &ours_lines, &region, &ours_hunks[ours_from..i]);
+        let theirs_version = apply(
+            &base_lines,
+            &theirs_lines,
+            &region,
+            &theirs_hunks[theirs_from..j],
+        );
+        if ours_from == i || ours_version == theirs_version {
+            merged.text.extend(theirs_version);
+        } else if theirs_from == j {
+            merged.text.extend(ours_version);
+        } else {
+            merged.conflicts += 1;
+            push_marker(&mut merged.text, "<<<<<<<", &labels.ours);
+            push_lines(&mut merged.text, &ours_version);
+            push_marker(&mut merged.text, "|||||||", &labels.base);
+            push_lines(&mut merged.text, &base_lines[region]);
+            merged.text.push_str("=======\n");
+            push_lines(&mut merged.text, &theirs_version);
+            push_marker(&mut merged.text, ">>>>>>>", &labels.theirs);
+        }
+    }
+    merged.text.extend(base_lines[position..].iter().copied());
+    merged
+}
+
+/// The changes turning `base` into `side`, in base order
+fn hunks(base: &[&str], side: &[&str]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for op in capture_diff_slices(Algorithm::Myers, base, side) {
+        let (tag, old, new) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        // Adjacent operations are one change
+        match hunks.last_mut() {
+            Some(last) if last.base.end == old.start => {
+                last.base.end = old.end;
+                last.side.end = new.end;
+            }
+            _ => hunks.push(Hunk {
+                base: old,
+                side: new,
+            }),
+        }
+    }
+    hunks
+}
+
+/// One side's version of a region of the base
+fn apply<'a>(
+    base: &[&'a str],
+    side: &[&'a str],
+    region: &Range<usize>,
+    hunks: &[Hunk],
+) -> Vec<&'a str> {
+    let mut lines = Vec::new();
+    let mut position = region.start;
+    for hunk in hunks {
+        lines.extend_from_slice(&base[position..hunk.base.start]);
+        lines.extend_from_slice(&side[hunk.side.clone()]);
+        position = hunk.base.end;
+    }
+    lines.extend_from_slice(&base[position..region.end]);
+    lines
+}
+
+/// Append lines, making sure the text ends with a newline before a marker
+fn push_lines(text: &mut String, lines: &[&str]) {
+    text.extend(lines.iter().copied());
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+fn push_marker(text: &mut String, marker: &str, label: &str) {
+    text.push_str(marker);
+    text.push(' ');
+    text.push_str(label);
+    text.push('\n');
+}
+
+/// Content as text, or `None` for binary content
+fn as_text(content: &[u8]) -> Option<&str> {
+    if is_binary_content(content) {
+        return None;
+    }
+    std::str::from_utf8(content).ok()
+}
+
+fn short_id(checkpoint_id: &str) -> &str {
+    checkpoint_id.get(..8).unwrap_or(checkpoint_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::{checkpoint, deleted, snapshot};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn labels() -> MarkerLabels {
+        MarkerLabels {
+            ours: "ours".to_string(),
+            base: "base".to_string(),
+            theirs: "theirs".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_merge_text_combines_and_marks_conflicts() {
+        let base = "a\nb\nc\nd\ne\n";
+
+        let merged = merge_text(base, "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\n", &labels());
+        assert_eq!(merged.text, "A\nb\nc\nd\nE\n");
+        assert_eq!(merged.conflicts, 0);
+
+        // The same change on both sides is not a conflict
+        let merged = merge_text(base, "a\nB\nc\nd\ne\n", "a\nB\nc\nd\nE\n", &labels());
+        assert_eq!(merged.text, "a\nB\nc\nd\nE\n");
+
+        let merged = merge_text(base, "a\nb\nours\nd\ne\n", "a\nb\ntheirs\nd\ne", &labels());
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            merged.text,
+            "a\nb\n<<<<<<< ours\nours\n||||||| base\nc\n=======\ntheirs\n>>>>>>> theirs\nd\ne"
+        );
+    }
+
+    #[test]
+    fn test_merge_checkpoints_records_both_parents() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+
+        let saves = [
+            (
+                checkpoint("base", None),
+                vec![
+                    snapshot("base", "lib.rs", "one\ntwo\nthree\n"),
+                    snapshot("base", "notes.md", "notes\n"),
+                ],
+            ),
+            (
+                checkpoint("ours", Some("base")),
+                vec![
+                    snapshot("ours", "lib.rs", "ONE\ntwo\nthree\n"),
+                    deleted("ours", "notes.md"),
+                ],
+            ),
+            (
+                checkpoint("theirs", Some("base")),
+                vec![
+                    snapshot("theirs", "lib.rs", "one\ntwo\nTHREE\n"),
+                    snapshot("theirs", "notes.md", "more notes\n"),
+                    snapshot("theirs", "new.rs", "new\n"),
+                ],
+            ),
+        ];
+        for (checkpoint, snapshots) in saves {
+            storage
+                .save_checkpoint("project", "session", &checkpoint, snapshots, "")
+                .unwrap();
+        }
+
+        let result = storage
+            .merge_checkpoints("project", "session", "ours", "theirs", None)
+            .unwrap();
+        assert_eq!(result.base_checkpoint_id, "base");
+        assert_eq!(result.merged_files, [PathBuf::from("lib.rs")]);
+        assert_eq!(result.conflicts.len(), 1);
+        assert_eq!(result.conflicts[0].kind, ConflictKind::ModifyDelete);
+
+        let merge_id = &result.checkpoint.id;
+        let (_, files, _) = storage
+            .load_checkpoint("project", "session", merge_id)
+            .unwrap();
+        let content = |path: &str| {
+            files
+                .iter()
+                .find(|f| f.file_path == Path::new(path))
+                .map(|f| String::from_utf8(f.content.clone()).unwrap())
+        };
+        assert_eq!(content("lib.rs").unwrap(), "ONE\ntwo\nTHREE\n");
+        assert_eq!(content("new.rs").unwrap(), "new\n");
+        assert_eq!(content("notes.md").unwrap(), "more notes\n");
+
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "session");
+        let timeline = storage.load_timeline(&paths).unwrap();
+        let graph = timeline.graph();
+        assert_eq!(graph.nodes.len(), 4);
+        assert!(graph
+            .edges
+            .iter()
+            .any(|e| e.from == "theirs" && &e.to == merge_id && e.kind == EdgeKind::Merge));
+        assert_eq!(
+            timeline.merge_base(merge_id, "theirs").as_deref(),
+            Some("theirs")
+        );
+
+        // Merging again is refused, the merge already has their changes
+        assert!(storage
+            .merge_checkpoints("project", "session", merge_id, "theirs", None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_failed_merge_leaves_timeline_unchanged() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+        let saves = [
+            ("base", None, "a\nb\nc\n"),
+            ("ours", Some("base"), "A\nb\nc\n"),
+            ("theirs", Some("base"), "a\nb\nC\n"),
+        ];
+        for (id, parent, content) in saves {
+            storage
+                .save_checkpoint(
+                    "project",
+                    "session",
+                    &checkpoint(id, parent),
+                    vec![snapshot(id, "lib.rs", content)],
+                    "",
+                )
+                .unwrap();
+        }
+
+        // Occupy the merged file's object so it cannot be stored
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "session");
+        let merged_hash = CheckpointStorage::calculate_file_hash(b"A\nb\nC\n");
+        fs::create_dir_all(paths.content_object_file(&merged_hash)).unwrap();
+
+        let before = storage.load_timeline(&paths).unwrap();
+        assert!(storage
+            .merge_checkpoints("project", "session", "ours", "theirs", None)
+            .is_err());
+        let after = storage.load_timeline(&paths).unwrap();
+        assert_eq!(after.current_checkpoint_id, before.current_checkpoint_id);
+        assert_eq!(after.total_checkpoints, 3);
+        assert_eq!(after.graph().nodes.len(), 3);
+    }
+}
@@ -0,0 +1,309 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use zstd::stream::{decode_all, encode_all};
+
+use super::{storage::CheckpointStorage, CheckpointPaths};
+
+/// First line of a checkpoint's incremental message log
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageLogHeader {
+    /// Number of leading messages taken from `inherits_from`'s transcript
+    pub inherited_messages: usize,
+    /// Checkpoint whose transcript the inherited messages come from, normally
+    /// the parent
+    pub inherits_from: Option<String>,
+}
+
+impl CheckpointStorage {
+    /// Store a checkpoint's messages, keeping only what was appended since
+    /// `parent_id`.
+    ///
+    /// The messages shared with the parent's transcript are recorded as a
+    /// count, so a long conversation is stored once rather than once per
+    /// checkpoint. Without a readable parent the full transcript is stored.
+    pub fn write_messages(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint_id: &str,
+        parent_id: Option<&str>,
+        messages: &str,
+    ) -> Result<()> {
+        let lines = split_messages(messages);
+
+        let mut header = MessageLogHeader::default();
+        if let Some(parent_id) = parent_id {
+            match self.read_messages(paths, parent_id) {
+                Ok(parent_messages) => {
+                    let inherited = split_messages(&parent_messages)
+                        .iter()
+                        .zip(&lines)
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    if inherited > 0 {
+                        header = MessageLogHeader {
+                            inherited_messages: inherited,
+                            inherits_from: Some(parent_id.to_string()),
+                        };
+                    }
+                }
+                Err(e) => log::warn!(
+                    "Storing full messages for {}, parent {} is unreadable: {:#}",
+                    checkpoint_id,
+                    parent_id,
+                    e
+                ),
+            }
+        }
+
+        let mut log = serde_json::to_string(&header).context("Failed to serialize messages")?;
+        for line in &lines[header.inherited_messages..] {
+            log.push('\n');
+            log.push_str(line);
+        }
+        let compressed = encode_all(log.as_bytes(), self.compression_level())
+            .context("Failed to compress messages")?;
+
+        // Replace atomically; a checkpoint's messages may be rewritten when the
+        // checkpoint it inherits from goes away
+        let log_path = paths.checkpoint_message_log_file(checkpoint_id);
+        let tmp_path = log_path.with_extension("jsonl.tmp");
+        fs::write(&tmp_path, compressed).context("Failed to write compressed messages")?;
+        fs::rename(&tmp_path, &log_path).context("Failed to move messages into place")?;
+
+        // Full copies written by older versions are superseded
+        let legacy_path = paths.checkpoint_messages_file(checkpoint_id);
+        if legacy_path.exists() {
+            fs::remove_file(&legacy_path).context("Failed to remove full message copy")?;
+        }
+
+        Ok(())
+    }
+
+    /// Reconstruct the full transcript of a checkpoint by walking back through
+    /// the checkpoints it inherits messages from
+    pub fn read_messages(&self, paths: &CheckpointPaths, checkpoint_id: &str) -> Result<String> {
+        let mut chain = Vec::new();
+        let mut visited = HashSet::new();
+        let mut next = Some(checkpoint_id.to_string());
+        while let Some(id) = next {
+            if !visited.insert(id.clone()) {
+                anyhow::bail!("Messages of checkpoint {} inherit from themselves", id);
+            }
+            let (header, own_messages) = self
+                .read_message_log(paths, &id)
+                .with_context(|| format!("Failed to read messages of checkpoint {}", id))?;
+            next = header
+                .inherits_from
+                .filter(|_| header.inherited_messages > 0);
+            chain.push((id, header.inherited_messages, own_messages));
+        }
+
+        // Replay from the oldest checkpoint forward
+        let mut lines: Vec<&str> = Vec::new();
+        for (id, inherited, own_messages) in chain.iter().rev() {
+            if *inherited > lines.len() {
+                anyhow::bail!(
+                    "Checkpoint {} inherits {} messages but only {} exist",
+                    id,
+                    inherited,
+                    lines.len()
+                );
+            }
+            lines.truncate(*inherited);
+            lines.extend(split_messages(own_messages));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// Map each checkpoint to the checkpoints that inherit messages from it.
+    ///
+    /// Every message log is read, so callers removing several checkpoints
+    /// build the map once and pass it to each
+    /// [`detach_dependent_messages`](Self::detach_dependent_messages).
+    pub fn message_dependents(
+        &self,
+        paths: &CheckpointPaths,
+    ) -> Result<HashMap<String, Vec<String>>> {
+        let mut dependents: HashMap<String, Vec<String>> = HashMap::new();
+        if !paths.checkpoints_dir.exists() {
+            return Ok(dependents);
+        }
+
+        for entry in fs::read_dir(&paths.checkpoints_dir)? {
+            let entry = entry?;
+            let id = match entry.file_name().to_str() {
+                Some(id) if entry.path().is_dir() => id.to_string(),
+                _ => continue,
+            };
+            if let Ok((header, _)) = self.read_message_log(paths, &id) {
+                match header.inherits_from {
+                    Some(parent_id) if parent_id != id => {
+                        dependents.entry(parent_id).or_default().push(id)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(dependents)
+    }
+
+    /// Rewrite the messages of every checkpoint that inherits from
+    /// `checkpoint_id` as full copies, so it can be removed.
+    ///
+    /// `dependents` comes from [`message_dependents`](Self::message_dependents)
+    /// and is kept up to date for the next removal.
+    pub fn detach_dependent_messages(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint_id: &str,
+        dependents: &mut HashMap<String, Vec<String>>,
+    ) -> Result<usize> {
+        let ids = dependents.remove(checkpoint_id).unwrap_or_default();
+        for id in &ids {
+            let messages = self.read_messages(paths, id)?;
+            self.write_messages(paths, id, None, &messages)?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Convert checkpoints that still store a full copy of the transcript to
+    /// incremental message logs
+    pub fn migrate_full_messages(&self, paths: &CheckpointPaths) -> Result<usize> {
+        let timeline = self.load_timeline(paths)?;
+        // Parents come first, so each parent is already converted when its
+        // children are compared against it
+        let mut migrated = 0;
+        for node in timeline.nodes() {
+            let id = &node.checkpoint.id;
+            if !paths.checkpoint_messages_file(id).exists() {
+                continue;
+            }
+            // A damaged copy is left for verify_checkpoints to report
+            let messages = match self.read_messages(paths, id) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    log::warn!("Not migrating messages of checkpoint {}: {:#}", id, e);
+                    continue;
+                }
+            };
+            self.write_messages(
+                paths,
+                id,
+                node.checkpoint.parent_checkpoint_id.as_deref(),
+                &messages,
+            )?;
+            migrated += 1;
+        }
+        Ok(migrated)
+    }
+
+    /// Read what a checkpoint stores itself: its log header and own messages
+    fn read_message_log(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint_id: &str,
+    ) -> Result<(MessageLogHeader, String)> {
+        let log_path = paths.checkpoint_message_log_file(checkpoint_id);
+        if !log_path.exists() {
+            // Checkpoints written before messages were stored incrementally
+            let compressed = fs::read(paths.checkpoint_messages_file(checkpoint_id))
+                .context("Failed to read compressed messages")?;
+            let messages = String::from_utf8(
+                decode_all(&compressed[..]).context("Failed to decompress messages")?,
+            )
+            .context("Invalid UTF-8 in messages")?;
+            return Ok((MessageLogHeader::default(), messages));
+        }
+
+        let compressed = fs::read(&log_path).context("Failed to read compressed messages")?;
+        let log = String::from_utf8(
+            decode_all(&compressed[..]).context("Failed to decompress messages")?,
+        )
+        .context("Invalid UTF-8 in messages")?;
+        let (header, messages) = log.split_once('\n').unwrap_or((&log, ""));
+        let header: MessageLogHeader =
+            serde_json::from_str(header).context("Failed to parse messages header")?;
+        Ok((header, messages.to_string()))
+    }
+}
+
+/// Split a transcript into messages; an empty transcript has none
+pub fn split_messages(messages: &str) -> Vec<&str> {
+    if messages.is_empty() {
+        Vec::new()
+    } else {
+        messages.split('\n').collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::checkpoint;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_messages_are_stored_incrementally_and_migrated() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "session");
+
+        // a -> b -> c, where c was created after rewinding one message of b
+        let transcripts = [
+            ("a", None, "1\n2"),
+            ("b", Some("a"), "1\n2\n3\n4"),
+            ("c", Some("b"), "1\n2\n3\n5"),
+        ];
+        for (id, parent, messages) in transcripts {
+            storage
+                .save_checkpoint(
+                    "project",
+                    "session",
+                    &checkpoint(id, parent),
+                    Vec::new(),
+                    messages,
+                )
+                .unwrap();
+        }
+
+        let (header, own) = storage.read_message_log(&paths, "c").unwrap();
+        assert_eq!(header.inherited_messages, 3);
+        assert_eq!(own, "5");
+        for (id, _, messages) in transcripts {
+            assert_eq!(storage.read_messages(&paths, id).unwrap(), messages);
+        }
+
+        // Removing b leaves c with a full copy
+        let mut dependents = storage.message_dependents(&paths).unwrap();
+        assert_eq!(dependents["a"], ["b"]);
+        assert_eq!(
+            storage
+                .detach_dependent_messages(&paths, "b", &mut dependents)
+                .unwrap(),
+            1
+        );
+        fs::remove_dir_all(paths.checkpoint_dir("b")).unwrap();
+        assert_eq!(storage.read_messages(&paths, "c").unwrap(), "1\n2\n3\n5");
+
+        // Full copies written by older versions are converted on startup
+        for (id, _, messages) in transcripts {
+            fs::create_dir_all(paths.checkpoint_dir(id)).unwrap();
+            fs::remove_file(paths.checkpoint_message_log_file(id)).ok();
+            fs::write(
+                paths.checkpoint_messages_file(id),
+                encode_all(messages.as_bytes(), 3).unwrap(),
+            )
+            .unwrap();
+        }
+        storage.init_storage("project", "session").unwrap();
+        for (id, _, messages) in transcripts {
+            assert!(!paths.checkpoint_messages_file(id).exists());
+            assert_eq!(storage.read_messages(&paths, id).unwrap(), messages);
+        }
+        assert_eq!(storage.read_message_log(&paths, "c").unwrap().1, "5");
+    }
+}
@@ -1,11 +1,32 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
 
+pub mod bisect;
+pub mod blame;
+pub mod bundle;
+pub mod delta;
+pub mod diff;
+pub mod git;
+pub mod index;
+pub mod integrity;
+pub mod labels;
 pub mod manager;
+pub mod manifest;
+pub mod merge;
+pub mod messages;
+pub mod pool;
+pub mod promote;
+pub mod restore;
+pub mod retention;
+pub mod rewind;
+pub mod rules;
+pub mod search;
 pub mod state;
 pub mod storage;
+pub mod walker;
+pub mod watcher;
 
 /// Represents a checkpoint in the session timeline
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +48,33 @@ pub struct Checkpoint {
     pub parent_checkpoint_id: Option<String>,
     /// Metadata about the checkpoint
     pub metadata: CheckpointMetadata,
+    /// Why the checkpoint was created
+    #[serde(default)]
+    pub kind: CheckpointKind,
+    /// Pinned checkpoints are never removed by retention
+    #[serde(default)]
+    pub pinned: bool,
+    /// User labels; tagged checkpoints are never removed by retention
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Further parents of a merge checkpoint. The timeline tree keeps the
+    /// checkpoint under `parent_checkpoint_id`; these are the branches merged
+    /// into it.
+    #[serde(default)]
+    pub merge_parent_ids: Vec<String>,
+}
+
+/// Why a checkpoint was created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckpointKind {
+    /// Created by the user or by auto-checkpointing
+    #[default]
+    Regular,
+    /// Working state saved automatically before a restore overwrote it
+    PreRestore,
+    /// Combines two branches of the timeline
+    Merge,
 }
 
 /// Metadata associated with a checkpoint
@@ -43,6 +91,9 @@ pub struct CheckpointMetadata {
     pub file_changes: usize,
     /// Size of all file snapshots in bytes
     pub snapshot_size: u64,
+    /// State of the project's git repository, if it is in one
+    #[serde(default)]
+    pub git: Option<git::GitState>,
 }
 
 /// Represents a snapshot of a file at a checkpoint
@@ -53,8 +104,8 @@ pub struct FileSnapshot {
     pub checkpoint_id: String,
     /// Relative path from project root
     pub file_path: PathBuf,
-    /// Full content of the file (will be compressed)
-    pub content: String,
+    /// Raw bytes of the file (will be compressed)
+    pub content: Vec<u8>,
     /// SHA-256 hash for integrity verification
     pub hash: String,
     /// Whether this file was deleted at this checkpoint
@@ -63,6 +114,24 @@ pub struct FileSnapshot {
     pub permissions: Option<u32>,
     /// File size in bytes
     pub size: u64,
+    /// What is at the path; a symlink's content is its target and a
+    /// directory's is empty
+    #[serde(default)]
+    pub kind: FileKind,
+}
+
+/// Type of a path recorded in a checkpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    /// A regular file
+    #[default]
+    File,
+    /// A symbolic link, recorded without following it
+    Symlink,
+    /// A directory; only empty directories are recorded, since the others
+    /// come back with their files
+    Directory,
 }
 
 /// Represents a node in the timeline tree
@@ -93,6 +162,30 @@ pub struct SessionTimeline {
     pub checkpoint_strategy: CheckpointStrategy,
     /// Total number of checkpoints in timeline
     pub total_checkpoints: usize,
+    /// Files larger than this many bytes are left out of checkpoints (0 for no limit)
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: u64,
+    /// The most recent restore, used to undo it
+    #[serde(default)]
+    pub last_restore: Option<RestoreRecord>,
+    /// Thresholds used by the `Rules` strategy
+    #[serde(default)]
+    pub auto_checkpoint_rules: rules::AutoCheckpointRules,
+    /// What to do when restoring a checkpoint taken on another branch or commit
+    #[serde(default)]
+    pub git_mismatch_policy: git::GitMismatchPolicy,
+}
+
+/// A restore that can be undone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreRecord {
+    /// Checkpoint that was restored
+    pub restored_checkpoint_id: String,
+    /// Checkpoint holding the working state from just before the restore
+    pub pre_restore_checkpoint_id: String,
+    /// When the restore happened
+    pub timestamp: DateTime<Utc>,
 }
 
 /// Strategy for automatic checkpoint creation
@@ -107,6 +200,8 @@ pub enum CheckpointStrategy {
     PerToolUse,
     /// Create checkpoint after destructive operations
     Smart,
+    /// Create checkpoint when the session's `auto_checkpoint_rules` are met
+    Rules,
 }
 
 /// Tracks the state of files for checkpointing
@@ -114,6 +209,32 @@ pub enum CheckpointStrategy {
 pub struct FileTracker {
     /// Map of file paths to their current state
     pub tracked_files: HashMap<PathBuf, FileState>,
+    /// Recent changes observed on disk by the file watcher, oldest first
+    pub events: VecDeque<FileEvent>,
+}
+
+/// A change to a project file observed on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileEvent {
+    /// Relative path from project root
+    pub path: PathBuf,
+    /// What happened to the file
+    pub kind: FileEventKind,
+    /// Previous path, for renames
+    pub renamed_from: Option<PathBuf>,
+    /// When the change was observed
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Kind of change observed for a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileEventKind {
+    Created,
+    Modified,
+    Deleted,
+    Renamed,
 }
 
 /// State of a tracked file
@@ -127,10 +248,15 @@ pub struct FileState {
     pub last_modified: DateTime<Utc>,
     /// Whether the file currently exists
     pub exists: bool,
+    /// Type of the path when it was last seen
+    pub kind: FileKind,
+    /// Permissions when the path was last seen (Unix mode)
+    pub mode: Option<u32>,
 }
 
 /// Result of a checkpoint operation
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CheckpointResult {
     /// The created/restored checkpoint
     pub checkpoint: Checkpoint,
@@ -138,10 +264,15 @@ pub struct CheckpointResult {
     pub files_processed: usize,
     /// Any warnings during the operation
     pub warnings: Vec<String>,
+    /// New session to resume, when a restore continued in a new session
+    pub session_id: Option<String>,
+    /// What the retention policy pruned after a new checkpoint
+    pub retention: Option<retention::RetentionReport>,
 }
 
 /// Diff between two checkpoints
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CheckpointDiff {
     /// Source checkpoint ID
     pub from_checkpoint_id: String,
@@ -153,15 +284,20 @@ pub struct CheckpointDiff {
     pub added_files: Vec<PathBuf>,
     /// Files that were deleted
     pub deleted_files: Vec<PathBuf>,
+    /// Files that were moved to a new path (possibly with edits)
+    pub renamed_files: Vec<RenamedFile>,
     /// Token usage difference
     pub token_delta: i64,
 }
 
 /// Diff for a single file
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct FileDiff {
     /// File path
     pub path: PathBuf,
+    /// Whether either side of the diff is binary content
+    pub is_binary: bool,
     /// Number of additions
     pub additions: usize,
     /// Number of deletions
@@ -170,12 +306,43 @@ pub struct FileDiff {
     pub diff_content: Option<String>,
 }
 
+/// A file detected as renamed between two checkpoints
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenamedFile {
+    /// Path at the source checkpoint
+    pub from: PathBuf,
+    /// Path at the target checkpoint
+    pub to: PathBuf,
+    /// Line similarity between the two versions (1.0 for identical content)
+    pub similarity: f32,
+    /// Diff between the two versions
+    pub diff: FileDiff,
+}
+
+impl FileSnapshot {
+    /// Whether the snapshot content should be treated as binary
+    pub fn is_binary(&self) -> bool {
+        is_binary_content(&self.content)
+    }
+}
+
+/// Heuristic binary detection: NUL bytes in the first 8KB or invalid UTF-8
+pub fn is_binary_content(content: &[u8]) -> bool {
+    let head = &content[..content.len().min(8192)];
+    head.contains(&0) || std::str::from_utf8(content).is_err()
+}
+
 impl Default for CheckpointStrategy {
     fn default() -> Self {
         CheckpointStrategy::Smart
     }
 }
 
+fn default_max_file_size() -> u64 {
+    walker::DEFAULT_MAX_FILE_SIZE
+}
+
 impl SessionTimeline {
     /// Create a new empty timeline
     pub fn new(session_id: String) -> Self {
@@ -186,7 +353,64 @@ impl SessionTimeline {
             auto_checkpoint_enabled: false,
             checkpoint_strategy: CheckpointStrategy::default(),
             total_checkpoints: 0,
+            max_file_size: default_max_file_size(),
+            last_restore: None,
+            auto_checkpoint_rules: rules::AutoCheckpointRules::default(),
+            git_mismatch_policy: git::GitMismatchPolicy::default(),
+        }
+    }
+
+    /// Every node of the timeline tree, parents before children and siblings
+    /// in order
+    pub fn nodes(&self) -> Vec<&TimelineNode> {
+        let mut nodes = Vec::new();
+        let mut stack: Vec<&TimelineNode> = self.root_node.iter().collect();
+        while let Some(node) = stack.pop() {
+            nodes.push(node);
+            stack.extend(node.children.iter().rev());
+        }
+        nodes
+    }
+
+    /// Assemble nodes listed parents before children into a tree by their
+    /// `parent_checkpoint_id`, returning its root. Any `children` the nodes
+    /// already have are kept.
+    pub fn assemble_tree(nodes: Vec<TimelineNode>) -> anyhow::Result<Option<TimelineNode>> {
+        let order: Vec<(String, Option<String>)> = nodes
+            .iter()
+            .map(|node| {
+                (
+                    node.checkpoint.id.clone(),
+                    node.checkpoint.parent_checkpoint_id.clone(),
+                )
+            })
+            .collect();
+        let mut pending: HashMap<String, TimelineNode> = HashMap::with_capacity(nodes.len());
+        for node in nodes {
+            if let Some(duplicate) = pending.insert(node.checkpoint.id.clone(), node) {
+                anyhow::bail!("Checkpoint {} appears twice", duplicate.checkpoint.id);
+            }
+        }
+
+        // In reverse order every subtree is complete before it moves into its
+        // parent, which is still pending
+        let mut root = None;
+        for (id, parent_id) in order.into_iter().rev() {
+            let node = pending.remove(&id).expect("every listed node is pending");
+            match parent_id {
+                Some(parent_id) => match pending.get_mut(&parent_id) {
+                    Some(parent) => parent.children.insert(0, node),
+                    None => anyhow::bail!(
+                        "Checkpoint {} is listed before its parent {}, or the parent is missing",
+                        id,
+                        parent_id
+                    ),
+                },
+                None if root.is_none() => root = Some(node),
+                None => anyhow::bail!("The timeline has more than one root checkpoint"),
+            }
         }
+        Ok(root)
     }
 
     /// Find a checkpoint by ID in the timeline tree
@@ -196,23 +420,61 @@ impl SessionTimeline {
             .and_then(|root| Self::find_in_tree(root, checkpoint_id))
     }
 
-    fn find_in_tree<'a>(node: &'a TimelineNode, checkpoint_id: &str) -> Option<&'a TimelineNode> {
-        if node.checkpoint.id == checkpoint_id {
-            return Some(node);
+    /// The checkpoints from the root down to a checkpoint, oldest first,
+    /// following first parents; `None` if it is not in the timeline
+    pub fn path_to(&self, checkpoint_id: &str) -> Option<Vec<Checkpoint>> {
+        let mut path = Vec::new();
+        let mut next = Some(checkpoint_id.to_string());
+        while let Some(checkpoint_id) = next {
+            let node = self.find_checkpoint(&checkpoint_id)?;
+            next = node.checkpoint.parent_checkpoint_id.clone();
+            path.push(node.checkpoint.clone());
         }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Find a checkpoint by ID in the timeline tree, for changing it
+    pub fn find_checkpoint_mut(&mut self, checkpoint_id: &str) -> Option<&mut TimelineNode> {
+        self.root_node
+            .as_mut()
+            .and_then(|root| Self::find_in_tree_mut(root, checkpoint_id))
+    }
+
+    // The tree is searched with an explicit stack, since a long session
+    // nests checkpoints far deeper than is safe to recurse into
 
-        for child in &node.children {
-            if let Some(found) = Self::find_in_tree(child, checkpoint_id) {
-                return Some(found);
+    fn find_in_tree_mut<'a>(
+        root: &'a mut TimelineNode,
+        checkpoint_id: &str,
+    ) -> Option<&'a mut TimelineNode> {
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            if node.checkpoint.id == checkpoint_id {
+                return Some(node);
             }
+            stack.extend(node.children.iter_mut());
         }
+        None
+    }
 
+    fn find_in_tree<'a>(root: &'a TimelineNode, checkpoint_id: &str) -> Option<&'a TimelineNode> {
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            if node.checkpoint.id == checkpoint_id {
+                return Some(node);
+            }
+            stack.extend(node.children.iter());
+        }
         None
     }
 }
 
 /// Checkpoint storage paths
 pub struct CheckpointPaths {
+    pub session_id: String,
+    /// Per-project directory holding every session's timeline
+    pub timelines_dir: PathBuf,
     pub timeline_file: PathBuf,
     pub checkpoints_dir: PathBuf,
     pub files_dir: PathBuf,
@@ -220,13 +482,15 @@ pub struct CheckpointPaths {
 
 impl CheckpointPaths {
     pub fn new(claude_dir: &PathBuf, project_id: &str, session_id: &str) -> Self {
-        let base_dir = claude_dir
+        let timelines_dir = claude_dir
             .join("projects")
             .join(project_id)
-            .join(".timelines")
-            .join(session_id);
+            .join(".timelines");
+        let base_dir = timelines_dir.join(session_id);
 
         Self {
+            session_id: session_id.to_string(),
+            timelines_dir,
             timeline_file: base_dir.join("timeline.json"),
             checkpoints_dir: base_dir.join("checkpoints"),
             files_dir: base_dir.join("files"),
@@ -241,22 +505,129 @@ impl CheckpointPaths {
         self.checkpoint_dir(checkpoint_id).join("metadata.json")
     }
 
+    /// Full transcript copy written before messages were stored incrementally
     pub fn checkpoint_messages_file(&self, checkpoint_id: &str) -> PathBuf {
         self.checkpoint_dir(checkpoint_id).join("messages.jsonl")
     }
 
-    #[allow(dead_code)]
-    pub fn file_snapshot_path(&self, _checkpoint_id: &str, file_hash: &str) -> PathBuf {
+    /// Messages appended since the checkpoint this one inherits from
+    pub fn checkpoint_message_log_file(&self, checkpoint_id: &str) -> PathBuf {
+        self.checkpoint_dir(checkpoint_id)
+            .join("new_messages.jsonl")
+    }
+
+    /// Content shared by all sessions of the project
+    pub fn pool_dir(&self) -> PathBuf {
+        self.timelines_dir.join(".pool")
+    }
+
+    pub fn content_pool_dir(&self) -> PathBuf {
+        self.pool_dir().join("objects")
+    }
+
+    pub fn content_object_file(&self, file_hash: &str) -> PathBuf {
         // In content-addressable storage, files are stored by hash in the content pool
-        self.files_dir.join("content_pool").join(file_hash)
+        self.content_pool_dir().join(file_hash)
+    }
+
+    pub fn manifests_dir(&self) -> PathBuf {
+        self.files_dir.join("manifests")
+    }
+
+    pub fn manifest_file(&self, checkpoint_id: &str) -> PathBuf {
+        self.manifests_dir().join(format!("{}.json", checkpoint_id))
+    }
+
+    /// Lock file serializing changes to the session's timeline
+    pub fn timeline_lock_file(&self) -> PathBuf {
+        self.timeline_file.with_file_name("timeline.lock")
+    }
+
+    /// Lock file serializing garbage collection of the shared pool
+    pub fn pool_lock_file(&self) -> PathBuf {
+        self.pool_dir().join("gc.lock")
+    }
+
+    /// SQLite index of the checkpoints of every session of the project
+    pub fn index_file(&self) -> PathBuf {
+        self.pool_dir().join("index.db")
+    }
+
+    /// Retention policy applied to every session of the project
+    pub fn retention_policy_file(&self) -> PathBuf {
+        self.pool_dir().join("retention.json")
+    }
+
+    /// Per-session content pool used before content was shared across sessions
+    pub fn legacy_content_pool_dir(&self) -> PathBuf {
+        self.files_dir.join("content_pool")
+    }
+
+    /// Per-file reference directory used before manifests were introduced
+    pub fn legacy_refs_dir(&self) -> PathBuf {
+        self.files_dir.join("refs")
+    }
+}
+
+/// Checkpoints and snapshots for the tests of the checkpoint modules
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+    use storage::CheckpointStorage;
+
+    /// A regular checkpoint of session `session` in project `project`,
+    /// created now; tests override the fields they care about
+    pub fn checkpoint(id: &str, parent: Option<&str>) -> Checkpoint {
+        Checkpoint {
+            id: id.to_string(),
+            session_id: "session".to_string(),
+            project_id: "project".to_string(),
+            message_index: 0,
+            timestamp: Utc::now(),
+            description: None,
+            parent_checkpoint_id: parent.map(|p| p.to_string()),
+            metadata: CheckpointMetadata {
+                total_tokens: 0,
+                model_used: "test".to_string(),
+                user_prompt: String::new(),
+                file_changes: 1,
+                snapshot_size: 0,
+                git: None,
+            },
+            kind: CheckpointKind::Regular,
+            pinned: false,
+            tags: Vec::new(),
+            merge_parent_ids: Vec::new(),
+        }
     }
 
-    #[allow(dead_code)]
-    pub fn file_reference_path(&self, checkpoint_id: &str, safe_filename: &str) -> PathBuf {
-        // References are stored per checkpoint
-        self.files_dir
-            .join("refs")
-            .join(checkpoint_id)
-            .join(format!("{}.json", safe_filename))
+    /// A checkpoint whose prompt is `prompt`
+    pub fn checkpoint_with_prompt(id: &str, parent: Option<&str>, prompt: &str) -> Checkpoint {
+        let mut checkpoint = checkpoint(id, parent);
+        checkpoint.metadata.user_prompt = prompt.to_string();
+        checkpoint
+    }
+
+    /// A regular file at `path` holding `content`
+    pub fn snapshot(checkpoint_id: &str, path: &str, content: impl AsRef<[u8]>) -> FileSnapshot {
+        let content = content.as_ref();
+        FileSnapshot {
+            checkpoint_id: checkpoint_id.to_string(),
+            file_path: PathBuf::from(path),
+            content: content.to_vec(),
+            hash: CheckpointStorage::calculate_file_hash(content),
+            is_deleted: false,
+            permissions: None,
+            size: content.len() as u64,
+            kind: FileKind::File,
+        }
+    }
+
+    /// A deletion of `path`
+    pub fn deleted(checkpoint_id: &str, path: &str) -> FileSnapshot {
+        FileSnapshot {
+            is_deleted: true,
+            ..snapshot(checkpoint_id, path, "")
+        }
     }
 }
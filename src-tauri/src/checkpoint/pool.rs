@@ -0,0 +1,483 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use super::{
+    delta::DeltaHeader, manifest::CheckpointManifest, storage::CheckpointStorage, CheckpointPaths,
+};
+
+/// Unreferenced objects younger than this are never collected.
+///
+/// Checkpoints write (or touch) their content before saving the manifest that
+/// references it, so a collection running in another session at the same time
+/// must not mistake that content for garbage.
+pub const GC_GRACE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// Space used by the checkpoints of a project
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolStats {
+    /// Sessions with a checkpoint timeline
+    pub sessions: usize,
+    /// Checkpoint manifests across all sessions
+    pub checkpoints: usize,
+    /// Objects in the shared pool
+    pub objects: usize,
+    /// Objects stored as a delta against another object
+    pub delta_objects: usize,
+    /// Objects no checkpoint references any more
+    pub unreferenced_objects: usize,
+    /// Compressed size of the pool on disk
+    pub stored_bytes: u64,
+    /// Size of every distinct file content referenced in the project
+    pub unique_bytes: u64,
+    /// Size the content would take if each session kept its own pool
+    pub per_session_bytes: u64,
+    /// Size the content would take if every checkpoint stored full copies
+    pub snapshot_bytes: u64,
+}
+
+/// What a garbage collection of the content pool removed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GcSummary {
+    /// Pool objects removed
+    pub objects: usize,
+    /// Bytes those objects took on disk
+    pub bytes: u64,
+}
+
+/// Exclusive lock on a project's content pool, released when dropped
+pub struct PoolLock {
+    _file: File,
+}
+
+impl CheckpointStorage {
+    /// Take the project's pool lock, waiting for any other holder.
+    ///
+    /// Garbage collection and migrations hold it; creating checkpoints does
+    /// not, and relies on [`GC_GRACE_PERIOD`] instead.
+    pub fn lock_pool(&self, paths: &CheckpointPaths) -> Result<PoolLock> {
+        fs::create_dir_all(paths.pool_dir()).context("Failed to create content pool directory")?;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(paths.pool_lock_file())
+            .context("Failed to open content pool lock")?;
+        file.lock().context("Failed to lock content pool")?;
+        Ok(PoolLock { _file: file })
+    }
+
+    /// Move a session's own content pool into the shared project pool.
+    ///
+    /// Objects the project pool already has are dropped. Callers must hold
+    /// the pool lock.
+    pub fn migrate_session_pool(&self, paths: &CheckpointPaths) -> Result<usize> {
+        let legacy_dir = paths.legacy_content_pool_dir();
+        if !legacy_dir.exists() {
+            return Ok(0);
+        }
+        fs::create_dir_all(paths.content_pool_dir())
+            .context("Failed to create content pool directory")?;
+
+        let mut migrated = 0;
+        for entry in fs::read_dir(&legacy_dir)? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) if path.is_file() => name.to_string(),
+                _ => continue,
+            };
+
+            // Leftovers of interrupted writes are not worth keeping
+            if name.ends_with(".tmp") {
+                fs::remove_file(&path).context("Failed to remove partial object")?;
+                continue;
+            }
+
+            let target = paths.content_object_file(&name);
+            if target.exists() {
+                fs::remove_file(&path).context("Failed to remove duplicate object")?;
+            } else {
+                fs::rename(&path, &target).context("Failed to move object into shared pool")?;
+                migrated += 1;
+            }
+        }
+
+        fs::remove_dir(&legacy_dir).context("Failed to remove session content pool")?;
+        Ok(migrated)
+    }
+
+    /// Count how many checkpoint manifests, across all sessions of the
+    /// project, and how many deltas reference each object.
+    ///
+    /// Callers must hold the pool lock, see [`Self::project_manifests`].
+    pub fn content_refcounts(&self, project_id: &str) -> Result<HashMap<String, usize>> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, "");
+        Ok(live_refcounts(&paths, &self.project_manifests(project_id)?))
+    }
+
+    /// Remove pool objects that no checkpoint in any session references.
+    ///
+    /// Objects written within [`GC_GRACE_PERIOD`] are kept, so sessions that
+    /// are creating checkpoints concurrently are never robbed of content.
+    pub fn garbage_collect_content(&self, project_id: &str) -> Result<GcSummary> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, "");
+        let pool_dir = paths.content_pool_dir();
+        if !pool_dir.exists() {
+            return Ok(GcSummary::default());
+        }
+
+        let _lock = self.lock_pool(&paths)?;
+        let refcounts = self.content_refcounts(project_id)?;
+
+        let mut summary = GcSummary::default();
+        for path in self.collectable_objects(&paths, &refcounts)? {
+            let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            if fs::remove_file(&path).is_ok() {
+                summary.objects += 1;
+                summary.bytes += size;
+            }
+        }
+
+        // Temporary files of writes that never finished
+        for entry in fs::read_dir(&pool_dir)? {
+            let path = entry?.path();
+            let is_tmp = path.extension().and_then(|e| e.to_str()) == Some("tmp");
+            if is_tmp && is_past_grace_period(&path) {
+                let _ = fs::remove_file(&path);
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Report how much space the project's checkpoints use, and how much
+    /// sharing content between checkpoints and sessions saves
+    pub fn pool_stats(&self, project_id: &str) -> Result<PoolStats> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, "");
+        let _lock = self.lock_pool(&paths)?;
+        let manifests = self.project_manifests(project_id)?;
+        let refcounts = live_refcounts(&paths, &manifests);
+
+        let mut stats = PoolStats {
+            checkpoints: manifests.len(),
+            ..Default::default()
+        };
+
+        let mut sizes: HashMap<&str, u64> = HashMap::new();
+        let mut session_hashes: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for (session_id, manifest) in &manifests {
+            let hashes = session_hashes.entry(session_id.as_str()).or_default();
+            for entry in manifest.files.values().filter(|entry| !entry.deleted) {
+                stats.snapshot_bytes += entry.size;
+                sizes.insert(&entry.hash, entry.size);
+                if hashes.insert(&entry.hash) {
+                    stats.per_session_bytes += entry.size;
+                }
+            }
+        }
+        stats.sessions = self.session_ids(project_id)?.len();
+        stats.unique_bytes = sizes.values().sum();
+
+        let pool_dir = paths.content_pool_dir();
+        if pool_dir.exists() {
+            for entry in fs::read_dir(&pool_dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                let hash = match path.file_name().and_then(|n| n.to_str()) {
+                    Some(hash) if path.is_file() && !hash.ends_with(".tmp") => hash.to_string(),
+                    _ => continue,
+                };
+                stats.objects += 1;
+                stats.stored_bytes += entry.metadata()?.len();
+                if DeltaHeader::read(&path)?.is_some() {
+                    stats.delta_objects += 1;
+                }
+                if !refcounts.contains_key(&hash) {
+                    stats.unreferenced_objects += 1;
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Pool objects that nothing references and that are old enough to remove.
+    ///
+    /// Objects within the grace period may be about to be referenced, so the
+    /// delta bases they are stored against are kept however old they are.
+    pub fn collectable_objects(
+        &self,
+        paths: &CheckpointPaths,
+        refcounts: &HashMap<String, usize>,
+    ) -> Result<Vec<PathBuf>> {
+        let pool_dir = paths.content_pool_dir();
+        if !pool_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut old = Vec::new();
+        let mut fresh_bases = HashSet::new();
+        for entry in fs::read_dir(&pool_dir)? {
+            let path = entry?.path();
+            let hash = match path.file_name().and_then(|n| n.to_str()) {
+                Some(hash) if path.is_file() && !hash.ends_with(".tmp") => hash.to_string(),
+                _ => continue,
+            };
+            if is_past_grace_period(&path) {
+                old.push((hash, path));
+                continue;
+            }
+            let mut next = Some(hash);
+            while let Some(hash) = next.take() {
+                if let Ok(Some(header)) = DeltaHeader::read(&paths.content_object_file(&hash)) {
+                    if fresh_bases.insert(header.base_hash.clone()) {
+                        next = Some(header.base_hash);
+                    }
+                }
+            }
+        }
+        Ok(old
+            .into_iter()
+            .filter(|(hash, _)| !refcounts.contains_key(hash) && !fresh_bases.contains(hash))
+            .map(|(_, path)| path)
+            .collect())
+    }
+
+    /// Every checkpoint manifest of every session in the project, paired with
+    /// its session ID.
+    ///
+    /// Sessions still on the legacy layouts are migrated first, so callers
+    /// must hold the pool lock.
+    pub fn project_manifests(&self, project_id: &str) -> Result<Vec<(String, CheckpointManifest)>> {
+        let mut manifests = Vec::new();
+        for session_id in self.session_ids(project_id)? {
+            let session_paths = CheckpointPaths::new(&self.claude_dir, project_id, &session_id);
+
+            // Legacy references must be migrated first or their content would look orphaned
+            self.migrate_legacy_refs(&session_paths)?;
+            self.migrate_session_pool(&session_paths)?;
+
+            let manifests_dir = session_paths.manifests_dir();
+            if !manifests_dir.exists() {
+                continue;
+            }
+            for entry in fs::read_dir(&manifests_dir)? {
+                let manifest_path = entry?.path();
+                if manifest_path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                // An unreadable manifest could hide live references, so stop here
+                let manifest = CheckpointManifest::load(&manifest_path)
+                    .with_context(|| format!("Failed to load manifest {:?}", manifest_path))?;
+                manifests.push((session_id.clone(), manifest));
+            }
+        }
+        Ok(manifests)
+    }
+
+    /// IDs of the sessions that have checkpoint storage in the project
+    pub fn session_ids(&self, project_id: &str) -> Result<Vec<String>> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, "");
+        let mut session_ids = Vec::new();
+        if !paths.timelines_dir.exists() {
+            return Ok(session_ids);
+        }
+        for entry in fs::read_dir(&paths.timelines_dir)? {
+            let entry = entry?;
+            if !entry.path().is_dir() {
+                continue;
+            }
+            // The shared pool lives next to the sessions
+            match entry.file_name().to_str() {
+                Some(name) if !name.starts_with('.') => session_ids.push(name.to_string()),
+                _ => {}
+            }
+        }
+        session_ids.sort();
+        Ok(session_ids)
+    }
+}
+
+/// Number of manifests referencing each live object
+fn refcounts(manifests: &[(String, CheckpointManifest)]) -> HashMap<String, usize> {
+    let mut refcounts = HashMap::new();
+    for (_, manifest) in manifests {
+        let hashes: HashSet<&String> = manifest
+            .files
+            .values()
+            .filter(|entry| !entry.deleted)
+            .map(|entry| &entry.hash)
+            .collect();
+        for hash in hashes {
+            *refcounts.entry(hash.clone()).or_insert(0) += 1;
+        }
+    }
+    refcounts
+}
+
+/// Reference counts of manifests plus the delta bases they depend on.
+///
+/// A delta keeps its base alive, however old the checkpoints using the base
+/// itself are.
+pub fn live_refcounts(
+    paths: &CheckpointPaths,
+    manifests: &[(String, CheckpointManifest)],
+) -> HashMap<String, usize> {
+    let mut refcounts = refcounts(manifests);
+    let mut pending: Vec<String> = refcounts.keys().cloned().collect();
+    while let Some(hash) = pending.pop() {
+        let base_hash = match DeltaHeader::read(&paths.content_object_file(&hash)) {
+            Ok(Some(header)) => header.base_hash,
+            _ => continue,
+        };
+        let count = refcounts.entry(base_hash.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            pending.push(base_hash);
+        }
+    }
+    refcounts
+}
+
+/// Whether a pool file was last written long enough ago to be collected
+fn is_past_grace_period(path: &std::path::Path) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age >= GC_GRACE_PERIOD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::{checkpoint, snapshot};
+    use crate::checkpoint::Checkpoint;
+    use std::fs::FileTimes;
+    use tempfile::TempDir;
+
+    fn age(path: &std::path::Path) {
+        let old = SystemTime::now() - GC_GRACE_PERIOD * 2;
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(old))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sessions_share_content_and_gc_respects_other_sessions() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        for session_id in ["s1", "s2"] {
+            storage.init_storage("project", session_id).unwrap();
+            storage
+                .save_checkpoint(
+                    "project",
+                    session_id,
+                    &Checkpoint {
+                        session_id: session_id.to_string(),
+                        ..checkpoint(&format!("{}-cp", session_id), None)
+                    },
+                    vec![
+                        snapshot("cp", "shared.rs", "shared content"),
+                        snapshot("cp", "own.rs", session_id),
+                    ],
+                    "",
+                )
+                .unwrap();
+        }
+
+        let stats = storage.pool_stats("project").unwrap();
+        assert_eq!(
+            (stats.sessions, stats.checkpoints, stats.objects),
+            (2, 2, 3)
+        );
+        assert_eq!(stats.per_session_bytes - stats.unique_bytes, 14);
+
+        // Drop s1's checkpoint: its own file goes, the shared one stays for s2
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "s1");
+        fs::remove_file(paths.manifest_file("s1-cp")).unwrap();
+        let own = paths.content_object_file(&snapshot("cp", "own.rs", "s1").hash);
+        let shared = paths.content_object_file(&snapshot("cp", "shared.rs", "shared content").hash);
+
+        // Fresh objects are protected by the grace period
+        assert_eq!(
+            storage.garbage_collect_content("project").unwrap().objects,
+            0
+        );
+
+        for entry in fs::read_dir(paths.content_pool_dir()).unwrap() {
+            age(&entry.unwrap().path());
+        }
+        assert_eq!(
+            storage.garbage_collect_content("project").unwrap().objects,
+            1
+        );
+        assert!(!own.exists());
+        assert!(shared.exists());
+        assert_eq!(storage.content_refcounts("project").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_gc_keeps_bases_of_fresh_deltas() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "session");
+        // Content that compresses poorly, so the delta is the smaller copy
+        let base: String = (0..2000u64)
+            .map(|i| format!("{:x}\n", i.wrapping_mul(0x9e37_79b9_7f4a_7c15)))
+            .collect();
+        let changed = format!("{}changed\n", base);
+        let base_hash = CheckpointStorage::calculate_file_hash(base.as_bytes());
+        let changed_hash = CheckpointStorage::calculate_file_hash(changed.as_bytes());
+        storage
+            .write_content(&paths, &base_hash, base.as_bytes(), None)
+            .unwrap();
+        storage
+            .write_content(&paths, &changed_hash, changed.as_bytes(), Some(&base_hash))
+            .unwrap();
+        let delta = paths.content_object_file(&changed_hash);
+        assert!(DeltaHeader::read(&delta).unwrap().is_some());
+
+        // An old base is kept while a delta against it may still be referenced
+        age(&paths.content_object_file(&base_hash));
+        assert_eq!(
+            storage.garbage_collect_content("project").unwrap().objects,
+            0
+        );
+        age(&delta);
+        assert_eq!(
+            storage.garbage_collect_content("project").unwrap().objects,
+            2
+        );
+    }
+
+    #[test]
+    fn test_session_pools_are_migrated() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "session");
+
+        // A pool written by an older version, inside the session directory
+        let legacy = paths.legacy_content_pool_dir();
+        fs::create_dir_all(&legacy).unwrap();
+        let hash = CheckpointStorage::calculate_file_hash(b"old");
+        fs::write(
+            legacy.join(&hash),
+            zstd::stream::encode_all(&b"old"[..], 3).unwrap(),
+        )
+        .unwrap();
+
+        storage.init_storage("project", "session").unwrap();
+
+        assert!(!legacy.exists());
+        assert_eq!(storage.read_content(&paths, &hash).unwrap(), b"old");
+    }
+}
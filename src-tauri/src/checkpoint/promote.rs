@@ -0,0 +1,249 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use super::{storage::CheckpointStorage, Checkpoint, CheckpointPaths, FileKind};
+use crate::multi_session::git_worktree::{self, SnapshotFile};
+
+/// Subject lines longer than this are cut short
+const MAX_SUBJECT_LENGTH: usize = 72;
+
+/// A checkpoint written to git as a commit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromotedCheckpoint {
+    pub checkpoint_id: String,
+    pub branch: String,
+    pub commit: String,
+    /// Commit the checkpoint was committed on top of, if any
+    pub parent_commit: Option<String>,
+    /// Whether the branch was created for the commit
+    pub created_branch: bool,
+    /// Number of files in the commit's snapshot of the project
+    pub files: usize,
+    /// Number of files the commit deletes
+    pub deleted_files: usize,
+}
+
+impl CheckpointStorage {
+    /// Commit a checkpoint's snapshot of the project to `branch` without
+    /// touching the working tree, the index or HEAD.
+    ///
+    /// An existing branch gets the commit on top of its tip. A new branch
+    /// starts from the commit the checkpoint was taken at, or from HEAD when
+    /// that was not recorded. The commit changes only the files the
+    /// checkpoint has and the ones the session deleted; files it never
+    /// snapshot, such as ones over the size limit, stay as they are.
+    pub fn promote_checkpoint(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        checkpoint_id: &str,
+        project_path: &Path,
+        branch: &str,
+    ) -> Result<PromotedCheckpoint> {
+        let (checkpoint, snapshots, _) =
+            self.load_checkpoint(project_id, session_id, checkpoint_id)?;
+
+        // Deleted files are the ones marked so, and the ones an ancestor had
+        // that are gone now, since a deletion is only marked once
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        let timeline = self.load_timeline(&paths)?;
+        let mut deleted: BTreeSet<PathBuf> = BTreeSet::new();
+        let mut next = checkpoint.parent_checkpoint_id.clone();
+        while let Some(id) = next {
+            if let Some(manifest) = self.read_manifest(&paths, &id)? {
+                deleted.extend(manifest.live_paths().cloned());
+            }
+            next = timeline
+                .find_checkpoint(&id)
+                .and_then(|node| node.checkpoint.parent_checkpoint_id.clone());
+        }
+        for snapshot in &snapshots {
+            if snapshot.is_deleted {
+                deleted.insert(snapshot.file_path.clone());
+            } else {
+                deleted.remove(&snapshot.file_path);
+            }
+        }
+        let deleted: Vec<PathBuf> = deleted.into_iter().collect();
+
+        // Git has no empty directories, so only files and symlinks are committed
+        let files: Vec<SnapshotFile> = snapshots
+            .into_iter()
+            .filter(|snapshot| !snapshot.is_deleted && snapshot.kind != FileKind::Directory)
+            .map(|snapshot| SnapshotFile {
+                path: snapshot.file_path,
+                executable: snapshot.permissions.is_some_and(|mode| mode & 0o111 != 0),
+                symlink: snapshot.kind == FileKind::Symlink,
+                content: snapshot.content,
+            })
+            .collect();
+
+        let start_point = checkpoint
+            .metadata
+            .git
+            .as_ref()
+            .and_then(|git| git.head.as_deref());
+        let committed = git_worktree::commit_snapshot(
+            project_path,
+            branch,
+            start_point,
+            &files,
+            &deleted,
+            &commit_message(&checkpoint),
+        )
+        .with_context(|| format!("Failed to commit checkpoint {}", checkpoint_id))?;
+
+        Ok(PromotedCheckpoint {
+            checkpoint_id: checkpoint_id.to_string(),
+            branch: branch.to_string(),
+            commit: committed.commit,
+            parent_commit: committed.parent,
+            created_branch: committed.created_branch,
+            files: files.len(),
+            deleted_files: deleted.len(),
+        })
+    }
+}
+
+/// Commit message for a checkpoint: its description, or else the first line
+/// of its prompt, as the subject, the rest of the prompt as the body, and a
+/// trailer naming the checkpoint
+pub fn commit_message(checkpoint: &Checkpoint) -> String {
+    let prompt = checkpoint.metadata.user_prompt.trim();
+    let (subject, body) = match checkpoint.description.as_deref().map(str::trim) {
+        Some(description) if !description.is_empty() => (description, prompt),
+        _ => match prompt.split_once('\n') {
+            Some((first_line, rest)) => (first_line, rest.trim()),
+            None => (prompt, ""),
+        },
+    };
+
+    let mut message = match subject.lines().next().map(str::trim) {
+        Some(line) if !line.is_empty() => line.to_string(),
+        _ => format!(
+            "Checkpoint {}",
+            checkpoint.id.get(..8).unwrap_or(&checkpoint.id)
+        ),
+    };
+    if message.chars().count() > MAX_SUBJECT_LENGTH {
+        message = message.chars().take(MAX_SUBJECT_LENGTH - 3).collect();
+        message.push_str("...");
+    }
+    if !body.is_empty() {
+        message.push_str("\n\n");
+        message.push_str(body);
+    }
+    message.push_str(&format!("\n\nCheckpoint: {}\n", checkpoint.id));
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::{checkpoint_with_prompt, snapshot};
+    use crate::checkpoint::{git::git, FileSnapshot};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn executable(path: &str, content: &str, permissions: u32) -> FileSnapshot {
+        FileSnapshot {
+            permissions: Some(permissions),
+            ..snapshot("checkpoint", path, content)
+        }
+    }
+
+    #[test]
+    fn test_promote_checkpoint_to_branch() {
+        let repo_dir = TempDir::new().unwrap();
+        let repo = repo_dir.path();
+        git(repo, &["init", "-q", "-b", "main"]).unwrap();
+        git(repo, &["config", "user.name", "Test"]).unwrap();
+        git(repo, &["config", "user.email", "test@example.com"]).unwrap();
+        fs::write(repo.join("README"), "readme").unwrap();
+        fs::create_dir_all(repo.join("app")).unwrap();
+        fs::write(repo.join("app/old.txt"), "old").unwrap();
+        fs::write(repo.join("app/notes.txt"), "notes").unwrap();
+        git(repo, &["add", "-A"]).unwrap();
+        git(repo, &["commit", "-q", "-m", "initial"]).unwrap();
+
+        // The project is a subdirectory of the repository
+        let claude_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(claude_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+        // notes.txt is deleted one checkpoint before the promoted one
+        let deleted = FileSnapshot {
+            is_deleted: true,
+            ..snapshot("second", "notes.txt", "")
+        };
+        for (id, parent, snapshots) in [
+            (
+                "first",
+                None,
+                vec![
+                    executable("main.rs", "fn main() {}", 0o644),
+                    executable("notes.txt", "notes", 0o644),
+                ],
+            ),
+            ("second", Some("first"), vec![deleted]),
+            (
+                "checkpoint",
+                Some("second"),
+                vec![executable("build.sh", "cargo build", 0o755)],
+            ),
+        ] {
+            storage
+                .save_checkpoint(
+                    "project",
+                    "session",
+                    &checkpoint_with_prompt(id, parent, "Add a build script\n\nKeep it small"),
+                    snapshots,
+                    "",
+                )
+                .unwrap();
+        }
+
+        let promoted = storage
+            .promote_checkpoint(
+                "project",
+                "session",
+                "checkpoint",
+                &repo.join("app"),
+                "checkpoint/build",
+            )
+            .unwrap();
+        assert!(promoted.created_branch);
+        assert_eq!((promoted.files, promoted.deleted_files), (2, 1));
+
+        let tree = git(repo, &["ls-tree", "-r", "checkpoint/build"]).unwrap();
+        assert!(tree.contains("100755 blob"));
+        assert!(tree.contains("app/build.sh"));
+        assert!(tree.contains("app/main.rs"));
+        assert!(tree.contains("README"));
+        // Files the session never snapshot are kept, deleted ones are not
+        assert!(tree.contains("app/old.txt"));
+        assert!(!tree.contains("app/notes.txt"));
+        let message = git(repo, &["log", "-1", "--format=%B", "checkpoint/build"]).unwrap();
+        assert_eq!(
+            message,
+            "Add a build script\n\nKeep it small\n\nCheckpoint: checkpoint"
+        );
+
+        // The working tree and the checked out branch are left alone
+        assert_eq!(git(repo, &["status", "--porcelain"]).unwrap(), "");
+        assert!(repo.join("app/old.txt").exists());
+
+        let result = storage.promote_checkpoint(
+            "project",
+            "session",
+            "checkpoint",
+            &repo.join("app"),
+            "checkpoint/build",
+        );
+        assert!(result.is_err());
+        let result = storage.promote_checkpoint("project", "session", "checkpoint", repo, "main");
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,417 @@
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{
+    diff::diff_file,
+    diff::DEFAULT_CONTEXT_LINES,
+    storage::CheckpointStorage,
+    walker::{read_entry, walk_project},
+};
+use super::{FileDiff, FileKind, FileSnapshot};
+
+/// What restoring a checkpoint will do to the working tree.
+///
+/// Every change carries a diff from the current disk contents to the restored
+/// state, so a deletion is a diff to empty and a creation a diff from empty.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestorePlan {
+    /// Checkpoint being restored
+    pub checkpoint_id: String,
+    /// Existing files whose content, type or permissions will be replaced
+    pub overwrite: Vec<FileDiff>,
+    /// Files that will be created
+    pub create: Vec<FileDiff>,
+    /// Files captured by the current checkpoint that will be deleted
+    pub delete: Vec<FileDiff>,
+    /// Files never captured by the current checkpoint that will be deleted
+    pub delete_untracked: Vec<FileDiff>,
+    /// Directories that will be removed because they end up empty
+    pub prune_dirs: Vec<PathBuf>,
+    /// Number of selected files that already match the checkpoint
+    pub unchanged: usize,
+}
+
+impl RestorePlan {
+    /// Whether restoring would leave the working tree as it is
+    pub fn is_empty(&self) -> bool {
+        self.overwrite.is_empty()
+            && self.create.is_empty()
+            && self.delete.is_empty()
+            && self.delete_untracked.is_empty()
+            && self.prune_dirs.is_empty()
+    }
+
+    /// Paths of all files the plan writes or deletes
+    pub fn changed_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.overwrite
+            .iter()
+            .chain(&self.create)
+            .chain(&self.delete)
+            .chain(&self.delete_untracked)
+            .map(|diff| &diff.path)
+    }
+}
+
+/// Selects project-relative paths, for partial restores and checkpoint search.
+///
+/// Each pattern is a glob (`src/**/*.rs`) or a plain path. A file matches when
+/// the pattern matches the file itself or any directory containing it, so
+/// `src/components` selects everything below that directory.
+#[derive(Debug, Clone)]
+pub struct PathFilter {
+    patterns: Vec<Pattern>,
+}
+
+impl PathFilter {
+    /// Build a filter from user-supplied paths or glob patterns
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|raw| {
+                let normalized = raw.trim().trim_start_matches("./").trim_end_matches('/');
+                if normalized.is_empty() {
+                    anyhow::bail!("Empty path pattern");
+                }
+                Pattern::new(normalized).with_context(|| format!("Invalid path pattern: {}", raw))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if patterns.is_empty() {
+            anyhow::bail!("No paths given");
+        }
+
+        Ok(Self { patterns })
+    }
+
+    /// Whether a project-relative path is selected by the filter
+    pub fn matches(&self, path: &Path) -> bool {
+        let options = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+
+        path.ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .any(|ancestor| {
+                self.patterns
+                    .iter()
+                    .any(|pattern| pattern.matches_path_with(ancestor, options))
+            })
+    }
+}
+
+/// Compute the restore plan for a checkpoint's snapshots against the files
+/// currently in `project_path`.
+///
+/// `tracked` lists the paths captured by the current checkpoint; deleting
+/// anything else loses work that was never checkpointed. With a `filter`,
+/// only selected paths are considered and only directories emptied by the
+/// plan's own deletions are pruned.
+///
+/// Ignored files and files over `max_file_size` are never part of a
+/// checkpoint, so they are left alone and keep their directories.
+pub fn plan_restore(
+    project_path: &Path,
+    checkpoint_id: &str,
+    file_snapshots: &[FileSnapshot],
+    tracked: &HashSet<PathBuf>,
+    filter: Option<&PathFilter>,
+    max_file_size: u64,
+) -> Result<RestorePlan> {
+    let selected = |path: &Path| filter.is_none_or(|f| f.matches(path));
+
+    let project_files = walk_project(project_path, max_file_size)?;
+    let current_files = project_files.files;
+
+    let targets: HashMap<&PathBuf, &FileSnapshot> = file_snapshots
+        .iter()
+        .filter(|snapshot| !snapshot.is_deleted && selected(&snapshot.file_path))
+        .map(|snapshot| (&snapshot.file_path, snapshot))
+        .collect();
+
+    let mut plan = RestorePlan {
+        checkpoint_id: checkpoint_id.to_string(),
+        overwrite: Vec::new(),
+        create: Vec::new(),
+        delete: Vec::new(),
+        delete_untracked: Vec::new(),
+        prune_dirs: Vec::new(),
+        unchanged: 0,
+    };
+
+    // Files the checkpoint wants to exist
+    for (path, snapshot) in &targets {
+        match read_entry(&project_path.join(path))? {
+            Some(current) => {
+                let same = current.kind == snapshot.kind
+                    && CheckpointStorage::calculate_file_hash(&current.content) == snapshot.hash
+                    && same_mode(current.mode, snapshot.permissions);
+                if same {
+                    plan.unchanged += 1;
+                } else {
+                    plan.overwrite.push(diff_file(
+                        path,
+                        &current.content,
+                        &snapshot.content,
+                        DEFAULT_CONTEXT_LINES,
+                    ));
+                }
+            }
+            None => {
+                plan.create.push(diff_file(
+                    path,
+                    &[],
+                    &snapshot.content,
+                    DEFAULT_CONTEXT_LINES,
+                ));
+            }
+        }
+    }
+
+    // Files on disk that the checkpoint does not have
+    let mut deleted_paths = Vec::new();
+    for path in &current_files {
+        if !selected(path) || targets.contains_key(path) {
+            continue;
+        }
+        let current = read_entry(&project_path.join(path))?
+            .map(|entry| entry.content)
+            .unwrap_or_default();
+        let diff = diff_file(path, &current, &[], DEFAULT_CONTEXT_LINES);
+        if tracked.contains(path) {
+            plan.delete.push(diff);
+        } else {
+            plan.delete_untracked.push(diff);
+        }
+        deleted_paths.push(path);
+    }
+
+    // Directories that end up without any file below them, unless the
+    // checkpoint recorded them as empty directories
+    let remaining_files = targets.keys().copied().chain(
+        current_files
+            .iter()
+            .filter(|path| !selected(path) || targets.contains_key(path)),
+    );
+    let mut occupied = HashSet::new();
+    for file in remaining_files {
+        occupied.extend(file.ancestors().skip(1).map(Path::to_path_buf));
+    }
+    occupied.extend(
+        targets
+            .values()
+            .filter(|snapshot| snapshot.kind == FileKind::Directory)
+            .map(|snapshot| snapshot.file_path.clone()),
+    );
+    let candidates: BTreeSet<PathBuf> = match filter {
+        None => project_files.dirs.into_iter().collect(),
+        Some(_) => deleted_paths
+            .iter()
+            .flat_map(|path| path.ancestors().skip(1))
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .collect(),
+    };
+
+    // A directory is only pruned if everything in it on disk goes away too,
+    // so ignored files keep their directories. Children sort after their
+    // parents, so walking backwards visits the deepest directories first.
+    let deleted: HashSet<&PathBuf> = deleted_paths.into_iter().collect();
+    let mut pruned = BTreeSet::new();
+    for dir in candidates.iter().rev() {
+        if occupied.contains(dir) {
+            continue;
+        }
+        let entries = match fs::read_dir(project_path.join(dir)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let emptied = entries.filter_map(|entry| entry.ok()).all(|entry| {
+            let rel = dir.join(entry.file_name());
+            deleted.contains(&rel) || pruned.contains(&rel)
+        });
+        if emptied {
+            pruned.insert(dir.clone());
+        }
+    }
+    plan.prune_dirs = pruned.into_iter().collect();
+
+    for list in [
+        &mut plan.overwrite,
+        &mut plan.create,
+        &mut plan.delete,
+        &mut plan.delete_untracked,
+    ] {
+        list.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    Ok(plan)
+}
+
+/// Whether a path on disk already has the permissions recorded in a
+/// snapshot. Older snapshots recorded the whole `st_mode`, so only the
+/// permission bits are compared.
+fn same_mode(current: Option<u32>, recorded: Option<u32>) -> bool {
+    match (current, recorded) {
+        (Some(current), Some(recorded)) => current & 0o7777 == recorded & 0o7777,
+        _ => true,
+    }
+}
+
+/// Write a snapshot to `full_path` so that its type, content and permissions
+/// match the snapshot exactly, replacing whatever is at the path.
+///
+/// A symlink in the way is removed rather than written through, and a
+/// directory in the way is only removed if it is empty.
+pub fn write_entry(full_path: &Path, snapshot: &FileSnapshot) -> Result<()> {
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create parent directories")?;
+    }
+
+    if let Ok(metadata) = fs::symlink_metadata(full_path) {
+        let file_type = metadata.file_type();
+        let in_the_way = match snapshot.kind {
+            FileKind::File => !file_type.is_file(),
+            FileKind::Symlink => true,
+            FileKind::Directory => !file_type.is_dir(),
+        };
+        if in_the_way {
+            if file_type.is_dir() {
+                fs::remove_dir(full_path).context("A non-empty directory is in the way")?;
+            } else {
+                fs::remove_file(full_path).context("Failed to remove the existing file")?;
+            }
+        }
+    }
+
+    match snapshot.kind {
+        FileKind::File => {
+            fs::write(full_path, &snapshot.content).context("Failed to write file")?
+        }
+        FileKind::Directory => {
+            fs::create_dir_all(full_path).context("Failed to create directory")?
+        }
+        FileKind::Symlink => create_symlink(&snapshot.content, full_path)?,
+    }
+
+    // Symlinks have no permissions of their own; setting them would change
+    // the target's
+    #[cfg(unix)]
+    if let (Some(mode), false) = (snapshot.permissions, snapshot.kind == FileKind::Symlink) {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(full_path, fs::Permissions::from_mode(mode & 0o7777))
+            .context("Failed to set file permissions")?;
+    }
+
+    Ok(())
+}
+
+/// Create a symlink pointing at the target recorded as a snapshot's content
+fn create_symlink(target: &[u8], link: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        std::os::unix::fs::symlink(OsStr::from_bytes(target), link)
+            .context("Failed to create symlink")
+    }
+    #[cfg(windows)]
+    {
+        let target = PathBuf::from(String::from_utf8_lossy(target).into_owned());
+        let resolved = link.parent().map(|parent| parent.join(&target));
+        let created = if resolved.is_some_and(|resolved| resolved.is_dir()) {
+            std::os::windows::fs::symlink_dir(&target, link)
+        } else {
+            std::os::windows::fs::symlink_file(&target, link)
+        };
+        created.context("Failed to create symlink")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support;
+
+    #[test]
+    fn test_path_filter_matches_files_directories_and_globs() {
+        let filter = PathFilter::new(&[
+            "src/components/".to_string(),
+            "./README.md".to_string(),
+            "tests/**/*.rs".to_string(),
+        ])
+        .unwrap();
+
+        assert!(filter.matches(Path::new("src/components/Button.tsx")));
+        assert!(filter.matches(Path::new("src/components/nested/Icon.tsx")));
+        assert!(filter.matches(Path::new("README.md")));
+        assert!(filter.matches(Path::new("tests/unit/parser.rs")));
+        assert!(!filter.matches(Path::new("src/main.rs")));
+        assert!(!filter.matches(Path::new("docs/README.md")));
+        assert!(!filter.matches(Path::new("tests/fixtures/data.json")));
+        assert!(PathFilter::new(&[]).is_err());
+    }
+
+    #[test]
+    fn test_plan_restore_classifies_changes() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project = temp_dir.path();
+        for (path, content) in [
+            ("same.txt", "same"),
+            ("changed.txt", "new\n"),
+            ("tracked.txt", "keep me?"),
+            ("scratch/notes.txt", "never checkpointed"),
+            ("scratch/cache.o", "ignored build output"),
+            ("old/only.txt", "stale"),
+            (".gitignore", "*.o\n"),
+        ] {
+            let full_path = project.join(path);
+            fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            fs::write(full_path, content).unwrap();
+        }
+
+        let snapshot = |path: &str, content: &str| test_support::snapshot("cp", path, content);
+        let snapshots = vec![
+            snapshot("same.txt", "same"),
+            snapshot("changed.txt", "old\n"),
+            snapshot("restored/again.txt", "back"),
+            snapshot(".gitignore", "*.o\n"),
+        ];
+        let tracked: HashSet<PathBuf> = [PathBuf::from("tracked.txt")].into_iter().collect();
+
+        let plan = plan_restore(project, "cp", &snapshots, &tracked, None, 0).unwrap();
+
+        let paths =
+            |diffs: &[FileDiff]| -> Vec<PathBuf> { diffs.iter().map(|d| d.path.clone()).collect() };
+        assert_eq!(plan.unchanged, 2);
+        assert_eq!(paths(&plan.overwrite), vec![PathBuf::from("changed.txt")]);
+        assert!(plan.overwrite[0]
+            .diff_content
+            .as_deref()
+            .unwrap()
+            .contains("-new\n+old"));
+        assert_eq!(
+            paths(&plan.create),
+            vec![PathBuf::from("restored/again.txt")]
+        );
+        assert_eq!(paths(&plan.delete), vec![PathBuf::from("tracked.txt")]);
+        assert_eq!(
+            paths(&plan.delete_untracked),
+            vec![
+                PathBuf::from("old/only.txt"),
+                PathBuf::from("scratch/notes.txt")
+            ]
+        );
+        // The ignored file keeps its directory alive
+        assert_eq!(plan.prune_dirs, vec![PathBuf::from("old")]);
+
+        // Nothing was touched
+        assert!(project.join("scratch/notes.txt").exists());
+    }
+}
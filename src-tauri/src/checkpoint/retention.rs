@@ -0,0 +1,662 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use super::{
+    delta::DeltaHeader, pool::live_refcounts, storage::CheckpointStorage, Checkpoint,
+    CheckpointPaths, SessionTimeline,
+};
+
+/// Keep one checkpoint per interval among checkpoints up to a certain age
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionTier {
+    /// Length of each interval; the newest checkpoint of an interval is kept
+    pub interval_seconds: u64,
+    /// Checkpoints at least this old fall through to the next tier
+    pub max_age_seconds: u64,
+}
+
+/// Which checkpoints of a project to keep as they age.
+///
+/// Pinned and tagged checkpoints, and those on a session's current branch,
+/// are kept regardless of the policy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPolicy {
+    /// Checkpoints younger than this are all kept
+    pub keep_all_seconds: u64,
+    /// Sparser tiers for older checkpoints, youngest first. Checkpoints
+    /// older than the last tier are removed.
+    pub tiers: Vec<RetentionTier>,
+    /// Bytes the project's checkpoints may use; the oldest are removed beyond it
+    pub max_project_bytes: Option<u64>,
+}
+
+impl Default for RetentionPolicy {
+    /// Everything from the last hour, hourly for a day and daily for a week
+    fn default() -> Self {
+        const HOUR: u64 = 60 * 60;
+        const DAY: u64 = 24 * HOUR;
+        Self {
+            keep_all_seconds: HOUR,
+            tiers: vec![
+                RetentionTier {
+                    interval_seconds: HOUR,
+                    max_age_seconds: DAY,
+                },
+                RetentionTier {
+                    interval_seconds: DAY,
+                    max_age_seconds: 7 * DAY,
+                },
+            ],
+            max_project_bytes: None,
+        }
+    }
+}
+
+/// Why retention removed a checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PruneReason {
+    /// A newer checkpoint represents its tier interval
+    Thinned,
+    /// Older than every tier of the policy
+    Expired,
+    /// Removed to bring the project under its byte quota
+    OverQuota,
+}
+
+/// A checkpoint removed by retention
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrunedCheckpoint {
+    pub session_id: String,
+    pub checkpoint_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub reason: PruneReason,
+}
+
+/// Outcome of applying a retention policy
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionReport {
+    /// Checkpoints removed, oldest first
+    pub pruned: Vec<PrunedCheckpoint>,
+    /// Pool objects garbage collection removed afterwards
+    pub reclaimed_objects: usize,
+    /// Bytes garbage collection freed
+    pub reclaimed_bytes: u64,
+    /// Bytes the project's checkpoints use after pruning, measured only when
+    /// the policy sets a quota
+    pub project_bytes: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Reject policies whose tiers cannot be evaluated
+    pub fn validate(&self) -> Result<()> {
+        if self.tiers.iter().any(|tier| tier.interval_seconds == 0) {
+            anyhow::bail!("Retention tier intervals must be at least one second");
+        }
+        Ok(())
+    }
+
+    /// Checkpoints of one session the tiers no longer keep, given the
+    /// checkpoints that must stay
+    fn thin(
+        &self,
+        timeline: &SessionTimeline,
+        protected: &HashSet<String>,
+        now: DateTime<Utc>,
+    ) -> Vec<(Checkpoint, PruneReason)> {
+        let mut checkpoints = Vec::new();
+        if let Some(root) = &timeline.root_node {
+            CheckpointStorage::collect_checkpoints(root, &mut checkpoints);
+        }
+        // Newest first, so the first checkpoint seen in an interval is the one kept
+        checkpoints.sort_by_key(|c| std::cmp::Reverse(c.timestamp));
+
+        let mut intervals = HashSet::new();
+        let mut thinned = Vec::new();
+        for checkpoint in checkpoints {
+            let age = (now - checkpoint.timestamp).num_seconds().max(0) as u64;
+            if age < self.keep_all_seconds {
+                continue;
+            }
+            let reason = match self
+                .tiers
+                .iter()
+                .enumerate()
+                .find(|(_, tier)| age < tier.max_age_seconds)
+            {
+                Some((i, tier)) => {
+                    let interval = checkpoint
+                        .timestamp
+                        .timestamp()
+                        .div_euclid(tier.interval_seconds as i64);
+                    if intervals.insert((i, interval)) {
+                        continue;
+                    }
+                    PruneReason::Thinned
+                }
+                None => PruneReason::Expired,
+            };
+            if !protected.contains(&checkpoint.id) {
+                thinned.push((checkpoint, reason));
+            }
+        }
+        thinned
+    }
+}
+
+impl CheckpointStorage {
+    /// The retention policy of a project, if one was set
+    pub fn load_retention_policy(&self, project_id: &str) -> Result<Option<RetentionPolicy>> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, "");
+        let policy_file = paths.retention_policy_file();
+        if !policy_file.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&policy_file).context("Failed to read retention policy")?;
+        let policy = serde_json::from_str(&json).context("Failed to parse retention policy")?;
+        Ok(Some(policy))
+    }
+
+    /// Set or, with `None`, clear the retention policy of a project
+    pub fn save_retention_policy(
+        &self,
+        project_id: &str,
+        policy: Option<&RetentionPolicy>,
+    ) -> Result<()> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, "");
+        let policy_file = paths.retention_policy_file();
+        match policy {
+            Some(policy) => {
+                policy.validate()?;
+                fs::create_dir_all(paths.pool_dir())
+                    .context("Failed to create content pool directory")?;
+                let json = serde_json::to_string_pretty(policy)
+                    .context("Failed to serialize retention policy")?;
+                fs::write(&policy_file, json).context("Failed to write retention policy")?;
+            }
+            None if policy_file.exists() => {
+                fs::remove_file(&policy_file).context("Failed to remove retention policy")?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Checkpoints of a session that retention must never remove: pinned and
+    /// tagged ones, the current checkpoint and its ancestors, and the two ends
+    /// of the last restore so it can still be undone
+    pub fn protected_checkpoints(timeline: &SessionTimeline) -> HashSet<String> {
+        let mut checkpoints = Vec::new();
+        if let Some(root) = &timeline.root_node {
+            Self::collect_checkpoints(root, &mut checkpoints);
+        }
+        let parents: HashMap<&str, Vec<&str>> = checkpoints
+            .iter()
+            .map(|c| {
+                let parents = c
+                    .parent_checkpoint_id
+                    .iter()
+                    .chain(&c.merge_parent_ids)
+                    .map(String::as_str)
+                    .collect();
+                (c.id.as_str(), parents)
+            })
+            .collect();
+
+        let mut protected: HashSet<String> = checkpoints
+            .iter()
+            .filter(|c| c.pinned || !c.tags.is_empty())
+            .map(|c| c.id.clone())
+            .collect();
+        if let Some(root) = &timeline.root_node {
+            protected.insert(root.checkpoint.id.clone());
+        }
+        if let Some(record) = &timeline.last_restore {
+            protected.insert(record.restored_checkpoint_id.clone());
+            protected.insert(record.pre_restore_checkpoint_id.clone());
+        }
+
+        // Everything the current checkpoint descends from, through merges too
+        let mut branch = HashSet::new();
+        let mut pending: Vec<&str> = timeline
+            .current_checkpoint_id
+            .as_deref()
+            .into_iter()
+            .collect();
+        while let Some(id) = pending.pop() {
+            if branch.insert(id) {
+                pending.extend(parents.get(id).into_iter().flatten());
+            }
+        }
+        protected.extend(branch.into_iter().map(String::from));
+        protected
+    }
+
+    /// Remove checkpoints from a session, handing each one's children to its
+    /// parent so the rest of the timeline stays connected.
+    ///
+    /// The root is never removed. Returns the IDs actually removed.
+    pub fn prune_checkpoints(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint_ids: &[String],
+    ) -> Result<Vec<String>> {
+        if checkpoint_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Only the removed checkpoints and their children are written, so
+        // checkpoints another manager adds meanwhile are kept. The timeline
+        // goes first; if removing files fails part way, what is left behind
+        // is unreachable rather than a node with missing data.
+        let (parents, removed, adopted, timeline) = {
+            let _lock = self.lock_timeline(paths)?;
+            let mut index = self.open_index(paths)?;
+            self.ensure_indexed(&mut index, paths)?;
+            let parents: HashMap<String, Option<String>> = index
+                .checkpoints(&paths.session_id)?
+                .into_iter()
+                .map(|c| (c.id, c.parent_checkpoint_id))
+                .collect();
+            let (removed, adopted) =
+                index.remove_checkpoints(&paths.session_id, checkpoint_ids, |checkpoint| {
+                    self.touched_files(paths, checkpoint)
+                })?;
+            if removed.is_empty() {
+                return Ok(removed);
+            }
+            let timeline = index
+                .timeline(&paths.session_id)?
+                .with_context(|| format!("Session {} has no timeline", paths.session_id))?;
+            Self::write_timeline_file(&paths.timeline_file, &timeline)?;
+            (parents, removed, adopted, timeline)
+        };
+        self.remove_checkpoints(paths, &removed)?;
+
+        // Removal left the adopted children with full transcripts; share
+        // them with their new parents again
+        for id in adopted {
+            let parent_id = match timeline.find_checkpoint(&id) {
+                Some(node) => node.checkpoint.parent_checkpoint_id.clone(),
+                None => continue,
+            };
+            if let Err(e) = self.reparent_checkpoint(paths, &id, parent_id.as_deref()) {
+                log::warn!(
+                    "Failed to move checkpoint {} to its new parent: {:#}",
+                    id,
+                    e
+                );
+            }
+        }
+
+        // Merges of a removed checkpoint now merge its nearest surviving ancestor
+        let removed_set: HashSet<&str> = removed.iter().map(String::as_str).collect();
+        let surviving_ancestor = |id: &str| -> Option<String> {
+            let mut next = Some(id.to_string());
+            while let Some(id) = next.take() {
+                if !removed_set.contains(id.as_str()) {
+                    return Some(id);
+                }
+                next = parents.get(&id).cloned().flatten();
+            }
+            None
+        };
+        let mut remaining = Vec::new();
+        if let Some(root) = &timeline.root_node {
+            Self::collect_checkpoints(root, &mut remaining);
+        }
+        for checkpoint in remaining {
+            if !checkpoint
+                .merge_parent_ids
+                .iter()
+                .any(|id| removed_set.contains(id.as_str()))
+            {
+                continue;
+            }
+            let mut merge_parent_ids: Vec<String> = Vec::new();
+            for id in checkpoint
+                .merge_parent_ids
+                .iter()
+                .filter_map(|id| surviving_ancestor(id))
+            {
+                if Some(&id) != checkpoint.parent_checkpoint_id.as_ref()
+                    && !merge_parent_ids.contains(&id)
+                {
+                    merge_parent_ids.push(id);
+                }
+            }
+            if let Err(e) = self.update_checkpoint(paths, &checkpoint.id, |c| {
+                c.merge_parent_ids = merge_parent_ids.clone();
+            }) {
+                log::warn!(
+                    "Failed to update merge parents of checkpoint {}: {:#}",
+                    checkpoint.id,
+                    e
+                );
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Apply a retention policy to every session of a project, then collect
+    /// the content nothing references any more
+    pub fn apply_retention(
+        &self,
+        project_id: &str,
+        policy: &RetentionPolicy,
+    ) -> Result<RetentionReport> {
+        policy.validate()?;
+        let project_paths = CheckpointPaths::new(&self.claude_dir, project_id, "");
+        let now = Utc::now();
+        let mut report = RetentionReport::default();
+
+        {
+            let _lock = self.lock_pool(&project_paths)?;
+
+            let mut timelines = Vec::new();
+            for session_id in self.session_ids(project_id)? {
+                let paths = CheckpointPaths::new(&self.claude_dir, project_id, &session_id);
+                let Some(timeline) = self.indexed_timeline(&paths)? else {
+                    continue;
+                };
+                let protected = Self::protected_checkpoints(&timeline);
+                for (checkpoint, reason) in policy.thin(&timeline, &protected, now) {
+                    report.pruned.push(PrunedCheckpoint {
+                        session_id: session_id.clone(),
+                        checkpoint_id: checkpoint.id,
+                        timestamp: checkpoint.timestamp,
+                        reason,
+                    });
+                }
+                timelines.push((session_id, timeline, protected));
+            }
+
+            if let Some(max_bytes) = policy.max_project_bytes {
+                let mut usage = ProjectUsage::measure(self, project_id)?;
+                for pruned in &report.pruned {
+                    usage.release(&pruned.checkpoint_id);
+                }
+
+                // The oldest unprotected checkpoints go first, whatever their session
+                let planned: HashSet<String> = report
+                    .pruned
+                    .iter()
+                    .map(|p| p.checkpoint_id.clone())
+                    .collect();
+                let mut candidates = Vec::new();
+                for (session_id, timeline, protected) in &timelines {
+                    let mut checkpoints = Vec::new();
+                    if let Some(root) = &timeline.root_node {
+                        Self::collect_checkpoints(root, &mut checkpoints);
+                    }
+                    candidates.extend(
+                        checkpoints
+                            .into_iter()
+                            .filter(|c| !protected.contains(&c.id) && !planned.contains(&c.id))
+                            .map(|c| (session_id.clone(), c)),
+                    );
+                }
+                candidates.sort_by_key(|(_, c)| c.timestamp);
+
+                for (session_id, checkpoint) in candidates {
+                    if usage.total <= max_bytes {
+                        break;
+                    }
+                    usage.release(&checkpoint.id);
+                    report.pruned.push(PrunedCheckpoint {
+                        session_id,
+                        checkpoint_id: checkpoint.id,
+                        timestamp: checkpoint.timestamp,
+                        reason: PruneReason::OverQuota,
+                    });
+                }
+                report.project_bytes = Some(usage.total);
+            }
+
+            report.pruned.sort_by_key(|p| p.timestamp);
+            for (session_id, _, _) in &timelines {
+                let ids: Vec<String> = report
+                    .pruned
+                    .iter()
+                    .filter(|p| &p.session_id == session_id)
+                    .map(|p| p.checkpoint_id.clone())
+                    .collect();
+                let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+                self.prune_checkpoints(&paths, &ids)?;
+            }
+        }
+
+        // Collection takes the pool lock itself
+        if !report.pruned.is_empty() {
+            let gc = self.garbage_collect_content(project_id)?;
+            report.reclaimed_objects = gc.objects;
+            report.reclaimed_bytes = gc.bytes;
+        }
+        Ok(report)
+    }
+
+    /// Point a checkpoint at a new parent, storing its messages relative to it
+    fn reparent_checkpoint(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint_id: &str,
+        parent_id: Option<&str>,
+    ) -> Result<()> {
+        let metadata_path = paths.checkpoint_metadata_file(checkpoint_id);
+        let metadata_json =
+            fs::read_to_string(&metadata_path).context("Failed to read checkpoint metadata")?;
+        let mut checkpoint: Checkpoint =
+            serde_json::from_str(&metadata_json).context("Failed to parse checkpoint metadata")?;
+        checkpoint.parent_checkpoint_id = parent_id.map(|id| id.to_string());
+
+        let messages = self.read_messages(paths, checkpoint_id)?;
+        self.write_checkpoint_record(paths, &checkpoint, &messages)
+    }
+}
+
+/// Bytes used by a project's checkpoints, updated as checkpoints are released
+struct ProjectUsage {
+    /// References to each live object, see [`live_refcounts`]
+    refcounts: HashMap<String, usize>,
+    /// Size and delta base of each pool object
+    objects: HashMap<String, (u64, Option<String>)>,
+    /// Distinct objects each checkpoint's manifest references
+    checkpoint_objects: HashMap<String, HashSet<String>>,
+    /// Metadata, messages and manifest size of each checkpoint
+    checkpoint_bytes: HashMap<String, u64>,
+    total: u64,
+}
+
+impl ProjectUsage {
+    /// Measure the project's usage; callers must hold the pool lock
+    fn measure(storage: &CheckpointStorage, project_id: &str) -> Result<Self> {
+        let project_paths = CheckpointPaths::new(&storage.claude_dir, project_id, "");
+        let manifests = storage.project_manifests(project_id)?;
+        let refcounts = live_refcounts(&project_paths, &manifests);
+
+        let mut objects = HashMap::new();
+        let pool_dir = project_paths.content_pool_dir();
+        if pool_dir.exists() {
+            for entry in fs::read_dir(&pool_dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                let hash = match path.file_name().and_then(|n| n.to_str()) {
+                    Some(hash) if path.is_file() && !hash.ends_with(".tmp") => hash.to_string(),
+                    _ => continue,
+                };
+                let base = DeltaHeader::read(&path)?.map(|header| header.base_hash);
+                objects.insert(hash, (entry.metadata()?.len(), base));
+            }
+        }
+
+        let mut usage = Self {
+            refcounts,
+            objects,
+            checkpoint_objects: HashMap::new(),
+            checkpoint_bytes: HashMap::new(),
+            total: 0,
+        };
+        for (hash, (size, _)) in &usage.objects {
+            if usage.refcounts.contains_key(hash) {
+                usage.total += size;
+            }
+        }
+        for (session_id, manifest) in manifests {
+            let paths = CheckpointPaths::new(&storage.claude_dir, project_id, &session_id);
+            let id = manifest.checkpoint_id;
+            let bytes = dir_size(&paths.checkpoint_dir(&id))
+                + fs::metadata(paths.manifest_file(&id)).map_or(0, |m| m.len());
+            usage.total += bytes;
+            usage.checkpoint_bytes.insert(id.clone(), bytes);
+            let hashes = manifest
+                .files
+                .into_values()
+                .filter(|entry| !entry.deleted)
+                .map(|entry| entry.hash)
+                .collect();
+            usage.checkpoint_objects.insert(id, hashes);
+        }
+        Ok(usage)
+    }
+
+    /// Account for a checkpoint being removed
+    fn release(&mut self, checkpoint_id: &str) {
+        self.total = self
+            .total
+            .saturating_sub(self.checkpoint_bytes.remove(checkpoint_id).unwrap_or(0));
+        let mut pending: Vec<String> = self
+            .checkpoint_objects
+            .remove(checkpoint_id)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        while let Some(hash) = pending.pop() {
+            let count = match self.refcounts.get_mut(&hash) {
+                Some(count) => count,
+                None => continue,
+            };
+            *count -= 1;
+            if *count > 0 {
+                continue;
+            }
+            self.refcounts.remove(&hash);
+            if let Some((size, base)) = self.objects.get(&hash) {
+                self.total = self.total.saturating_sub(*size);
+                pending.extend(base.clone());
+            }
+        }
+    }
+}
+
+/// Total size of the files below a directory
+fn dir_size(dir: &Path) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support;
+    use chrono::{Duration, DurationRound};
+    use tempfile::TempDir;
+
+    fn checkpoint(id: &str, parent: Option<&str>, timestamp: DateTime<Utc>) -> Checkpoint {
+        Checkpoint {
+            timestamp,
+            ..test_support::checkpoint(id, parent)
+        }
+    }
+
+    #[test]
+    fn test_retention_thins_tiers_and_enforces_quota() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "session");
+
+        // a -> e is the current branch; c and d share an hour on a side branch
+        let now = Utc::now();
+        let hour = (now - Duration::hours(5))
+            .duration_trunc(Duration::hours(1))
+            .unwrap();
+        let mut pinned = checkpoint("y", Some("a"), now - Duration::days(9));
+        pinned.pinned = true;
+        let checkpoints = [
+            (checkpoint("a", None, now - Duration::days(10)), "1"),
+            (checkpoint("x", Some("a"), now - Duration::days(9)), "1\nx"),
+            (pinned, "1\ny"),
+            (
+                checkpoint("c", Some("a"), hour + Duration::minutes(10)),
+                "1\n2",
+            ),
+            (
+                checkpoint("d", Some("c"), hour + Duration::minutes(20)),
+                "1\n2\n3",
+            ),
+            (
+                checkpoint("e", Some("a"), now - Duration::minutes(10)),
+                "1\ne",
+            ),
+        ];
+        for (checkpoint, messages) in &checkpoints {
+            storage
+                .save_checkpoint("project", "session", checkpoint, Vec::new(), messages)
+                .unwrap();
+        }
+
+        let mut policy = RetentionPolicy::default();
+        let report = storage.apply_retention("project", &policy).unwrap();
+        let pruned: Vec<(&str, PruneReason)> = report
+            .pruned
+            .iter()
+            .map(|p| (p.checkpoint_id.as_str(), p.reason))
+            .collect();
+        assert_eq!(
+            pruned,
+            [("x", PruneReason::Expired), ("c", PruneReason::Thinned)]
+        );
+        assert_eq!(report.project_bytes, None);
+
+        // d moved up to a, keeping its transcript
+        let timeline = storage.load_timeline(&paths).unwrap();
+        assert_eq!(timeline.total_checkpoints, 4);
+        assert!(timeline.find_checkpoint("c").is_none());
+        let d = timeline.find_checkpoint("d").unwrap();
+        assert_eq!(d.checkpoint.parent_checkpoint_id.as_deref(), Some("a"));
+        let (d, _, messages) = storage.load_checkpoint("project", "session", "d").unwrap();
+        assert_eq!(d.parent_checkpoint_id.as_deref(), Some("a"));
+        assert_eq!(messages, "1\n2\n3");
+        assert!(!paths.checkpoint_dir("c").exists());
+
+        // Only protected checkpoints survive a quota nothing fits in
+        policy.max_project_bytes = Some(0);
+        let report = storage.apply_retention("project", &policy).unwrap();
+        assert_eq!(report.pruned.len(), 1);
+        assert_eq!(report.pruned[0].checkpoint_id, "d");
+        assert_eq!(report.pruned[0].reason, PruneReason::OverQuota);
+        assert!(report.project_bytes.unwrap() > 0);
+        let timeline = storage.load_timeline(&paths).unwrap();
+        for id in ["a", "y", "e"] {
+            assert!(timeline.find_checkpoint(id).is_some());
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use super::{storage::CheckpointStorage, Checkpoint, CheckpointKind, FileSnapshot};
+
+/// How a restore treats the Claude conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreMode {
+    /// Rewrite the current session's JSONL with the checkpoint's messages
+    #[default]
+    InPlace,
+    /// Leave the current session alone and continue from the checkpoint in a
+    /// new session
+    NewSession,
+}
+
+/// Rewrite checkpoint messages for a new session.
+///
+/// Keeps the messages up to and including `message_index` and points every
+/// message that carries a `sessionId` at `session_id`. Lines that are not
+/// JSON objects are kept as they are.
+pub fn rewind_messages(messages: &str, message_index: usize, session_id: &str) -> String {
+    let mut rewound = Vec::new();
+    for line in messages
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .take(message_index + 1)
+    {
+        match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(serde_json::Value::Object(mut message)) => {
+                if message.contains_key("sessionId") {
+                    message.insert(
+                        "sessionId".to_string(),
+                        serde_json::Value::String(session_id.to_string()),
+                    );
+                }
+                rewound.push(serde_json::Value::Object(message).to_string());
+            }
+            _ => rewound.push(line.to_string()),
+        }
+    }
+    rewound.join("\n")
+}
+
+/// Write a checkpoint's conversation to the new Claude session `session_id`
+/// under `~/.claude/projects/<project>`, which `resume_claude_code` can
+/// continue from.
+pub fn write_rewound_session(
+    claude_dir: &Path,
+    checkpoint: &Checkpoint,
+    messages: &str,
+    session_id: &str,
+) -> Result<()> {
+    let project_dir = claude_dir.join("projects").join(&checkpoint.project_id);
+    fs::create_dir_all(&project_dir).context("Failed to create project directory")?;
+
+    let mut content = rewind_messages(messages, checkpoint.message_index, session_id);
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    fs::write(project_dir.join(format!("{}.jsonl", session_id)), content)
+        .context("Failed to write rewound session")
+}
+
+/// Start the timeline of the rewound session `session_id` with a copy of
+/// `checkpoint`, so the new session can be restored to where it began.
+///
+/// The copy has the checkpoint's files and the rewound messages; its
+/// description names the checkpoint it continues.
+pub fn seed_rewound_timeline(
+    storage: &CheckpointStorage,
+    checkpoint: &Checkpoint,
+    file_snapshots: Vec<FileSnapshot>,
+    messages: &str,
+    session_id: &str,
+) -> Result<Checkpoint> {
+    storage.init_storage(&checkpoint.project_id, session_id)?;
+
+    let seed_id = CheckpointStorage::generate_checkpoint_id();
+    let seed = Checkpoint {
+        id: seed_id.clone(),
+        session_id: session_id.to_string(),
+        parent_checkpoint_id: None,
+        timestamp: chrono::Utc::now(),
+        description: Some(format!(
+            "Continued from checkpoint {}",
+            checkpoint.id.get(..8).unwrap_or(&checkpoint.id)
+        )),
+        kind: CheckpointKind::Regular,
+        pinned: false,
+        tags: Vec::new(),
+        merge_parent_ids: Vec::new(),
+        ..checkpoint.clone()
+    };
+    let file_snapshots = file_snapshots
+        .into_iter()
+        .map(|snapshot| FileSnapshot {
+            checkpoint_id: seed_id.clone(),
+            ..snapshot
+        })
+        .collect();
+    let messages = rewind_messages(messages, checkpoint.message_index, session_id);
+
+    let result = storage.save_checkpoint(
+        &checkpoint.project_id,
+        session_id,
+        &seed,
+        file_snapshots,
+        &messages,
+    )?;
+    if let Some(warning) = result.warnings.first() {
+        anyhow::bail!("Failed to seed the new session: {}", warning);
+    }
+    Ok(seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewind_messages_truncates_and_renames_session() {
+        let messages = [
+            r#"{"type":"user","sessionId":"old","uuid":"1","message":{"content":"hi"}}"#,
+            r#"{"type":"assistant","sessionId":"old","uuid":"2"}"#,
+            r#"{"type":"summary","summary":"no session id"}"#,
+            r#"{"type":"user","sessionId":"old","uuid":"3"}"#,
+        ]
+        .join("\n");
+
+        let rewound = rewind_messages(&messages, 2, "new");
+        let lines: Vec<serde_json::Value> = rewound
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["sessionId"], "new");
+        assert_eq!(lines[0]["message"]["content"], "hi");
+        assert_eq!(lines[1]["sessionId"], "new");
+        assert!(lines[2].get("sessionId").is_none());
+    }
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+
+/// How the thresholds and groups of [`AutoCheckpointRules`] combine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleCombinator {
+    /// Checkpoint when any threshold is reached
+    #[default]
+    Any,
+    /// Checkpoint only when every threshold is reached
+    All,
+}
+
+/// Thresholds for the `Rules` auto-checkpoint strategy.
+///
+/// Each threshold is measured since the current checkpoint; unset thresholds
+/// and empty groups are ignored, and a rule set without any threshold never
+/// triggers. Groups nest rule sets with their own combinator, so
+/// "30 minutes, or both 20k tokens and 5 files" is an `Any` set with an
+/// elapsed threshold and an `All` group.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoCheckpointRules {
+    /// How the thresholds below combine
+    #[serde(default)]
+    pub combinator: RuleCombinator,
+    /// Wall-clock seconds since the last checkpoint
+    pub elapsed_seconds: Option<u64>,
+    /// Tokens consumed since the last checkpoint
+    pub tokens: Option<u64>,
+    /// Files changed since the last checkpoint
+    pub changed_files: Option<usize>,
+    /// Lines added or removed since the last checkpoint
+    pub changed_lines: Option<usize>,
+    /// Nested rule sets, each counting as one threshold of this set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<AutoCheckpointRules>,
+}
+
+/// What happened in a session since its current checkpoint
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleProgress {
+    pub elapsed_seconds: u64,
+    pub tokens: u64,
+    pub changed_files: usize,
+    /// Only measured when a rule needs it, since it means diffing files
+    pub changed_lines: usize,
+}
+
+impl AutoCheckpointRules {
+    /// Whether line counts have to be measured to evaluate the rules
+    pub fn needs_line_counts(&self) -> bool {
+        self.changed_lines.is_some() || self.groups.iter().any(Self::needs_line_counts)
+    }
+
+    /// Whether the rule set has no threshold at all, in itself or its groups
+    pub fn is_empty(&self) -> bool {
+        self.elapsed_seconds.is_none()
+            && self.tokens.is_none()
+            && self.changed_files.is_none()
+            && self.changed_lines.is_none()
+            && self.groups.iter().all(Self::is_empty)
+    }
+
+    /// Whether the progress since the last checkpoint calls for a new one
+    pub fn is_met(&self, progress: &RuleProgress) -> bool {
+        let mut checks = [
+            self.elapsed_seconds
+                .map(|limit| progress.elapsed_seconds >= limit),
+            self.tokens.map(|limit| progress.tokens >= limit),
+            self.changed_files
+                .map(|limit| progress.changed_files >= limit),
+            self.changed_lines
+                .map(|limit| progress.changed_lines >= limit),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(
+            self.groups
+                .iter()
+                .filter(|group| !group.is_empty())
+                .map(|group| group.is_met(progress)),
+        )
+        .peekable();
+
+        if checks.peek().is_none() {
+            return false;
+        }
+        match self.combinator {
+            RuleCombinator::Any => checks.any(|met| met),
+            RuleCombinator::All => checks.all(|met| met),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_combine_thresholds() {
+        let progress = RuleProgress {
+            elapsed_seconds: 600,
+            tokens: 20_000,
+            changed_files: 2,
+            changed_lines: 40,
+        };
+        let mut rules = AutoCheckpointRules {
+            combinator: RuleCombinator::Any,
+            elapsed_seconds: Some(900),
+            tokens: Some(10_000),
+            changed_files: None,
+            changed_lines: Some(100),
+            groups: Vec::new(),
+        };
+        assert!(rules.is_met(&progress));
+
+        rules.combinator = RuleCombinator::All;
+        assert!(!rules.is_met(&progress));
+        rules.elapsed_seconds = Some(300);
+        rules.changed_lines = Some(40);
+        assert!(rules.is_met(&progress));
+
+        // No thresholds means no automatic checkpoints
+        assert!(!AutoCheckpointRules::default().is_met(&progress));
+    }
+
+    #[test]
+    fn test_rules_nest_groups() {
+        let progress = RuleProgress {
+            elapsed_seconds: 600,
+            tokens: 20_000,
+            changed_files: 2,
+            changed_lines: 40,
+        };
+        // 30 minutes, or both 10k tokens and 5 changed files
+        let mut rules = AutoCheckpointRules {
+            combinator: RuleCombinator::Any,
+            elapsed_seconds: Some(1800),
+            groups: vec![AutoCheckpointRules {
+                combinator: RuleCombinator::All,
+                tokens: Some(10_000),
+                changed_files: Some(5),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(!rules.is_met(&progress));
+        assert!(!rules.needs_line_counts());
+
+        rules.groups[0].changed_files = Some(2);
+        assert!(rules.is_met(&progress));
+
+        // An empty group is ignored rather than counted as unmet
+        rules.combinator = RuleCombinator::All;
+        rules.elapsed_seconds = None;
+        rules.groups.push(AutoCheckpointRules::default());
+        assert!(rules.is_met(&progress));
+
+        rules.groups[1].changed_lines = Some(100);
+        assert!(rules.needs_line_counts());
+        assert!(!rules.is_met(&progress));
+    }
+}
@@ -0,0 +1,291 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use super::{
+    manifest::CheckpointManifest, restore::PathFilter, storage::CheckpointStorage, Checkpoint,
+    CheckpointPaths,
+};
+
+/// What to look for in checkpoints; every criterion that is set must match
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckpointQuery {
+    /// Case-insensitive text in the prompt or description
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Tags the checkpoint must all carry
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Paths or glob patterns, one of which a file the checkpoint touched
+    /// must match
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Only checkpoints created at or after this time
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    /// Only checkpoints created before this time
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+    /// Only pinned, or only unpinned, checkpoints
+    #[serde(default)]
+    pub pinned: Option<bool>,
+    /// Return at most this many matches
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// A checkpoint found by [`CheckpointStorage::search_checkpoints`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckpointMatch {
+    pub checkpoint: Checkpoint,
+    /// Files changed since the parent checkpoint that match the query's
+    /// paths; empty when the query has none
+    pub touched_files: Vec<PathBuf>,
+}
+
+impl CheckpointStorage {
+    /// Search the checkpoints of one session, or of every session of the
+    /// project, newest first
+    pub fn search_checkpoints(
+        &self,
+        project_id: &str,
+        session_id: Option<&str>,
+        query: &CheckpointQuery,
+    ) -> Result<Vec<CheckpointMatch>> {
+        let path_filter = if query.paths.is_empty() {
+            None
+        } else {
+            Some(PathFilter::new(&query.paths)?)
+        };
+        let text = query.text.as_ref().map(|text| text.to_lowercase());
+
+        let session_ids = match session_id {
+            Some(session_id) => vec![session_id.to_string()],
+            None => self.session_ids(project_id)?,
+        };
+
+        let project_paths = CheckpointPaths::new(&self.claude_dir, project_id, "");
+        let mut index = self.open_index(&project_paths)?;
+        let mut indexed = Vec::new();
+        for session_id in session_ids {
+            let paths = CheckpointPaths::new(&self.claude_dir, project_id, &session_id);
+            self.ensure_indexed(&mut index, &paths)?;
+            if index.has_session(&session_id)? {
+                indexed.push(session_id);
+            }
+        }
+
+        let mut matches = Vec::new();
+        for (session_id, checkpoint) in index.find_checkpoints(&indexed, query)? {
+            if query.limit.is_some_and(|limit| matches.len() >= limit) {
+                break;
+            }
+            if !matches_metadata(&checkpoint, query, text.as_deref()) {
+                continue;
+            }
+
+            let touched_files = match &path_filter {
+                Some(filter) => {
+                    let touched: Vec<PathBuf> = index
+                        .touched_paths(&session_id, &checkpoint.id)?
+                        .into_iter()
+                        .filter(|path| filter.matches(path))
+                        .collect();
+                    if touched.is_empty() {
+                        continue;
+                    }
+                    touched
+                }
+                None => Vec::new(),
+            };
+            matches.push(CheckpointMatch {
+                checkpoint,
+                touched_files,
+            });
+        }
+        Ok(matches)
+    }
+
+    /// Files a checkpoint added, changed or deleted relative to its parent
+    pub fn touched_files(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint: &Checkpoint,
+    ) -> Result<Vec<PathBuf>> {
+        let manifest = match self.read_manifest(paths, &checkpoint.id)? {
+            Some(manifest) => manifest,
+            None => return Ok(Vec::new()),
+        };
+        let parent = match &checkpoint.parent_checkpoint_id {
+            Some(parent_id) => self.read_manifest(paths, parent_id)?,
+            None => None,
+        };
+        Ok(changed_paths(&manifest, parent.as_ref()))
+    }
+}
+
+/// Whether a checkpoint passes the criteria that need nothing but its metadata
+fn matches_metadata(checkpoint: &Checkpoint, query: &CheckpointQuery, text: Option<&str>) -> bool {
+    if query
+        .since
+        .is_some_and(|since| checkpoint.timestamp < since)
+        || query
+            .until
+            .is_some_and(|until| checkpoint.timestamp >= until)
+        || query
+            .pinned
+            .is_some_and(|pinned| checkpoint.pinned != pinned)
+    {
+        return false;
+    }
+    if !query
+        .tags
+        .iter()
+        .all(|tag| checkpoint.tags.iter().any(|t| t == tag.trim()))
+    {
+        return false;
+    }
+    match text {
+        Some(text) => {
+            checkpoint
+                .metadata
+                .user_prompt
+                .to_lowercase()
+                .contains(text)
+                || checkpoint
+                    .description
+                    .as_ref()
+                    .is_some_and(|d| d.to_lowercase().contains(text))
+        }
+        None => true,
+    }
+}
+
+/// Paths whose entry differs between a manifest and its parent's
+fn changed_paths(
+    manifest: &CheckpointManifest,
+    parent: Option<&CheckpointManifest>,
+) -> Vec<PathBuf> {
+    let mut changed = BTreeSet::new();
+    for (path, entry) in &manifest.files {
+        let previous = parent.and_then(|parent| parent.files.get(path));
+        if previous != Some(entry) && !(previous.is_none() && entry.deleted) {
+            changed.insert(path.clone());
+        }
+    }
+    // Files that disappeared from the manifest without a deletion marker
+    if let Some(parent) = parent {
+        for path in parent.live_paths() {
+            if !manifest.files.contains_key(path) {
+                changed.insert(path.clone());
+            }
+        }
+    }
+    changed.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::{checkpoint_with_prompt, snapshot};
+    use chrono::Duration;
+    use tempfile::TempDir;
+
+    fn checkpoint(id: &str, parent: Option<&str>, prompt: &str, age_hours: i64) -> Checkpoint {
+        Checkpoint {
+            timestamp: Utc::now() - Duration::hours(age_hours),
+            ..checkpoint_with_prompt(id, parent, prompt)
+        }
+    }
+
+    #[test]
+    fn test_search_by_tags_prompt_paths_and_dates() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+
+        let saves = [
+            ("a", None, "Add the parser", 5, ("src/parser.rs", "v1")),
+            (
+                "b",
+                Some("a"),
+                "Fix parser tests",
+                3,
+                ("tests/parser.rs", "v1"),
+            ),
+            (
+                "c",
+                Some("b"),
+                "Refactor the lexer",
+                1,
+                ("src/lexer.rs", "v1"),
+            ),
+        ];
+        for (id, parent, prompt, age, (path, content)) in saves {
+            storage
+                .save_checkpoint(
+                    "project",
+                    "session",
+                    &checkpoint(id, parent, prompt, age),
+                    vec![snapshot(id, path, content)],
+                    "",
+                )
+                .unwrap();
+        }
+        for id in ["a", "b"] {
+            storage
+                .set_checkpoint_tags("project", "session", id, &[" tests-green ".to_string()])
+                .unwrap();
+        }
+        let pinned = storage
+            .set_checkpoint_pinned("project", "session", "c", true)
+            .unwrap();
+        assert!(pinned.pinned);
+
+        let search = |query: CheckpointQuery| -> Vec<String> {
+            storage
+                .search_checkpoints("project", None, &query)
+                .unwrap()
+                .into_iter()
+                .map(|m| m.checkpoint.id)
+                .collect()
+        };
+
+        // The last checkpoint where tests passed comes first
+        let query = CheckpointQuery {
+            tags: vec!["tests-green".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(search(query), ["b", "a"]);
+
+        let query = CheckpointQuery {
+            text: Some("PARSER".to_string()),
+            since: Some(Utc::now() - Duration::hours(4)),
+            ..Default::default()
+        };
+        assert_eq!(search(query), ["b"]);
+
+        let query = CheckpointQuery {
+            paths: vec!["src/".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(search(query), ["c", "a"]);
+
+        let query = CheckpointQuery {
+            pinned: Some(true),
+            ..Default::default()
+        };
+        let matches = storage
+            .search_checkpoints("project", Some("session"), &query)
+            .unwrap();
+        assert_eq!(matches.len(), 1);
+
+        // Labels are kept in the checkpoint's metadata as well as the timeline
+        let (b, _, _) = storage.load_checkpoint("project", "session", "b").unwrap();
+        assert_eq!(b.tags, ["tests-green"]);
+    }
+}
@@ -1,14 +1,27 @@
 use anyhow::{Context, Result};
+use serde::Deserialize;
 use sha2::{Digest, Sha256};
-use std::fs;
+use std::fs::{self, File, OpenOptions};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use uuid::Uuid;
 use zstd::stream::{decode_all, encode_all};
 
 use super::{
-    Checkpoint, CheckpointPaths, CheckpointResult, FileSnapshot, SessionTimeline, TimelineNode,
+    delta::{decode_delta, encode_delta, DeltaHeader, MAX_DELTA_CHAIN},
+    manifest::{CheckpointManifest, ManifestEntry},
+    Checkpoint, CheckpointPaths, CheckpointResult, FileKind, FileSnapshot, SessionTimeline,
+    TimelineNode,
 };
 
+/// Stack size of the threads parsing and writing a `timeline.json`
+const TIMELINE_THREAD_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Exclusive lock on a session's timeline, released when dropped
+pub struct TimelineLock {
+    _file: File,
+}
+
 /// Manages checkpoint storage operations
 pub struct CheckpointStorage {
     pub claude_dir: PathBuf,
@@ -24,6 +37,11 @@ impl CheckpointStorage {
         }
     }
 
+    /// zstd level used for everything this storage writes
+    pub fn compression_level(&self) -> i32 {
+        self.compression_level
+    }
+
     /// Initialize checkpoint storage for a session
     pub fn init_storage(&self, project_id: &str, session_id: &str) -> Result<()> {
         let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
@@ -34,15 +52,56 @@ impl CheckpointStorage {
         fs::create_dir_all(&paths.files_dir).context("Failed to create files directory")?;
 
         // Initialize empty timeline if it doesn't exist
-        if !paths.timeline_file.exists() {
-            let timeline = SessionTimeline::new(session_id.to_string());
-            self.save_timeline(&paths.timeline_file, &timeline)?;
+        {
+            let _lock = self.lock_timeline(&paths)?;
+            let mut index = self.open_index(&paths)?;
+            self.ensure_indexed(&mut index, &paths)?;
+            if !index.has_session(session_id)? {
+                let timeline = SessionTimeline::new(session_id.to_string());
+                self.save_timeline(&paths, &timeline)?;
+            }
+        }
+
+        // Convert sessions written with per-file reference JSON or their own content pool
+        if paths.legacy_refs_dir().exists() || paths.legacy_content_pool_dir().exists() {
+            let _lock = self.lock_pool(&paths)?;
+
+            let migrated = self.migrate_legacy_refs(&paths)?;
+            if migrated > 0 {
+                log::info!(
+                    "Migrated {} checkpoints of session {} to manifests",
+                    migrated,
+                    session_id
+                );
+            }
+
+            let moved = self.migrate_session_pool(&paths)?;
+            if moved > 0 {
+                log::info!(
+                    "Moved {} objects of session {} to the shared content pool",
+                    moved,
+                    session_id
+                );
+            }
+        }
+
+        // Convert checkpoints that store a full copy of the transcript
+        let migrated = self.migrate_full_messages(&paths)?;
+        if migrated > 0 {
+            log::info!(
+                "Migrated messages of {} checkpoints of session {} to incremental storage",
+                migrated,
+                session_id
+            );
         }
 
         Ok(())
     }
 
-    /// Save a checkpoint to disk
+    /// Save a checkpoint to disk.
+    ///
+    /// Its files are written first and its timeline rows last, in one
+    /// transaction, so the timeline only lists checkpoints that are complete.
     pub fn save_checkpoint(
         &self,
         project_id: &str,
@@ -52,31 +111,36 @@ impl CheckpointStorage {
         messages: &str, // JSONL content up to checkpoint
     ) -> Result<CheckpointResult> {
         let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
-        let checkpoint_dir = paths.checkpoint_dir(&checkpoint.id);
 
-        // Create checkpoint directory
-        fs::create_dir_all(&checkpoint_dir).context("Failed to create checkpoint directory")?;
+        // Save checkpoint metadata and messages
+        self.write_checkpoint_record(&paths, checkpoint, messages)?;
 
-        // Save checkpoint metadata
-        let metadata_path = paths.checkpoint_metadata_file(&checkpoint.id);
-        let metadata_json = serde_json::to_string_pretty(checkpoint)
-            .context("Failed to serialize checkpoint metadata")?;
-        fs::write(&metadata_path, metadata_json).context("Failed to write checkpoint metadata")?;
-
-        // Save messages (compressed)
-        let messages_path = paths.checkpoint_messages_file(&checkpoint.id);
-        let compressed_messages = encode_all(messages.as_bytes(), self.compression_level)
-            .context("Failed to compress messages")?;
-        fs::write(&messages_path, compressed_messages)
-            .context("Failed to write compressed messages")?;
+        // Start from the parent's manifest so unchanged files carry over
+        let mut manifest = match &checkpoint.parent_checkpoint_id {
+            Some(parent_id) => match self.read_manifest(&paths, parent_id)? {
+                Some(parent) => parent.inherit(&checkpoint.id),
+                None => CheckpointManifest::new(&checkpoint.id),
+            },
+            None => CheckpointManifest::new(&checkpoint.id),
+        };
 
         // Save file snapshots
         let mut warnings = Vec::new();
         let mut files_processed = 0;
 
         for snapshot in &file_snapshots {
-            match self.save_file_snapshot(&paths, snapshot) {
-                Ok(_) => files_processed += 1,
+            // The parent's version of the same path is the natural delta base
+            let base_hash = manifest
+                .files
+                .get(&snapshot.file_path)
+                .filter(|entry| !entry.deleted)
+                .map(|entry| entry.hash.clone());
+
+            match self.save_file_snapshot(&paths, snapshot, base_hash.as_deref()) {
+                Ok(_) => {
+                    manifest.apply_snapshot(snapshot);
+                    files_processed += 1;
+                }
                 Err(e) => warnings.push(format!(
                     "Failed to save {}: {}",
                     snapshot.file_path.display(),
@@ -85,64 +149,143 @@ impl CheckpointStorage {
             }
         }
 
+        manifest.save(&paths.manifest_file(&checkpoint.id))?;
+
         // Update timeline
-        self.update_timeline_with_checkpoint(&paths.timeline_file, checkpoint, &file_snapshots)?;
+        self.update_timeline_with_checkpoint(&paths, checkpoint, &file_snapshots)?;
 
         Ok(CheckpointResult {
             checkpoint: checkpoint.clone(),
             files_processed,
             warnings,
+            session_id: None,
+            retention: None,
         })
     }
 
-    /// Save a single file snapshot
-    fn save_file_snapshot(&self, paths: &CheckpointPaths, snapshot: &FileSnapshot) -> Result<()> {
+    /// Write a checkpoint's metadata and compressed messages into its directory
+    pub fn write_checkpoint_record(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint: &Checkpoint,
+        messages: &str,
+    ) -> Result<()> {
+        fs::create_dir_all(paths.checkpoint_dir(&checkpoint.id))
+            .context("Failed to create checkpoint directory")?;
+
+        // Save checkpoint metadata
+        let metadata_path = paths.checkpoint_metadata_file(&checkpoint.id);
+        let metadata_json = serde_json::to_string_pretty(checkpoint)
+            .context("Failed to serialize checkpoint metadata")?;
+        fs::write(&metadata_path, metadata_json).context("Failed to write checkpoint metadata")?;
+
+        // Save the messages appended since the parent (compressed)
+        self.write_messages(
+            paths,
+            &checkpoint.id,
+            checkpoint.parent_checkpoint_id.as_deref(),
+            messages,
+        )
+    }
+
+    /// Save a single file snapshot's content to the content pool
+    fn save_file_snapshot(
+        &self,
+        paths: &CheckpointPaths,
+        snapshot: &FileSnapshot,
+        base_hash: Option<&str>,
+    ) -> Result<()> {
+        // Deleted files have no content to store
+        if snapshot.is_deleted {
+            return Ok(());
+        }
+
+        self.write_content(paths, &snapshot.hash, &snapshot.content, base_hash)
+    }
+
+    /// Store content in the project's shared content pool under its hash.
+    ///
+    /// With a `base_hash`, usually the previous version of the same file, the
+    /// content is stored as a delta against that object when this is smaller
+    /// and the delta chain stays within [`MAX_DELTA_CHAIN`].
+    pub fn write_content(
+        &self,
+        paths: &CheckpointPaths,
+        hash: &str,
+        content: &[u8],
+        base_hash: Option<&str>,
+    ) -> Result<()> {
         // Use content-addressable storage: store files by their hash
         // This prevents duplication of identical file content across checkpoints
-        let content_pool_dir = paths.files_dir.join("content_pool");
-        fs::create_dir_all(&content_pool_dir).context("Failed to create content pool directory")?;
+        fs::create_dir_all(paths.content_pool_dir())
+            .context("Failed to create content pool directory")?;
 
         // Store the actual content in the content pool
-        let content_file = content_pool_dir.join(&snapshot.hash);
+        let content_file = paths.content_object_file(hash);
 
         // Only write the content if it doesn't already exist
-        if !content_file.exists() {
+        if content_file.exists() {
+            // Refresh the object so a concurrent garbage collection treats it,
+            // and the delta bases it is stored against, as fresh until the
+            // manifest referencing it has been saved
+            File::options()
+                .write(true)
+                .open(&content_file)
+                .and_then(|file| file.set_modified(SystemTime::now()))
+                .context("Failed to refresh file content in pool")?;
+        } else {
             // Compress and save file content
-            let compressed_content =
-                encode_all(snapshot.content.as_bytes(), self.compression_level)
-                    .context("Failed to compress file content")?;
-            fs::write(&content_file, compressed_content)
+            let full_content = encode_all(content, self.compression_level)
+                .context("Failed to compress file content")?;
+            let compressed_content = match base_hash.filter(|base| *base != hash) {
+                Some(base) => match self.encode_against(paths, content, base) {
+                    Ok(Some(delta)) if delta.len() < full_content.len() => delta,
+                    Ok(_) => full_content,
+                    Err(e) => {
+                        log::warn!("Storing {} without a delta: {:#}", hash, e);
+                        full_content
+                    }
+                },
+                None => full_content,
+            };
+
+            // Write via a temporary file so an interrupted write never leaves a
+            // truncated object that would be skipped by the exists() check above.
+            // The name is unique because other sessions share the pool.
+            let tmp_file = content_file.with_extension(format!("{}.tmp", Uuid::new_v4()));
+            fs::write(&tmp_file, compressed_content)
                 .context("Failed to write file content to pool")?;
+            fs::rename(&tmp_file, &content_file)
+                .context("Failed to move file content into pool")?;
         }
 
-        // Create a reference in the checkpoint-specific directory
-        let checkpoint_refs_dir = paths.files_dir.join("refs").join(&snapshot.checkpoint_id);
-        fs::create_dir_all(&checkpoint_refs_dir)
-            .context("Failed to create checkpoint refs directory")?;
-
-        // Save file metadata with reference to content
-        let ref_metadata = serde_json::json!({
-            "path": snapshot.file_path,
-            "hash": snapshot.hash,
-            "is_deleted": snapshot.is_deleted,
-            "permissions": snapshot.permissions,
-            "size": snapshot.size,
-        });
-
-        // Use a sanitized filename for the reference
-        let safe_filename = snapshot
-            .file_path
-            .to_string_lossy()
-            .replace('/', "_")
-            .replace('\\', "_");
-        let ref_path = checkpoint_refs_dir.join(format!("{}.json", safe_filename));
-
-        fs::write(&ref_path, serde_json::to_string_pretty(&ref_metadata)?)
-            .context("Failed to write file reference")?;
-
         Ok(())
     }
 
+    /// Encode content as a delta against a pool object, or `None` if the
+    /// chain below that object is already as long as allowed
+    fn encode_against(
+        &self,
+        paths: &CheckpointPaths,
+        content: &[u8],
+        base_hash: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        let depth = match DeltaHeader::read(&self.object_file(paths, base_hash))? {
+            Some(base_header) => base_header.depth + 1,
+            None => 1,
+        };
+        if depth > MAX_DELTA_CHAIN {
+            return Ok(None);
+        }
+
+        let base = self.read_content(paths, base_hash)?;
+        let header = DeltaHeader {
+            base_hash: base_hash.to_string(),
+            depth,
+        };
+        encode_delta(content, &base, &header, self.compression_level).map(Some)
+    }
+
     /// Load a checkpoint from disk
     pub fn load_checkpoint(
         &self,
@@ -160,13 +303,7 @@ impl CheckpointStorage {
             serde_json::from_str(&metadata_json).context("Failed to parse checkpoint metadata")?;
 
         // Load messages
-        let messages_path = paths.checkpoint_messages_file(checkpoint_id);
-        let compressed_messages =
-            fs::read(&messages_path).context("Failed to read compressed messages")?;
-        let messages = String::from_utf8(
-            decode_all(&compressed_messages[..]).context("Failed to decompress messages")?,
-        )
-        .context("Invalid UTF-8 in messages")?;
+        let messages = self.read_messages(&paths, checkpoint_id)?;
 
         // Load file snapshots
         let file_snapshots = self.load_file_snapshots(&paths, checkpoint_id)?;
@@ -180,142 +317,310 @@ impl CheckpointStorage {
         paths: &CheckpointPaths,
         checkpoint_id: &str,
     ) -> Result<Vec<FileSnapshot>> {
-        let refs_dir = paths.files_dir.join("refs").join(checkpoint_id);
-        if !refs_dir.exists() {
-            return Ok(Vec::new());
+        let manifest = match self.read_manifest(paths, checkpoint_id)? {
+            Some(manifest) => manifest,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut snapshots = Vec::with_capacity(manifest.files.len());
+        for (path, entry) in manifest.files {
+            let content = if entry.deleted {
+                Vec::new()
+            } else {
+                // A missing or corrupt object must never turn into an empty file on restore
+                self.read_content(paths, &entry.hash)
+                    .with_context(|| format!("Failed to load content of {}", path.display()))?
+            };
+
+            snapshots.push(entry.to_snapshot(checkpoint_id, &path, content));
         }
 
-        let content_pool_dir = paths.files_dir.join("content_pool");
-        let mut snapshots = Vec::new();
+        Ok(snapshots)
+    }
+
+    /// Read an object from the content pool and verify it against its hash.
+    ///
+    /// Delta objects are rebuilt from their chain of base objects.
+    pub fn read_content(&self, paths: &CheckpointPaths, hash: &str) -> Result<Vec<u8>> {
+        self.read_object(paths, hash, MAX_DELTA_CHAIN)
+    }
+
+    fn read_object(&self, paths: &CheckpointPaths, hash: &str, links_left: u32) -> Result<Vec<u8>> {
+        let content_file = self.object_file(paths, hash);
+        if !content_file.exists() {
+            anyhow::bail!("Content object {} is missing from the pool", hash);
+        }
+
+        let compressed_content =
+            fs::read(&content_file).context("Failed to read file content from pool")?;
+        let content = match DeltaHeader::parse(&compressed_content) {
+            Some((header, payload)) => {
+                if links_left == 0 {
+                    anyhow::bail!(
+                        "Content object {} has a delta chain longer than {}",
+                        hash,
+                        MAX_DELTA_CHAIN
+                    );
+                }
+                let base = self
+                    .read_object(paths, &header.base_hash, links_left - 1)
+                    .with_context(|| format!("Failed to load delta base of {}", hash))?;
+                decode_delta(payload, &base)?
+            }
+            None => {
+                decode_all(&compressed_content[..]).context("Failed to decompress file content")?
+            }
+        };
+
+        let actual_hash = Self::calculate_file_hash(&content);
+        if actual_hash != hash {
+            anyhow::bail!(
+                "Content object {} is corrupt (content hashes to {})",
+                hash,
+                actual_hash
+            );
+        }
 
-        // Read all reference files
+        Ok(content)
+    }
+
+    /// Location of a pool object.
+    ///
+    /// Sessions that were never opened since the pool became shared still
+    /// keep their objects in their own pool.
+    pub fn object_file(&self, paths: &CheckpointPaths, hash: &str) -> PathBuf {
+        match paths.content_object_file(hash) {
+            file if file.exists() => file,
+            _ => paths.legacy_content_pool_dir().join(hash),
+        }
+    }
+
+    /// Load the manifest of a checkpoint, if it has one
+    pub fn load_manifest(
+        &self,
+        project_id: &str,
+        session_id: &str,
+        checkpoint_id: &str,
+    ) -> Result<Option<CheckpointManifest>> {
+        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
+        self.read_manifest(&paths, checkpoint_id)
+    }
+
+    /// Read a checkpoint manifest, migrating a legacy reference directory on the fly
+    pub fn read_manifest(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint_id: &str,
+    ) -> Result<Option<CheckpointManifest>> {
+        let manifest_file = paths.manifest_file(checkpoint_id);
+        if manifest_file.exists() {
+            return CheckpointManifest::load(&manifest_file).map(Some);
+        }
+
+        let legacy_dir = paths.legacy_refs_dir().join(checkpoint_id);
+        if legacy_dir.is_dir() {
+            return self
+                .migrate_legacy_checkpoint(paths, checkpoint_id)
+                .map(Some);
+        }
+
+        Ok(None)
+    }
+
+    /// Convert every legacy `files/refs/<checkpoint>/` directory into a manifest
+    pub fn migrate_legacy_refs(&self, paths: &CheckpointPaths) -> Result<usize> {
+        let refs_dir = paths.legacy_refs_dir();
+        if !refs_dir.exists() {
+            return Ok(0);
+        }
+
+        let mut migrated = 0;
         for entry in fs::read_dir(&refs_dir)? {
             let entry = entry?;
-            let path = entry.path();
+            if !entry.path().is_dir() {
+                continue;
+            }
+            if let Some(checkpoint_id) = entry.file_name().to_str() {
+                self.migrate_legacy_checkpoint(paths, checkpoint_id)?;
+                migrated += 1;
+            }
+        }
+
+        // Only remove the legacy root once it holds nothing but migrated checkpoints
+        if fs::read_dir(&refs_dir)?.next().is_none() {
+            fs::remove_dir(&refs_dir).context("Failed to remove legacy refs directory")?;
+        }
+
+        Ok(migrated)
+    }
+
+    /// Build a manifest from one checkpoint's per-file reference JSON and drop the old files
+    fn migrate_legacy_checkpoint(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint_id: &str,
+    ) -> Result<CheckpointManifest> {
+        let legacy_dir = paths.legacy_refs_dir().join(checkpoint_id);
+        let mut manifest = CheckpointManifest::new(checkpoint_id);
+
+        for entry in fs::read_dir(&legacy_dir)? {
+            let path = entry?.path();
 
             // Skip non-JSON files
             if path.extension().and_then(|e| e.to_str()) != Some("json") {
                 continue;
             }
 
-            // Load reference metadata
             let ref_json = fs::read_to_string(&path).context("Failed to read file reference")?;
             let ref_metadata: serde_json::Value =
                 serde_json::from_str(&ref_json).context("Failed to parse file reference")?;
 
-            let hash = ref_metadata["hash"]
-                .as_str()
-                .ok_or_else(|| anyhow::anyhow!("Missing hash in reference"))?;
-
-            // Load content from pool
-            let content_file = content_pool_dir.join(hash);
-            let content = if content_file.exists() {
-                let compressed_content =
-                    fs::read(&content_file).context("Failed to read file content from pool")?;
-                String::from_utf8(
-                    decode_all(&compressed_content[..])
-                        .context("Failed to decompress file content")?,
-                )
-                .context("Invalid UTF-8 in file content")?
-            } else {
-                // Handle missing content gracefully
-                log::warn!("Content file missing for hash: {}", hash);
-                String::new()
+            let file_path = match ref_metadata["path"].as_str() {
+                Some(file_path) => PathBuf::from(file_path),
+                None => {
+                    log::warn!("Skipping file reference without path: {:?}", path);
+                    continue;
+                }
             };
 
-            snapshots.push(FileSnapshot {
-                checkpoint_id: checkpoint_id.to_string(),
-                file_path: PathBuf::from(ref_metadata["path"].as_str().unwrap_or("")),
-                content,
-                hash: hash.to_string(),
-                is_deleted: ref_metadata["is_deleted"].as_bool().unwrap_or(false),
-                permissions: ref_metadata["permissions"].as_u64().map(|p| p as u32),
-                size: ref_metadata["size"].as_u64().unwrap_or(0),
-            });
+            manifest.files.insert(
+                file_path,
+                ManifestEntry {
+                    hash: ref_metadata["hash"].as_str().unwrap_or("").to_string(),
+                    mode: ref_metadata["permissions"].as_u64().map(|p| p as u32),
+                    size: ref_metadata["size"].as_u64().unwrap_or(0),
+                    deleted: ref_metadata["is_deleted"].as_bool().unwrap_or(false),
+                    kind: FileKind::File,
+                },
+            );
         }
 
-        Ok(snapshots)
+        manifest.save(&paths.manifest_file(checkpoint_id))?;
+        fs::remove_dir_all(&legacy_dir).context("Failed to remove legacy file references")?;
+
+        Ok(manifest)
     }
 
-    /// Save timeline to disk
-    pub fn save_timeline(&self, timeline_path: &Path, timeline: &SessionTimeline) -> Result<()> {
-        let timeline_json =
-            serde_json::to_string_pretty(timeline).context("Failed to serialize timeline")?;
-        fs::write(timeline_path, timeline_json).context("Failed to write timeline")?;
-        Ok(())
+    /// Take a session's timeline lock, waiting for any other holder.
+    ///
+    /// Everything that loads a timeline to change and save it holds it, so
+    /// managers of other sessions, such as when retention prunes this one,
+    /// cannot undo each other's changes.
+    pub fn lock_timeline(&self, paths: &CheckpointPaths) -> Result<TimelineLock> {
+        let lock_file = paths.timeline_lock_file();
+        if let Some(dir) = lock_file.parent() {
+            fs::create_dir_all(dir).context("Failed to create timeline directory")?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_file)
+            .context("Failed to open timeline lock")?;
+        file.lock().context("Failed to lock timeline")?;
+        Ok(TimelineLock { _file: file })
     }
 
-    /// Load timeline from disk
-    pub fn load_timeline(&self, timeline_path: &Path) -> Result<SessionTimeline> {
+    /// Save a session's timeline to the project's checkpoint index, then
+    /// export it to `timeline.json`.
+    ///
+    /// Only the checkpoints that changed are written to the index. Callers
+    /// that loaded `timeline` to change it hold the timeline lock.
+    pub fn save_timeline(&self, paths: &CheckpointPaths, timeline: &SessionTimeline) -> Result<()> {
+        let mut index = self.open_index(paths)?;
+        self.ensure_indexed(&mut index, paths)?;
+        index
+            .save_session(&paths.session_id, timeline, |checkpoint| {
+                self.touched_files(paths, checkpoint)
+            })
+            .with_context(|| format!("Failed to index timeline of session {}", paths.session_id))?;
+        Self::write_timeline_file(&paths.timeline_file, timeline)
+    }
+
+    /// Load a session's timeline from the project's checkpoint index
+    pub fn load_timeline(&self, paths: &CheckpointPaths) -> Result<SessionTimeline> {
+        self.indexed_timeline(paths)?
+            .with_context(|| format!("Session {} has no timeline", paths.session_id))
+    }
+
+    /// Write a timeline to a `timeline.json` file
+    pub fn write_timeline_file(timeline_path: &Path, timeline: &SessionTimeline) -> Result<()> {
+        // Serializing nests as deep as parsing does, see `read_timeline_file`
+        let timeline_json = std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .name("timeline-writer".to_string())
+                .stack_size(TIMELINE_THREAD_STACK_SIZE)
+                .spawn_scoped(scope, || serde_json::to_string_pretty(timeline))
+                .context("Failed to start timeline writer")?
+                .join()
+                .map_err(|_| anyhow::anyhow!("Timeline writer panicked"))?
+                .context("Failed to serialize timeline")
+        })?;
+        // A session missing from the index is imported from this file, so
+        // never leave it half written
+        let partial = timeline_path.with_extension("json.partial");
+        fs::write(&partial, timeline_json).context("Failed to write timeline")?;
+        fs::rename(&partial, timeline_path).context("Failed to write timeline")
+    }
+
+    /// Parse a `timeline.json` file
+    pub fn read_timeline_file(timeline_path: &Path) -> Result<SessionTimeline> {
         let timeline_json = fs::read_to_string(timeline_path).context("Failed to read timeline")?;
-        let timeline: SessionTimeline =
-            serde_json::from_str(&timeline_json).context("Failed to parse timeline")?;
+
+        // Every checkpoint nests its children a few levels deeper, so a long
+        // session goes far past serde_json's recursion limit and the stack of
+        // an ordinary thread
+        let parser = std::thread::Builder::new()
+            .name("timeline-parser".to_string())
+            .stack_size(TIMELINE_THREAD_STACK_SIZE)
+            .spawn(move || {
+                let mut deserializer = serde_json::Deserializer::from_str(&timeline_json);
+                deserializer.disable_recursion_limit();
+                SessionTimeline::deserialize(&mut deserializer)
+                    .and_then(|timeline| deserializer.end().map(|_| timeline))
+            })
+            .context("Failed to start timeline parser")?;
+        let timeline = parser
+            .join()
+            .map_err(|_| anyhow::anyhow!("Timeline parser panicked"))?
+            .context("Failed to parse timeline")?;
         Ok(timeline)
     }
 
-    /// Update timeline with a new checkpoint
+    /// Add a new checkpoint to the timeline in the project's checkpoint index,
+    /// make it current and export the timeline
     fn update_timeline_with_checkpoint(
         &self,
-        timeline_path: &Path,
+        paths: &CheckpointPaths,
         checkpoint: &Checkpoint,
         file_snapshots: &[FileSnapshot],
     ) -> Result<()> {
-        let mut timeline = self.load_timeline(timeline_path)?;
+        let _lock = self.lock_timeline(paths)?;
+        let mut index = self.open_index(paths)?;
+        self.ensure_indexed(&mut index, paths)?;
 
-        let new_node = TimelineNode {
+        let node = TimelineNode {
             checkpoint: checkpoint.clone(),
             children: Vec::new(),
             file_snapshot_ids: file_snapshots.iter().map(|s| s.hash.clone()).collect(),
         };
-
-        // If this is the first checkpoint
-        if timeline.root_node.is_none() {
-            timeline.root_node = Some(new_node);
-            timeline.current_checkpoint_id = Some(checkpoint.id.clone());
-        } else if let Some(parent_id) = &checkpoint.parent_checkpoint_id {
-            // Check if parent exists before modifying
-            let parent_exists = timeline.find_checkpoint(parent_id).is_some();
-
-            if parent_exists {
-                if let Some(root) = &mut timeline.root_node {
-                    Self::add_child_to_node(root, parent_id, new_node)?;
-                    timeline.current_checkpoint_id = Some(checkpoint.id.clone());
-                }
-            } else {
-                anyhow::bail!("Parent checkpoint not found: {}", parent_id);
-            }
-        }
-
-        timeline.total_checkpoints += 1;
-        self.save_timeline(timeline_path, &timeline)?;
-
-        Ok(())
-    }
-
-    /// Recursively add a child node to the timeline tree
-    fn add_child_to_node(
-        node: &mut TimelineNode,
-        parent_id: &str,
-        child: TimelineNode,
-    ) -> Result<()> {
-        if node.checkpoint.id == parent_id {
-            node.children.push(child);
-            return Ok(());
-        }
-
-        for child_node in &mut node.children {
-            if Self::add_child_to_node(child_node, parent_id, child.clone()).is_ok() {
-                return Ok(());
-            }
-        }
-
-        anyhow::bail!("Parent checkpoint not found: {}", parent_id)
+        let touched = self.touched_files(paths, checkpoint)?;
+        index
+            .add_checkpoint(&paths.session_id, &node, &touched)
+            .with_context(|| format!("Failed to index checkpoint {}", checkpoint.id))?;
+
+        // Keep the export complete, since the session is imported from it if
+        // the index is lost
+        let timeline = index
+            .timeline(&paths.session_id)?
+            .context("Indexed checkpoint has no timeline")?;
+        Self::write_timeline_file(&paths.timeline_file, &timeline)
     }
 
     /// Calculate hash of file content
-    pub fn calculate_file_hash(content: &str) -> String {
+    pub fn calculate_file_hash(content: &[u8]) -> String {
         let mut hasher = Sha256::new();
-        hasher.update(content.as_bytes());
+        hasher.update(content);
         format!("{:x}", hasher.finalize())
     }
 
@@ -333,7 +638,10 @@ impl CheckpointStorage {
         (messages_size + files_size) / 4
     }
 
-    /// Clean up old checkpoints based on retention policy
+    /// Remove all but the newest `keep_count` checkpoints of a session.
+    ///
+    /// Checkpoints that retention protects are kept even when they are among
+    /// the oldest, see [`Self::protected_checkpoints`].
     pub fn cleanup_old_checkpoints(
         &self,
         project_id: &str,
@@ -341,7 +649,8 @@ impl CheckpointStorage {
         keep_count: usize,
     ) -> Result<usize> {
         let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
-        let timeline = self.load_timeline(&paths.timeline_file)?;
+        let timeline = self.load_timeline(&paths)?;
+        let protected = Self::protected_checkpoints(&timeline);
 
         // Collect all checkpoint IDs in chronological order
         let mut all_checkpoints = Vec::new();
@@ -354,19 +663,19 @@ impl CheckpointStorage {
 
         // Keep only the most recent checkpoints
         let to_remove = all_checkpoints.len().saturating_sub(keep_count);
-        let mut removed_count = 0;
-
-        for checkpoint in all_checkpoints.into_iter().take(to_remove) {
-            if self.remove_checkpoint(&paths, &checkpoint.id).is_ok() {
-                removed_count += 1;
-            }
-        }
+        let candidates: Vec<String> = all_checkpoints
+            .into_iter()
+            .take(to_remove)
+            .filter(|checkpoint| !protected.contains(&checkpoint.id))
+            .map(|checkpoint| checkpoint.id)
+            .collect();
+        let removed_count = self.prune_checkpoints(&paths, &candidates)?.len();
 
         // Run garbage collection to clean up orphaned content
         if removed_count > 0 {
-            match self.garbage_collect_content(project_id, session_id) {
-                Ok(gc_count) => {
-                    log::info!("Garbage collected {} orphaned content files", gc_count);
+            match self.garbage_collect_content(project_id) {
+                Ok(gc) => {
+                    log::info!("Garbage collected {} orphaned content files", gc.objects);
                 }
                 Err(e) => {
                     log::warn!("Failed to garbage collect content: {}", e);
@@ -377,26 +686,41 @@ impl CheckpointStorage {
         Ok(removed_count)
     }
 
-    /// Collect all checkpoints from the tree in order
-    fn collect_checkpoints(node: &TimelineNode, checkpoints: &mut Vec<Checkpoint>) {
-        checkpoints.push(node.checkpoint.clone());
-        for child in &node.children {
-            Self::collect_checkpoints(child, checkpoints);
+    /// Collect all checkpoints from the tree in order, each before its children
+    pub fn collect_checkpoints(root: &TimelineNode, checkpoints: &mut Vec<Checkpoint>) {
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            checkpoints.push(node.checkpoint.clone());
+            stack.extend(node.children.iter().rev());
         }
     }
 
-    /// Remove a checkpoint and its associated files
-    fn remove_checkpoint(&self, paths: &CheckpointPaths, checkpoint_id: &str) -> Result<()> {
-        // Remove checkpoint metadata directory
-        let checkpoint_dir = paths.checkpoint_dir(checkpoint_id);
-        if checkpoint_dir.exists() {
-            fs::remove_dir_all(&checkpoint_dir).context("Failed to remove checkpoint directory")?;
-        }
+    /// Remove checkpoints and their associated files.
+    ///
+    /// The timeline is left as it is; use [`Self::prune_checkpoints`] to take
+    /// checkpoints out of a session.
+    pub fn remove_checkpoints(
+        &self,
+        paths: &CheckpointPaths,
+        checkpoint_ids: &[String],
+    ) -> Result<()> {
+        let mut dependents = self.message_dependents(paths)?;
+        for checkpoint_id in checkpoint_ids {
+            // Checkpoints that inherit messages from this one need their own copy first
+            self.detach_dependent_messages(paths, checkpoint_id, &mut dependents)?;
+
+            // Remove checkpoint metadata directory
+            let checkpoint_dir = paths.checkpoint_dir(checkpoint_id);
+            if checkpoint_dir.exists() {
+                fs::remove_dir_all(&checkpoint_dir)
+                    .context("Failed to remove checkpoint directory")?;
+            }
 
-        // Remove file references for this checkpoint
-        let refs_dir = paths.files_dir.join("refs").join(checkpoint_id);
-        if refs_dir.exists() {
-            fs::remove_dir_all(&refs_dir).context("Failed to remove file references")?;
+            // Remove the file manifest for this checkpoint
+            let manifest_file = paths.manifest_file(checkpoint_id);
+            if manifest_file.exists() {
+                fs::remove_file(&manifest_file).context("Failed to remove checkpoint manifest")?;
+            }
         }
 
         // Note: We don't remove content from the pool here as it might be
@@ -404,57 +728,98 @@ impl CheckpointStorage {
 
         Ok(())
     }
+}
 
-    /// Garbage collect unreferenced content from the content pool
-    pub fn garbage_collect_content(&self, project_id: &str, session_id: &str) -> Result<usize> {
-        let paths = CheckpointPaths::new(&self.claude_dir, project_id, session_id);
-        let content_pool_dir = paths.files_dir.join("content_pool");
-        let refs_dir = paths.files_dir.join("refs");
-
-        if !content_pool_dir.exists() {
-            return Ok(0);
-        }
-
-        // Collect all referenced hashes
-        let mut referenced_hashes = std::collections::HashSet::new();
-
-        if refs_dir.exists() {
-            for checkpoint_entry in fs::read_dir(&refs_dir)? {
-                let checkpoint_dir = checkpoint_entry?.path();
-                if checkpoint_dir.is_dir() {
-                    for ref_entry in fs::read_dir(&checkpoint_dir)? {
-                        let ref_path = ref_entry?.path();
-                        if ref_path.extension().and_then(|e| e.to_str()) == Some("json") {
-                            if let Ok(ref_json) = fs::read_to_string(&ref_path) {
-                                if let Ok(ref_metadata) =
-                                    serde_json::from_str::<serde_json::Value>(&ref_json)
-                                {
-                                    if let Some(hash) = ref_metadata["hash"].as_str() {
-                                        referenced_hashes.insert(hash.to_string());
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        // Remove unreferenced content
-        let mut removed_count = 0;
-        for entry in fs::read_dir(&content_pool_dir)? {
-            let content_file = entry?.path();
-            if content_file.is_file() {
-                if let Some(hash) = content_file.file_name().and_then(|n| n.to_str()) {
-                    if !referenced_hashes.contains(hash) {
-                        if fs::remove_file(&content_file).is_ok() {
-                            removed_count += 1;
-                        }
-                    }
-                }
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::test_support::{checkpoint, snapshot};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_manifest_keeps_colliding_paths_apart() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+
+        let snapshots = vec![
+            snapshot("cp1", "a/b_c.rs", b"first"),
+            snapshot("cp1", "a_b/c.rs", b"second"),
+            snapshot("cp1", "logo.png", b"\x89PNG\0\xff"),
+        ];
+        storage
+            .save_checkpoint(
+                "project",
+                "session",
+                &checkpoint("cp1", None),
+                snapshots,
+                "",
+            )
+            .unwrap();
+
+        // The child only snapshots one changed file but inherits the rest
+        let changed = vec![snapshot("cp2", "a/b_c.rs", b"changed")];
+        storage
+            .save_checkpoint(
+                "project",
+                "session",
+                &checkpoint("cp2", Some("cp1")),
+                changed,
+                "",
+            )
+            .unwrap();
+
+        let (_, files, _) = storage
+            .load_checkpoint("project", "session", "cp2")
+            .unwrap();
+        let contents: Vec<(String, Vec<u8>)> = files
+            .into_iter()
+            .map(|s| (s.file_path.to_string_lossy().to_string(), s.content))
+            .collect();
+        assert_eq!(
+            contents,
+            vec![
+                ("a/b_c.rs".to_string(), b"changed".to_vec()),
+                ("a_b/c.rs".to_string(), b"second".to_vec()),
+                ("logo.png".to_string(), b"\x89PNG\0\xff".to_vec()),
+            ]
+        );
+    }
 
-        Ok(removed_count)
+    #[test]
+    fn test_legacy_refs_are_migrated() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CheckpointStorage::new(temp_dir.path().to_path_buf());
+        storage.init_storage("project", "session").unwrap();
+        let paths = CheckpointPaths::new(&storage.claude_dir, "project", "session");
+
+        // Write a checkpoint the way older versions did, with one reference file per path
+        let snapshot = snapshot("legacy", "src/main.rs", b"fn main() {}");
+        storage.save_file_snapshot(&paths, &snapshot, None).unwrap();
+        let refs_dir = paths.legacy_refs_dir().join("legacy");
+        fs::create_dir_all(&refs_dir).unwrap();
+        fs::write(
+            refs_dir.join("src_main.rs.json"),
+            serde_json::json!({
+                "path": "src/main.rs",
+                "hash": snapshot.hash,
+                "is_deleted": false,
+                "permissions": 0o644,
+                "size": snapshot.size,
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        storage.init_storage("project", "session").unwrap();
+
+        assert!(!paths.legacy_refs_dir().exists());
+        let manifest = storage
+            .load_manifest("project", "session", "legacy")
+            .unwrap()
+            .unwrap();
+        let entry = &manifest.files[&PathBuf::from("src/main.rs")];
+        assert_eq!(entry.hash, snapshot.hash);
+        assert_eq!(entry.mode, Some(0o644));
     }
 }
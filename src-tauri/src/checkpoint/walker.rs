@@ -0,0 +1,216 @@
+use anyhow::{Context, Result};
+use ignore::WalkBuilder;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::FileKind;
+
+/// Project-level ignore file, using `.gitignore` syntax
+pub const IGNORE_FILE_NAME: &str = ".claudiaignore";
+
+/// Files larger than this are left out of checkpoints unless configured otherwise
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// The files and directories of a project that take part in checkpoints
+#[derive(Debug, Default)]
+pub struct ProjectFiles {
+    /// Files and symlinks relative to the project root
+    pub files: Vec<PathBuf>,
+    /// Directories relative to the project root
+    pub dirs: Vec<PathBuf>,
+    /// Directories with nothing in them, which are recorded like files
+    pub empty_dirs: Vec<PathBuf>,
+    /// Files left out because they exceed the size limit, with their size
+    pub oversized: Vec<(PathBuf, u64)>,
+}
+
+impl ProjectFiles {
+    /// Human-readable warnings for files that were left out
+    pub fn warnings(&self, max_file_size: u64) -> Vec<String> {
+        self.oversized
+            .iter()
+            .map(|(path, size)| {
+                format!(
+                    "Skipped {} ({} bytes, limit is {} bytes)",
+                    path.display(),
+                    size,
+                    max_file_size
+                )
+            })
+            .collect()
+    }
+}
+
+/// A walker over `dir` and everything below it that checkpoints cover, with
+/// the ignore rules of [`walk_project`]. Ignore files of the directories
+/// above `dir` apply too, so it can start inside a project.
+pub fn project_walker(dir: &Path) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(dir);
+    builder
+        .hidden(false)
+        .require_git(false)
+        .add_custom_ignore_filename(IGNORE_FILE_NAME)
+        .filter_entry(|entry| {
+            // Skip hidden directories like .git, but keep hidden files
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            !(is_dir && entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.'))
+        });
+    builder
+}
+
+/// Walk a project the way checkpoints see it.
+///
+/// Honors `.gitignore` (even outside a git repository), `.git/info/exclude`
+/// and `.claudiaignore`, skips hidden directories such as `.git`, and leaves
+/// out files larger than `max_file_size` bytes (0 means no limit). Symlinks
+/// are listed as files and never followed.
+pub fn walk_project(project_path: &Path, max_file_size: u64) -> Result<ProjectFiles> {
+    let walker = project_walker(project_path).build();
+
+    let mut project_files = ProjectFiles::default();
+    for entry in walker {
+        let entry = entry.with_context(|| format!("Failed to scan {}", project_path.display()))?;
+        let rel = match entry.path().strip_prefix(project_path) {
+            Ok(rel) if !rel.as_os_str().is_empty() => rel.to_path_buf(),
+            _ => continue,
+        };
+
+        let file_type = match entry.file_type() {
+            Some(file_type) => file_type,
+            None => continue,
+        };
+        if file_type.is_dir() {
+            let is_empty = fs::read_dir(entry.path())
+                .map(|mut entries| entries.next().is_none())
+                .unwrap_or(false);
+            if is_empty {
+                project_files.empty_dirs.push(rel.clone());
+            }
+            project_files.dirs.push(rel);
+        } else if file_type.is_symlink() {
+            project_files.files.push(rel);
+        } else if file_type.is_file() {
+            let size = entry
+                .metadata()
+                .with_context(|| format!("Failed to read metadata for {}", rel.display()))?
+                .len();
+            if max_file_size > 0 && size > max_file_size {
+                project_files.oversized.push((rel, size));
+            } else {
+                project_files.files.push(rel);
+            }
+        }
+    }
+
+    Ok(project_files)
+}
+
+/// A path on disk as checkpoints record it
+#[derive(Debug)]
+pub struct DiskEntry {
+    pub kind: FileKind,
+    /// File content, symlink target or nothing for a directory
+    pub content: Vec<u8>,
+    /// Permission bits including setuid, setgid and sticky; `None` for
+    /// symlinks and on platforms without Unix modes
+    pub mode: Option<u32>,
+}
+
+/// Read what is at a path without following symlinks, or `None` if nothing is
+pub fn read_entry(full_path: &Path) -> Result<Option<DiskEntry>> {
+    let metadata = match fs::symlink_metadata(full_path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read metadata for {}", full_path.display()))
+        }
+    };
+
+    let file_type = metadata.file_type();
+    let (kind, content) = if file_type.is_symlink() {
+        let target = fs::read_link(full_path)
+            .with_context(|| format!("Failed to read link {}", full_path.display()))?;
+        (FileKind::Symlink, path_bytes(&target))
+    } else if file_type.is_dir() {
+        (FileKind::Directory, Vec::new())
+    } else {
+        let content = fs::read(full_path)
+            .with_context(|| format!("Failed to read {}", full_path.display()))?;
+        (FileKind::File, content)
+    };
+
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        (kind != FileKind::Symlink).then(|| metadata.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let mode = None;
+
+    Ok(Some(DiskEntry {
+        kind,
+        content,
+        mode,
+    }))
+}
+
+/// Raw bytes of a path, as stored for a symlink target
+fn path_bytes(path: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
+    }
+    #[cfg(not(unix))]
+    {
+        path.to_string_lossy().into_owned().into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_walk_project_honors_ignore_files_and_size_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        let project = temp_dir.path();
+        for (path, content) in [
+            (".gitignore", "target/\n*.log\n"),
+            (".claudiaignore", "node_modules\n"),
+            (".git/info/exclude", "secret.txt\n"),
+            (".env", "KEY=1"),
+            ("src/main.rs", "fn main() {}"),
+            ("target/debug/app", "binary"),
+            ("node_modules/pkg/index.js", "module.exports = {}"),
+            ("build.log", "log"),
+            ("secret.txt", "hidden"),
+            ("big.bin", "0123456789abcdefghijklmnopqrstuvwxyz"),
+        ] {
+            let full_path = project.join(path);
+            fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            fs::write(full_path, content).unwrap();
+        }
+
+        let walked = walk_project(project, 32).unwrap();
+
+        let mut files = walked.files.clone();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from(".claudiaignore"),
+                PathBuf::from(".env"),
+                PathBuf::from(".gitignore"),
+                PathBuf::from("src/main.rs"),
+            ]
+        );
+        assert_eq!(walked.dirs, vec![PathBuf::from("src")]);
+        assert_eq!(walked.oversized, vec![(PathBuf::from("big.bin"), 36)]);
+        assert_eq!(walked.warnings(32).len(), 1);
+    }
+}
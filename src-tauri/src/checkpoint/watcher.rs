@@ -0,0 +1,564 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex, PoisonError, Weak};
+use tokio::sync::RwLock;
+
+use super::{
+    storage::CheckpointStorage,
+    walker::{project_walker, read_entry, IGNORE_FILE_NAME},
+    FileEvent, FileEventKind, FileKind, FileState, FileTracker, SessionTimeline,
+};
+
+/// Maximum number of events kept in the tracker
+const MAX_EVENTS: usize = 10_000;
+
+/// Ignore files read in every directory, from the highest precedence to the
+/// lowest, as the project walker ranks them
+const IGNORE_FILES: [&str; 3] = [IGNORE_FILE_NAME, ".ignore", ".gitignore"];
+
+/// Watches a project directory and feeds real file changes into a [`FileTracker`].
+///
+/// Watching stops when the watcher is dropped.
+pub struct FileWatcher {
+    _watcher: Arc<Mutex<RecommendedWatcher>>,
+}
+
+impl FileWatcher {
+    /// Start watching `project_path`.
+    ///
+    /// Every directory checkpoints cover is watched on its own, so ignored
+    /// trees such as `node_modules`, `target` or `.git` cost no watches.
+    /// Events are handled on a thread of their own, which reads and hashes
+    /// changed files before briefly locking the tracker. Files over the
+    /// session's `max_file_size` are left alone, as checkpoints leave them out.
+    ///
+    /// Fails if the platform watcher cannot be created or a directory cannot
+    /// be watched, for example when the inotify watch limit is exhausted;
+    /// callers should fall back to tracking changes from tool usage.
+    pub fn start(
+        project_path: &Path,
+        tracker: Arc<RwLock<FileTracker>>,
+        timeline: Arc<RwLock<SessionTimeline>>,
+    ) -> Result<Self> {
+        let root = project_path
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", project_path.display()))?;
+
+        // Only forward events: the handler runs on the platform watcher's
+        // thread, and adding watches from there would wait on itself
+        let (sender, receiver) = mpsc::channel();
+        let watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            let _ = sender.send(res);
+        })
+        .context("Failed to create file watcher")?;
+        let watcher = Arc::new(Mutex::new(watcher));
+
+        let mut handler = EventHandler {
+            ignore: ProjectIgnore::new(&root),
+            root,
+            watcher: Arc::downgrade(&watcher),
+            tracker,
+            timeline,
+        };
+        for dir in watched_dirs(&handler.root) {
+            handler.watch(&dir)?;
+        }
+
+        std::thread::Builder::new()
+            .name("checkpoint-watcher".to_string())
+            .spawn(move || {
+                // Ends once the watcher, and with it the sender, is dropped
+                for res in receiver {
+                    match res {
+                        Ok(event) => handler.handle(event),
+                        Err(e) => log::warn!("File watcher error: {}", e),
+                    }
+                }
+            })
+            .context("Failed to start file watcher thread")?;
+
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+/// `dir` and every directory below it that checkpoints cover
+fn watched_dirs(dir: &Path) -> Vec<PathBuf> {
+    project_walker(dir)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_dir()))
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// Turns watcher events into tracker changes
+struct EventHandler {
+    root: PathBuf,
+    ignore: ProjectIgnore,
+    watcher: Weak<Mutex<RecommendedWatcher>>,
+    tracker: Arc<RwLock<FileTracker>>,
+    /// Read for the current `max_file_size`, which settings can change
+    timeline: Arc<RwLock<SessionTimeline>>,
+}
+
+impl EventHandler {
+    fn watch(&self, dir: &Path) -> Result<()> {
+        if let Some(watcher) = self.watcher.upgrade() {
+            watcher
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .watch(dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("Failed to watch {}", dir.display()))?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, event: Event) {
+        let timestamp = Utc::now();
+        let observed = self.observe_event(event);
+        if observed.is_empty() {
+            return;
+        }
+        let mut tracker = self.tracker.blocking_write();
+        for observed in observed {
+            apply_observed(&mut tracker, observed, timestamp);
+        }
+    }
+
+    /// Read what the paths of an event hold now, watching directories that
+    /// appeared
+    fn observe_event(&mut self, event: Event) -> Vec<Observed> {
+        for path in &event.paths {
+            self.ignore.forget_changed_rules(path);
+        }
+
+        let max_file_size = self.timeline.blocking_read().max_file_size;
+        let mut observed = Vec::new();
+        match event.kind {
+            EventKind::Access(_) => {}
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                let from = self.ignore.relative_path(&event.paths[0]);
+                if let Some(from) = &from {
+                    observed.extend(observe(&self.root, from, None, max_file_size));
+                }
+                if event.paths[1].is_dir() {
+                    self.add_tree(&event.paths[1], max_file_size, &mut observed);
+                } else if let Some(to) = self.ignore.relative_path(&event.paths[1]) {
+                    observed.extend(observe(&self.root, &to, from, max_file_size));
+                }
+            }
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_)) => {
+                for path in &event.paths {
+                    if path.is_dir() {
+                        self.add_tree(path, max_file_size, &mut observed);
+                    } else if let Some(rel) = self.ignore.relative_path(path) {
+                        observed.extend(observe(&self.root, &rel, None, max_file_size));
+                    }
+                }
+            }
+            _ => {
+                for path in &event.paths {
+                    if let Some(rel) = self.ignore.relative_path(path) {
+                        observed.extend(observe(&self.root, &rel, None, max_file_size));
+                    }
+                }
+            }
+        }
+        observed
+    }
+
+    /// Watch a directory that appeared, and the directories in it that
+    /// checkpoints cover. Files written right after their directory was
+    /// created can land before it is watched, so its files are read too.
+    fn add_tree(&mut self, dir: &Path, max_file_size: u64, observed: &mut Vec<Observed>) {
+        if self.ignore.relative_path(dir).is_none() {
+            return;
+        }
+        for entry in project_walker(dir).build().filter_map(|entry| entry.ok()) {
+            if entry.file_type().is_some_and(|t| t.is_dir()) {
+                if let Err(e) = self.watch(entry.path()) {
+                    log::warn!("{:#}", e);
+                }
+            } else if let Ok(rel) = entry.path().strip_prefix(&self.root) {
+                observed.extend(observe(&self.root, rel, None, max_file_size));
+            }
+        }
+    }
+}
+
+/// The ignore rules of the project walker, for testing single paths as
+/// events arrive.
+///
+/// Rules come from the ignore files of every directory from a path up to the
+/// root, then `.git/info/exclude` and the global git excludes. Each
+/// directory's files are read when first needed and again after they change.
+struct ProjectIgnore {
+    root: PathBuf,
+    /// Rules of each directory's ignore files, in the order of [`IGNORE_FILES`]
+    dirs: HashMap<PathBuf, [Gitignore; 3]>,
+    exclude: Gitignore,
+    global: Gitignore,
+}
+
+impl ProjectIgnore {
+    fn new(root: &Path) -> Self {
+        let (global, error) = Gitignore::global();
+        if let Some(e) = error {
+            log::warn!("Failed to read global git excludes: {}", e);
+        }
+        Self {
+            root: root.to_path_buf(),
+            dirs: HashMap::new(),
+            exclude: build_matcher(root, &root.join(".git").join("info").join("exclude")),
+            global,
+        }
+    }
+
+    /// Map an absolute event path to a project-relative path, or `None` if
+    /// checkpoints don't cover it
+    fn relative_path(&mut self, path: &Path) -> Option<PathBuf> {
+        let rel = path.strip_prefix(&self.root).ok()?;
+        if rel.as_os_str().is_empty() {
+            return None;
+        }
+
+        // Files inside hidden directories like .git are never checkpointed
+        let is_dir = path.is_dir();
+        let mut components = rel.components().peekable();
+        while let Some(component) = components.next() {
+            let in_dir = is_dir || components.peek().is_some();
+            if let Component::Normal(name) = component {
+                if in_dir && name.to_string_lossy().starts_with('.') {
+                    return None;
+                }
+            }
+        }
+
+        if self.is_ignored(path, is_dir) {
+            return None;
+        }
+        Some(rel.to_path_buf())
+    }
+
+    fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let dirs: Vec<PathBuf> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root))
+            .map(Path::to_path_buf)
+            .collect();
+
+        // Like the walker, the kind of ignore file ranks first and the
+        // nearest directory second
+        for kind in 0..IGNORE_FILES.len() {
+            for dir in &dirs {
+                let matcher = &self.matchers(dir)[kind];
+                let matched = matcher.matched_path_or_any_parents(path, is_dir);
+                if !matched.is_none() {
+                    return matched.is_ignore();
+                }
+            }
+        }
+        let matched = self.exclude.matched_path_or_any_parents(path, is_dir);
+        if !matched.is_none() {
+            return matched.is_ignore();
+        }
+        // Global excludes are rooted elsewhere, so match the relative path
+        path.strip_prefix(&self.root).is_ok_and(|rel| {
+            self.global
+                .matched_path_or_any_parents(rel, is_dir)
+                .is_ignore()
+        })
+    }
+
+    fn matchers(&mut self, dir: &Path) -> &[Gitignore; 3] {
+        self.dirs
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| IGNORE_FILES.map(|name| build_matcher(dir, &dir.join(name))))
+    }
+
+    /// Drop the rules of a directory whose ignore file `path` may have changed
+    fn forget_changed_rules(&mut self, path: &Path) {
+        let is_rules = path
+            .file_name()
+            .is_some_and(|name| IGNORE_FILES.iter().any(|file| name == *file));
+        if let (true, Some(dir)) = (is_rules, path.parent()) {
+            self.dirs.remove(dir);
+        }
+    }
+}
+
+/// Rules of one ignore file relative to `dir`; none if it does not exist
+fn build_matcher(dir: &Path, file: &Path) -> Gitignore {
+    if !file.exists() {
+        return Gitignore::empty();
+    }
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(e) = builder.add(file) {
+        log::warn!("Failed to read ignore file {}: {}", file.display(), e);
+    }
+    builder.build().unwrap_or_else(|e| {
+        log::warn!("Invalid ignore rules in {}: {}", file.display(), e);
+        Gitignore::empty()
+    })
+}
+
+/// What a project path held when an event for it was handled
+pub struct Observed {
+    rel: PathBuf,
+    renamed_from: Option<PathBuf>,
+    /// Content hash, type and permissions; `None` when nothing is there
+    entry: Option<(String, FileKind, Option<u32>)>,
+}
+
+/// Read and hash what is at a project path, without following symlinks.
+///
+/// `None` for directories, which are recorded through their files, and for
+/// files larger than `max_file_size` bytes (0 means no limit), which are only
+/// stat'ed so that a growing log or video is not re-read on every write.
+pub fn observe(
+    root: &Path,
+    rel: &Path,
+    renamed_from: Option<PathBuf>,
+    max_file_size: u64,
+) -> Option<Observed> {
+    let full_path = root.join(rel);
+    if max_file_size > 0
+        && fs::symlink_metadata(&full_path)
+            .is_ok_and(|metadata| metadata.is_file() && metadata.len() > max_file_size)
+    {
+        return None;
+    }
+    let entry = match read_entry(&full_path) {
+        Ok(Some(entry)) if entry.kind == FileKind::Directory => return None,
+        Ok(Some(entry)) => Some((
+            CheckpointStorage::calculate_file_hash(&entry.content),
+            entry.kind,
+            entry.mode,
+        )),
+        Ok(None) | Err(_) => None,
+    };
+    Some(Observed {
+        rel: rel.to_path_buf(),
+        renamed_from,
+        entry,
+    })
+}
+
+/// Compare what was observed at a path with what the tracker knows and
+/// record a [`FileEvent`] if it really changed.
+///
+/// Observations whose content, type and permissions match what is known,
+/// such as files rewritten by a restore, are dropped.
+pub fn apply_observed(
+    tracker: &mut FileTracker,
+    observed: Observed,
+    timestamp: DateTime<Utc>,
+) -> Option<FileEvent> {
+    let Observed {
+        rel,
+        renamed_from,
+        entry,
+    } = observed;
+    let previous = tracker.tracked_files.get(&rel);
+    let was_present = previous.is_some_and(|state| state.exists);
+    let (kind, hash, exists, file_kind, mode) = match entry {
+        Some((hash, file_kind, mode)) => {
+            if previous.is_some_and(|state| {
+                state.exists
+                    && state.last_hash == hash
+                    && state.kind == file_kind
+                    && state.mode == mode
+            }) {
+                return None;
+            }
+            let kind = if renamed_from.is_some() {
+                FileEventKind::Renamed
+            } else if was_present {
+                FileEventKind::Modified
+            } else {
+                FileEventKind::Created
+            };
+            (kind, hash, true, file_kind, mode)
+        }
+        // Only deletions of files we knew about are changes worth recording
+        None if was_present => (
+            FileEventKind::Deleted,
+            String::new(),
+            false,
+            FileKind::File,
+            None,
+        ),
+        None => return None,
+    };
+
+    tracker.tracked_files.insert(
+        rel.clone(),
+        FileState {
+            last_hash: hash,
+            is_modified: true,
+            last_modified: timestamp,
+            exists,
+            kind: file_kind,
+            mode,
+        },
+    );
+
+    let event = FileEvent {
+        path: rel,
+        kind,
+        renamed_from,
+        timestamp,
+    };
+    if tracker.events.len() >= MAX_EVENTS {
+        tracker.events.pop_front();
+    }
+    tracker.events.push_back(event.clone());
+    Some(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, VecDeque};
+    use tempfile::TempDir;
+
+    fn record_change(
+        tracker: &mut FileTracker,
+        root: &Path,
+        rel: &Path,
+        renamed_from: Option<PathBuf>,
+        timestamp: DateTime<Utc>,
+    ) -> Option<FileEvent> {
+        let observed = observe(root, rel, renamed_from, 0)?;
+        apply_observed(tracker, observed, timestamp)
+    }
+
+    #[test]
+    fn test_record_change_classifies_real_changes() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let mut tracker = FileTracker {
+            tracked_files: HashMap::new(),
+            events: VecDeque::new(),
+        };
+        let now = Utc::now();
+        let path = Path::new("src/lib.rs");
+        fs::create_dir_all(root.join("src")).unwrap();
+
+        fs::write(root.join(path), "one").unwrap();
+        let created = record_change(&mut tracker, root, path, None, now).unwrap();
+        assert_eq!(created.kind, FileEventKind::Created);
+
+        // Rewriting the same content is not a change
+        fs::write(root.join(path), "one").unwrap();
+        assert!(record_change(&mut tracker, root, path, None, now).is_none());
+
+        fs::write(root.join(path), "two").unwrap();
+        let modified = record_change(&mut tracker, root, path, None, now).unwrap();
+        assert_eq!(modified.kind, FileEventKind::Modified);
+
+        let new_path = Path::new("src/main.rs");
+        fs::rename(root.join(path), root.join(new_path)).unwrap();
+        let deleted = record_change(&mut tracker, root, path, None, now).unwrap();
+        let renamed =
+            record_change(&mut tracker, root, new_path, Some(path.to_path_buf()), now).unwrap();
+        assert_eq!(deleted.kind, FileEventKind::Deleted);
+        assert_eq!(renamed.kind, FileEventKind::Renamed);
+        assert_eq!(renamed.renamed_from.as_deref(), Some(path));
+
+        // Deleting a file that was never seen is not recorded
+        assert!(record_change(&mut tracker, root, Path::new("gone.txt"), None, now).is_none());
+
+        assert_eq!(tracker.events.len(), 4);
+        assert!(!tracker.tracked_files[path].exists);
+        assert!(tracker.tracked_files[new_path].is_modified);
+    }
+
+    #[test]
+    fn test_observe_skips_oversized_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("small.txt"), "tiny").unwrap();
+        fs::write(root.join("big.log"), vec![b'x'; 64]).unwrap();
+
+        assert!(observe(root, Path::new("small.txt"), None, 32).is_some());
+        assert!(observe(root, Path::new("big.log"), None, 32).is_none());
+        assert!(observe(root, Path::new("big.log"), None, 0).is_some());
+    }
+
+    #[test]
+    fn test_relative_path_skips_hidden_and_ignored() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("web/dist")).unwrap();
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(root.join(".cache")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        fs::write(root.join("web/.gitignore"), "dist/\n*.log\n").unwrap();
+        fs::write(root.join(".git/info/exclude"), "scratch.txt\n").unwrap();
+        let mut ignore = ProjectIgnore::new(root);
+
+        assert_eq!(
+            ignore.relative_path(&root.join("src/lib.rs")),
+            Some(PathBuf::from("src/lib.rs"))
+        );
+        assert_eq!(
+            ignore.relative_path(&root.join(".env")),
+            Some(PathBuf::from(".env"))
+        );
+        assert_eq!(ignore.relative_path(&root.join(".git/HEAD")), None);
+        assert_eq!(ignore.relative_path(&root.join(".cache")), None);
+        assert_eq!(ignore.relative_path(&root.join("target/debug/app")), None);
+        assert_eq!(ignore.relative_path(&root.join("web/dist/app.js")), None);
+        assert_eq!(ignore.relative_path(&root.join("web/dist")), None);
+        assert_eq!(ignore.relative_path(&root.join("web/debug.log")), None);
+        assert_eq!(ignore.relative_path(&root.join("scratch.txt")), None);
+        assert_eq!(
+            ignore.relative_path(&root.join("debug.log")),
+            Some(PathBuf::from("debug.log"))
+        );
+
+        // Edited rules apply once their file's event is seen
+        fs::write(root.join("web/.gitignore"), "dist/\n").unwrap();
+        assert_eq!(ignore.relative_path(&root.join("web/debug.log")), None);
+        ignore.forget_changed_rules(&root.join("web/.gitignore"));
+        assert_eq!(
+            ignore.relative_path(&root.join("web/debug.log")),
+            Some(PathBuf::from("web/debug.log"))
+        );
+    }
+
+    #[test]
+    fn test_watched_dirs_skip_ignored_trees() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        for dir in [
+            "src/bin",
+            "node_modules/pkg",
+            "target/debug",
+            ".git/objects",
+            "web/dist",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join(".gitignore"), "node_modules/\ntarget/\n").unwrap();
+        fs::write(root.join("web/.gitignore"), "dist/\n").unwrap();
+
+        let mut dirs: Vec<PathBuf> = watched_dirs(root)
+            .into_iter()
+            .map(|dir| dir.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        dirs.sort();
+        assert_eq!(
+            dirs,
+            ["", "src", "src/bin", "web"].map(PathBuf::from).to_vec()
+        );
+    }
+}
@@ -421,6 +421,9 @@ pub fn init_database(app: &AppHandle) -> SqliteResult<Connection> {
         [],
     )?;
 
+    // Create multi_sessions table so sessions survive an app restart
+    crate::multi_session::manager::create_session_tables(&conn)?;
+
     Ok(conn)
 }
 
@@ -1520,13 +1520,21 @@ pub async fn create_checkpoint(
         }
     }
 
-    manager
+    let result = manager
         .create_checkpoint(description, None)
         .await
-        .map_err(|e| format!("Failed to create checkpoint: {}", e))
+        .map_err(|e| format!("Failed to create checkpoint: {}", e))?;
+    reload_pruned_sessions(&app, &result).await;
+    Ok(result)
 }
 
 /// Restores a session to a specific checkpoint
+///
+/// By default the current working state is saved first and can be returned
+/// to with `undo_last_restore`. With the `new_session` mode the current
+/// session is left untouched: only the checkpoint's files are restored, and
+/// its messages are written to a new session, whose ID is returned for
+/// `resume_claude_code`.
 #[tauri::command]
 pub async fn restore_checkpoint(
     app: tauri::State<'_, crate::checkpoint::state::CheckpointState>,
@@ -1534,11 +1542,17 @@ pub async fn restore_checkpoint(
     session_id: String,
     project_id: String,
     project_path: String,
+    mode: Option<crate::checkpoint::rewind::RestoreMode>,
+    force: Option<bool>,
 ) -> Result<crate::checkpoint::CheckpointResult, String> {
+    use crate::checkpoint::rewind::RestoreMode;
+
+    let mode = mode.unwrap_or_default();
     log::info!(
-        "Restoring checkpoint: {} for session: {}",
+        "Restoring checkpoint: {} for session: {} ({:?})",
         checkpoint_id,
-        session_id
+        session_id,
+        mode
     );
 
     let manager = app
@@ -1550,29 +1564,178 @@ pub async fn restore_checkpoint(
         .await
         .map_err(|e| format!("Failed to get checkpoint manager: {}", e))?;
 
-    let result = manager
-        .restore_checkpoint(&checkpoint_id)
+    let force = force.unwrap_or(false);
+    match mode {
+        RestoreMode::InPlace => {
+            // The pre-restore checkpoint must capture the conversation as it is on disk
+            let session_path = get_claude_dir()
+                .map_err(|e| e.to_string())?
+                .join("projects")
+                .join(&project_id)
+                .join(format!("{}.jsonl", session_id));
+            sync_session_messages(&manager, &session_path).await?;
+
+            let result = manager
+                .restore_checkpoint(&checkpoint_id, force)
+                .await
+                .map_err(|e| format!("Failed to restore checkpoint: {}", e))?;
+            write_restored_session(&manager, &result.checkpoint, &session_path)?;
+            Ok(result)
+        }
+        RestoreMode::NewSession => {
+            let result = manager
+                .restore_in_new_session(&checkpoint_id, force)
+                .await
+                .map_err(|e| format!("Failed to restore checkpoint: {}", e))?;
+            log::info!(
+                "Continuing checkpoint {} in session {}",
+                checkpoint_id,
+                result.session_id.as_deref().unwrap_or_default()
+            );
+            Ok(result)
+        }
+    }
+}
+
+/// Undoes the last restore, returning the project and conversation to the
+/// state saved just before it
+#[tauri::command]
+pub async fn undo_last_restore(
+    app: tauri::State<'_, crate::checkpoint::state::CheckpointState>,
+    session_id: String,
+    project_id: String,
+    project_path: String,
+    force: Option<bool>,
+) -> Result<crate::checkpoint::CheckpointResult, String> {
+    log::info!("Undoing last restore for session: {}", session_id);
+
+    let manager = app
+        .get_or_create_manager(
+            session_id.clone(),
+            project_id.clone(),
+            PathBuf::from(&project_path),
+        )
         .await
-        .map_err(|e| format!("Failed to restore checkpoint: {}", e))?;
+        .map_err(|e| format!("Failed to get checkpoint manager: {}", e))?;
 
-    // Update the session JSONL file with restored messages
-    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
-    let session_path = claude_dir
+    let session_path = get_claude_dir()
+        .map_err(|e| e.to_string())?
         .join("projects")
-        .join(&result.checkpoint.project_id)
+        .join(&project_id)
         .join(format!("{}.jsonl", session_id));
+    sync_session_messages(&manager, &session_path).await?;
+
+    let result = manager
+        .undo_last_restore(force.unwrap_or(false))
+        .await
+        .map_err(|e| format!("Failed to undo restore: {}", e))?;
+
+    write_restored_session(&manager, &result.checkpoint, &session_path)?;
+
+    Ok(result)
+}
+
+/// Loads the session's JSONL messages into the checkpoint manager
+async fn sync_session_messages(
+    manager: &crate::checkpoint::manager::CheckpointManager,
+    session_path: &std::path::Path,
+) -> Result<(), String> {
+    if session_path.exists() {
+        let content = fs::read_to_string(session_path)
+            .map_err(|e| format!("Failed to read session file: {}", e))?;
+        manager
+            .replace_messages(content.lines().map(|line| line.to_string()).collect())
+            .await;
+    }
+    Ok(())
+}
 
+/// Rewrites the session JSONL file with a restored checkpoint's messages
+fn write_restored_session(
+    manager: &crate::checkpoint::manager::CheckpointManager,
+    checkpoint: &crate::checkpoint::Checkpoint,
+    session_path: &std::path::Path,
+) -> Result<(), String> {
     // The manager has already restored the messages internally,
     // but we need to update the actual session file
     let (_, _, messages) = manager
         .storage
-        .load_checkpoint(&result.checkpoint.project_id, &session_id, &checkpoint_id)
+        .load_checkpoint(
+            &checkpoint.project_id,
+            &checkpoint.session_id,
+            &checkpoint.id,
+        )
         .map_err(|e| format!("Failed to load checkpoint data: {}", e))?;
 
-    fs::write(&session_path, messages)
-        .map_err(|e| format!("Failed to update session file: {}", e))?;
+    fs::write(session_path, messages).map_err(|e| format!("Failed to update session file: {}", e))
+}
 
-    Ok(result)
+/// Restores only the given paths or glob patterns from a checkpoint
+#[tauri::command]
+pub async fn restore_checkpoint_paths(
+    app: tauri::State<'_, crate::checkpoint::state::CheckpointState>,
+    checkpoint_id: String,
+    session_id: String,
+    project_id: String,
+    project_path: String,
+    paths: Vec<String>,
+    force: Option<bool>,
+) -> Result<crate::checkpoint::CheckpointResult, String> {
+    log::info!(
+        "Restoring {} paths from checkpoint: {} for session: {}",
+        paths.len(),
+        checkpoint_id,
+        session_id
+    );
+
+    let manager = app
+        .get_or_create_manager(
+            session_id.clone(),
+            project_id.clone(),
+            PathBuf::from(&project_path),
+        )
+        .await
+        .map_err(|e| format!("Failed to get checkpoint manager: {}", e))?;
+
+    // The pre-restore checkpoint must capture the conversation as it is on disk
+    let session_path = get_claude_dir()
+        .map_err(|e| e.to_string())?
+        .join("projects")
+        .join(&project_id)
+        .join(format!("{}.jsonl", session_id));
+    sync_session_messages(&manager, &session_path).await?;
+
+    manager
+        .restore_paths(&checkpoint_id, &paths, force.unwrap_or(false))
+        .await
+        .map_err(|e| format!("Failed to restore paths: {}", e))
+}
+
+/// Previews what restoring a checkpoint would change, without touching any files
+#[tauri::command]
+pub async fn preview_restore_checkpoint(
+    app: tauri::State<'_, crate::checkpoint::state::CheckpointState>,
+    checkpoint_id: String,
+    session_id: String,
+    project_id: String,
+    project_path: String,
+    paths: Option<Vec<String>>,
+) -> Result<crate::checkpoint::restore::RestorePlan, String> {
+    log::info!(
+        "Previewing restore of checkpoint: {} for session: {}",
+        checkpoint_id,
+        session_id
+    );
+
+    let manager = app
+        .get_or_create_manager(session_id, project_id, PathBuf::from(&project_path))
+        .await
+        .map_err(|e| format!("Failed to get checkpoint manager: {}", e))?;
+
+    manager
+        .preview_restore(&checkpoint_id, paths.as_deref())
+        .await
+        .map_err(|e| format!("Failed to preview restore: {}", e))
 }
 
 /// Lists all checkpoints for a session
@@ -1641,10 +1804,12 @@ pub async fn fork_from_checkpoint(
         .await
         .map_err(|e| format!("Failed to get checkpoint manager: {}", e))?;
 
-    manager
+    let result = manager
         .fork_from_checkpoint(&checkpoint_id, description)
         .await
-        .map_err(|e| format!("Failed to fork checkpoint: {}", e))
+        .map_err(|e| format!("Failed to fork checkpoint: {}", e))?;
+    reload_pruned_sessions(&app, &result).await;
+    Ok(result)
 }
 
 /// Gets the timeline for a session
@@ -1669,6 +1834,161 @@ pub async fn get_session_timeline(
     Ok(manager.get_timeline().await)
 }
 
+/// Gets the timeline of a session as a graph, with merges as extra edges
+#[tauri::command]
+pub async fn get_timeline_graph(
+    app: tauri::State<'_, crate::checkpoint::state::CheckpointState>,
+    session_id: String,
+    project_id: String,
+    project_path: String,
+) -> Result<crate::checkpoint::merge::TimelineGraph, String> {
+    log::info!("Getting timeline graph for session: {}", session_id);
+
+    let manager = app
+        .get_or_create_manager(session_id, project_id, PathBuf::from(&project_path))
+        .await
+        .map_err(|e| format!("Failed to get checkpoint manager: {}", e))?;
+
+    Ok(manager.get_timeline().await.graph())
+}
+
+/// Three-way merges one checkpoint into another and continues from the merge
+#[tauri::command]
+pub async fn merge_checkpoints(
+    app: tauri::State<'_, crate::checkpoint::state::CheckpointState>,
+    session_id: String,
+    project_id: String,
+    project_path: String,
+    ours_checkpoint_id: String,
+    theirs_checkpoint_id: String,
+    base_checkpoint_id: Option<String>,
+    force: Option<bool>,
+) -> Result<crate::checkpoint::merge::MergeResult, String> {
+    log::info!(
+        "Merging checkpoint {} into {} in session: {}",
+        theirs_checkpoint_id,
+        ours_checkpoint_id,
+        session_id
+    );
+
+    let manager = app
+        .get_or_create_manager(session_id, project_id, PathBuf::from(&project_path))
+        .await
+        .map_err(|e| format!("Failed to get checkpoint manager: {}", e))?;
+
+    manager
+        .merge_checkpoints(
+            &ours_checkpoint_id,
+            &theirs_checkpoint_id,
+            base_checkpoint_id.as_deref(),
+            force.unwrap_or(false),
+        )
+        .await
+        .map_err(|e| format!("Failed to merge checkpoints: {}", e))
+}
+
+/// Commits a checkpoint's snapshot to a new or existing git branch, without
+/// touching the working tree
+#[tauri::command]
+pub async fn promote_checkpoint(
+    checkpoint_id: String,
+    session_id: String,
+    project_id: String,
+    project_path: String,
+    branch: String,
+) -> Result<crate::checkpoint::promote::PromotedCheckpoint, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!(
+        "Promoting checkpoint: {} to branch: {}",
+        checkpoint_id,
+        branch
+    );
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    // Every file of the checkpoint is read and written to git
+    tokio::task::spawn_blocking(move || {
+        storage.promote_checkpoint(
+            &project_id,
+            &session_id,
+            &checkpoint_id,
+            std::path::Path::new(&project_path),
+            &branch,
+        )
+    })
+    .await
+    .map_err(|e| format!("Failed to spawn blocking task: {}", e))?
+    .map_err(|e| format!("Failed to promote checkpoint: {}", e))
+}
+
+/// Finds the first checkpoint between the root and the current checkpoint at
+/// which a test command fails, running it on a scratch copy of each
+/// checkpoint tested
+#[tauri::command]
+pub async fn bisect_checkpoints(
+    session_id: String,
+    project_id: String,
+    command: String,
+    timeout_seconds: Option<u64>,
+) -> Result<crate::checkpoint::bisect::BisectResult, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!(
+        "Bisecting checkpoints of session: {} with: {}",
+        session_id,
+        command
+    );
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+    let timeout = timeout_seconds.map(std::time::Duration::from_secs);
+
+    // The test command may run for a long time on every step
+    tokio::task::spawn_blocking(move || {
+        storage.bisect_checkpoints(&project_id, &session_id, &command, timeout)
+    })
+    .await
+    .map_err(|e| format!("Failed to spawn blocking task: {}", e))?
+    .map_err(|e| format!("Failed to bisect checkpoints: {}", e))
+}
+
+/// Attributes each line of a file to the checkpoint, and so the prompt, that
+/// introduced it
+#[tauri::command]
+pub async fn blame_checkpoint_file(
+    session_id: String,
+    project_id: String,
+    file_path: String,
+    checkpoint_id: Option<String>,
+) -> Result<crate::checkpoint::blame::FileBlame, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!(
+        "Blaming {} in session: {} at checkpoint: {:?}",
+        file_path,
+        session_id,
+        checkpoint_id
+    );
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    // The file is read and diffed at every checkpoint up to the given one
+    tokio::task::spawn_blocking(move || {
+        storage.blame_file(
+            &project_id,
+            &session_id,
+            std::path::Path::new(&file_path),
+            checkpoint_id.as_deref(),
+        )
+    })
+    .await
+    .map_err(|e| format!("Failed to spawn blocking task: {}", e))?
+    .map_err(|e| format!("Failed to blame file: {}", e))
+}
+
 /// Updates checkpoint settings for a session
 #[tauri::command]
 pub async fn update_checkpoint_settings(
@@ -1678,6 +1998,9 @@ pub async fn update_checkpoint_settings(
     project_path: String,
     auto_checkpoint_enabled: bool,
     checkpoint_strategy: String,
+    max_file_size: Option<u64>,
+    auto_checkpoint_rules: Option<crate::checkpoint::rules::AutoCheckpointRules>,
+    git_mismatch_policy: Option<crate::checkpoint::git::GitMismatchPolicy>,
 ) -> Result<(), String> {
     use crate::checkpoint::CheckpointStrategy;
 
@@ -1688,6 +2011,7 @@ pub async fn update_checkpoint_settings(
         "per_prompt" => CheckpointStrategy::PerPrompt,
         "per_tool_use" => CheckpointStrategy::PerToolUse,
         "smart" => CheckpointStrategy::Smart,
+        "rules" => CheckpointStrategy::Rules,
         _ => {
             return Err(format!(
                 "Invalid checkpoint strategy: {}",
@@ -1702,7 +2026,13 @@ pub async fn update_checkpoint_settings(
         .map_err(|e| format!("Failed to get checkpoint manager: {}", e))?;
 
     manager
-        .update_settings(auto_checkpoint_enabled, strategy)
+        .update_settings(
+            auto_checkpoint_enabled,
+            strategy,
+            max_file_size,
+            auto_checkpoint_rules,
+            git_mismatch_policy,
+        )
         .await
         .map_err(|e| format!("Failed to update settings: {}", e))
 }
@@ -1714,7 +2044,9 @@ pub async fn get_checkpoint_diff(
     to_checkpoint_id: String,
     session_id: String,
     project_id: String,
+    context_lines: Option<usize>,
 ) -> Result<crate::checkpoint::CheckpointDiff, String> {
+    use crate::checkpoint::diff::{diff_checkpoints, DEFAULT_CONTEXT_LINES};
     use crate::checkpoint::storage::CheckpointStorage;
 
     log::info!(
@@ -1734,64 +2066,293 @@ pub async fn get_checkpoint_diff(
         .load_checkpoint(&project_id, &session_id, &to_checkpoint_id)
         .map_err(|e| format!("Failed to load target checkpoint: {}", e))?;
 
-    // Build file maps
-    let mut from_map: std::collections::HashMap<PathBuf, &crate::checkpoint::FileSnapshot> =
-        std::collections::HashMap::new();
-    for file in &from_files {
-        from_map.insert(file.file_path.clone(), file);
-    }
+    Ok(diff_checkpoints(
+        &from_checkpoint,
+        &from_files,
+        &to_checkpoint,
+        &to_files,
+        context_lines.unwrap_or(DEFAULT_CONTEXT_LINES),
+    ))
+}
 
-    let mut to_map: std::collections::HashMap<PathBuf, &crate::checkpoint::FileSnapshot> =
-        std::collections::HashMap::new();
-    for file in &to_files {
-        to_map.insert(file.file_path.clone(), file);
-    }
+/// Verifies the integrity of a session's checkpoint store, optionally repairing it
+#[tauri::command]
+pub async fn verify_checkpoints(
+    session_id: String,
+    project_id: String,
+    repair: Option<bool>,
+) -> Result<crate::checkpoint::integrity::IntegrityReport, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
 
-    // Calculate differences
-    let mut modified_files = Vec::new();
-    let mut added_files = Vec::new();
-    let mut deleted_files = Vec::new();
-
-    // Check for modified and deleted files
-    for (path, from_file) in &from_map {
-        if let Some(to_file) = to_map.get(path) {
-            if from_file.hash != to_file.hash {
-                // File was modified
-                let additions = to_file.content.lines().count();
-                let deletions = from_file.content.lines().count();
-
-                modified_files.push(crate::checkpoint::FileDiff {
-                    path: path.clone(),
-                    additions,
-                    deletions,
-                    diff_content: None, // TODO: Generate actual diff
-                });
-            }
-        } else {
-            // File was deleted
-            deleted_files.push(path.clone());
-        }
-    }
+    log::info!("Verifying checkpoints for session: {}", session_id);
 
-    // Check for added files
-    for (path, _) in &to_map {
-        if !from_map.contains_key(path) {
-            added_files.push(path.clone());
-        }
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    // Every object the session references is read and hashed
+    tokio::task::spawn_blocking(move || {
+        storage.verify_checkpoints(&project_id, &session_id, repair.unwrap_or(false))
+    })
+    .await
+    .map_err(|e| format!("Failed to spawn blocking task: {}", e))?
+    .map_err(|e| format!("Failed to verify checkpoints: {}", e))
+}
+
+/// Exports a session's checkpoints, or a subset of them, to a bundle file
+#[tauri::command]
+pub async fn export_checkpoints(
+    session_id: String,
+    project_id: String,
+    project_path: String,
+    checkpoint_ids: Option<Vec<String>>,
+    output_path: String,
+) -> Result<crate::checkpoint::bundle::BundleSummary, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!(
+        "Exporting checkpoints for session {} to {}",
+        session_id,
+        output_path
+    );
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    // Every exported file content is read and compressed
+    tokio::task::spawn_blocking(move || {
+        storage.export_bundle(
+            &project_id,
+            &session_id,
+            &project_path,
+            checkpoint_ids.as_deref(),
+            &PathBuf::from(output_path),
+        )
+    })
+    .await
+    .map_err(|e| format!("Failed to spawn blocking task: {}", e))?
+    .map_err(|e| format!("Failed to export checkpoints: {}", e))
+}
+
+/// Imports a checkpoint bundle as a new session of a project
+#[tauri::command]
+pub async fn import_checkpoints(
+    bundle_path: String,
+    project_id: String,
+    project_path: String,
+    session_id: Option<String>,
+) -> Result<crate::checkpoint::bundle::BundleSummary, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!(
+        "Importing checkpoint bundle {} into project {}",
+        bundle_path,
+        project_id
+    );
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    tokio::task::spawn_blocking(move || {
+        storage.import_bundle(
+            &PathBuf::from(bundle_path),
+            &project_id,
+            &project_path,
+            session_id,
+        )
+    })
+    .await
+    .map_err(|e| format!("Failed to spawn blocking task: {}", e))?
+    .map_err(|e| format!("Failed to import checkpoints: {}", e))
+}
+
+/// Reports how much space a project's checkpoints use across all sessions
+#[tauri::command]
+pub async fn get_checkpoint_storage_stats(
+    project_id: String,
+) -> Result<crate::checkpoint::pool::PoolStats, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!(
+        "Getting checkpoint storage stats for project: {}",
+        project_id
+    );
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    storage
+        .pool_stats(&project_id)
+        .map_err(|e| format!("Failed to get checkpoint storage stats: {}", e))
+}
+
+/// Gets the retention policy of a project, if one was set
+#[tauri::command]
+pub async fn get_retention_policy(
+    project_id: String,
+) -> Result<Option<crate::checkpoint::retention::RetentionPolicy>, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!("Getting retention policy for project: {}", project_id);
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    storage
+        .load_retention_policy(&project_id)
+        .map_err(|e| format!("Failed to get retention policy: {}", e))
+}
+
+/// Sets the retention policy of a project, or clears it when none is given
+#[tauri::command]
+pub async fn set_retention_policy(
+    project_id: String,
+    policy: Option<crate::checkpoint::retention::RetentionPolicy>,
+) -> Result<(), String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!("Setting retention policy for project: {}", project_id);
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    storage
+        .save_retention_policy(&project_id, policy.as_ref())
+        .map_err(|e| format!("Failed to set retention policy: {}", e))
+}
+
+/// Applies a project's retention policy now, rather than after the next
+/// checkpoint
+#[tauri::command]
+pub async fn apply_retention_policy(
+    app: tauri::State<'_, crate::checkpoint::state::CheckpointState>,
+    project_id: String,
+) -> Result<crate::checkpoint::retention::RetentionReport, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!("Applying retention policy for project: {}", project_id);
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    let policy = storage
+        .load_retention_policy(&project_id)
+        .map_err(|e| format!("Failed to get retention policy: {}", e))?
+        .ok_or_else(|| "Project has no retention policy".to_string())?;
+    // Every session of the project is thinned, then the pool collected
+    let report = tokio::task::spawn_blocking(move || storage.apply_retention(&project_id, &policy))
+        .await
+        .map_err(|e| format!("Failed to spawn blocking task: {}", e))?
+        .map_err(|e| format!("Failed to apply retention policy: {}", e))?;
+
+    // Active sessions must not keep showing pruned checkpoints
+    for session_id in app.list_active_sessions().await {
+        reload_manager_timeline(&app, &session_id).await;
     }
 
-    // Calculate token delta
-    let token_delta = (to_checkpoint.metadata.total_tokens as i64)
-        - (from_checkpoint.metadata.total_tokens as i64);
+    Ok(report)
+}
 
-    Ok(crate::checkpoint::CheckpointDiff {
-        from_checkpoint_id,
-        to_checkpoint_id,
-        modified_files,
-        added_files,
-        deleted_files,
-        token_delta,
+/// Pins or unpins a checkpoint so retention never removes it
+#[tauri::command]
+pub async fn set_checkpoint_pinned(
+    app: tauri::State<'_, crate::checkpoint::state::CheckpointState>,
+    session_id: String,
+    project_id: String,
+    checkpoint_id: String,
+    pinned: bool,
+) -> Result<crate::checkpoint::Checkpoint, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!("Setting pinned={} on checkpoint: {}", pinned, checkpoint_id);
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    let checkpoint = storage
+        .set_checkpoint_pinned(&project_id, &session_id, &checkpoint_id, pinned)
+        .map_err(|e| format!("Failed to pin checkpoint: {}", e))?;
+    reload_manager_timeline(&app, &session_id).await;
+    Ok(checkpoint)
+}
+
+/// Replaces the tags of a checkpoint
+#[tauri::command]
+pub async fn set_checkpoint_tags(
+    app: tauri::State<'_, crate::checkpoint::state::CheckpointState>,
+    session_id: String,
+    project_id: String,
+    checkpoint_id: String,
+    tags: Vec<String>,
+) -> Result<crate::checkpoint::Checkpoint, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!("Setting tags {:?} on checkpoint: {}", tags, checkpoint_id);
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    let checkpoint = storage
+        .set_checkpoint_tags(&project_id, &session_id, &checkpoint_id, &tags)
+        .map_err(|e| format!("Failed to tag checkpoint: {}", e))?;
+    reload_manager_timeline(&app, &session_id).await;
+    Ok(checkpoint)
+}
+
+/// Searches the checkpoints of a session, or of the whole project when no
+/// session is given, newest first
+#[tauri::command]
+pub async fn search_checkpoints(
+    project_id: String,
+    session_id: Option<String>,
+    query: crate::checkpoint::search::CheckpointQuery,
+) -> Result<Vec<crate::checkpoint::search::CheckpointMatch>, String> {
+    use crate::checkpoint::storage::CheckpointStorage;
+
+    log::info!("Searching checkpoints of project: {}", project_id);
+
+    let claude_dir = get_claude_dir().map_err(|e| e.to_string())?;
+    let storage = CheckpointStorage::new(claude_dir);
+
+    // A search across the project goes through every session in the index
+    tokio::task::spawn_blocking(move || {
+        storage.search_checkpoints(&project_id, session_id.as_deref(), &query)
     })
+    .await
+    .map_err(|e| format!("Failed to spawn blocking task: {}", e))?
+    .map_err(|e| format!("Failed to search checkpoints: {}", e))
+}
+
+/// Refreshes an active session's timeline after its checkpoints were changed
+/// on disk
+async fn reload_manager_timeline(
+    app: &crate::checkpoint::state::CheckpointState,
+    session_id: &str,
+) {
+    if let Some(manager) = app.get_manager(session_id).await {
+        if let Err(e) = manager.reload_timeline().await {
+            log::warn!("Failed to reload timeline of session {}: {}", session_id, e);
+        }
+    }
+}
+
+/// Refreshes the active sessions that retention pruned while a checkpoint was
+/// created; the session that created it is already up to date
+async fn reload_pruned_sessions(
+    app: &crate::checkpoint::state::CheckpointState,
+    result: &crate::checkpoint::CheckpointResult,
+) {
+    let Some(report) = &result.retention else {
+        return;
+    };
+    let sessions: std::collections::HashSet<&str> = report
+        .pruned
+        .iter()
+        .map(|pruned| pruned.session_id.as_str())
+        .filter(|session_id| *session_id != result.checkpoint.session_id)
+        .collect();
+    for session_id in sessions {
+        reload_manager_timeline(app, session_id).await;
+    }
 }
 
 /// Tracks a message for checkpointing
@@ -1859,10 +2420,16 @@ pub async fn cleanup_old_checkpoints(
         .await
         .map_err(|e| format!("Failed to get checkpoint manager: {}", e))?;
 
-    manager
+    let removed = manager
         .storage
         .cleanup_old_checkpoints(&project_id, &session_id, keep_count)
-        .map_err(|e| format!("Failed to cleanup checkpoints: {}", e))
+        .map_err(|e| format!("Failed to cleanup checkpoints: {}", e))?;
+    manager
+        .reload_timeline()
+        .await
+        .map_err(|e| format!("Failed to reload timeline: {}", e))?;
+
+    Ok(removed)
 }
 
 /// Gets checkpoint settings for a session
@@ -1885,6 +2452,9 @@ pub async fn get_checkpoint_settings(
     Ok(serde_json::json!({
         "auto_checkpoint_enabled": timeline.auto_checkpoint_enabled,
         "checkpoint_strategy": timeline.checkpoint_strategy,
+        "auto_checkpoint_rules": timeline.auto_checkpoint_rules,
+        "max_file_size": timeline.max_file_size,
+        "git_mismatch_policy": timeline.git_mismatch_policy,
         "total_checkpoints": timeline.total_checkpoints,
         "current_checkpoint_id": timeline.current_checkpoint_id,
     }))
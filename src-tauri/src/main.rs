@@ -19,15 +19,20 @@ use commands::agents::{
     list_running_sessions, set_claude_binary_path, stream_session_output, update_agent, AgentDb,
 };
 use commands::claude::{
-    cancel_claude_execution, check_auto_checkpoint, check_claude_version, cleanup_old_checkpoints,
-    clear_checkpoint_manager, continue_claude_code, create_checkpoint, execute_claude_code,
+    apply_retention_policy, bisect_checkpoints, blame_checkpoint_file, cancel_claude_execution,
+    check_auto_checkpoint, check_claude_version, cleanup_old_checkpoints, clear_checkpoint_manager,
+    continue_claude_code, create_checkpoint, execute_claude_code, export_checkpoints,
     find_claude_md_files, fork_from_checkpoint, get_checkpoint_diff, get_checkpoint_settings,
-    get_checkpoint_state_stats, get_claude_settings, get_project_sessions,
-    get_recently_modified_files, get_session_timeline, get_system_prompt, list_checkpoints,
-    list_directory_contents, list_projects, load_session_history, open_new_session,
-    read_claude_md_file, restore_checkpoint, resume_claude_code, save_claude_md_file,
-    save_claude_settings, save_system_prompt, search_files, track_checkpoint_message,
-    track_session_messages, update_checkpoint_settings, ClaudeProcessState,
+    get_checkpoint_state_stats, get_checkpoint_storage_stats, get_claude_settings,
+    get_project_sessions, get_recently_modified_files, get_retention_policy, get_session_timeline,
+    get_system_prompt, get_timeline_graph, import_checkpoints, list_checkpoints,
+    list_directory_contents, list_projects, load_session_history, merge_checkpoints,
+    open_new_session, preview_restore_checkpoint, promote_checkpoint, read_claude_md_file,
+    restore_checkpoint, restore_checkpoint_paths, resume_claude_code, save_claude_md_file,
+    save_claude_settings, save_system_prompt, search_checkpoints, search_files,
+    set_checkpoint_pinned, set_checkpoint_tags, set_retention_policy, track_checkpoint_message,
+    track_session_messages, undo_last_restore, update_checkpoint_settings, verify_checkpoints,
+    ClaudeProcessState,
 };
 use commands::mcp::{
     mcp_add, mcp_add_from_claude_desktop, mcp_add_json, mcp_get, mcp_get_server_status, mcp_list,
@@ -109,6 +114,18 @@ fn main() {
                 5, // max concurrent sessions
             )));
             
+            // Bring back the sessions that were open when the app last closed
+            let restore_manager = session_manager.clone();
+            tauri::async_runtime::spawn(async move {
+                // Restoring runs git for every session, so work on a clone
+                // rather than keep the manager locked meanwhile
+                let manager = restore_manager.lock().await.clone();
+                match manager.restore_sessions().await {
+                    Ok(count) => log::info!("Restored {} multi-sessions", count),
+                    Err(e) => log::error!("Failed to restore multi-sessions: {}", e),
+                }
+            });
+
             app.manage(session_manager);
 
             Ok(())
@@ -134,11 +151,29 @@ fn main() {
             search_files,
             create_checkpoint,
             restore_checkpoint,
+            restore_checkpoint_paths,
+            preview_restore_checkpoint,
+            undo_last_restore,
             list_checkpoints,
             fork_from_checkpoint,
             get_session_timeline,
             update_checkpoint_settings,
             get_checkpoint_diff,
+            verify_checkpoints,
+            export_checkpoints,
+            import_checkpoints,
+            get_checkpoint_storage_stats,
+            get_retention_policy,
+            set_retention_policy,
+            apply_retention_policy,
+            set_checkpoint_pinned,
+            set_checkpoint_tags,
+            search_checkpoints,
+            get_timeline_graph,
+            merge_checkpoints,
+            promote_checkpoint,
+            bisect_checkpoints,
+            blame_checkpoint_file,
             track_checkpoint_message,
             track_session_messages,
             check_auto_checkpoint,
@@ -1,5 +1,7 @@
-use std::path::PathBuf;
-use std::process::Command;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use anyhow::{Result, Context, bail};
 use regex::Regex;
 
@@ -87,6 +89,47 @@ impl GitWorktree {
         Ok(())
     }
 
+    /// Check that the worktree still has its branch checked out, adding it
+    /// back from the branch if its directory has gone missing
+    pub fn ensure_checked_out(&self) -> Result<()> {
+        if self.worktree_path.exists() {
+            let branch = run_git(
+                &self.worktree_path,
+                ["rev-parse", "--abbrev-ref", "HEAD"],
+                None,
+                None,
+            )
+            .with_context(|| format!("Not a git worktree: {:?}", self.worktree_path))?;
+            if branch != self.branch_name {
+                bail!(
+                    "Worktree {:?} has {} checked out instead of {}",
+                    self.worktree_path,
+                    branch,
+                    self.branch_name
+                );
+            }
+            return Ok(());
+        }
+
+        // Forget the missing directory before checking the branch out again
+        run_git(&self.repo_path, ["worktree", "prune"], None, None)?;
+        if let Some(parent) = self.worktree_path.parent() {
+            std::fs::create_dir_all(parent)
+                .context("Failed to create worktree parent directory")?;
+        }
+        let worktree_path = self.worktree_path.to_str()
+            .context("Worktree path is not valid UTF-8")?;
+        run_git(
+            &self.repo_path,
+            ["worktree", "add", worktree_path, &self.branch_name],
+            None,
+            None,
+        )
+        .with_context(|| format!("Failed to restore worktree for branch {}", self.branch_name))?;
+
+        Ok(())
+    }
+
     pub fn get_diff_stats(&self) -> Result<super::DiffStats> {
         let output = Command::new("git")
             .current_dir(&self.worktree_path)
@@ -186,9 +229,225 @@ impl GitWorktree {
     }
 }
 
-impl Drop for GitWorktree {
-    fn drop(&mut self) {
-        // Best effort cleanup
-        let _ = self.remove();
+/// A file to write into a commit made by [`commit_snapshot`]
+pub struct SnapshotFile {
+    /// Path relative to the directory the snapshot was taken of
+    pub path: PathBuf,
+    /// File content, or the target of a symlink
+    pub content: Vec<u8>,
+    pub executable: bool,
+    pub symlink: bool,
+}
+
+/// A commit written by [`commit_snapshot`]
+pub struct SnapshotCommit {
+    pub commit: String,
+    /// Commit the new one was made on top of, if any
+    pub parent: Option<String>,
+    /// Whether the branch was created for the commit
+    pub created_branch: bool,
+}
+
+/// Commit a snapshot of `dir` to `branch`, leaving the working tree, the
+/// index and HEAD alone.
+///
+/// The commit is built in a temporary index: it starts from the tree of the
+/// branch's tip, or of `start_point` (falling back to HEAD) when the branch
+/// does not exist yet, writes `files` and removes `deleted`, both relative to
+/// `dir`. Everything else in that tree is kept, including files the snapshot
+/// does not cover. Branches that are checked out in a worktree are refused,
+/// since moving them would leave that worktree out of step with its branch.
+pub fn commit_snapshot(
+    dir: &Path,
+    branch: &str,
+    start_point: Option<&str>,
+    files: &[SnapshotFile],
+    deleted: &[PathBuf],
+    message: &str,
+) -> Result<SnapshotCommit> {
+    let top_level = PathBuf::from(run_git(dir, ["rev-parse", "--show-toplevel"], None, None)?);
+    let prefix = run_git(dir, ["rev-parse", "--show-prefix"], None, None)?;
+
+    run_git(dir, ["check-ref-format", "--branch", branch], None, None)
+        .with_context(|| format!("Invalid branch name: {}", branch))?;
+    let branch_ref = format!("refs/heads/{}", branch);
+    let worktrees = run_git(dir, ["worktree", "list", "--porcelain"], None, None)?;
+    if worktrees
+        .lines()
+        .any(|line| line.strip_prefix("branch ") == Some(branch_ref.as_str()))
+    {
+        bail!("Branch {} is checked out; commit to another branch", branch);
+    }
+
+    let branch_tip = resolve_commit(dir, &branch_ref);
+    let parent = match &branch_tip {
+        Some(tip) => Some(tip.clone()),
+        None => match start_point {
+            Some(start_point) => Some(
+                resolve_commit(dir, start_point)
+                    .with_context(|| format!("Unknown start point: {}", start_point))?,
+            ),
+            None => resolve_commit(dir, "HEAD"),
+        },
+    };
+
+    // Build the tree in a private index so the real one is never touched
+    let index_dir = tempfile::tempdir().context("Failed to create temporary index directory")?;
+    let index = index_dir.path().join("index");
+    let index = Some(index.as_path());
+    match &parent {
+        Some(parent) => run_git(&top_level, ["read-tree", parent.as_str()], None, index)?,
+        None => run_git(&top_level, ["read-tree", "--empty"], None, index)?,
+    };
+    let index_path = |path: &Path| -> Result<String> {
+        let path = path
+            .to_str()
+            .with_context(|| format!("Path is not valid UTF-8: {:?}", path))?;
+        Ok(format!("{}{}", prefix, path.replace('\\', "/")))
+    };
+
+    // Blobs are written by a single git process, from copies of the contents
+    // next to the temporary index
+    let mut blob_paths = String::new();
+    for (i, file) in files.iter().enumerate() {
+        let blob_path = index_dir.path().join(format!("blob-{}", i));
+        fs::write(&blob_path, &file.content).context("Failed to write snapshot file")?;
+        blob_paths.push_str(&blob_path.to_string_lossy());
+        blob_paths.push('\n');
+    }
+    let blobs = if files.is_empty() {
+        String::new()
+    } else {
+        run_git(
+            &top_level,
+            ["hash-object", "-w", "--no-filters", "--stdin-paths"],
+            Some(blob_paths.as_bytes()),
+            None,
+        )?
+    };
+    if blobs.lines().count() != files.len() {
+        bail!("git hash-object did not write every snapshot file");
+    }
+
+    let mut index_info = Vec::new();
+    for (file, blob) in files.iter().zip(blobs.lines()) {
+        let mode = if file.symlink {
+            "120000"
+        } else if file.executable {
+            "100755"
+        } else {
+            "100644"
+        };
+        index_info.extend_from_slice(
+            format!("{} {}\t{}\0", mode, blob, index_path(&file.path)?).as_bytes(),
+        );
+    }
+    if !index_info.is_empty() {
+        run_git(
+            &top_level,
+            ["update-index", "-z", "--index-info"],
+            Some(&index_info),
+            index,
+        )?;
+    }
+
+    let mut removed = Vec::new();
+    for path in deleted {
+        removed.extend_from_slice(format!("{}\0", index_path(path)?).as_bytes());
+    }
+    if !removed.is_empty() {
+        run_git(
+            &top_level,
+            ["update-index", "-z", "--force-remove", "--stdin"],
+            Some(&removed),
+            index,
+        )?;
+    }
+    let tree = run_git(&top_level, ["write-tree"], None, index)?;
+
+    if let Some(parent) = &parent {
+        let parent_tree = run_git(
+            &top_level,
+            ["rev-parse", &format!("{}^{{tree}}", parent)],
+            None,
+            None,
+        )?;
+        if parent_tree == tree {
+            bail!("Nothing to commit: {} already matches the snapshot", branch);
+        }
+    }
+
+    let mut commit_args = vec!["commit-tree", tree.as_str(), "-F", "-"];
+    if let Some(parent) = &parent {
+        commit_args.extend(["-p", parent.as_str()]);
+    }
+    let commit = run_git(&top_level, commit_args, Some(message.as_bytes()), None)?;
+
+    // Only move the branch if nobody else moved it in the meantime
+    let expected = branch_tip.as_deref().unwrap_or("");
+    run_git(
+        &top_level,
+        ["update-ref", branch_ref.as_str(), commit.as_str(), expected],
+        None,
+        None,
+    )?;
+
+    Ok(SnapshotCommit {
+        commit,
+        parent,
+        created_branch: branch_tip.is_none(),
+    })
+}
+
+/// The commit a revision points to, or `None` if it does not resolve to one
+fn resolve_commit(dir: &Path, revision: &str) -> Option<String> {
+    let revision = format!("{}^{{commit}}", revision);
+    run_git(
+        dir,
+        ["rev-parse", "--verify", "-q", revision.as_str()],
+        None,
+        None,
+    )
+    .ok()
+}
+
+/// Run a git command, optionally feeding it `input` and pointing it at another
+/// index file, and return its trimmed output
+fn run_git<'a>(
+    dir: &Path,
+    args: impl IntoIterator<Item = &'a str>,
+    input: Option<&[u8]>,
+    index: Option<&Path>,
+) -> Result<String> {
+    let args: Vec<&str> = args.into_iter().collect();
+    let mut command = Command::new("git");
+    command
+        .current_dir(dir)
+        .args(&args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(index) = index {
+        command.env("GIT_INDEX_FILE", index);
     }
-}
\ No newline at end of file
+
+    let mut child = command.spawn().context("Failed to run git")?;
+    if let Some(input) = input {
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input).context("Failed to write to git")?;
+        }
+    }
+    let output = child.wait_with_output().context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
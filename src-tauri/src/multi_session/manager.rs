@@ -1,12 +1,14 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{Mutex, RwLock, broadcast};
 use anyhow::{Result, Context, bail};
+use chrono::{DateTime, Utc};
 use rusqlite::Connection;
 use super::{
     Session, SessionConfig, SessionEvent, SessionInfo, SessionStatus,
-    GitWorktree, process::ProcessManager, auto_yes::AutoYesManager,
+    GitWorktree, process::{ProcessIdentity, ProcessManager}, auto_yes::AutoYesManager,
     DiffStats,
 };
 
@@ -81,17 +83,33 @@ impl SessionManager {
         });
         
         // Store in database
-        self.store_session_in_db(&session).await?;
+        if let Err(e) = self.store_session_in_db(&session).await {
+            let _ = worktree.remove();
+            return Err(e);
+        }
         
         // Start Claude process
-        let child = ProcessManager::spawn_claude_session(
+        let child = match ProcessManager::spawn_claude_session(
             &session,
             self.event_tx.clone(),
-        ).await?;
+        ).await {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = worktree.remove();
+                session.set_status(SessionStatus::Terminated).await;
+                let _ = self.update_session_state_in_db(&session).await;
+                return Err(e);
+            }
+        };
         
         // Store process handle
+        *session.pid.lock().await = match child.id() {
+            Some(pid) => Some(ProcessManager::identify(pid).await),
+            None => None,
+        };
         *session.process.lock().await = Some(child);
         session.set_status(SessionStatus::Running).await;
+        self.update_session_state_in_db(&session).await?;
         
         // Add to active sessions
         self.sessions.write().await.insert(session_id.clone(), session.clone());
@@ -123,7 +141,7 @@ impl SessionManager {
         worktree.remove()?;
         
         // Update database
-        self.update_session_status_in_db(session_id, SessionStatus::Terminated).await?;
+        self.update_session_state_in_db(&session).await?;
         
         // Send termination event
         let _ = self.event_tx.send(SessionEvent::SessionTerminated {
@@ -147,12 +165,10 @@ impl SessionManager {
         worktree.commit_changes("WIP: Pausing session")?;
         
         // Terminate the process but keep the session
-        if let Some(mut process) = session.process.lock().await.take() {
-            let _ = process.kill().await;
-        }
+        session.stop_process().await;
         
         session.set_status(SessionStatus::Paused).await;
-        self.update_session_status_in_db(session_id, SessionStatus::Paused).await?;
+        self.update_session_state_in_db(session).await?;
         
         Ok(())
     }
@@ -162,19 +178,32 @@ impl SessionManager {
         let session = sessions.get(session_id)
             .context("Session not found")?;
         
-        if *session.status.lock().await != SessionStatus::Paused {
-            bail!("Session is not paused");
+        let status = session.status.lock().await.clone();
+        if status != SessionStatus::Paused && status != SessionStatus::Interrupted {
+            bail!("Session is not paused or interrupted");
         }
         
+        // The worktree may have been removed while the session was stopped
+        let worktree = GitWorktree {
+            repo_path: session.project_path.clone(),
+            worktree_path: session.worktree_path.clone(),
+            branch_name: session.branch_name.clone(),
+        };
+        tokio::task::spawn_blocking(move || worktree.ensure_checked_out()).await??;
+        
         // Restart Claude process
         let child = ProcessManager::spawn_claude_session(
             session,
             self.event_tx.clone(),
         ).await?;
         
+        *session.pid.lock().await = match child.id() {
+            Some(pid) => Some(ProcessManager::identify(pid).await),
+            None => None,
+        };
         *session.process.lock().await = Some(child);
         session.set_status(SessionStatus::Running).await;
-        self.update_session_status_in_db(session_id, SessionStatus::Running).await?;
+        self.update_session_state_in_db(session).await?;
         
         Ok(())
     }
@@ -187,6 +216,8 @@ impl SessionManager {
         let mut process_guard = session.process.lock().await;
         if let Some(child) = process_guard.as_mut() {
             ProcessManager::send_input(child, input).await?;
+        } else if session.pid.lock().await.is_some() {
+            bail!("Session process was started before the app restarted and can't take input; pause and resume the session to reconnect");
         } else {
             bail!("Session process not running");
         }
@@ -234,7 +265,7 @@ impl SessionManager {
         session_id: &str,
         config: SessionConfig,
     ) -> Result<()> {
-        let sessions = self.sessions.read().await;
+        let mut sessions = self.sessions.write().await;
         let session = sessions.get(session_id)
             .context("Session not found")?;
         
@@ -242,59 +273,245 @@ impl SessionManager {
         // Full config update would require session restart
         let mut current_config = session.config.clone();
         current_config.auto_yes = config.auto_yes;
+        let session = Arc::new(Session {
+            config: current_config,
+            ..(**session).clone()
+        });
         
         // Update in database
-        let db = self.db.lock().await;
-        db.execute(
-            "UPDATE multi_sessions SET auto_yes = ?1, updated_at = datetime('now') WHERE id = ?2",
-            rusqlite::params![config.auto_yes, session_id]
-        )?;
+        let config_json = serde_json::to_string(&session.config)?;
+        {
+            let db = self.db.lock().await;
+            db.execute(
+                "UPDATE multi_sessions SET config = ?1, updated_at = ?2 WHERE id = ?3",
+                rusqlite::params![config_json, Utc::now().to_rfc3339(), session_id]
+            )?;
+        }
+        
+        sessions.insert(session_id.to_string(), session);
         
         Ok(())
     }
     
+    /// Reload the sessions that were open when the app last closed.
+    ///
+    /// Each session's worktree is checked and, if its directory is gone,
+    /// checked out again from the session's branch; sessions whose worktree
+    /// can't be restored come back in the error state. Paused sessions stay
+    /// paused. A session whose Claude process outlived the app is tracked
+    /// again, though its output and input were lost with the app, and one
+    /// whose process died with the app is marked interrupted so it can be
+    /// resumed. Returns how many sessions were restored.
+    ///
+    /// A process only counts as the session's if it started when the saved
+    /// one did and runs `claude` in the session's directory, so a process
+    /// that was given the same id since is left alone.
+    pub async fn restore_sessions(&self) -> Result<usize> {
+        let stored = self.load_sessions_from_db().await?;
+        let mut restored = 0;
+        
+        for (session, process) in stored {
+            if self.sessions.read().await.contains_key(&session.id) {
+                continue;
+            }
+            let session = Arc::new(session);
+            let status = session.status.lock().await.clone();
+            
+            // git and process lookups block, so they run off the runtime
+            let worktree = GitWorktree {
+                repo_path: session.project_path.clone(),
+                worktree_path: session.worktree_path.clone(),
+                branch_name: session.branch_name.clone(),
+            };
+            let working_dir = session.working_dir().to_path_buf();
+            let checked = tokio::task::spawn_blocking(move || {
+                worktree.ensure_checked_out()?;
+                Ok::<_, anyhow::Error>(process.filter(|process| {
+                    status.is_live() && ProcessManager::is_claude_process(process, &working_dir)
+                }))
+            })
+            .await
+            .context("Failed to restore worktree")
+            .and_then(|checked| checked);
+            
+            match checked {
+                Err(e) => {
+                    log::warn!("Failed to restore worktree of session {}: {:#}", session.id, e);
+                    session.set_error(format!("{:#}", e)).await;
+                }
+                Ok(Some(process)) => {
+                    *session.pid.lock().await = Some(process.clone());
+                    session.set_status(SessionStatus::Running).await;
+                    self.watch_detached_process(session.clone(), process);
+                }
+                Ok(None) if session.status.lock().await.is_live() => {
+                    session.set_status(SessionStatus::Interrupted).await;
+                }
+                Ok(None) => {}
+            }
+            
+            if let Err(e) = self.update_session_state_in_db(&session).await {
+                log::warn!("Failed to save restored session {}: {}", session.id, e);
+            }
+            self.sessions.write().await.insert(session.id.clone(), session);
+            restored += 1;
+        }
+        
+        Ok(restored)
+    }
+    
+    /// Mark a session interrupted once the process it was reattached to
+    /// after a restart exits
+    fn watch_detached_process(&self, session: Arc<Session>, process: ProcessIdentity) {
+        let manager = self.clone();
+        
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(2));
+            loop {
+                ticker.tick().await;
+                
+                // Paused or terminated in the meantime
+                if session.pid.lock().await.as_ref() != Some(&process) {
+                    break;
+                }
+                let (check, working_dir) = (process.clone(), session.working_dir().to_path_buf());
+                let running = tokio::task::spawn_blocking(move || {
+                    ProcessManager::is_claude_process(&check, &working_dir)
+                })
+                .await
+                .unwrap_or(false);
+                if running {
+                    continue;
+                }
+                
+                *session.pid.lock().await = None;
+                session.set_status(SessionStatus::Interrupted).await;
+                if let Err(e) = manager.update_session_state_in_db(&session).await {
+                    log::warn!("Failed to update session {}: {}", session.id, e);
+                }
+                let _ = manager.event_tx.send(SessionEvent::StatusChanged {
+                    session_id: session.id.clone(),
+                    status: SessionStatus::Interrupted,
+                });
+                break;
+            }
+        });
+    }
+    
     // Database operations
     async fn store_session_in_db(&self, session: &Session) -> Result<()> {
+        let status = session.status.lock().await.clone();
+        let config_json = serde_json::to_string(&session.config)?;
         let db = self.db.lock().await;
         db.execute(
             r#"
             INSERT INTO multi_sessions (
-                id, project_id, worktree_path, branch_name, status,
-                created_at, updated_at, auto_yes, output_log
+                id, project_id, project_path, worktree_path, branch_name,
+                status, config, created_at, updated_at
             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
             "#,
             rusqlite::params![
                 session.id,
                 session.project_id,
-                session.worktree_path.to_str(),
+                session.project_path.to_string_lossy(),
+                session.worktree_path.to_string_lossy(),
                 session.branch_name,
-                "running",
+                status_to_db(&status),
+                config_json,
                 session.created_at.to_rfc3339(),
                 session.created_at.to_rfc3339(),
-                session.config.auto_yes,
-                ""
             ]
         )?;
         
         Ok(())
     }
     
-    async fn update_session_status_in_db(
-        &self,
-        session_id: &str,
-        status: SessionStatus,
-    ) -> Result<()> {
-        let status_str = serde_json::to_string(&status)?;
+    /// Save a session's status, process and error message
+    async fn update_session_state_in_db(&self, session: &Session) -> Result<()> {
+        let status = session.status.lock().await.clone();
+        let process = session.pid.lock().await.clone();
+        let pid = process.as_ref().map(|process| process.pid);
+        let pid_started_at = process.and_then(|process| process.started_at);
+        let error_message = session.error_message.lock().await.clone();
+        let updated_at = session.updated_at.lock().await.to_rfc3339();
         let db = self.db.lock().await;
         
         db.execute(
-            "UPDATE multi_sessions SET status = ?1, updated_at = datetime('now') WHERE id = ?2",
-            rusqlite::params![status_str, session_id]
+            "UPDATE multi_sessions SET status = ?1, pid = ?2, pid_started_at = ?3, error_message = ?4, updated_at = ?5 WHERE id = ?6",
+            rusqlite::params![status_to_db(&status), pid, pid_started_at, error_message, updated_at, session.id]
         )?;
         
         Ok(())
     }
     
+    /// Load every session that wasn't terminated, oldest first, along with
+    /// the process it last ran
+    async fn load_sessions_from_db(&self) -> Result<Vec<(Session, Option<ProcessIdentity>)>> {
+        let db = self.db.lock().await;
+        let mut stmt = db.prepare(
+            r#"
+            SELECT id, project_id, project_path, worktree_path, branch_name,
+                status, pid, error_message, config, created_at, updated_at,
+                pid_started_at
+            FROM multi_sessions
+            WHERE status != 'terminated'
+            ORDER BY created_at
+            "#,
+        )?;
+        
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, Option<u32>>(6)?,
+                row.get::<_, Option<String>>(7)?,
+                row.get::<_, String>(8)?,
+                row.get::<_, String>(9)?,
+                row.get::<_, String>(10)?,
+                row.get::<_, Option<String>>(11)?,
+            ))
+        })?;
+        
+        let mut sessions = Vec::new();
+        for row in rows {
+            let (
+                id, project_id, project_path, worktree_path, branch_name,
+                status, pid, error_message, config, created_at, updated_at,
+                pid_started_at,
+            ) = row?;
+            
+            let config: SessionConfig = serde_json::from_str(&config).unwrap_or_else(|e| {
+                log::warn!("Invalid config for session {}, using defaults: {}", id, e);
+                SessionConfig::default()
+            });
+            // A status this version doesn't know can still be resumed
+            let status = status_from_db(&status).unwrap_or(SessionStatus::Interrupted);
+            
+            let session = Session {
+                id,
+                status: Arc::new(Mutex::new(status)),
+                created_at: parse_timestamp(&created_at),
+                updated_at: Arc::new(Mutex::new(parse_timestamp(&updated_at))),
+                error_message: Arc::new(Mutex::new(error_message)),
+                ..Session::new(
+                    project_id,
+                    PathBuf::from(project_path),
+                    PathBuf::from(worktree_path),
+                    branch_name,
+                    config,
+                )
+            };
+            let process = pid.map(|pid| ProcessIdentity { pid, started_at: pid_started_at });
+            sessions.push((session, process));
+        }
+        
+        Ok(sessions)
+    }
+    
     pub async fn start_auto_yes_daemon(&self) {
         let manager = self.clone();
         let shutdown_rx = self.shutdown_tx.subscribe();
@@ -306,6 +523,50 @@ impl SessionManager {
     }
 }
 
+/// Create the table sessions are saved in so they survive an app restart
+pub fn create_session_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS multi_sessions (
+            id TEXT PRIMARY KEY,
+            project_id TEXT NOT NULL,
+            project_path TEXT NOT NULL,
+            worktree_path TEXT NOT NULL,
+            branch_name TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'initializing',
+            pid INTEGER,
+            pid_started_at TEXT,
+            error_message TEXT,
+            config TEXT NOT NULL DEFAULT '{}',
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_multi_sessions_status ON multi_sessions(status)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// How a status is stored in the database: its serialized name, unquoted
+fn status_to_db(status: &SessionStatus) -> String {
+    match serde_json::to_value(status) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => format!("{:?}", status).to_lowercase(),
+    }
+}
+
+fn status_from_db(status: &str) -> Option<SessionStatus> {
+    serde_json::from_value(serde_json::Value::String(status.to_string())).ok()
+}
+
+fn parse_timestamp(timestamp: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now())
+}
+
 // Implement Clone manually to handle broadcast receiver
 impl Clone for SessionManager {
     fn clone(&self) -> Self {
@@ -319,4 +580,203 @@ impl Clone for SessionManager {
             max_concurrent_sessions: self.max_concurrent_sessions,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::process::Command;
+    use tempfile::TempDir;
+    
+    fn manager() -> SessionManager {
+        let conn = Connection::open_in_memory().unwrap();
+        create_session_tables(&conn).unwrap();
+        SessionManager::new(Arc::new(Mutex::new(conn)), 5)
+    }
+    
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+    
+    /// A repository with one commit, in its own directory of `temp_dir` so
+    /// worktrees are created next to it
+    fn repository(temp_dir: &TempDir) -> PathBuf {
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir(&repo).unwrap();
+        git(&repo, &["init", "-q", "-b", "main"]);
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        repo
+    }
+    
+    /// Save a session with a worktree of `repo` in `status`, as it was when
+    /// the app closed
+    async fn stored_session(
+        manager: &SessionManager,
+        repo: &Path,
+        status: SessionStatus,
+        process: Option<ProcessIdentity>,
+    ) -> Session {
+        let session = Session::new(
+            "project".to_string(),
+            repo.to_path_buf(),
+            PathBuf::new(),
+            String::new(),
+            SessionConfig::default(),
+        );
+        let worktree = GitWorktree::new(repo.to_path_buf(), &session.id, "test").unwrap();
+        worktree.create().unwrap();
+        let session = Session {
+            worktree_path: worktree.worktree_path.clone(),
+            branch_name: worktree.branch_name.clone(),
+            ..session
+        };
+        manager.store_session_in_db(&session).await.unwrap();
+        session.set_status(status).await;
+        *session.pid.lock().await = process;
+        manager.update_session_state_in_db(&session).await.unwrap();
+        session
+    }
+    
+    async fn status_of(manager: &SessionManager, session_id: &str) -> SessionStatus {
+        let sessions = manager.sessions.read().await;
+        let status = sessions[session_id].status.lock().await.clone();
+        status
+    }
+    
+    #[test]
+    fn test_status_db_names() {
+        for status in [
+            SessionStatus::Initializing,
+            SessionStatus::Running,
+            SessionStatus::Ready,
+            SessionStatus::Loading,
+            SessionStatus::Paused,
+            SessionStatus::Error,
+            SessionStatus::Completed,
+            SessionStatus::Terminated,
+            SessionStatus::Interrupted,
+        ] {
+            assert_eq!(status_from_db(&status_to_db(&status)), Some(status));
+        }
+        // The query for sessions to restore relies on this name
+        assert_eq!(status_to_db(&SessionStatus::Terminated), "terminated");
+        assert_eq!(status_from_db("hibernating"), None);
+    }
+    
+    #[tokio::test]
+    async fn test_sessions_round_trip_through_db() {
+        let manager = manager();
+        let config = SessionConfig {
+            auto_yes: true,
+            claude_args: vec!["--verbose".to_string()],
+            ..SessionConfig::default()
+        };
+        let session = Session::new(
+            "project".to_string(),
+            PathBuf::from("/work/repo"),
+            PathBuf::from("/work/.claudia-worktrees/session-1"),
+            "claudia-session-1".to_string(),
+            config,
+        );
+        manager.store_session_in_db(&session).await.unwrap();
+        let process = ProcessIdentity { pid: 4242, started_at: Some("98765".to_string()) };
+        *session.pid.lock().await = Some(process.clone());
+        session.set_error("worktree is gone".to_string()).await;
+        manager.update_session_state_in_db(&session).await.unwrap();
+        
+        let loaded = manager.load_sessions_from_db().await.unwrap();
+        assert_eq!(loaded.len(), 1);
+        let (loaded, loaded_process) = &loaded[0];
+        assert_eq!(loaded.id, session.id);
+        assert_eq!(loaded.project_path, session.project_path);
+        assert_eq!(loaded.worktree_path, session.worktree_path);
+        assert_eq!(loaded.branch_name, session.branch_name);
+        assert!(loaded.config.auto_yes);
+        assert_eq!(loaded.config.claude_args, ["--verbose"]);
+        assert_eq!(*loaded.status.lock().await, SessionStatus::Error);
+        assert_eq!(loaded.error_message.lock().await.as_deref(), Some("worktree is gone"));
+        assert_eq!(loaded.created_at, session.created_at);
+        assert_eq!(loaded_process.as_ref(), Some(&process));
+        
+        // Terminated sessions are not brought back
+        session.set_status(SessionStatus::Terminated).await;
+        manager.update_session_state_in_db(&session).await.unwrap();
+        assert!(manager.load_sessions_from_db().await.unwrap().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_restore_interrupts_live_sessions_without_a_process() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = repository(&temp_dir);
+        let manager = manager();
+        
+        let running = stored_session(&manager, &repo, SessionStatus::Running, None).await;
+        // The id of a live process that isn't this session's Claude process
+        let reused = ProcessIdentity { pid: std::process::id(), started_at: None };
+        let ready = stored_session(&manager, &repo, SessionStatus::Ready, Some(reused)).await;
+        let paused = stored_session(&manager, &repo, SessionStatus::Paused, None).await;
+        // A worktree removed while the app was closed is checked out again
+        std::fs::remove_dir_all(&paused.worktree_path).unwrap();
+        
+        assert_eq!(manager.restore_sessions().await.unwrap(), 3);
+        assert_eq!(status_of(&manager, &running.id).await, SessionStatus::Interrupted);
+        assert_eq!(status_of(&manager, &ready.id).await, SessionStatus::Interrupted);
+        assert_eq!(status_of(&manager, &paused.id).await, SessionStatus::Paused);
+        assert!(paused.worktree_path.join(".git").exists());
+        
+        // The new statuses are saved, and restoring again changes nothing
+        let stored = manager.load_sessions_from_db().await.unwrap();
+        let interrupted = stored.iter()
+            .filter(|(session, process)| {
+                process.is_none()
+                    && *session.status.try_lock().unwrap() == SessionStatus::Interrupted
+            })
+            .count();
+        assert_eq!(interrupted, 2);
+        assert_eq!(manager.restore_sessions().await.unwrap(), 0);
+    }
+    
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_restore_reattaches_only_the_same_process() {
+        use std::os::unix::process::CommandExt;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let repo = repository(&temp_dir);
+        let manager = manager();
+        
+        let session = stored_session(&manager, &repo, SessionStatus::Running, None).await;
+        let mut claude = Command::new("sleep")
+            .arg0("claude")
+            .arg("30")
+            .current_dir(&session.worktree_path)
+            .spawn()
+            .unwrap();
+        let process = ProcessManager::identify(claude.id()).await;
+        assert!(process.started_at.is_some());
+        *session.pid.lock().await = Some(process.clone());
+        manager.update_session_state_in_db(&session).await.unwrap();
+        
+        // The same id with another start time belongs to a later process
+        let earlier = ProcessIdentity { started_at: Some("0".to_string()), ..process.clone() };
+        assert!(!ProcessManager::is_claude_process(&earlier, &session.worktree_path));
+        // And one running elsewhere isn't this session's
+        assert!(!ProcessManager::is_claude_process(&process, &repo));
+        
+        assert_eq!(manager.restore_sessions().await.unwrap(), 1);
+        assert_eq!(status_of(&manager, &session.id).await, SessionStatus::Running);
+        let sessions = manager.sessions.read().await;
+        assert_eq!(sessions[&session.id].pid.lock().await.as_ref(), Some(&process));
+        drop(sessions);
+        
+        claude.kill().unwrap();
+        claude.wait().unwrap();
+    }
+}
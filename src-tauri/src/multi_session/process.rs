@@ -1,4 +1,5 @@
 
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use tokio::process::{Command, Child};
 use tokio::io::{AsyncBufReadExt, BufReader};
@@ -16,10 +17,7 @@ impl ProcessManager {
         let mut cmd = Command::new("claude");
         
         // Set working directory
-        let working_dir = session.config.working_directory
-            .as_ref()
-            .unwrap_or(&session.worktree_path);
-        cmd.current_dir(working_dir);
+        cmd.current_dir(session.working_dir());
         
         // Add any additional arguments
         for arg in &session.config.claude_args {
@@ -139,4 +137,187 @@ impl ProcessManager {
     pub async fn check_process_health(child: &mut Child) -> bool {
         matches!(child.try_wait(), Ok(None))
     }
-}
\ No newline at end of file
+    
+    /// Identify a process just spawned, so it can be recognized after a
+    /// restart even if its id is handed to another process
+    pub async fn identify(pid: u32) -> ProcessIdentity {
+        let started_at = tokio::task::spawn_blocking(move || {
+            process_info(pid).map(|info| info.started_at)
+        })
+        .await
+        .ok()
+        .flatten();
+        ProcessIdentity { pid, started_at }
+    }
+    
+    /// Whether `process` is still running as the Claude process of the
+    /// session working in `working_dir`: the same process, by its start
+    /// time, running `claude` in that directory, rather than a finished one
+    /// whose id has been handed to something else.
+    ///
+    /// Runs system tools, so call it off the async runtime.
+    pub fn is_claude_process(process: &ProcessIdentity, working_dir: &Path) -> bool {
+        let Some(info) = process_info(process.pid) else {
+            return false;
+        };
+        if process.started_at.as_ref().is_some_and(|started_at| *started_at != info.started_at) {
+            return false;
+        }
+        // Windows doesn't tell another process's working directory
+        let in_working_dir = info.cwd.is_none_or(|cwd| same_path(&cwd, working_dir));
+        in_working_dir && is_claude_command(&info.args)
+    }
+    
+    /// Kill a process that was not spawned by this run of the app.
+    ///
+    /// Runs system tools, so call it off the async runtime.
+    pub fn kill_process(pid: u32) {
+        let result = if cfg!(target_os = "windows") {
+            std::process::Command::new("taskkill")
+                .args(["/F", "/PID", &pid.to_string()])
+                .output()
+        } else {
+            std::process::Command::new("kill")
+                .args(["-KILL", &pid.to_string()])
+                .output()
+        };
+        if let Err(e) = result {
+            log::warn!("Failed to kill process {}: {}", pid, e);
+        }
+    }
+}
+
+/// A process id along with when the process started, since ids are reused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessIdentity {
+    pub pid: u32,
+    /// Start time as the system reports it; `None` if it couldn't be read
+    pub started_at: Option<String>,
+}
+
+/// What the system reports about a running process
+struct ProcessInfo {
+    args: Vec<String>,
+    cwd: Option<PathBuf>,
+    started_at: String,
+}
+
+/// Look up a running process; `None` if there is no process with this id
+#[cfg(target_os = "linux")]
+fn process_info(pid: u32) -> Option<ProcessInfo> {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+    let args = std::fs::read(proc_dir.join("cmdline")).ok()?
+        .split(|byte| *byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    // The start time, in clock ticks since boot, is the 22nd field; the
+    // command name before it is in parentheses and may contain spaces
+    let stat = std::fs::read_to_string(proc_dir.join("stat")).ok()?;
+    let started_at = stat[stat.rfind(')')? + 1..]
+        .split_whitespace()
+        .nth(19)?
+        .to_string();
+    Some(ProcessInfo {
+        args,
+        cwd: std::fs::read_link(proc_dir.join("cwd")).ok(),
+        started_at,
+    })
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_info(pid: u32) -> Option<ProcessInfo> {
+    let ps = |field: &str| {
+        let output = std::process::Command::new("ps")
+            .args(["-p", &pid.to_string(), "-o", field])
+            .output()
+            .ok()?;
+        let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !value.is_empty()).then_some(value)
+    };
+    let started_at = ps("lstart=")?;
+    let args = split_command_line(&ps("args=")?);
+    let cwd = std::process::Command::new("lsof")
+        .args(["-a", "-p", &pid.to_string(), "-d", "cwd", "-Fn"])
+        .output()
+        .ok()
+        .and_then(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .find_map(|line| line.strip_prefix('n').map(PathBuf::from))
+        });
+    Some(ProcessInfo { args, cwd, started_at })
+}
+
+#[cfg(windows)]
+fn process_info(pid: u32) -> Option<ProcessInfo> {
+    let script = format!(
+        "$p = Get-CimInstance Win32_Process -Filter 'ProcessId = {}'; \
+         if ($p) {{ $p.CreationDate.ToUniversalTime().ToString('o'); $p.CommandLine }}",
+        pid
+    );
+    let output = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .output()
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut lines = output.lines();
+    let started_at = lines.next()?.trim().to_string();
+    if started_at.is_empty() {
+        return None;
+    }
+    Some(ProcessInfo {
+        args: split_command_line(lines.next().unwrap_or_default()),
+        cwd: None,
+        started_at,
+    })
+}
+
+/// Split a command line on spaces outside double quotes
+#[cfg(not(target_os = "linux"))]
+fn split_command_line(command_line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut quoted = false;
+    for c in command_line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !arg.is_empty() {
+                    args.push(std::mem::take(&mut arg));
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        args.push(arg);
+    }
+    args
+}
+
+/// Whether a command line runs the Claude CLI, either directly or as the
+/// script of a JavaScript runtime, as an npm install does
+fn is_claude_command(args: &[String]) -> bool {
+    let file_name = |arg: &String| {
+        Path::new(arg)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    };
+    let Some(program) = args.first().map(file_name) else {
+        return false;
+    };
+    if program.starts_with("claude") {
+        return true;
+    }
+    (program.starts_with("node") || program.starts_with("bun"))
+        && args.get(1).is_some_and(|script| script.to_lowercase().contains("claude"))
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
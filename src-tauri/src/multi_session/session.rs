@@ -1,11 +1,12 @@
 use std::collections::VecDeque;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::process::Child;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use super::process::{ProcessIdentity, ProcessManager};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
@@ -18,6 +19,22 @@ pub enum SessionStatus {
     Error,
     Completed,
     Terminated,
+    /// The app was closed while the session's Claude process was running;
+    /// the worktree is intact and the session can be resumed
+    Interrupted,
+}
+
+impl SessionStatus {
+    /// Whether a session in this status has a Claude process attached
+    pub fn is_live(&self) -> bool {
+        matches!(
+            self,
+            SessionStatus::Initializing
+                | SessionStatus::Running
+                | SessionStatus::Ready
+                | SessionStatus::Loading
+        )
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,6 +68,9 @@ pub struct Session {
     pub worktree_path: PathBuf,
     pub branch_name: String,
     pub process: Arc<Mutex<Option<Child>>>,
+    /// Id and start time of the Claude process, saved so a process that
+    /// outlives the app can be found again after a restart
+    pub pid: Arc<Mutex<Option<ProcessIdentity>>>,
     pub status: Arc<Mutex<SessionStatus>>,
     pub output_buffer: Arc<Mutex<VecDeque<String>>>,
     pub created_at: DateTime<Utc>,
@@ -68,6 +88,7 @@ impl Clone for Session {
             worktree_path: self.worktree_path.clone(),
             branch_name: self.branch_name.clone(),
             process: self.process.clone(),
+            pid: self.pid.clone(),
             status: self.status.clone(),
             output_buffer: self.output_buffer.clone(),
             created_at: self.created_at,
@@ -96,6 +117,7 @@ impl Session {
             worktree_path,
             branch_name,
             process: Arc::new(Mutex::new(None)),
+            pid: Arc::new(Mutex::new(None)),
             status: Arc::new(Mutex::new(SessionStatus::Initializing)),
             output_buffer: Arc::new(Mutex::new(VecDeque::with_capacity(config.max_output_buffer))),
             created_at: now,
@@ -105,6 +127,13 @@ impl Session {
         }
     }
 
+    /// Directory the session's Claude process runs in
+    pub fn working_dir(&self) -> &Path {
+        self.config.working_directory
+            .as_deref()
+            .unwrap_or(&self.worktree_path)
+    }
+
     pub async fn append_output(&self, line: String) {
         let mut buffer = self.output_buffer.lock().await;
         if buffer.len() >= self.config.max_output_buffer {
@@ -140,10 +169,26 @@ impl Session {
         self.set_status(SessionStatus::Error).await;
     }
 
-    pub async fn terminate(&self) {
+    /// Kill the Claude process, whether it was spawned by this run of the
+    /// app or survived from a previous one. A process from a previous run is
+    /// only killed while it is still this session's Claude process.
+    pub async fn stop_process(&self) {
+        let identity = self.pid.lock().await.take();
         if let Some(mut process) = self.process.lock().await.take() {
             let _ = process.kill().await;
+        } else if let Some(identity) = identity {
+            let working_dir = self.working_dir().to_path_buf();
+            let _ = tokio::task::spawn_blocking(move || {
+                if ProcessManager::is_claude_process(&identity, &working_dir) {
+                    ProcessManager::kill_process(identity.pid);
+                }
+            })
+            .await;
         }
+    }
+
+    pub async fn terminate(&self) {
+        self.stop_process().await;
         self.set_status(SessionStatus::Terminated).await;
     }
 
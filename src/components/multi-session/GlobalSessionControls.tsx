@@ -50,7 +50,9 @@ export default function GlobalSessionControls({ sessions }: GlobalSessionControl
   };
 
   const resumeAll = async () => {
-    const pausedSessions = sessions.filter(s => s.status === 'paused');
+    const pausedSessions = sessions.filter(s =>
+      s.status === 'paused' || s.status === 'interrupted'
+    );
 
     if (pausedSessions.length === 0) {
       setToast({
@@ -127,6 +129,11 @@ export default function GlobalSessionControls({ sessions }: GlobalSessionControl
             Paused: {sessionCounts.paused}
           </Badge>
         )}
+        {sessionCounts.interrupted > 0 && (
+          <Badge variant="secondary">
+            Interrupted: {sessionCounts.interrupted}
+          </Badge>
+        )}
         {sessionCounts.error > 0 && (
           <Badge variant="destructive">
             Error: {sessionCounts.error}
@@ -148,7 +155,7 @@ export default function GlobalSessionControls({ sessions }: GlobalSessionControl
           variant="outline"
           size="sm"
           onClick={resumeAll}
-          disabled={sessionCounts.paused === 0}
+          disabled={!sessionCounts.paused && !sessionCounts.interrupted}
         >
           <PlayCircle className="w-4 h-4 mr-2" />
           Resume All
@@ -21,6 +21,8 @@ export default function SessionCard({ session, isActive, onClick }: SessionCardP
         return 'bg-yellow-500';
       case 'paused':
         return 'bg-gray-500';
+      case 'interrupted':
+        return 'bg-orange-500';
       case 'error':
         return 'bg-red-500';
       case 'completed':
@@ -36,6 +38,7 @@ export default function SessionCard({ session, isActive, onClick }: SessionCardP
       case 'loading':
         return <Play className="w-3 h-3" />;
       case 'paused':
+      case 'interrupted':
         return <Pause className="w-3 h-3" />;
       case 'terminated':
       case 'error':
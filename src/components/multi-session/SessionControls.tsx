@@ -65,7 +65,7 @@ export default function SessionControls({ session, onUpdate }: SessionControlsPr
   };
 
   const canPause = session.status === 'running' || session.status === 'ready';
-  const canResume = session.status === 'paused';
+  const canResume = session.status === 'paused' || session.status === 'interrupted';
   const canTerminate = session.status !== 'terminated';
 
   return (
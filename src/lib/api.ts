@@ -312,8 +312,20 @@ export interface Checkpoint {
   description?: string;
   parentCheckpointId?: string;
   metadata: CheckpointMetadata;
+  kind: CheckpointKind;
+  /** Pinned checkpoints are never removed by retention */
+  pinned: boolean;
+  /** Tagged checkpoints are never removed by retention */
+  tags: string[];
+  /** Further parents of a merge checkpoint, the branches merged into it */
+  mergeParentIds: string[];
 }
 
+/**
+ * Why a checkpoint was created
+ */
+export type CheckpointKind = 'regular' | 'pre_restore' | 'merge';
+
 /**
  * Metadata associated with a checkpoint
  */
@@ -323,21 +335,48 @@ export interface CheckpointMetadata {
   userPrompt: string;
   fileChanges: number;
   snapshotSize: number;
+  /** State of the project's git repository, if it is in one */
+  git?: GitState;
 }
 
+/**
+ * Git repository state recorded with a checkpoint
+ */
+export interface GitState {
+  /** Commit at HEAD; absent in a repository without commits */
+  head?: string;
+  /** Checked out branch; absent when HEAD is detached */
+  branch?: string;
+  dirty: boolean;
+  stashes: string[];
+}
+
+/**
+ * What to do when restoring a checkpoint taken on another branch or commit
+ */
+export type GitMismatchPolicy = 'ignore' | 'warn' | 'refuse';
+
 /**
  * Represents a file snapshot at a checkpoint
  */
 export interface FileSnapshot {
   checkpointId: string;
   filePath: string;
-  content: string;
+  /** Raw file bytes */
+  content: number[];
   hash: string;
   isDeleted: boolean;
   permissions?: number;
   size: number;
+  /** A symlink's content is its target and a directory's is empty */
+  kind: FileKind;
 }
 
+/**
+ * Type of a path recorded in a checkpoint; only empty directories are recorded
+ */
+export type FileKind = 'file' | 'symlink' | 'directory';
+
 /**
  * Represents a node in the timeline tree
  */
@@ -357,12 +396,43 @@ export interface SessionTimeline {
   autoCheckpointEnabled: boolean;
   checkpointStrategy: CheckpointStrategy;
   totalCheckpoints: number;
+  /** Files larger than this many bytes are left out of checkpoints (0 for no limit) */
+  maxFileSize: number;
+  lastRestore?: RestoreRecord;
+  /** Thresholds used by the 'rules' strategy */
+  autoCheckpointRules: AutoCheckpointRules;
+  gitMismatchPolicy: GitMismatchPolicy;
+}
+
+/**
+ * A restore that can be undone with `undoLastRestore`
+ */
+export interface RestoreRecord {
+  restoredCheckpointId: string;
+  preRestoreCheckpointId: string;
+  timestamp: string;
 }
 
 /**
  * Strategy for automatic checkpoint creation
  */
-export type CheckpointStrategy = 'manual' | 'per_prompt' | 'per_tool_use' | 'smart';
+export type CheckpointStrategy = 'manual' | 'per_prompt' | 'per_tool_use' | 'smart' | 'rules';
+
+/**
+ * Thresholds for the 'rules' strategy, measured since the last checkpoint.
+ * Unset thresholds are ignored.
+ */
+export interface AutoCheckpointRules {
+  /** Whether any or all of the thresholds have to be reached */
+  combinator: 'any' | 'all';
+  elapsedSeconds?: number;
+  tokens?: number;
+  changedFiles?: number;
+  /** Lines added or removed */
+  changedLines?: number;
+  /** Nested rule sets, each counting as one threshold of this set */
+  groups?: AutoCheckpointRules[];
+}
 
 /**
  * Result of a checkpoint operation
@@ -371,8 +441,18 @@ export interface CheckpointResult {
   checkpoint: Checkpoint;
   filesProcessed: number;
   warnings: string[];
+  /** New session to resume, when a restore continued in a new session */
+  sessionId?: string;
+  /** What the retention policy pruned after a new checkpoint */
+  retention?: RetentionReport;
 }
 
+/**
+ * How a restore treats the Claude conversation: rewrite the current session,
+ * or keep it and continue from the checkpoint in a new session
+ */
+export type RestoreMode = 'in_place' | 'new_session';
+
 /**
  * Diff between two checkpoints
  */
@@ -382,19 +462,276 @@ export interface CheckpointDiff {
   modifiedFiles: FileDiff[];
   addedFiles: string[];
   deletedFiles: string[];
+  renamedFiles: RenamedFile[];
   tokenDelta: number;
 }
 
+/**
+ * A file detected as renamed between two checkpoints
+ */
+export interface RenamedFile {
+  from: string;
+  to: string;
+  similarity: number;
+  diff: FileDiff;
+}
+
 /**
  * Diff for a single file
  */
 export interface FileDiff {
   path: string;
+  isBinary: boolean;
   additions: number;
   deletions: number;
   diffContent?: string;
 }
 
+/**
+ * A single problem found while verifying a checkpoint store
+ */
+export type IntegrityIssue =
+  | { kind: 'unreadable_timeline'; error: string }
+  | { kind: 'missing_metadata'; checkpoint_id: string }
+  | { kind: 'unparsable_metadata'; checkpoint_id: string; error: string }
+  | { kind: 'unreadable_messages'; checkpoint_id: string; error: string }
+  | { kind: 'missing_manifest'; checkpoint_id: string }
+  | { kind: 'unreadable_manifest'; checkpoint_id: string; error: string }
+  | { kind: 'missing_object'; hash: string; referenced_by: string[] }
+  | { kind: 'missing_delta_base'; hash: string; base_hash: string; referenced_by: string[] }
+  | { kind: 'corrupt_object'; hash: string; error: string; referenced_by: string[] }
+  | { kind: 'orphan_object'; hash: string }
+  | { kind: 'orphan_checkpoint'; checkpoint_id: string };
+
+/**
+ * Result of verifying a session's checkpoint store
+ */
+export interface IntegrityReport {
+  checkpointsChecked: number;
+  objectsChecked: number;
+  issues: IntegrityIssue[];
+  damagedCheckpoints: string[];
+  repairs: string[];
+}
+
+/**
+ * Outcome of exporting or importing a checkpoint bundle
+ */
+export interface BundleSummary {
+  sessionId: string;
+  checkpoints: number;
+  objects: number;
+  bundleSize: number;
+}
+
+/**
+ * Space used by a project's checkpoints across all of its sessions
+ */
+export interface CheckpointStorageStats {
+  sessions: number;
+  checkpoints: number;
+  objects: number;
+  /** Objects stored as a delta against an earlier version of the file */
+  deltaObjects: number;
+  unreferencedObjects: number;
+  /** Compressed size of the shared content pool on disk */
+  storedBytes: number;
+  /** Size of every distinct file content referenced in the project */
+  uniqueBytes: number;
+  /** Size if each session kept its own content pool */
+  perSessionBytes: number;
+  /** Size if every checkpoint stored full copies of its files */
+  snapshotBytes: number;
+}
+
+/**
+ * Keep one checkpoint per interval among checkpoints up to a certain age
+ */
+export interface RetentionTier {
+  intervalSeconds: number;
+  maxAgeSeconds: number;
+}
+
+/**
+ * Which checkpoints of a project to keep as they age. Pinned and tagged
+ * checkpoints, and those on a session's current branch, are always kept.
+ */
+export interface RetentionPolicy {
+  /** Checkpoints younger than this are all kept */
+  keepAllSeconds: number;
+  /** Sparser tiers for older checkpoints, youngest first */
+  tiers: RetentionTier[];
+  /** Bytes the project's checkpoints may use */
+  maxProjectBytes?: number;
+}
+
+/**
+ * Why retention removed a checkpoint
+ */
+export type PruneReason = 'thinned' | 'expired' | 'over_quota';
+
+/**
+ * Outcome of applying a retention policy
+ */
+export interface RetentionReport {
+  pruned: {
+    sessionId: string;
+    checkpointId: string;
+    timestamp: string;
+    reason: PruneReason;
+  }[];
+  reclaimedObjects: number;
+  reclaimedBytes: number;
+  /** Measured only when the policy sets a quota */
+  projectBytes?: number;
+}
+
+/**
+ * A file that needs attention after a merge
+ */
+export interface MergeConflict {
+  path: string;
+  /** content: conflict markers were written; modify_delete: the changed
+   * version was kept; binary: our version was kept */
+  kind: 'content' | 'modify_delete' | 'binary';
+  /** Conflicting regions marked in the file */
+  regions: number;
+}
+
+/**
+ * Outcome of merging two checkpoints
+ */
+export interface MergeResult {
+  /** The merge checkpoint, a child of ours with theirs as a merge parent */
+  checkpoint: Checkpoint;
+  baseCheckpointId: string;
+  /** Files changed on both sides and combined without conflicts */
+  mergedFiles: string[];
+  conflicts: MergeConflict[];
+  /** Problems applying the merge and git state differences */
+  warnings: string[];
+}
+
+/**
+ * One run of the test command during a bisect
+ */
+export interface BisectStep {
+  checkpointId: string;
+  passed: boolean;
+  /** Absent if the command was killed or timed out */
+  exitCode?: number;
+  timedOut: boolean;
+  /** The end of the command's combined output */
+  output: string;
+}
+
+/**
+ * Result of bisecting the path from the root to the current checkpoint
+ */
+export interface BisectResult {
+  /** First checkpoint where the command fails; absent if it passes on the current one */
+  firstBad?: Checkpoint;
+  lastGood?: Checkpoint;
+  /** Changes between the last good and the first bad checkpoint */
+  diff?: CheckpointDiff;
+  steps: BisectStep[];
+  pathLength: number;
+}
+
+/**
+ * A run of consecutive lines introduced by the same checkpoint
+ */
+export interface BlameSpan {
+  /** First line of the span, counting from 1 */
+  startLine: number;
+  /** Last line of the span, inclusive */
+  endLine: number;
+  checkpointId: string;
+  timestamp: string;
+  userPrompt: string;
+  description?: string;
+}
+
+/**
+ * Which checkpoint introduced each line of a file
+ */
+export interface FileBlame {
+  filePath: string;
+  /** Checkpoint whose version of the file was blamed */
+  checkpointId: string;
+  totalLines: number;
+  spans: BlameSpan[];
+}
+
+/**
+ * A checkpoint committed to a git branch
+ */
+export interface PromotedCheckpoint {
+  checkpointId: string;
+  branch: string;
+  commit: string;
+  parentCommit?: string;
+  createdBranch: boolean;
+  /** Number of files in the commit's snapshot of the project */
+  files: number;
+  /** Number of files the commit deletes */
+  deletedFiles: number;
+}
+
+/**
+ * The timeline as a DAG, with merges joining branches of the tree
+ */
+export interface TimelineGraph {
+  nodes: Checkpoint[];
+  edges: {
+    from: string;
+    to: string;
+    kind: 'parent' | 'merge';
+  }[];
+}
+
+/**
+ * What to look for in checkpoints; every criterion that is set must match
+ */
+export interface CheckpointQuery {
+  /** Case-insensitive text in the prompt or description */
+  text?: string;
+  /** Tags the checkpoint must all carry */
+  tags?: string[];
+  /** Paths or glob patterns, one of which a touched file must match */
+  paths?: string[];
+  since?: string;
+  until?: string;
+  pinned?: boolean;
+  limit?: number;
+}
+
+/**
+ * A checkpoint found by a search
+ */
+export interface CheckpointMatch {
+  checkpoint: Checkpoint;
+  /** Files changed since the parent that match the query's paths */
+  touchedFiles: string[];
+}
+
+/**
+ * What restoring a checkpoint would change, with a diff against the current
+ * disk contents for every file
+ */
+export interface RestorePlan {
+  checkpointId: string;
+  overwrite: FileDiff[];
+  create: FileDiff[];
+  /** Files captured by the current checkpoint that would be deleted */
+  delete: FileDiff[];
+  /** Files never checkpointed that would be deleted */
+  deleteUntracked: FileDiff[];
+  /** Directories removed because they end up empty */
+  pruneDirs: string[];
+  unchanged: number;
+}
+
 /**
  * Represents an MCP server configuration
  */
@@ -1476,19 +1813,87 @@ export const api = {
   },
 
   /**
-   * Restores a session to a specific checkpoint
+   * Restores a session to a specific checkpoint. The current working state is
+   * saved first so the restore can be undone. With `new_session` mode the
+   * result's `sessionId` is the session to resume. Pass `force` to restore a
+   * checkpoint taken on another branch or commit when the session's git
+   * mismatch policy refuses it
    */
   async restoreCheckpoint(
     checkpointId: string,
     sessionId: string,
     projectId: string,
-    projectPath: string
+    projectPath: string,
+    mode?: RestoreMode,
+    force?: boolean
   ): Promise<CheckpointResult> {
     return invoke("restore_checkpoint", {
       checkpointId,
       sessionId,
       projectId,
-      projectPath
+      projectPath,
+      mode,
+      force
+    });
+  },
+
+  /**
+   * Restores only the given paths or glob patterns from a checkpoint,
+   * leaving the rest of the working tree untouched; like a full restore,
+   * it can be undone with undoLastRestore
+   */
+  async restoreCheckpointPaths(
+    checkpointId: string,
+    sessionId: string,
+    projectId: string,
+    projectPath: string,
+    paths: string[],
+    force?: boolean
+  ): Promise<CheckpointResult> {
+    return invoke("restore_checkpoint_paths", {
+      checkpointId,
+      sessionId,
+      projectId,
+      projectPath,
+      paths,
+      force
+    });
+  },
+
+  /**
+   * Undoes the last restore, returning to the state saved just before it
+   */
+  async undoLastRestore(
+    sessionId: string,
+    projectId: string,
+    projectPath: string,
+    force?: boolean
+  ): Promise<CheckpointResult> {
+    return invoke("undo_last_restore", {
+      sessionId,
+      projectId,
+      projectPath,
+      force
+    });
+  },
+
+  /**
+   * Previews a restore without touching any files. Pass `paths` to preview a
+   * selective restore
+   */
+  async previewRestoreCheckpoint(
+    checkpointId: string,
+    sessionId: string,
+    projectId: string,
+    projectPath: string,
+    paths?: string[]
+  ): Promise<RestorePlan> {
+    return invoke("preview_restore_checkpoint", {
+      checkpointId,
+      sessionId,
+      projectId,
+      projectPath,
+      paths
     });
   },
 
@@ -1543,6 +1948,129 @@ export const api = {
     });
   },
 
+  /**
+   * Gets the timeline of a session as a graph, with merges as extra edges
+   */
+  async getTimelineGraph(
+    sessionId: string,
+    projectId: string,
+    projectPath: string
+  ): Promise<TimelineGraph> {
+    try {
+      return await invoke<TimelineGraph>("get_timeline_graph", {
+        sessionId,
+        projectId,
+        projectPath
+      });
+    } catch (error) {
+      console.error("Failed to get timeline graph:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Three-way merges one checkpoint into another and continues from the
+   * merge; the working state is saved first so it can be undone
+   */
+  async mergeCheckpoints(
+    sessionId: string,
+    projectId: string,
+    projectPath: string,
+    oursCheckpointId: string,
+    theirsCheckpointId: string,
+    baseCheckpointId?: string,
+    force?: boolean
+  ): Promise<MergeResult> {
+    try {
+      return await invoke<MergeResult>("merge_checkpoints", {
+        sessionId,
+        projectId,
+        projectPath,
+        oursCheckpointId,
+        theirsCheckpointId,
+        baseCheckpointId,
+        force
+      });
+    } catch (error) {
+      console.error("Failed to merge checkpoints:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Commits a checkpoint's snapshot to a new or existing git branch, using
+   * its description or prompt as the message. The working tree, index and
+   * checked out branch are left untouched
+   */
+  async promoteCheckpoint(
+    checkpointId: string,
+    sessionId: string,
+    projectId: string,
+    projectPath: string,
+    branch: string
+  ): Promise<PromotedCheckpoint> {
+    try {
+      return await invoke<PromotedCheckpoint>("promote_checkpoint", {
+        checkpointId,
+        sessionId,
+        projectId,
+        projectPath,
+        branch
+      });
+    } catch (error) {
+      console.error("Failed to promote checkpoint:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Finds the first checkpoint between the root and the current checkpoint
+   * where a shell command fails. Each tested checkpoint is written to a
+   * scratch directory, without ignored files such as dependencies
+   */
+  async bisectCheckpoints(
+    sessionId: string,
+    projectId: string,
+    command: string,
+    timeoutSeconds?: number
+  ): Promise<BisectResult> {
+    try {
+      return await invoke<BisectResult>("bisect_checkpoints", {
+        sessionId,
+        projectId,
+        command,
+        timeoutSeconds
+      });
+    } catch (error) {
+      console.error("Failed to bisect checkpoints:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Attributes each line of a file to the checkpoint, and so the prompt, that
+   * introduced it. Blames the current checkpoint's version unless
+   * `checkpointId` is given
+   */
+  async blameCheckpointFile(
+    sessionId: string,
+    projectId: string,
+    filePath: string,
+    checkpointId?: string
+  ): Promise<FileBlame> {
+    try {
+      return await invoke<FileBlame>("blame_checkpoint_file", {
+        sessionId,
+        projectId,
+        filePath,
+        checkpointId
+      });
+    } catch (error) {
+      console.error("Failed to blame file:", error);
+      throw error;
+    }
+  },
+
   /**
    * Updates checkpoint settings for a session
    */
@@ -1551,14 +2079,20 @@ export const api = {
     projectId: string,
     projectPath: string,
     autoCheckpointEnabled: boolean,
-    checkpointStrategy: CheckpointStrategy
+    checkpointStrategy: CheckpointStrategy,
+    maxFileSize?: number,
+    autoCheckpointRules?: AutoCheckpointRules,
+    gitMismatchPolicy?: GitMismatchPolicy
   ): Promise<void> {
     return invoke("update_checkpoint_settings", {
       sessionId,
       projectId,
       projectPath,
       autoCheckpointEnabled,
-      checkpointStrategy
+      checkpointStrategy,
+      maxFileSize,
+      autoCheckpointRules,
+      gitMismatchPolicy
     });
   },
 
@@ -1569,14 +2103,16 @@ export const api = {
     fromCheckpointId: string,
     toCheckpointId: string,
     sessionId: string,
-    projectId: string
+    projectId: string,
+    contextLines?: number
   ): Promise<CheckpointDiff> {
     try {
       return await invoke<CheckpointDiff>("get_checkpoint_diff", {
         fromCheckpointId,
         toCheckpointId,
         sessionId,
-        projectId
+        projectId,
+        contextLines
       });
     } catch (error) {
       console.error("Failed to get checkpoint diff:", error);
@@ -1584,6 +2120,186 @@ export const api = {
     }
   },
 
+  /**
+   * Verifies the integrity of a session's checkpoint store
+   */
+  async verifyCheckpoints(
+    sessionId: string,
+    projectId: string,
+    repair: boolean = false
+  ): Promise<IntegrityReport> {
+    try {
+      return await invoke<IntegrityReport>("verify_checkpoints", {
+        sessionId,
+        projectId,
+        repair
+      });
+    } catch (error) {
+      console.error("Failed to verify checkpoints:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Exports a session's checkpoints, or only the given ones, to a bundle file
+   */
+  async exportCheckpoints(
+    sessionId: string,
+    projectId: string,
+    projectPath: string,
+    outputPath: string,
+    checkpointIds?: string[]
+  ): Promise<BundleSummary> {
+    try {
+      return await invoke<BundleSummary>("export_checkpoints", {
+        sessionId,
+        projectId,
+        projectPath,
+        checkpointIds,
+        outputPath
+      });
+    } catch (error) {
+      console.error("Failed to export checkpoints:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Imports a checkpoint bundle as a new session, returning its session ID
+   * in the summary
+   */
+  async importCheckpoints(
+    bundlePath: string,
+    projectId: string,
+    projectPath: string,
+    sessionId?: string
+  ): Promise<BundleSummary> {
+    try {
+      return await invoke<BundleSummary>("import_checkpoints", {
+        bundlePath,
+        projectId,
+        projectPath,
+        sessionId
+      });
+    } catch (error) {
+      console.error("Failed to import checkpoints:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Gets checkpoint storage usage and savings for a project
+   */
+  async getCheckpointStorageStats(projectId: string): Promise<CheckpointStorageStats> {
+    try {
+      return await invoke<CheckpointStorageStats>("get_checkpoint_storage_stats", { projectId });
+    } catch (error) {
+      console.error("Failed to get checkpoint storage stats:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Gets the retention policy of a project, if one was set
+   */
+  async getRetentionPolicy(projectId: string): Promise<RetentionPolicy | null> {
+    try {
+      return await invoke<RetentionPolicy | null>("get_retention_policy", { projectId });
+    } catch (error) {
+      console.error("Failed to get retention policy:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Sets the retention policy of a project; null clears it
+   */
+  async setRetentionPolicy(projectId: string, policy: RetentionPolicy | null): Promise<void> {
+    try {
+      await invoke("set_retention_policy", { projectId, policy });
+    } catch (error) {
+      console.error("Failed to set retention policy:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Applies a project's retention policy now
+   */
+  async applyRetentionPolicy(projectId: string): Promise<RetentionReport> {
+    try {
+      return await invoke<RetentionReport>("apply_retention_policy", { projectId });
+    } catch (error) {
+      console.error("Failed to apply retention policy:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Pins or unpins a checkpoint so retention never removes it
+   */
+  async setCheckpointPinned(
+    sessionId: string,
+    projectId: string,
+    checkpointId: string,
+    pinned: boolean
+  ): Promise<Checkpoint> {
+    try {
+      return await invoke<Checkpoint>("set_checkpoint_pinned", {
+        sessionId,
+        projectId,
+        checkpointId,
+        pinned
+      });
+    } catch (error) {
+      console.error("Failed to pin checkpoint:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Replaces the tags of a checkpoint
+   */
+  async setCheckpointTags(
+    sessionId: string,
+    projectId: string,
+    checkpointId: string,
+    tags: string[]
+  ): Promise<Checkpoint> {
+    try {
+      return await invoke<Checkpoint>("set_checkpoint_tags", {
+        sessionId,
+        projectId,
+        checkpointId,
+        tags
+      });
+    } catch (error) {
+      console.error("Failed to tag checkpoint:", error);
+      throw error;
+    }
+  },
+
+  /**
+   * Searches the checkpoints of a session, or of the whole project when no
+   * session is given, newest first
+   */
+  async searchCheckpoints(
+    projectId: string,
+    query: CheckpointQuery,
+    sessionId?: string
+  ): Promise<CheckpointMatch[]> {
+    try {
+      return await invoke<CheckpointMatch[]>("search_checkpoints", {
+        projectId,
+        sessionId,
+        query
+      });
+    } catch (error) {
+      console.error("Failed to search checkpoints:", error);
+      throw error;
+    }
+  },
+
   /**
    * Tracks a message for checkpointing
    */
@@ -1660,6 +2376,9 @@ export const api = {
   ): Promise<{
     auto_checkpoint_enabled: boolean;
     checkpoint_strategy: CheckpointStrategy;
+    auto_checkpoint_rules: AutoCheckpointRules;
+    max_file_size: number;
+    git_mismatch_policy: GitMismatchPolicy;
     total_checkpoints: number;
     current_checkpoint_id?: string;
   }> {
@@ -20,7 +20,8 @@ export type SessionStatus =
   | 'paused'
   | 'error'
   | 'completed'
-  | 'terminated';
+  | 'terminated'
+  | 'interrupted';
 
 export interface SessionConfig {
   auto_yes: boolean;